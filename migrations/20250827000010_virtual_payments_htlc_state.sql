@@ -0,0 +1,19 @@
+-- Persist virtual HTLCs in virtual_payments so pending payments survive restarts
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS from_virtual_node_id TEXT;
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS to_virtual_node_id TEXT;
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS btc_amount_msat BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS rgb_contract_id TEXT;
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS rgb_amount BIGINT;
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'pending';
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS preimage TEXT;
+ALTER TABLE virtual_payments ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW();
+
+-- The owning user is resolved separately from the HTLC itself
+ALTER TABLE virtual_payments ALTER COLUMN user_id DROP NOT NULL;
+
+-- Only Pending -> Settled and Pending -> Failed are valid transitions
+ALTER TABLE virtual_payments ADD CONSTRAINT virtual_payments_status_check
+    CHECK (status IN ('pending', 'settled', 'failed'));
+
+-- Index for reloading pending HTLCs on unlock
+CREATE INDEX IF NOT EXISTS idx_virtual_payments_status ON virtual_payments(status);
@@ -0,0 +1,6 @@
+-- Record which virtual node a per-user payment entry belongs to
+ALTER TABLE ln_user_transactions ADD COLUMN IF NOT EXISTS virtual_node_id TEXT;
+
+-- One mapping per payment and virtual node, so sender and receiver rows don't collide
+CREATE UNIQUE INDEX IF NOT EXISTS idx_user_transactions_txid_virtual_node ON ln_user_transactions(txid, virtual_node_id);
+CREATE INDEX IF NOT EXISTS idx_user_transactions_virtual_node_id ON ln_user_transactions(virtual_node_id);
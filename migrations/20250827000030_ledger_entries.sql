@@ -0,0 +1,38 @@
+-- Double-entry journal for virtual balances, balances are derived from it
+CREATE TABLE IF NOT EXISTS ln_ledger_entries (
+    id BIGSERIAL PRIMARY KEY,
+    journal_id UUID NOT NULL,
+    user_id TEXT NOT NULL,
+    asset_id TEXT,
+    amount BIGINT NOT NULL CHECK (amount <> 0),
+    reference TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_account ON ln_ledger_entries(user_id, COALESCE(asset_id, ''));
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_journal_id ON ln_ledger_entries(journal_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_reference ON ln_ledger_entries(reference);
+
+-- Entries are immutable, corrections must be posted as new journals
+CREATE OR REPLACE FUNCTION ln_ledger_entries_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'ln_ledger_entries is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS ln_ledger_entries_no_update ON ln_ledger_entries;
+CREATE TRIGGER ln_ledger_entries_no_update
+    BEFORE UPDATE OR DELETE ON ln_ledger_entries
+    FOR EACH ROW EXECUTE FUNCTION ln_ledger_entries_append_only();
+
+-- Carry existing balances over as opening entries against the external account
+INSERT INTO ln_ledger_entries (journal_id, user_id, asset_id, amount, reference)
+SELECT '00000000-0000-0000-0000-000000000000', user_id, asset_id, balance, 'opening_balance'
+FROM ln_user_balances WHERE balance <> 0;
+
+INSERT INTO ln_ledger_entries (journal_id, user_id, asset_id, amount, reference)
+SELECT '00000000-0000-0000-0000-000000000000', '@external', asset_id, -SUM(balance), 'opening_balance'
+FROM ln_user_balances GROUP BY asset_id HAVING SUM(balance) <> 0;
+
+-- Keep the old mutable balances around for auditing only
+ALTER TABLE ln_user_balances RENAME TO ln_user_balances_legacy;
@@ -0,0 +1,13 @@
+-- Invoices issued on behalf of users, mapping payment hashes to the receiving user
+CREATE TABLE IF NOT EXISTS ln_user_invoices (
+    payment_hash TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    invoice TEXT NOT NULL,
+    amount_msat BIGINT,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'paid')),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    paid_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_invoices_user_id ON ln_user_invoices(user_id);
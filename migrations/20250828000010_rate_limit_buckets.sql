@@ -0,0 +1,8 @@
+-- Token buckets of the per-user rate limiter, shared by hub replicas
+CREATE TABLE IF NOT EXISTS ln_rate_limit_buckets (
+    bucket_key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_rate_limit_buckets_updated_at ON ln_rate_limit_buckets(updated_at);
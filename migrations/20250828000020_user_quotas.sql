@@ -0,0 +1,27 @@
+-- Quota definitions per user, users without a row get the default quotas
+CREATE TABLE IF NOT EXISTS ln_user_quotas (
+    user_id TEXT PRIMARY KEY,
+    max_channels BIGINT NOT NULL,
+    max_assets BIGINT NOT NULL,
+    max_transactions_per_day BIGINT NOT NULL,
+    max_transactions_per_month BIGINT NOT NULL,
+    max_balance_btc BIGINT NOT NULL,
+    max_sats_per_day BIGINT NOT NULL,
+    max_sats_per_month BIGINT NOT NULL,
+    max_asset_units_per_day BIGINT NOT NULL,
+    max_asset_units_per_month BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Usage counted against the quotas, one row per request let through
+CREATE TABLE IF NOT EXISTS ln_user_quota_usage (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    action TEXT NOT NULL CHECK (action IN ('create_channel', 'create_asset', 'transaction')),
+    sats BIGINT NOT NULL DEFAULT 0,
+    asset_id TEXT,
+    asset_amount BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_quota_usage_user_created_at ON ln_user_quota_usage(user_id, created_at);
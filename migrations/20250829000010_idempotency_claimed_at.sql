@@ -0,0 +1,2 @@
+-- When an in-progress key was last claimed, so claims left behind by a crash can be recovered
+ALTER TABLE ln_idempotency_keys ADD COLUMN IF NOT EXISTS claimed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
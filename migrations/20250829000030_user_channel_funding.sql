@@ -0,0 +1,3 @@
+-- Ledger journal that funded a channel opened for a user, reversed when the channel closes
+ALTER TABLE ln_user_channels ADD COLUMN IF NOT EXISTS funding_reference TEXT;
+CREATE INDEX IF NOT EXISTS idx_user_channels_channel_id ON ln_user_channels(channel_id);
@@ -0,0 +1,14 @@
+-- Lightning payments made from a user balance, one row per attempt to pay an invoice
+CREATE TABLE IF NOT EXISTS ln_user_payment_attempts (
+    reference TEXT PRIMARY KEY,
+    payment_hash TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    max_fee_sats BIGINT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- An invoice can be retried once an attempt failed, but never paid by two attempts at a time
+CREATE UNIQUE INDEX IF NOT EXISTS idx_user_payment_attempts_pending
+    ON ln_user_payment_attempts(payment_hash) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_user_payment_attempts_user_id ON ln_user_payment_attempts(user_id);
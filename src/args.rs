@@ -5,11 +5,15 @@ use std::path::PathBuf;
 use crate::error::AppError;
 use crate::utils::check_port_is_available;
 
+/// Environment variable holding the backup password for `--verify-backup`
+pub(crate) const BACKUP_PASSWORD_ENV: &str = "RLN_BACKUP_PASSWORD";
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Path for the node storage directory
-    storage_directory_path: PathBuf,
+    #[arg(required_unless_present = "verify_backup")]
+    storage_directory_path: Option<PathBuf>,
 
     /// Listening port of the daemon
     #[arg(long, default_value_t = 3001)]
@@ -26,6 +30,20 @@ struct Args {
     /// Max allowed media size for upload (in MB)
     #[arg(long, default_value_t = 5)]
     max_media_upload_size_mb: u16,
+
+    /// Verify the backup at the given path and exit, reading the password from the
+    /// RLN_BACKUP_PASSWORD env variable or stdin
+    #[arg(long)]
+    verify_backup: Option<PathBuf>,
+
+    /// Snapshot of an incremental backup to verify (latest if not given)
+    #[arg(long, requires = "verify_backup")]
+    verify_backup_snapshot: Option<String>,
+}
+
+pub(crate) struct VerifyBackupArgs {
+    pub(crate) backup_path: PathBuf,
+    pub(crate) snapshot_id: Option<String>,
 }
 
 pub(crate) struct LdkUserInfo {
@@ -34,6 +52,7 @@ pub(crate) struct LdkUserInfo {
     pub(crate) ldk_peer_listening_port: u16,
     pub(crate) network: BitcoinNetwork,
     pub(crate) max_media_upload_size_mb: u16,
+    pub(crate) verify_backup: Option<VerifyBackupArgs>,
 }
 
 pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, AppError> {
@@ -41,16 +60,25 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, AppError> {
 
     let network = args.network;
 
+    let verify_backup = args.verify_backup.map(|backup_path| VerifyBackupArgs {
+        backup_path,
+        snapshot_id: args.verify_backup_snapshot,
+    });
+
+    // no daemon is started when verifying a backup
     let daemon_listening_port = args.daemon_listening_port;
-    check_port_is_available(daemon_listening_port)?;
     let ldk_peer_listening_port = args.ldk_peer_listening_port;
-    check_port_is_available(ldk_peer_listening_port)?;
+    if verify_backup.is_none() {
+        check_port_is_available(daemon_listening_port)?;
+        check_port_is_available(ldk_peer_listening_port)?;
+    }
 
     Ok(LdkUserInfo {
-        storage_dir_path: args.storage_directory_path,
+        storage_dir_path: args.storage_directory_path.unwrap_or_default(),
         daemon_listening_port,
         ldk_peer_listening_port,
         network,
         max_media_upload_size_mb: args.max_media_upload_size_mb,
+        verify_backup,
     })
 }
@@ -1,20 +1,25 @@
 use amplify::s;
-use chacha20poly1305::aead::{generic_array::GenericArray, stream};
-use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305};
+use chacha20poly1305::aead::{generic_array::GenericArray, stream, Aead};
+use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
 use rand::{distributions::Alphanumeric, Rng};
 use scrypt::password_hash::{PasswordHasher, Salt};
 use scrypt::Scrypt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tempfile::TempDir;
 use typenum::consts::U32;
 use walkdir::WalkDir;
 use zip::write::SimpleFileOptions;
 
-use std::fs::{create_dir_all, read_to_string, remove_file, write, File};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read, read_dir, read_to_string, remove_file, rename, write, File};
 use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 use crate::error::APIError;
-use crate::utils::LOGS_DIR;
+use crate::utils::{get_current_timestamp, hex_str, LOGS_DIR};
 
 const BACKUP_BUFFER_LEN_ENCRYPT: usize = 239; // 255 max, leaving 16 for the checksum
 const BACKUP_BUFFER_LEN_DECRYPT: usize = BACKUP_BUFFER_LEN_ENCRYPT + 16;
@@ -22,6 +27,17 @@ const BACKUP_KEY_LENGTH: usize = 32;
 const BACKUP_NONCE_LENGTH: usize = 19;
 const BACKUP_VERSION: u8 = 1;
 
+const BACKUP_V2_VERSION: u8 = 2;
+const BACKUP_V2_CHUNK_SIZE: u64 = 1024 * 1024;
+const BACKUP_V2_XNONCE_LENGTH: usize = 24;
+const BACKUP_V2_CHECK_PLAINTEXT: &[u8] = b"rln-backup-v2";
+const BACKUP_V2_CHUNK_ID_DOMAIN: &[u8] = b"rln-backup-chunk-id";
+const BACKUP_V2_CHECK_FNAME: &str = "backup.check";
+const BACKUP_V2_SALT_FNAME: &str = "backup.salt";
+const BACKUP_V2_VERSION_FNAME: &str = "backup.version";
+const BACKUP_V2_CHUNKS_DIR: &str = "chunks";
+const BACKUP_V2_SNAPSHOTS_DIR: &str = "snapshots";
+
 struct BackupPaths {
     encrypted: PathBuf,
     nonce: PathBuf,
@@ -36,6 +52,58 @@ struct CypherSecrets {
     nonce: [u8; BACKUP_NONCE_LENGTH],
 }
 
+/// Snapshot manifest of a version 2 backup
+///
+/// Lists every directory and file of the wallet dir at backup time, with files described by the
+/// IDs of the chunks holding their content, in order.
+#[derive(Debug, Deserialize, Serialize)]
+struct BackupManifest {
+    version: u8,
+    snapshot_id: String,
+    parent: Option<String>,
+    created_at: u64,
+    dirs: Vec<String>,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ManifestFile {
+    path: String,
+    size: u64,
+    modified_ns: Option<u64>,
+    chunks: Vec<String>,
+}
+
+/// Result of a version 2 backup
+#[derive(Debug)]
+pub(crate) struct BackupSnapshot {
+    pub(crate) snapshot_id: String,
+    pub(crate) new_chunks: usize,
+    pub(crate) reused_chunks: usize,
+}
+
+/// Version and snapshot of a restored backup
+#[derive(Debug)]
+pub(crate) struct RestoredBackup {
+    pub(crate) version: u8,
+    pub(crate) snapshot_id: Option<String>,
+}
+
+/// Encrypted, content-addressed chunk store of a version 2 backup
+struct ChunkStore {
+    dir: PathBuf,
+    cipher: BackupCipher,
+    id_key: [u8; 32],
+}
+
+/// Cipher with a key derived from the backup password
+///
+/// Encrypted messages are prefixed with their random xchacha20poly1305 nonce.
+pub(crate) struct BackupCipher {
+    key: GenericArray<u8, U32>,
+    aead: XChaCha20Poly1305,
+}
+
 /// Create a backup of the wallet as a file with the provided name and encrypted with the
 /// provided password.
 ///
@@ -87,13 +155,28 @@ pub(crate) fn do_backup(
 }
 
 /// Restore a backup from the given file and password to the provided target directory.
+///
+/// Version 2 backup directories are restored to the given snapshot, or to the latest one if no
+/// snapshot ID is provided.
 pub(crate) fn restore_backup(
     backup_path: &Path,
     password: &str,
     target_dir: &Path,
-) -> Result<(), APIError> {
+    snapshot_id: Option<&str>,
+) -> Result<RestoredBackup, APIError> {
     // setup
     tracing::info!("starting restore...");
+    if backup_path.is_dir() {
+        let snapshot_id = _restore_backup_v2(backup_path, password, target_dir, snapshot_id)?;
+        tracing::info!("restore completed");
+        return Ok(RestoredBackup {
+            version: BACKUP_V2_VERSION,
+            snapshot_id: Some(snapshot_id),
+        });
+    }
+    if let Some(snapshot_id) = snapshot_id {
+        return Err(APIError::BackupSnapshotNotFound(snapshot_id.to_string()));
+    }
     let backup_file = PathBuf::from(backup_path);
     let tmp_base_path = _get_parent_path(&backup_file)?;
     let files = _get_backup_paths(&tmp_base_path)?;
@@ -123,6 +206,409 @@ pub(crate) fn restore_backup(
     _unzip(&files.zip, &target_dir_path)?;
 
     tracing::info!("restore completed");
+    Ok(RestoredBackup {
+        version: BACKUP_VERSION,
+        snapshot_id: None,
+    })
+}
+
+/// Create a version 2 snapshot of the wallet in the provided backup directory.
+///
+/// The backup directory is created if missing. File contents are split in chunks, each stored
+/// once encrypted under a name derived from its content with a keyed hash, so snapshots after
+/// the first one only write the chunks that changed. Files whose size and modification time
+/// match the previous snapshot are not read again. Each snapshot is described by an encrypted
+/// manifest that [`restore_backup`] can replay.
+pub(crate) fn do_incremental_backup(
+    wallet_dir: &Path,
+    backup_dir: &Path,
+    password: &str,
+) -> Result<BackupSnapshot, APIError> {
+    tracing::info!("starting incremental backup...");
+    let store = _open_backup_dir(backup_dir, password, true)?;
+
+    let snapshot_ids = _list_snapshot_ids(backup_dir)?;
+    let parent = match snapshot_ids.last() {
+        Some(id) => Some(_read_manifest(backup_dir, &store, id)?),
+        None => None,
+    };
+    let parent_files: HashMap<&str, &ManifestFile> = parent
+        .iter()
+        .flat_map(|m| m.files.iter())
+        .map(|f| (f.path.as_str(), f))
+        .collect();
+    let sequence = snapshot_ids
+        .last()
+        .and_then(|id| id.parse::<u64>().ok())
+        .unwrap_or(0)
+        + 1;
+    let snapshot_id = format!("{sequence:08}");
+
+    let mut manifest = BackupManifest {
+        version: BACKUP_V2_VERSION,
+        snapshot_id: snapshot_id.clone(),
+        parent: parent.as_ref().map(|m| m.snapshot_id.clone()),
+        created_at: get_current_timestamp(),
+        dirs: vec![],
+        files: vec![],
+    };
+    let mut new_chunks = 0;
+    let mut reused_chunks = 0;
+
+    let entry_iterator = WalkDir::new(wallet_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok());
+    for entry in entry_iterator {
+        let path = entry.path();
+        let name = path
+            .strip_prefix(wallet_dir)
+            .map_err(|e| APIError::Unexpected(format!("Failed to get file name: {e}")))?;
+        let name_str = name
+            .to_str()
+            .ok_or_else(|| APIError::Unexpected(s!("Failed to convert file name to string")))?
+            .replace(std::path::MAIN_SEPARATOR, "/");
+        if path.is_file() {
+            if path.ends_with("log") {
+                continue;
+            }
+            let metadata = entry
+                .metadata()
+                .map_err(|e| APIError::Unexpected(format!("Failed to get file metadata: {e}")))?;
+            let size = metadata.len();
+            let modified_ns = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .and_then(|d| u64::try_from(d.as_nanos()).ok());
+            if let Some(parent_file) = parent_files.get(name_str.as_str()) {
+                if modified_ns.is_some()
+                    && parent_file.modified_ns == modified_ns
+                    && parent_file.size == size
+                    && parent_file.chunks.iter().all(|id| store.has_chunk(id))
+                {
+                    tracing::debug!("reusing unchanged file {path:?}");
+                    reused_chunks += parent_file.chunks.len();
+                    manifest.files.push((*parent_file).clone());
+                    continue;
+                }
+            }
+            tracing::debug!("adding file {path:?} as {name_str}");
+            let mut chunks = vec![];
+            let mut f = File::open(path)?;
+            loop {
+                let mut chunk = vec![];
+                let read_count = (&mut f)
+                    .take(BACKUP_V2_CHUNK_SIZE)
+                    .read_to_end(&mut chunk)?;
+                if read_count == 0 {
+                    break;
+                }
+                let (id, added) = store.put_chunk(&chunk)?;
+                if added {
+                    new_chunks += 1;
+                } else {
+                    reused_chunks += 1;
+                }
+                chunks.push(id);
+            }
+            manifest.files.push(ManifestFile {
+                path: name_str,
+                size,
+                modified_ns,
+                chunks,
+            });
+        } else if !name.as_os_str().is_empty() {
+            if name_str.ends_with(LOGS_DIR) {
+                continue;
+            }
+            tracing::debug!("adding directory {path:?} as {name_str}");
+            manifest.dirs.push(name_str);
+        }
+    }
+
+    let manifest_bytes = serde_json::to_vec(&manifest)
+        .map_err(|e| APIError::Unexpected(format!("Failed to serialize manifest: {e}")))?;
+    let manifest_path = backup_dir.join(BACKUP_V2_SNAPSHOTS_DIR).join(&snapshot_id);
+    if manifest_path.exists() {
+        return Err(APIError::Unexpected(format!(
+            "Backup snapshot {snapshot_id} already exists"
+        )));
+    }
+    _write_atomic(&manifest_path, &store.cipher.encrypt(&manifest_bytes)?)?;
+
+    tracing::info!(
+        "incremental backup completed: snapshot {snapshot_id}, {new_chunks} new chunks, \
+         {reused_chunks} reused chunks"
+    );
+    Ok(BackupSnapshot {
+        snapshot_id,
+        new_chunks,
+        reused_chunks,
+    })
+}
+
+fn _restore_backup_v2(
+    backup_dir: &Path,
+    password: &str,
+    target_dir: &Path,
+    snapshot_id: Option<&str>,
+) -> Result<String, APIError> {
+    let store = _open_backup_dir(backup_dir, password, false)?;
+    let snapshot_ids = _list_snapshot_ids(backup_dir)?;
+    let snapshot_id = match snapshot_id {
+        Some(id) if snapshot_ids.iter().any(|s| s == id) => id.to_string(),
+        Some(id) => return Err(APIError::BackupSnapshotNotFound(id.to_string())),
+        None => snapshot_ids
+            .last()
+            .cloned()
+            .ok_or_else(|| APIError::BackupSnapshotNotFound(s!("latest")))?,
+    };
+    let manifest = _read_manifest(backup_dir, &store, &snapshot_id)?;
+    tracing::info!(
+        "replaying snapshot {} created at {} to {:?}",
+        manifest.snapshot_id,
+        manifest.created_at,
+        target_dir
+    );
+
+    create_dir_all(target_dir)?;
+    for dir in &manifest.dirs {
+        let outpath = target_dir.join(_manifest_path(dir)?);
+        tracing::debug!("creating directory {}", outpath.display());
+        create_dir_all(&outpath)?;
+    }
+    for file in &manifest.files {
+        let outpath = target_dir.join(_manifest_path(&file.path)?);
+        tracing::debug!("restoring file {} ({} bytes)", outpath.display(), file.size);
+        if let Some(p) = outpath.parent() {
+            create_dir_all(p)?;
+        }
+        let mut outfile = File::create(&outpath)?;
+        for id in &file.chunks {
+            outfile.write_all(&store.get_chunk(id)?)?;
+        }
+        outfile.flush()?;
+    }
+
+    Ok(manifest.snapshot_id)
+}
+
+impl ChunkStore {
+    fn chunk_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn chunk_id(&self, data: &[u8]) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.id_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(data);
+        hex_str(&mac.finalize().into_bytes())
+    }
+
+    fn has_chunk(&self, id: &str) -> bool {
+        self.chunk_path(id).is_file()
+    }
+
+    /// Store a chunk if missing, returning its ID and whether it has been added
+    fn put_chunk(&self, data: &[u8]) -> Result<(String, bool), APIError> {
+        let id = self.chunk_id(data);
+        if self.has_chunk(&id) {
+            return Ok((id, false));
+        }
+        _write_atomic(&self.chunk_path(&id), &self.cipher.encrypt(data)?)?;
+        Ok((id, true))
+    }
+
+    fn get_chunk(&self, id: &str) -> Result<Vec<u8>, APIError> {
+        let encrypted = read(self.chunk_path(id))
+            .map_err(|e| APIError::Unexpected(format!("Failed to read backup chunk {id}: {e}")))?;
+        let data = self.cipher.decrypt(&encrypted)?;
+        if self.chunk_id(&data) != id {
+            return Err(APIError::Unexpected(format!("Corrupted backup chunk {id}")));
+        }
+        Ok(data)
+    }
+}
+
+impl BackupCipher {
+    /// Derive the cipher key from the password and salt with scrypt
+    pub(crate) fn new(password: &str, salt: &str) -> Result<Self, APIError> {
+        let key = _derive_key(password, salt)?;
+        Ok(Self {
+            aead: XChaCha20Poly1305::new(&key),
+            key,
+        })
+    }
+
+    /// Generate a random salt to derive a new key
+    pub(crate) fn generate_salt() -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(BACKUP_KEY_LENGTH)
+            .map(char::from)
+            .collect()
+    }
+
+    /// Derive a key for another purpose, identified by the provided domain
+    fn derive_subkey(&self, domain: &[u8]) -> [u8; 32] {
+        let mut engine = Sha256::new();
+        engine.update(domain);
+        engine.update(self.key.as_slice());
+        engine.finalize().into()
+    }
+
+    pub(crate) fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, APIError> {
+        let mut nonce = [0u8; BACKUP_V2_XNONCE_LENGTH];
+        rand::thread_rng().fill(&mut nonce);
+        let ciphertext = self
+            .aead
+            .encrypt(XNonce::from_slice(&nonce), data)
+            .map_err(|e| APIError::Unexpected(format!("Failed to encrypt: {e}")))?;
+        let mut res = nonce.to_vec();
+        res.extend(ciphertext);
+        Ok(res)
+    }
+
+    pub(crate) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, APIError> {
+        if data.len() < BACKUP_V2_XNONCE_LENGTH {
+            return Err(APIError::Unexpected(s!(
+                "Encrypted backup data is too short"
+            )));
+        }
+        let (nonce, ciphertext) = data.split_at(BACKUP_V2_XNONCE_LENGTH);
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_e| APIError::WrongPassword)
+    }
+}
+
+fn _get_backup_dir_version(backup_dir: &Path) -> Result<Option<u8>, APIError> {
+    let version_path = backup_dir.join(BACKUP_V2_VERSION_FNAME);
+    if !backup_dir.is_dir() || !version_path.is_file() {
+        return Ok(None);
+    }
+    let version = read_to_string(version_path)?
+        .trim()
+        .parse::<u8>()
+        .map_err(|e| APIError::Unexpected(format!("Failed to get backup version: {e}")))?;
+    Ok(Some(version))
+}
+
+/// Open the chunk store of a version 2 backup directory, initializing it if allowed
+fn _open_backup_dir(
+    backup_dir: &Path,
+    password: &str,
+    create: bool,
+) -> Result<ChunkStore, APIError> {
+    let salt_path = backup_dir.join(BACKUP_V2_SALT_FNAME);
+    let check_path = backup_dir.join(BACKUP_V2_CHECK_FNAME);
+    let chunks_dir = backup_dir.join(BACKUP_V2_CHUNKS_DIR);
+
+    let initialized = match _get_backup_dir_version(backup_dir)? {
+        Some(BACKUP_V2_VERSION) => true,
+        Some(version) => {
+            return Err(APIError::UnsupportedBackupVersion {
+                version: version.to_string(),
+            })
+        }
+        None if !create => return Err(APIError::InvalidBackupPath),
+        None if backup_dir.is_file() => return Err(APIError::InvalidBackupPath),
+        None if backup_dir.is_dir() && read_dir(backup_dir)?.next().is_some() => {
+            return Err(APIError::InvalidBackupPath)
+        }
+        None => false,
+    };
+
+    let salt = if initialized {
+        read_to_string(&salt_path)?
+    } else {
+        BackupCipher::generate_salt()
+    };
+    let cipher = BackupCipher::new(password, &salt)?;
+    let store = ChunkStore {
+        dir: chunks_dir,
+        id_key: cipher.derive_subkey(BACKUP_V2_CHUNK_ID_DOMAIN),
+        cipher,
+    };
+
+    if initialized {
+        let check = read(&check_path)?;
+        if store.cipher.decrypt(&check)? != BACKUP_V2_CHECK_PLAINTEXT {
+            return Err(APIError::WrongPassword);
+        }
+    } else {
+        tracing::debug!("initializing backup directory {:?}", backup_dir);
+        create_dir_all(&store.dir)?;
+        create_dir_all(backup_dir.join(BACKUP_V2_SNAPSHOTS_DIR))?;
+        write(&salt_path, salt)?;
+        write(
+            &check_path,
+            store.cipher.encrypt(BACKUP_V2_CHECK_PLAINTEXT)?,
+        )?;
+        write(
+            backup_dir.join(BACKUP_V2_VERSION_FNAME),
+            BACKUP_V2_VERSION.to_string(),
+        )?;
+    }
+
+    Ok(store)
+}
+
+fn _list_snapshot_ids(backup_dir: &Path) -> Result<Vec<String>, APIError> {
+    let mut ids = vec![];
+    for entry in read_dir(backup_dir.join(BACKUP_V2_SNAPSHOTS_DIR))? {
+        let entry = entry?;
+        if !entry.path().is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            if name.len() == 8 && name.bytes().all(|b| b.is_ascii_digit()) {
+                ids.push(name.to_string());
+            }
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+fn _read_manifest(
+    backup_dir: &Path,
+    store: &ChunkStore,
+    snapshot_id: &str,
+) -> Result<BackupManifest, APIError> {
+    let encrypted = read(backup_dir.join(BACKUP_V2_SNAPSHOTS_DIR).join(snapshot_id))?;
+    let manifest: BackupManifest = serde_json::from_slice(&store.cipher.decrypt(&encrypted)?)
+        .map_err(|e| APIError::Unexpected(format!("Failed to parse backup manifest: {e}")))?;
+    if manifest.version != BACKUP_V2_VERSION {
+        return Err(APIError::UnsupportedBackupVersion {
+            version: manifest.version.to_string(),
+        });
+    }
+    Ok(manifest)
+}
+
+/// Convert a manifest path to a relative path, refusing paths escaping the target directory
+fn _manifest_path(path: &str) -> Result<PathBuf, APIError> {
+    let path: PathBuf = path.split('/').collect();
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(APIError::Unexpected(format!(
+            "Invalid path in backup manifest: {}",
+            path.display()
+        )));
+    }
+    Ok(path)
+}
+
+fn _write_atomic(path: &Path, data: &[u8]) -> Result<(), APIError> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    rename(&tmp_path, path)?;
     Ok(())
 }
 
@@ -251,6 +737,18 @@ fn _get_cypher_secrets(
     salt_str: &str,
     nonce_str: &str,
 ) -> Result<CypherSecrets, APIError> {
+    let key = _derive_key(password, salt_str)?;
+
+    // get nonce from provided str
+    let nonce_bytes = nonce_str.as_bytes();
+    let nonce: [u8; BACKUP_NONCE_LENGTH] = nonce_bytes[0..BACKUP_NONCE_LENGTH]
+        .try_into()
+        .map_err(|e| APIError::Unexpected(format!("Failed to get nonce: {e}")))?;
+
+    Ok(CypherSecrets { key, nonce })
+}
+
+fn _derive_key(password: &str, salt_str: &str) -> Result<GenericArray<u8, U32>, APIError> {
     // hash password using scrypt with the provided salt
     let password_bytes = password.as_bytes();
     let salt = Salt::from_b64(salt_str)
@@ -264,15 +762,7 @@ fn _get_cypher_secrets(
     let hash = hash_output.as_bytes();
 
     // get key from password hash
-    let key = Key::clone_from_slice(&hash[..BACKUP_KEY_LENGTH]);
-
-    // get nonce from provided str
-    let nonce_bytes = nonce_str.as_bytes();
-    let nonce: [u8; BACKUP_NONCE_LENGTH] = nonce_bytes[0..BACKUP_NONCE_LENGTH]
-        .try_into()
-        .map_err(|e| APIError::Unexpected(format!("Failed to get nonce: {e}")))?;
-
-    Ok(CypherSecrets { key, nonce })
+    Ok(Key::clone_from_slice(&hash[..BACKUP_KEY_LENGTH]))
 }
 
 fn _encrypt_file(
@@ -0,0 +1,430 @@
+//! Offline verification of backups.
+//!
+//! A backup is restored into a temporary directory under the node data dir and its parts are
+//! checked against each other: the mnemonic must decrypt with the password, the LDK keys derived
+//! from it must read the channel monitors, the channel manager must be for the same network and
+//! deserialize against those monitors as it would at startup, and the RGB database of the
+//! mnemonic must hold the assets of RGB channels. The live node data is never touched.
+
+use bitcoin::constants::ChainHash;
+use bitcoin::{BlockHash, Network, Transaction};
+use hex::DisplayHex;
+use lightning::chain::chaininterface::{
+    BroadcasterInterface, ConfirmationTarget, FeeEstimator, FEERATE_FLOOR_SATS_PER_KW,
+};
+use lightning::chain::{chainmonitor, Filter};
+use lightning::ln::channelmanager::{self, ChannelManagerReadArgs};
+use lightning::rgb_utils::{BITCOIN_NETWORK_FNAME, WALLET_MASTER_FINGERPRINT_FNAME};
+use lightning::routing::router::DefaultRouter;
+use lightning::routing::scoring::{
+    ProbabilisticScorer, ProbabilisticScoringDecayParameters, ProbabilisticScoringFeeParameters,
+};
+use lightning::sign::{InMemorySigner, KeysManager, NodeSigner, Recipient};
+use lightning::util::config::UserConfig;
+use lightning::util::persist::MonitorUpdatingPersister;
+use lightning::util::ser::{Readable, ReadableArgs};
+use lightning_persister::fs_store::FilesystemStore;
+use rgb_lib::bdk_wallet::keys::bip39::Mnemonic;
+use rgb_lib::utils::get_account_data;
+use rgb_lib::wallet::{DatabaseType, Wallet as RgbLibWallet, WalletData};
+use rgb_lib::{AssetSchema, BitcoinNetwork};
+use std::collections::HashSet;
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::backup::restore_backup;
+use crate::disk::FilesystemLogger;
+use crate::error::APIError;
+use crate::ldk::{derive_ldk_seed, NetworkGraph, Router};
+use crate::rgb::get_rgb_channel_info_optional;
+use crate::routes::{BackupAsset, BackupChannel, VerifyBackupResponse};
+use crate::utils::{check_password_validity, LDK_DIR};
+
+const CHANNEL_MANAGER_FNAME: &str = "manager";
+const RGB_DATA_DIR: &str = "rgb_sqlite";
+
+struct NoopBroadcaster;
+
+impl BroadcasterInterface for NoopBroadcaster {
+    fn broadcast_transactions(&self, _txs: &[&Transaction]) {}
+}
+
+struct NoopFeeEstimator;
+
+impl FeeEstimator for NoopFeeEstimator {
+    fn get_est_sat_per_1000_weight(&self, _confirmation_target: ConfirmationTarget) -> u32 {
+        FEERATE_FLOOR_SATS_PER_KW
+    }
+}
+
+type VerifyPersister = MonitorUpdatingPersister<
+    Arc<FilesystemStore>,
+    Arc<FilesystemLogger>,
+    Arc<KeysManager>,
+    Arc<KeysManager>,
+    Arc<NoopBroadcaster>,
+    Arc<NoopFeeEstimator>,
+>;
+
+type VerifyChainMonitor = chainmonitor::ChainMonitor<
+    InMemorySigner,
+    Arc<dyn Filter + Send + Sync>,
+    Arc<NoopBroadcaster>,
+    Arc<NoopFeeEstimator>,
+    Arc<FilesystemLogger>,
+    Arc<VerifyPersister>,
+>;
+
+/// The node's channel manager, with chain access replaced by no-ops
+type VerifyChannelManager = channelmanager::ChannelManager<
+    Arc<VerifyChainMonitor>,
+    Arc<NoopBroadcaster>,
+    Arc<KeysManager>,
+    Arc<KeysManager>,
+    Arc<KeysManager>,
+    Arc<NoopFeeEstimator>,
+    Arc<Router>,
+    Arc<FilesystemLogger>,
+>;
+
+/// Header of a serialized channel manager
+struct ChannelManagerHeader {
+    chain_hash: ChainHash,
+    best_block_height: u32,
+    funded_channels: u64,
+}
+
+fn read_channel_manager_header(path: &Path) -> Result<ChannelManagerHeader, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(file);
+    let err = |e: lightning::ln::msgs::DecodeError| e.to_string();
+    let _version: u8 = Readable::read(&mut reader).map_err(err)?;
+    let _min_version: u8 = Readable::read(&mut reader).map_err(err)?;
+    let chain_hash: ChainHash = Readable::read(&mut reader).map_err(err)?;
+    let best_block_height: u32 = Readable::read(&mut reader).map_err(err)?;
+    let _best_block_hash: BlockHash = Readable::read(&mut reader).map_err(err)?;
+    let funded_channels: u64 = Readable::read(&mut reader).map_err(err)?;
+    Ok(ChannelManagerHeader {
+        chain_hash,
+        best_block_height,
+        funded_channels,
+    })
+}
+
+/// Decrypt and unpack a backup in a temporary directory and check its consistency
+///
+/// Problems with the backup content are listed in the report, errors are only returned when the
+/// backup can't be restored at all (e.g. wrong password or unknown snapshot).
+///
+/// The backup is unpacked next to `data_dir`, so its secrets stay on the node's storage device
+/// without ending up in the backups of the node, which archive the whole data dir.
+pub(crate) fn do_verify_backup(
+    backup_path: &Path,
+    password: &str,
+    snapshot_id: Option<&str>,
+    bitcoin_network: BitcoinNetwork,
+    data_dir: &Path,
+) -> Result<VerifyBackupResponse, APIError> {
+    fs::create_dir_all(data_dir)?;
+    let data_dir = fs::canonicalize(data_dir)?;
+    let (Some(parent_dir), Some(data_dir_name)) = (data_dir.parent(), data_dir.file_name()) else {
+        return Err(APIError::Unexpected(format!(
+            "Cannot unpack the backup next to {}",
+            data_dir.display()
+        )));
+    };
+    let tmp_dir = tempfile::Builder::new()
+        .prefix(&format!(".{}_verify", data_dir_name.to_string_lossy()))
+        .tempdir_in(parent_dir)?;
+    let storage_dir = tmp_dir.path();
+    let restored = restore_backup(backup_path, password, storage_dir, snapshot_id)?;
+    let mnemonic = check_password_validity(password, storage_dir)?;
+    let network: Network = bitcoin_network.into();
+
+    let mut report = VerifyBackupResponse {
+        valid: true,
+        version: restored.version,
+        snapshot_id: restored.snapshot_id,
+        network: bitcoin_network.into(),
+        node_pubkey: String::new(),
+        best_block_height: None,
+        channels: vec![],
+        assets: vec![],
+        issues: vec![],
+    };
+
+    match fs::read_to_string(storage_dir.join(BITCOIN_NETWORK_FNAME)) {
+        Ok(backup_network) if backup_network.trim() != bitcoin_network.to_string() => {
+            report.issues.push(format!(
+                "backup is for network {}, expected {bitcoin_network}",
+                backup_network.trim()
+            ));
+        }
+        Ok(_) => {}
+        Err(_) => report
+            .issues
+            .push("network file missing, the node has never been unlocked".to_string()),
+    }
+
+    let assets = verify_rgb_wallet(storage_dir, &mnemonic, bitcoin_network, &mut report);
+    verify_ldk(storage_dir, &mnemonic, network, &assets, &mut report);
+
+    report.valid = report.issues.is_empty();
+    Ok(report)
+}
+
+/// Check the RGB database belongs to the mnemonic and list its assets
+fn verify_rgb_wallet(
+    storage_dir: &Path,
+    mnemonic: &Mnemonic,
+    bitcoin_network: BitcoinNetwork,
+    report: &mut VerifyBackupResponse,
+) -> HashSet<String> {
+    let mnemonic_str = mnemonic.to_string();
+    let (account_xpub_vanilla, account_xpub_colored, master_fingerprint) = match (
+        get_account_data(bitcoin_network, &mnemonic_str, false),
+        get_account_data(bitcoin_network, &mnemonic_str, true),
+    ) {
+        (Ok((_, vanilla, _)), Ok((_, colored, fingerprint))) => (vanilla, colored, fingerprint),
+        _ => {
+            report
+                .issues
+                .push("cannot derive the RGB wallet keys from the mnemonic".to_string());
+            return HashSet::new();
+        }
+    };
+
+    if let Ok(stored_fingerprint) =
+        fs::read_to_string(storage_dir.join(WALLET_MASTER_FINGERPRINT_FNAME))
+    {
+        if stored_fingerprint.trim() != master_fingerprint.to_string() {
+            report.issues.push(format!(
+                "wallet fingerprint {} doesn't match the mnemonic fingerprint {master_fingerprint}",
+                stored_fingerprint.trim()
+            ));
+        }
+    }
+    let rgb_data_dir = storage_dir.join(RGB_DATA_DIR);
+    if !rgb_data_dir.join(master_fingerprint.to_string()).is_dir() {
+        report.issues.push(format!(
+            "RGB database for wallet {master_fingerprint} is missing"
+        ));
+        return HashSet::new();
+    }
+
+    let wallet = RgbLibWallet::new(WalletData {
+        data_dir: rgb_data_dir.to_string_lossy().to_string(),
+        bitcoin_network,
+        database_type: DatabaseType::Sqlite,
+        max_allocations_per_utxo: 1,
+        account_xpub_vanilla: account_xpub_vanilla.to_string(),
+        account_xpub_colored: account_xpub_colored.to_string(),
+        master_fingerprint: master_fingerprint.to_string(),
+        mnemonic: Some(mnemonic_str),
+        vanilla_keychain: None,
+        supported_schemas: vec![AssetSchema::Nia, AssetSchema::Cfa, AssetSchema::Uda],
+    });
+    let assets = match wallet.and_then(|w| w.list_assets(vec![])) {
+        Ok(assets) => assets,
+        Err(e) => {
+            report
+                .issues
+                .push(format!("cannot read the RGB database: {e}"));
+            return HashSet::new();
+        }
+    };
+
+    for asset in assets.nia.unwrap_or_default() {
+        report.assets.push(BackupAsset {
+            asset_id: asset.asset_id,
+            schema: AssetSchema::Nia.into(),
+            name: asset.name,
+            settled_balance: asset.balance.settled,
+            spendable_balance: asset.balance.spendable,
+        });
+    }
+    for asset in assets.uda.unwrap_or_default() {
+        report.assets.push(BackupAsset {
+            asset_id: asset.asset_id,
+            schema: AssetSchema::Uda.into(),
+            name: asset.name,
+            settled_balance: asset.balance.settled,
+            spendable_balance: asset.balance.spendable,
+        });
+    }
+    for asset in assets.cfa.unwrap_or_default() {
+        report.assets.push(BackupAsset {
+            asset_id: asset.asset_id,
+            schema: AssetSchema::Cfa.into(),
+            name: asset.name,
+            settled_balance: asset.balance.settled,
+            spendable_balance: asset.balance.spendable,
+        });
+    }
+    report.assets.iter().map(|a| a.asset_id.clone()).collect()
+}
+
+/// Read the channel monitors and channel manager with the keys derived from the mnemonic
+fn verify_ldk(
+    storage_dir: &Path,
+    mnemonic: &Mnemonic,
+    network: Network,
+    assets: &HashSet<String>,
+    report: &mut VerifyBackupResponse,
+) {
+    let ldk_data_dir = storage_dir.join(LDK_DIR);
+    if !ldk_data_dir.is_dir() {
+        report.issues.push("LDK data dir is missing".to_string());
+        return;
+    }
+
+    let ldk_seed = derive_ldk_seed(mnemonic, network);
+    let cur = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let keys_manager = Arc::new(KeysManager::new(
+        &ldk_seed,
+        cur.as_secs(),
+        cur.subsec_nanos(),
+        ldk_data_dir.clone(),
+    ));
+    if let Ok(node_id) = keys_manager.get_node_id(Recipient::Node) {
+        report.node_pubkey = node_id.to_string();
+    }
+
+    let logger = Arc::new(FilesystemLogger::new(ldk_data_dir.clone()));
+    let broadcaster = Arc::new(NoopBroadcaster);
+    let fee_estimator = Arc::new(NoopFeeEstimator);
+    let persister: Arc<VerifyPersister> = Arc::new(MonitorUpdatingPersister::new(
+        Arc::new(FilesystemStore::new(ldk_data_dir.clone())),
+        Arc::clone(&logger),
+        1000,
+        Arc::clone(&keys_manager),
+        Arc::clone(&keys_manager),
+        Arc::clone(&broadcaster),
+        Arc::clone(&fee_estimator),
+        ldk_data_dir.clone(),
+    ));
+    let mut monitors = match persister.read_all_channel_monitors_with_updates() {
+        Ok(monitors) => monitors,
+        Err(e) => {
+            report
+                .issues
+                .push(format!("cannot read the channel monitors: {e}"));
+            return;
+        }
+    };
+
+    for (_, monitor) in &monitors {
+        let (funding_txo, _) = monitor.get_funding_txo();
+        let channel_id = monitor.channel_id();
+        let rgb_info =
+            get_rgb_channel_info_optional(&channel_id, &ldk_data_dir, false).map(|(info, _)| info);
+        if let Some(info) = &rgb_info {
+            let contract_id = info.contract_id.to_string();
+            if !assets.contains(&contract_id) {
+                report.issues.push(format!(
+                    "channel {} uses asset {contract_id}, missing from the RGB database",
+                    channel_id.0.as_hex()
+                ));
+            }
+        }
+        report.channels.push(BackupChannel {
+            channel_id: channel_id.0.as_hex().to_string(),
+            funding_txid: funding_txo.txid.to_string(),
+            funding_output_index: funding_txo.index,
+            peer_pubkey: monitor.get_counterparty_node_id().map(|p| p.to_string()),
+            latest_update_id: monitor.get_latest_update_id(),
+            claimable_balance_sat: monitor
+                .get_claimable_balances()
+                .iter()
+                .map(|b| b.claimable_amount_satoshis())
+                .sum(),
+            asset_id: rgb_info.as_ref().map(|i| i.contract_id.to_string()),
+            asset_local_amount: rgb_info.as_ref().map(|i| i.local_rgb_amount),
+        });
+    }
+
+    let manager_path = ldk_data_dir.join(CHANNEL_MANAGER_FNAME);
+    if !manager_path.exists() {
+        if !monitors.is_empty() {
+            report
+                .issues
+                .push("channel manager is missing but there are channel monitors".to_string());
+        }
+        return;
+    }
+    match read_channel_manager_header(&manager_path) {
+        Ok(header) => {
+            report.best_block_height = Some(header.best_block_height);
+            if header.chain_hash != ChainHash::using_genesis_block(network) {
+                report
+                    .issues
+                    .push(format!("channel manager is not for network {network}"));
+            }
+            if header.funded_channels > monitors.len() as u64 {
+                report.issues.push(format!(
+                    "channel manager has {} funded channels but there are only {} channel monitors",
+                    header.funded_channels,
+                    monitors.len()
+                ));
+            }
+        }
+        Err(e) => {
+            report
+                .issues
+                .push(format!("cannot read the channel manager: {e}"));
+            return;
+        }
+    }
+
+    // deserialize the whole channel manager against the monitors, which fails as it would at
+    // startup if they're out of sync
+    let chain_monitor: Arc<VerifyChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
+        None,
+        Arc::clone(&broadcaster),
+        Arc::clone(&logger),
+        Arc::clone(&fee_estimator),
+        persister,
+    ));
+    let network_graph = Arc::new(NetworkGraph::new(network, Arc::clone(&logger)));
+    let scorer = ProbabilisticScorer::new(
+        ProbabilisticScoringDecayParameters::default(),
+        Arc::clone(&network_graph),
+        Arc::clone(&logger),
+    );
+    let router = Arc::new(DefaultRouter::new(
+        network_graph,
+        Arc::clone(&logger),
+        Arc::clone(&keys_manager),
+        Arc::new(RwLock::new(scorer)),
+        ProbabilisticScoringFeeParameters::default(),
+    ));
+    let read_args = ChannelManagerReadArgs::new(
+        Arc::clone(&keys_manager),
+        Arc::clone(&keys_manager),
+        Arc::clone(&keys_manager),
+        fee_estimator,
+        chain_monitor,
+        broadcaster,
+        router,
+        logger,
+        UserConfig::default(),
+        monitors.iter_mut().map(|(_, monitor)| monitor).collect(),
+        ldk_data_dir.clone(),
+    );
+    let read = fs::File::open(&manager_path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| {
+            <(BlockHash, VerifyChannelManager)>::read(&mut BufReader::new(f), read_args)
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = read {
+        report.issues.push(format!(
+            "channel manager doesn't load against the channel monitors: {e}"
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+//! Reference remote signer for the `cloud` HSM provider.
+//!
+//! Holds a seed from which virtual node keys are derived and answers authenticated derive, sign
+//! and ECDH requests, so key custody can live on a separate host from the node.
+
+// the client side of the protocol is only used by the node
+#[allow(dead_code)]
+#[path = "../remote_signer.rs"]
+mod remote_signer;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoin::secp256k1::{All, Message, PublicKey, Secp256k1, SecretKey};
+use clap::Parser;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use remote_signer::{
+    compute_response_mac, parse_nonce, verify_request, SignerRequest, SignerResponse,
+    MAX_CLOCK_SKEW_SECS, SIGNER_MAC_HEADER, SIGNER_NONCE_HEADER, SIGNER_PATH,
+    SIGNER_TIMESTAMP_HEADER,
+};
+
+/// Environment variable holding the secret shared with the node
+const SIGNER_SECRET_ENV: &str = "REMOTE_SIGNER_SECRET";
+
+#[derive(Parser)]
+#[command(author, version, about = "Reference remote signer for virtual node keys", long_about = None)]
+struct Args {
+    /// Path of the seed file, a new random seed is written there if it doesn't exist
+    seed_path: PathBuf,
+
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:3100")]
+    listen: SocketAddr,
+}
+
+struct SignerState {
+    seed: [u8; 32],
+    secret: Vec<u8>,
+    secp_ctx: Secp256k1<All>,
+    /// Nonces of accepted requests, with their timestamp, still inside the accepted window
+    seen_nonces: Mutex<HashMap<[u8; 32], u64>>,
+}
+
+impl SignerState {
+    /// Record a request nonce, false if it was already used
+    fn register_nonce(&self, nonce: [u8; 32], timestamp: u64, now: u64) -> bool {
+        let mut seen_nonces = self.seen_nonces.lock().unwrap();
+        seen_nonces.retain(|_, t| now.abs_diff(*t) <= MAX_CLOCK_SKEW_SECS);
+        seen_nonces.insert(nonce, timestamp).is_none()
+    }
+
+    fn virtual_node_secret(&self, user_id: &str) -> Result<SecretKey, String> {
+        if user_id.is_empty() {
+            return Err("Invalid user ID".to_string());
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(self.seed);
+        hasher.update(b"virtual_node_");
+        hasher.update(user_id.as_bytes());
+        SecretKey::from_slice(&hasher.finalize()).map_err(|e| e.to_string())
+    }
+
+    fn handle(&self, request: SignerRequest) -> Result<SignerResponse, String> {
+        match request {
+            SignerRequest::DeriveNodeId { user_id } => {
+                let secret = self.virtual_node_secret(&user_id)?;
+                Ok(SignerResponse::NodeId {
+                    node_id: PublicKey::from_secret_key(&self.secp_ctx, &secret).to_string(),
+                })
+            }
+            SignerRequest::SignHash { user_id, hash } => {
+                let secret = self.virtual_node_secret(&user_id)?;
+                let hash = <[u8; 32]>::from_hex(&hash).map_err(|e| format!("Invalid hash: {e}"))?;
+                let signature = self
+                    .secp_ctx
+                    .sign_ecdsa_recoverable(&Message::from_digest(hash), &secret);
+                let (recovery_id, signature) = signature.serialize_compact();
+                Ok(SignerResponse::Signature {
+                    signature: signature.to_lower_hex_string(),
+                    recovery_id: recovery_id.to_i32(),
+                })
+            }
+            SignerRequest::Ecdh { user_id, public_key } => {
+                let secret = self.virtual_node_secret(&user_id)?;
+                let public_key = PublicKey::from_str(&public_key)
+                    .map_err(|e| format!("Invalid public key: {e}"))?;
+                Ok(SignerResponse::SharedSecret {
+                    secret: SharedSecret::new(&public_key, &secret)
+                        .secret_bytes()
+                        .to_lower_hex_string(),
+                })
+            }
+        }
+    }
+}
+
+fn load_or_create_seed(path: &Path) -> std::io::Result<[u8; 32]> {
+    if path.exists() {
+        let content = std::fs::read_to_string(path)?;
+        return <[u8; 32]>::from_hex(content.trim())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+    }
+    let mut seed = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut seed);
+    std::fs::write(path, seed.to_lower_hex_string())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(seed)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time after UNIX epoch")
+        .as_secs()
+}
+
+async fn sign(State(state): State<Arc<SignerState>>, headers: HeaderMap, body: Bytes) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let (Some(timestamp), Some(nonce), Some(mac)) = (
+        header(SIGNER_TIMESTAMP_HEADER).and_then(|t| t.parse::<u64>().ok()),
+        header(SIGNER_NONCE_HEADER).and_then(|n| parse_nonce(n).ok()),
+        header(SIGNER_MAC_HEADER),
+    ) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let now = now();
+    if let Err(e) = verify_request(&state.secret, timestamp, &nonce, &body, mac, now) {
+        tracing::warn!("Rejecting request: {e}");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if !state.register_nonce(nonce, timestamp, now) {
+        tracing::warn!("Rejecting request: repeated nonce");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let response = match serde_json::from_slice::<SignerRequest>(&body) {
+        Ok(request) => state
+            .handle(request)
+            .unwrap_or_else(|message| SignerResponse::Error { message }),
+        Err(e) => SignerResponse::Error {
+            message: format!("Invalid request: {e}"),
+        },
+    };
+    let response_body = serde_json::to_vec(&response).expect("valid response");
+    let mac = compute_response_mac(&state.secret, timestamp, &nonce, &body, &response_body);
+
+    let mut response = (StatusCode::OK, response_body).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        axum::http::header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(SIGNER_MAC_HEADER, HeaderValue::from_str(&mac).expect("hex MAC"));
+    response
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    let secret = std::env::var(SIGNER_SECRET_ENV)
+        .map_err(|_| anyhow::anyhow!("{SIGNER_SECRET_ENV} must be set"))?;
+    let state = Arc::new(SignerState {
+        seed: load_or_create_seed(&args.seed_path)?,
+        secret: secret.into_bytes(),
+        secp_ctx: Secp256k1::new(),
+        seen_nonces: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route(SIGNER_PATH, post(sign))
+        .with_state(state);
+
+    tracing::info!("Remote signer listening on {}", args.listen);
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -1,8 +1,29 @@
-use sqlx::PgPool;
-use std::collections::HashMap;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{BTreeMap, HashMap};
 use uuid::Uuid;
 use anyhow::Result;
 
+use crate::quota::{
+    check_quota, QuotaAction, QuotaExceeded, QuotaSpend, QuotaUsage, QuotaUsageRecord, UserQuotas,
+    QUOTA_MONTH_SECS,
+};
+use crate::user_archive::{
+    ArchivedBalance, ArchivedChannel, ArchivedInvoice, ArchivedTransaction, ArchivedVirtualChannel,
+    ArchivedWallet, UserRows,
+};
+
+/// Ledger account representing funds entering or leaving the hub
+pub const LEDGER_EXTERNAL_ACCOUNT: &str = "@external";
+
+/// Ledger account holding the balances of users exported to another hub
+pub const LEDGER_EXPORTED_ACCOUNT: &str = "@exported";
+
+/// Ledger asset holding the sub-satoshi part of BTC balances, in msat
+///
+/// BTC is tracked in sats, amounts received in msat carry their remainder here so it's credited
+/// once it adds up to a full sat.
+pub const LEDGER_MSAT_ASSET: &str = "msat";
+
 #[derive(Debug, Clone)]
 pub struct Database {
     pool: PgPool,
@@ -27,6 +48,166 @@ pub struct UserTransaction {
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Debug, Clone)]
+pub struct VirtualPaymentRecord {
+    pub payment_hash: String,
+    pub from_virtual_node_id: String,
+    pub to_virtual_node_id: String,
+    pub btc_amount_msat: i64,
+    pub rgb_contract_id: Option<String>,
+    pub rgb_amount: Option<i64>,
+    pub status: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserInvoice {
+    pub payment_hash: String,
+    pub user_id: String,
+    pub invoice: String,
+    pub amount_msat: Option<i64>,
+    pub status: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A single ledger entry, positive amounts credit the account and negative amounts debit it
+#[derive(Debug, Clone)]
+pub struct LedgerPosting {
+    pub user_id: String,
+    pub asset_id: Option<String>,
+    pub amount: i64,
+}
+
+impl LedgerPosting {
+    pub fn new(user_id: &str, asset_id: Option<&str>, amount: i64) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            asset_id: asset_id.map(|a| a.to_string()),
+            amount,
+        }
+    }
+
+    /// Postings moving `amount` from one account to another
+    pub fn transfer(from_user_id: &str, to_user_id: &str, asset_id: Option<&str>, amount: i64) -> Vec<Self> {
+        vec![
+            Self::new(from_user_id, asset_id, -amount),
+            Self::new(to_user_id, asset_id, amount),
+        ]
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LedgerError {
+    #[error("Journal is empty or contains zero amounts")]
+    InvalidPosting,
+    #[error("Journal is unbalanced for asset {0}")]
+    Unbalanced(String),
+    #[error("Insufficient balance for user {user_id} ({asset}): {available} available, {required} required")]
+    Overdraft {
+        user_id: String,
+        asset: String,
+        available: i64,
+        required: i64,
+    },
+    #[error("Journal {0} was already posted")]
+    DuplicateReference(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Check that every asset in a journal nets to zero
+pub(crate) fn validate_journal(postings: &[LedgerPosting]) -> Result<(), LedgerError> {
+    if postings.is_empty() || postings.iter().any(|p| p.amount == 0) {
+        return Err(LedgerError::InvalidPosting);
+    }
+    let mut totals: BTreeMap<Option<&str>, i128> = BTreeMap::new();
+    for posting in postings {
+        *totals.entry(posting.asset_id.as_deref()).or_default() += posting.amount as i128;
+    }
+    if let Some((asset_id, _)) = totals.into_iter().find(|(_, total)| *total != 0) {
+        return Err(LedgerError::Unbalanced(asset_id.unwrap_or("BTC").to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UserImportError {
+    #[error("User {0} already exists")]
+    UserExists(String),
+    #[error(transparent)]
+    Ledger(#[from] LedgerError),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QuotaError {
+    #[error("Quota exceeded: {0}")]
+    Exceeded(#[from] QuotaExceeded),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Quota limits are stored as BIGINT, with unlimited values saturating at `i64::MAX`
+fn quota_to_db(value: u64) -> i64 {
+    value.min(i64::MAX as u64) as i64
+}
+
+fn quota_from_db(value: i64) -> u64 {
+    if value == i64::MAX {
+        u64::MAX
+    } else {
+        value.max(0) as u64
+    }
+}
+
+fn quota_u32_from_db(value: i64) -> u32 {
+    value.clamp(0, u32::MAX as i64) as u32
+}
+
+/// System accounts (prefixed with '@') are allowed to go negative
+fn is_system_account(user_id: &str) -> bool {
+    user_id.starts_with('@')
+}
+
+/// Postings moving `amount_msat` of BTC between two accounts, given their msat remainders
+///
+/// The whole sats move as BTC and the rest as [`LEDGER_MSAT_ASSET`]. A user remainder that
+/// would go below zero borrows a sat, and one reaching 1000 msat is turned into a sat, both
+/// against the external account, so user remainders always stay within 0..1000 msat.
+pub(crate) fn msat_transfer_postings(
+    from_user_id: &str,
+    to_user_id: &str,
+    amount_msat: u64,
+    from_remainder_msat: i64,
+    to_remainder_msat: i64,
+) -> Vec<LedgerPosting> {
+    let sats = (amount_msat / 1000) as i64;
+    let msat = (amount_msat % 1000) as i64;
+    let mut postings = vec![];
+    if sats > 0 {
+        postings.extend(LedgerPosting::transfer(from_user_id, to_user_id, None, sats));
+    }
+    if msat > 0 {
+        postings.extend(LedgerPosting::transfer(from_user_id, to_user_id, Some(LEDGER_MSAT_ASSET), msat));
+        if !is_system_account(from_user_id) && from_remainder_msat < msat {
+            postings.extend(LedgerPosting::transfer(from_user_id, LEDGER_EXTERNAL_ACCOUNT, None, 1));
+            postings.extend(LedgerPosting::transfer(LEDGER_EXTERNAL_ACCOUNT, from_user_id, Some(LEDGER_MSAT_ASSET), 1000));
+        }
+        if !is_system_account(to_user_id) && to_remainder_msat + msat >= 1000 {
+            postings.extend(LedgerPosting::transfer(to_user_id, LEDGER_EXTERNAL_ACCOUNT, Some(LEDGER_MSAT_ASSET), 1000));
+            postings.extend(LedgerPosting::transfer(LEDGER_EXTERNAL_ACCOUNT, to_user_id, None, 1));
+        }
+    }
+    postings
+}
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    pub status: String,
+    pub response: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct UserChannel {
     pub id: Uuid,
@@ -127,6 +308,98 @@ impl Database {
         Ok(id)
     }
 
+    /// Store a pending channel opened for a user and funded by the ledger journal `funding_reference`
+    pub async fn save_funded_user_channel(
+        &self,
+        user_id: &str,
+        channel_id: &str,
+        peer_pubkey: &str,
+        capacity_sats: i64,
+        funding_reference: &str,
+    ) -> Result<Uuid> {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO ln_user_channels (id, user_id, channel_id, peer_pubkey, capacity_sats, status, funding_reference)
+             VALUES ($1, $2, $3, $4, $5, 'pending', $6)",
+            id, user_id, channel_id, peer_pubkey, capacity_sats, funding_reference
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    /// Follow a user channel from its temporary ID to the one it got when funded
+    pub async fn update_user_channel_id(&self, temporary_channel_id: &str, channel_id: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE ln_user_channels SET channel_id = $2 WHERE channel_id = $1",
+            temporary_channel_id, channel_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get the owner and funding journal reference of a channel funded from a user balance and
+    /// not closed yet
+    pub async fn get_funded_user_channel(&self, channel_id: &str) -> Result<Option<(String, String)>> {
+        let row = sqlx::query!(
+            r#"SELECT user_id, funding_reference AS "funding_reference!" FROM ln_user_channels
+               WHERE channel_id = $1 AND status <> 'closed' AND funding_reference IS NOT NULL"#,
+            channel_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| (r.user_id, r.funding_reference)))
+    }
+
+    pub async fn close_user_channel(&self, channel_id: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE ln_user_channels SET status = 'closed' WHERE channel_id = $1",
+            channel_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Record a pending attempt to pay an invoice from a user balance, funded by the ledger
+    /// journal `reference`
+    ///
+    /// Returns false if another attempt to pay the same invoice is still pending.
+    pub async fn begin_user_payment(&self, reference: &str, payment_hash: &str, user_id: &str, max_fee_sats: i64) -> Result<bool> {
+        let result = sqlx::query!(
+            "INSERT INTO ln_user_payment_attempts (reference, payment_hash, user_id, max_fee_sats)
+             VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+            reference, payment_hash, user_id, max_fee_sats
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Get the user, ledger reference and reserved routing fee of the pending attempt to pay an
+    /// invoice from a user balance
+    pub async fn get_pending_user_payment(&self, payment_hash: &str) -> Result<Option<(String, String, i64)>> {
+        let row = sqlx::query!(
+            "SELECT user_id, reference, max_fee_sats FROM ln_user_payment_attempts
+             WHERE payment_hash = $1 AND status = 'pending'",
+            payment_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| (r.user_id, r.reference, r.max_fee_sats)))
+    }
+
+    pub async fn finish_user_payment(&self, reference: &str, status: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE ln_user_payment_attempts SET status = $2 WHERE reference = $1 AND status = 'pending'",
+            reference, status
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
     pub async fn get_user_channels(&self, user_id: &str) -> Result<Vec<UserChannel>> {
         let rows = sqlx::query!(
             "SELECT id, user_id, channel_id, peer_pubkey, capacity_sats, status FROM ln_user_channels WHERE user_id = $1",
@@ -145,32 +418,21 @@ impl Database {
         }).collect())
     }
 
-    // User balance tracking
-    pub async fn update_user_balance(&self, user_id: &str, asset_id: Option<&str>, balance: i64) -> Result<()> {
-        sqlx::query!(
-            "INSERT INTO ln_user_balances (user_id, asset_id, balance, updated_at) VALUES ($1, $2, $3, NOW()) 
-             ON CONFLICT (user_id, COALESCE(asset_id, '')) DO UPDATE SET balance = $3, updated_at = NOW()",
-            user_id, asset_id, balance
-        )
-        .execute(&self.pool)
-        .await?;
-        Ok(())
-    }
-
+    // User balances, derived from the ledger journal
     pub async fn get_user_balance(&self, user_id: &str, asset_id: Option<&str>) -> Result<i64> {
         let row = sqlx::query!(
-            "SELECT balance FROM ln_user_balances WHERE user_id = $1 AND asset_id IS NOT DISTINCT FROM $2",
+            "SELECT COALESCE(SUM(amount), 0)::BIGINT AS balance FROM ln_ledger_entries WHERE user_id = $1 AND asset_id IS NOT DISTINCT FROM $2",
             user_id, asset_id
         )
-        .fetch_optional(&self.pool)
+        .fetch_one(&self.pool)
         .await?;
 
-        Ok(row.map(|r| r.balance).unwrap_or(0))
+        Ok(row.balance.unwrap_or(0))
     }
 
     pub async fn get_user_balances(&self, user_id: &str) -> Result<HashMap<String, i64>> {
         let rows = sqlx::query!(
-            "SELECT asset_id, balance FROM ln_user_balances WHERE user_id = $1",
+            "SELECT asset_id, SUM(amount)::BIGINT AS balance FROM ln_ledger_entries WHERE user_id = $1 GROUP BY asset_id",
             user_id
         )
         .fetch_all(&self.pool)
@@ -178,12 +440,217 @@ impl Database {
 
         let mut balances = HashMap::new();
         for row in rows {
+            if row.asset_id.as_deref() == Some(LEDGER_MSAT_ASSET) {
+                continue;
+            }
             let key = row.asset_id.unwrap_or_else(|| "BTC".to_string());
-            balances.insert(key, row.balance);
+            balances.insert(key, row.balance.unwrap_or(0));
         }
         Ok(balances)
     }
 
+    /// Post a balanced journal, rejecting it if any user account would go below zero
+    pub async fn post_journal(&self, reference: &str, postings: &[LedgerPosting]) -> Result<Uuid, LedgerError> {
+        let mut tx = self.pool.begin().await?;
+        let journal_id = Self::post_journal_in_tx(&mut tx, reference, postings).await?;
+        tx.commit().await?;
+        Ok(journal_id)
+    }
+
+    /// Post a journal, rejecting it if a journal with the same reference was already posted
+    pub async fn post_journal_once(&self, reference: &str, postings: &[LedgerPosting]) -> Result<Uuid, LedgerError> {
+        let mut tx = self.pool.begin().await?;
+        Self::claim_reference_in_tx(&mut tx, reference).await?;
+        let journal_id = Self::post_journal_in_tx(&mut tx, reference, postings).await?;
+        tx.commit().await?;
+        Ok(journal_id)
+    }
+
+    /// Fail with [`LedgerError::DuplicateReference`] if a journal was already posted with the
+    /// reference, holding a lock on it until the transaction ends
+    async fn claim_reference_in_tx(tx: &mut Transaction<'_, Postgres>, reference: &str) -> Result<(), LedgerError> {
+        sqlx::query!(
+            "SELECT pg_advisory_xact_lock(hashtext('ledger_reference'), hashtext($1))",
+            reference
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        let posted = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM ln_ledger_entries WHERE reference = $1)",
+            reference
+        )
+        .fetch_one(&mut **tx)
+        .await?
+        .unwrap_or(false);
+        if posted {
+            return Err(LedgerError::DuplicateReference(reference.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Post a journal inside an existing transaction
+    pub(crate) async fn post_journal_in_tx(
+        tx: &mut Transaction<'_, Postgres>,
+        reference: &str,
+        postings: &[LedgerPosting],
+    ) -> Result<Uuid, LedgerError> {
+        validate_journal(postings)?;
+
+        // Net change per account, ordered so concurrent journals lock accounts in the same order
+        let mut deltas: BTreeMap<(&str, Option<&str>), i64> = BTreeMap::new();
+        for posting in postings {
+            *deltas.entry((posting.user_id.as_str(), posting.asset_id.as_deref())).or_default() += posting.amount;
+        }
+
+        for ((user_id, asset_id), delta) in deltas {
+            if delta >= 0 || is_system_account(user_id) {
+                continue;
+            }
+
+            // Serialize debits on the same account until the transaction ends
+            sqlx::query!(
+                "SELECT pg_advisory_xact_lock(hashtext($1), hashtext(COALESCE($2, 'BTC')))",
+                user_id, asset_id
+            )
+            .execute(&mut **tx)
+            .await?;
+
+            let available = sqlx::query!(
+                "SELECT COALESCE(SUM(amount), 0)::BIGINT AS balance FROM ln_ledger_entries WHERE user_id = $1 AND asset_id IS NOT DISTINCT FROM $2",
+                user_id, asset_id
+            )
+            .fetch_one(&mut **tx)
+            .await?
+            .balance
+            .unwrap_or(0);
+
+            if available + delta < 0 {
+                return Err(LedgerError::Overdraft {
+                    user_id: user_id.to_string(),
+                    asset: asset_id.unwrap_or("BTC").to_string(),
+                    available,
+                    required: -delta,
+                });
+            }
+        }
+
+        let journal_id = Uuid::new_v4();
+        for posting in postings {
+            sqlx::query!(
+                "INSERT INTO ln_ledger_entries (journal_id, user_id, asset_id, amount, reference) VALUES ($1, $2, $3, $4, $5)",
+                journal_id, posting.user_id, posting.asset_id, posting.amount, reference
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+
+        Ok(journal_id)
+    }
+
+    /// BTC balance of a user in msat, including the sub-sat remainder
+    pub async fn get_user_balance_msat(&self, user_id: &str) -> Result<i64> {
+        let sats = self.get_user_balance(user_id, None).await?;
+        let remainder = self.get_user_balance(user_id, Some(LEDGER_MSAT_ASSET)).await?;
+        Ok(sats * 1000 + remainder)
+    }
+
+    /// Post a journal moving `amount_msat` of BTC along with other postings, such as RGB amounts
+    pub async fn post_msat_journal(
+        &self,
+        reference: &str,
+        from_user_id: &str,
+        to_user_id: &str,
+        amount_msat: u64,
+        postings: &[LedgerPosting],
+    ) -> Result<Uuid, LedgerError> {
+        let mut tx = self.pool.begin().await?;
+        let mut postings = postings.to_vec();
+        postings.extend(Self::msat_transfer_postings_in_tx(&mut tx, from_user_id, to_user_id, amount_msat).await?);
+        let journal_id = Self::post_journal_in_tx(&mut tx, reference, &postings).await?;
+        tx.commit().await?;
+        Ok(journal_id)
+    }
+
+    /// Postings moving `amount_msat` of BTC, locking the remainders they're based on
+    async fn msat_transfer_postings_in_tx(
+        tx: &mut Transaction<'_, Postgres>,
+        from_user_id: &str,
+        to_user_id: &str,
+        amount_msat: u64,
+    ) -> Result<Vec<LedgerPosting>, LedgerError> {
+        let mut remainders = [0; 2];
+        // sorted, so concurrent journals lock the remainders in the same order
+        let mut accounts = [(0, from_user_id), (1, to_user_id)];
+        accounts.sort_by_key(|(_, user_id)| *user_id);
+        for (i, user_id) in accounts {
+            if is_system_account(user_id) {
+                continue;
+            }
+            // the locks post_journal_in_tx takes on debits, in the same order, held until the
+            // transaction ends
+            for asset in ["BTC", LEDGER_MSAT_ASSET] {
+                sqlx::query!(
+                    "SELECT pg_advisory_xact_lock(hashtext($1), hashtext($2))",
+                    user_id, asset
+                )
+                .execute(&mut **tx)
+                .await?;
+            }
+            remainders[i] = sqlx::query!(
+                "SELECT COALESCE(SUM(amount), 0)::BIGINT AS balance FROM ln_ledger_entries WHERE user_id = $1 AND asset_id = $2",
+                user_id, LEDGER_MSAT_ASSET
+            )
+            .fetch_one(&mut **tx)
+            .await?
+            .balance
+            .unwrap_or(0);
+        }
+        Ok(msat_transfer_postings(from_user_id, to_user_id, amount_msat, remainders[0], remainders[1]))
+    }
+
+    /// Post the reverse of the journals with `reference`, once, under `reversal_reference`
+    ///
+    /// Returns whether a reversal was posted, false if there's nothing to reverse or it was
+    /// already reversed.
+    pub async fn reverse_journal(&self, reference: &str, reversal_reference: &str) -> Result<bool, LedgerError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "SELECT pg_advisory_xact_lock(hashtext('ledger_reversal'), hashtext($1))",
+            reference
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let reversed = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM ln_ledger_entries WHERE reference = $1)",
+            reversal_reference
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap_or(false);
+        if reversed {
+            return Ok(false);
+        }
+
+        let postings: Vec<LedgerPosting> = sqlx::query!(
+            "SELECT user_id, asset_id, amount FROM ln_ledger_entries WHERE reference = $1 ORDER BY id",
+            reference
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| LedgerPosting::new(&row.user_id, row.asset_id.as_deref(), -row.amount))
+        .collect();
+        if postings.is_empty() {
+            return Ok(false);
+        }
+
+        Self::post_journal_in_tx(&mut tx, reversal_reference, &postings).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
     // User address tracking
     pub async fn save_user_address(&self, user_id: &str, address: &str) -> Result<()> {
         sqlx::query!(
@@ -228,6 +695,17 @@ impl Database {
         Ok(row.and_then(|r| r.virtual_node_id))
     }
 
+    /// Get the user owning a virtual node ID
+    pub async fn get_user_id_by_virtual_node_id(&self, virtual_node_id: &str) -> Result<Option<String>> {
+        let row = sqlx::query!(
+            "SELECT user_id FROM ln_user_wallets WHERE virtual_node_id = $1",
+            virtual_node_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| r.user_id))
+    }
+
     /// Execute atomic internal transfer between users
     pub async fn execute_internal_transfer(
         &self,
@@ -239,21 +717,14 @@ impl Database {
     ) -> Result<()> {
         let mut tx = self.pool.begin().await?;
         
-        // Deduct from sender
-        sqlx::query!(
-            "UPDATE ln_user_balances SET balance = balance - $3 WHERE user_id = $1 AND asset_id IS NOT DISTINCT FROM $2",
-            from_user_id, asset_id, amount
+        // Move funds in the ledger once per payment ID, failing on overdraft before anything is
+        // recorded
+        Self::claim_reference_in_tx(&mut tx, payment_id).await?;
+        Self::post_journal_in_tx(
+            &mut tx,
+            payment_id,
+            &LedgerPosting::transfer(from_user_id, to_user_id, asset_id, amount),
         )
-        .execute(&mut *tx)
-        .await?;
-        
-        // Credit to receiver
-        sqlx::query!(
-            "INSERT INTO ln_user_balances (user_id, asset_id, balance, updated_at) VALUES ($1, $2, $3, NOW())
-             ON CONFLICT (user_id, COALESCE(asset_id, '')) DO UPDATE SET balance = ln_user_balances.balance + $3, updated_at = NOW()",
-            to_user_id, asset_id, amount
-        )
-        .execute(&mut *tx)
         .await?;
         
         // Record transaction for sender
@@ -275,4 +746,595 @@ impl Database {
         tx.commit().await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Persist a new pending virtual HTLC, returns false if the payment hash is already known
+    pub async fn insert_virtual_payment(
+        &self,
+        payment_hash: &str,
+        from_virtual_node_id: &str,
+        to_virtual_node_id: &str,
+        to_user_id: &str,
+        btc_amount_msat: i64,
+        rgb_contract_id: Option<&str>,
+        rgb_amount: Option<i64>,
+    ) -> Result<bool> {
+        let result = sqlx::query!(
+            "INSERT INTO virtual_payments (payment_hash, virtual_node_id, user_id, inbound, from_virtual_node_id, to_virtual_node_id, btc_amount_msat, rgb_contract_id, rgb_amount, status)
+             VALUES ($1, $3, $4, true, $2, $3, $5, $6, $7, 'pending') ON CONFLICT (payment_hash) DO NOTHING",
+            payment_hash, from_virtual_node_id, to_virtual_node_id, to_user_id, btc_amount_msat, rgb_contract_id, rgb_amount
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Get all virtual HTLCs still in pending status
+    pub async fn get_pending_virtual_payments(&self) -> Result<Vec<VirtualPaymentRecord>> {
+        let rows = sqlx::query!(
+            "SELECT payment_hash, from_virtual_node_id, to_virtual_node_id, btc_amount_msat, rgb_contract_id, rgb_amount, status
+             FROM virtual_payments WHERE status = 'pending' AND from_virtual_node_id IS NOT NULL AND to_virtual_node_id IS NOT NULL"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| VirtualPaymentRecord {
+            payment_hash: r.payment_hash,
+            from_virtual_node_id: r.from_virtual_node_id.unwrap_or_default(),
+            to_virtual_node_id: r.to_virtual_node_id.unwrap_or_default(),
+            btc_amount_msat: r.btc_amount_msat,
+            rgb_contract_id: r.rgb_contract_id,
+            rgb_amount: r.rgb_amount,
+            status: r.status,
+        }).collect())
+    }
+
+    /// Move a virtual HTLC from one status to another, returns false if it wasn't in the expected status
+    pub async fn transition_virtual_payment(
+        &self,
+        payment_hash: &str,
+        from_status: &str,
+        to_status: &str,
+        preimage: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE virtual_payments SET status = $3, preimage = COALESCE($4, preimage), updated_at = NOW()
+             WHERE payment_hash = $1 AND status = $2",
+            payment_hash, from_status, to_status, preimage
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Claim an idempotency key, returns the existing record if the key was already used
+    ///
+    /// A claim of the same request still in progress after `stale_after_secs` is taken over, as
+    /// the request holding it was interrupted.
+    pub async fn claim_idempotency_key(
+        &self,
+        endpoint: &str,
+        idempotency_key: &str,
+        request_hash: &str,
+        stale_after_secs: i64,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let result = sqlx::query!(
+            "INSERT INTO ln_idempotency_keys (endpoint, idempotency_key, request_hash) VALUES ($1, $2, $3)
+             ON CONFLICT (endpoint, idempotency_key) DO UPDATE SET claimed_at = NOW(), updated_at = NOW()
+             WHERE ln_idempotency_keys.status = 'in_progress'
+               AND ln_idempotency_keys.request_hash = EXCLUDED.request_hash
+               AND ln_idempotency_keys.claimed_at < NOW() - make_interval(secs => $4)",
+            endpoint, idempotency_key, request_hash, stale_after_secs as f64
+        )
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 1 {
+            return Ok(None);
+        }
+
+        let row = sqlx::query!(
+            "SELECT request_hash, status, response FROM ln_idempotency_keys WHERE endpoint = $1 AND idempotency_key = $2",
+            endpoint, idempotency_key
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Some(IdempotencyRecord {
+            request_hash: row.request_hash,
+            status: row.status,
+            response: row.response,
+        }))
+    }
+
+    /// Store the response for a claimed idempotency key
+    pub async fn complete_idempotency_key(
+        &self,
+        endpoint: &str,
+        idempotency_key: &str,
+        response: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE ln_idempotency_keys SET status = 'completed', response = $3, updated_at = NOW()
+             WHERE endpoint = $1 AND idempotency_key = $2",
+            endpoint, idempotency_key, response
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Release a claimed idempotency key whose request failed before producing a response
+    pub async fn release_idempotency_key(&self, endpoint: &str, idempotency_key: &str) -> Result<()> {
+        sqlx::query!(
+            "DELETE FROM ln_idempotency_keys WHERE endpoint = $1 AND idempotency_key = $2 AND status = 'in_progress'",
+            endpoint, idempotency_key
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Take a token from a rate limit bucket, refilled at `refill_per_sec` up to `capacity`
+    ///
+    /// Returns the seconds to wait for a token if the bucket is empty.
+    pub async fn take_rate_limit_token(
+        &self,
+        bucket_key: &str,
+        capacity: f64,
+        refill_per_sec: f64,
+    ) -> Result<Option<f64>> {
+        let result = sqlx::query!(
+            "INSERT INTO ln_rate_limit_buckets AS b (bucket_key, tokens, updated_at) VALUES ($1, $2::float8 - 1, NOW())
+             ON CONFLICT (bucket_key) DO UPDATE
+             SET tokens = LEAST($2::float8, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_at)::float8 * $3::float8) - 1,
+                 updated_at = NOW()
+             WHERE LEAST($2::float8, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_at)::float8 * $3::float8) >= 1",
+            bucket_key, capacity, refill_per_sec
+        )
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 1 {
+            return Ok(None);
+        }
+
+        let row = sqlx::query!(
+            r#"SELECT (1 - LEAST($2::float8, tokens + EXTRACT(EPOCH FROM NOW() - updated_at)::float8 * $3::float8)) / $3::float8 AS "wait_secs!"
+             FROM ln_rate_limit_buckets WHERE bucket_key = $1"#,
+            bucket_key, capacity, refill_per_sec
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Some(row.wait_secs))
+    }
+
+    async fn user_quotas_in_tx(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: &str,
+    ) -> Result<Option<UserQuotas>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT max_channels, max_assets, max_transactions_per_day, max_transactions_per_month, max_balance_btc,
+                    max_sats_per_day, max_sats_per_month, max_asset_units_per_day, max_asset_units_per_month
+             FROM ln_user_quotas WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        Ok(row.map(|r| UserQuotas {
+            max_channels: quota_u32_from_db(r.max_channels),
+            max_assets: quota_u32_from_db(r.max_assets),
+            max_transactions_per_day: quota_u32_from_db(r.max_transactions_per_day),
+            max_transactions_per_month: quota_u32_from_db(r.max_transactions_per_month),
+            max_balance_btc: quota_from_db(r.max_balance_btc),
+            max_sats_per_day: quota_from_db(r.max_sats_per_day),
+            max_sats_per_month: quota_from_db(r.max_sats_per_month),
+            max_asset_units_per_day: quota_from_db(r.max_asset_units_per_day),
+            max_asset_units_per_month: quota_from_db(r.max_asset_units_per_month),
+        }))
+    }
+
+    async fn quota_usage_in_tx(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: &str,
+    ) -> Result<QuotaUsage, sqlx::Error> {
+        let channels = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM ln_user_channels WHERE user_id = $1 AND status <> 'closed'"#,
+            user_id
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        let assets = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM ln_user_quota_usage WHERE user_id = $1 AND action = 'create_asset'"#,
+            user_id
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        let rows = sqlx::query!(
+            "SELECT action, sats, asset_id, asset_amount, created_at FROM ln_user_quota_usage
+             WHERE user_id = $1 AND created_at > NOW() - make_interval(secs => $2)",
+            user_id, QUOTA_MONTH_SECS as f64
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+
+        let records: Vec<QuotaUsageRecord> = rows
+            .into_iter()
+            .filter_map(|r| {
+                Some(QuotaUsageRecord {
+                    action: QuotaAction::from_label(&r.action)?,
+                    sats: quota_from_db(r.sats),
+                    asset_id: r.asset_id,
+                    asset_amount: quota_from_db(r.asset_amount),
+                    created_at: r.created_at,
+                })
+            })
+            .collect();
+        Ok(QuotaUsage::new(
+            channels.max(0) as u64,
+            assets.max(0) as u64,
+            &records,
+            chrono::Utc::now(),
+        ))
+    }
+
+    /// Get the quotas of a user, `None` if they have the default ones
+    pub async fn get_user_quotas(&self, user_id: &str) -> Result<Option<UserQuotas>> {
+        let mut tx = self.pool.begin().await?;
+        let quotas = Self::user_quotas_in_tx(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(quotas)
+    }
+
+    /// Set the quotas of a user
+    pub async fn set_user_quotas(&self, user_id: &str, quotas: &UserQuotas) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO ln_user_quotas (user_id, max_channels, max_assets, max_transactions_per_day, max_transactions_per_month,
+                 max_balance_btc, max_sats_per_day, max_sats_per_month, max_asset_units_per_day, max_asset_units_per_month)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             ON CONFLICT (user_id) DO UPDATE SET
+                 max_channels = EXCLUDED.max_channels, max_assets = EXCLUDED.max_assets,
+                 max_transactions_per_day = EXCLUDED.max_transactions_per_day,
+                 max_transactions_per_month = EXCLUDED.max_transactions_per_month,
+                 max_balance_btc = EXCLUDED.max_balance_btc,
+                 max_sats_per_day = EXCLUDED.max_sats_per_day, max_sats_per_month = EXCLUDED.max_sats_per_month,
+                 max_asset_units_per_day = EXCLUDED.max_asset_units_per_day,
+                 max_asset_units_per_month = EXCLUDED.max_asset_units_per_month,
+                 updated_at = NOW()",
+            user_id, quotas.max_channels as i64, quotas.max_assets as i64,
+            quotas.max_transactions_per_day as i64, quotas.max_transactions_per_month as i64,
+            quota_to_db(quotas.max_balance_btc), quota_to_db(quotas.max_sats_per_day),
+            quota_to_db(quotas.max_sats_per_month), quota_to_db(quotas.max_asset_units_per_day),
+            quota_to_db(quotas.max_asset_units_per_month)
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get the current usage of the quotas of a user
+    pub async fn get_quota_usage(&self, user_id: &str) -> Result<QuotaUsage> {
+        let mut tx = self.pool.begin().await?;
+        let usage = Self::quota_usage_in_tx(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(usage)
+    }
+
+    /// Record the usage of a request if it fits in the quotas of the user, returning its ID
+    pub async fn reserve_quota_usage(
+        &self,
+        user_id: &str,
+        spend: &QuotaSpend,
+    ) -> Result<Uuid, QuotaError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Serialize reservations of the same user until the transaction ends
+        sqlx::query!("SELECT pg_advisory_xact_lock(hashtext('user_quota'), hashtext($1))", user_id)
+            .execute(&mut *tx)
+            .await?;
+        let quotas = Self::user_quotas_in_tx(&mut tx, user_id)
+            .await?
+            .unwrap_or_default();
+        let usage = Self::quota_usage_in_tx(&mut tx, user_id).await?;
+        check_quota(&quotas, &usage, spend)?;
+
+        let id = Uuid::new_v4();
+        let (asset_id, asset_amount) = match &spend.asset {
+            Some((asset_id, units)) => (Some(asset_id.as_str()), *units),
+            None => (None, 0),
+        };
+        sqlx::query!(
+            "INSERT INTO ln_user_quota_usage (id, user_id, action, sats, asset_id, asset_amount) VALUES ($1, $2, $3, $4, $5, $6)",
+            id, user_id, spend.action.label(), quota_to_db(spend.sats), asset_id, quota_to_db(asset_amount)
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    /// Release the usage recorded for a request that failed
+    pub async fn release_quota_usage(&self, usage_id: Uuid) -> Result<()> {
+        sqlx::query!("DELETE FROM ln_user_quota_usage WHERE id = $1", usage_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Store the owning user of an invoice issued on their behalf
+    pub async fn insert_user_invoice(&self, invoice: &UserInvoice) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO ln_user_invoices (payment_hash, user_id, invoice, amount_msat, status, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            invoice.payment_hash, invoice.user_id, invoice.invoice, invoice.amount_msat, invoice.status,
+            invoice.expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get a user invoice by its payment hash
+    pub async fn get_user_invoice(&self, payment_hash: &str) -> Result<Option<UserInvoice>> {
+        let row = sqlx::query!(
+            "SELECT payment_hash, user_id, invoice, amount_msat, status, expires_at
+             FROM ln_user_invoices WHERE payment_hash = $1",
+            payment_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| UserInvoice {
+            payment_hash: r.payment_hash,
+            user_id: r.user_id,
+            invoice: r.invoice,
+            amount_msat: r.amount_msat,
+            status: r.status,
+            expires_at: r.expires_at,
+        }))
+    }
+
+    /// Mark a pending user invoice as paid and credit its owner, all in one transaction.
+    /// Returns the credited user, or None if the invoice is unknown or was already credited.
+    pub async fn credit_user_invoice(&self, payment_hash: &str, amount_msat: u64) -> Result<Option<String>> {
+        let mut tx = self.pool.begin().await?;
+
+        let row = sqlx::query!(
+            "UPDATE ln_user_invoices SET status = 'paid', paid_at = NOW() WHERE payment_hash = $1 AND status = 'pending' RETURNING user_id",
+            payment_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let amount_sats = (amount_msat / 1000) as i64;
+        let reference = format!("inbound_{}", payment_hash);
+        if amount_msat > 0 {
+            let postings =
+                Self::msat_transfer_postings_in_tx(&mut tx, LEDGER_EXTERNAL_ACCOUNT, &row.user_id, amount_msat).await?;
+            Self::post_journal_in_tx(&mut tx, &reference, &postings).await?;
+        }
+
+        sqlx::query!(
+            "INSERT INTO ln_user_transactions (id, user_id, txid, amount, status) VALUES ($1, $2, $3, $4, 'completed')",
+            Uuid::new_v4(), row.user_id, reference, amount_sats
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(row.user_id))
+    }
+
+    /// Collect the rows of a user, to move them to another hub
+    pub async fn export_user_rows(&self, user_id: &str) -> Result<UserRows> {
+        let wallet = sqlx::query!(
+            "SELECT mnemonic_encrypted, derivation_path, virtual_node_id, created_at FROM ln_user_wallets WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|r| ArchivedWallet {
+            mnemonic_encrypted: r.mnemonic_encrypted,
+            derivation_path: r.derivation_path,
+            virtual_node_id: r.virtual_node_id,
+            created_at: r.created_at,
+        });
+
+        let transactions = sqlx::query!(
+            "SELECT id, txid, amount, asset_id, status, virtual_node_id, created_at FROM ln_user_transactions WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| ArchivedTransaction {
+            id: r.id,
+            txid: r.txid,
+            amount: r.amount,
+            asset_id: r.asset_id,
+            status: r.status,
+            virtual_node_id: r.virtual_node_id,
+            created_at: r.created_at,
+        })
+        .collect();
+
+        let channels = self
+            .get_user_channels(user_id)
+            .await?
+            .into_iter()
+            .map(|c| ArchivedChannel {
+                id: c.id,
+                channel_id: c.channel_id,
+                peer_pubkey: c.peer_pubkey,
+                capacity_sats: c.capacity_sats,
+                status: c.status,
+            })
+            .collect();
+
+        let invoices = sqlx::query!(
+            "SELECT payment_hash, invoice, amount_msat, status, expires_at, paid_at
+             FROM ln_user_invoices WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| ArchivedInvoice {
+            payment_hash: r.payment_hash,
+            invoice: r.invoice,
+            amount_msat: r.amount_msat,
+            status: r.status,
+            expires_at: r.expires_at,
+            paid_at: r.paid_at,
+        })
+        .collect();
+
+        // Not using get_user_balances, as it can't tell BTC from an asset with that ID
+        let balances = sqlx::query!(
+            "SELECT asset_id, SUM(amount)::BIGINT AS balance FROM ln_ledger_entries WHERE user_id = $1 GROUP BY asset_id",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .filter_map(|r| {
+            let amount = r.balance.unwrap_or(0);
+            (amount > 0).then_some(ArchivedBalance { asset_id: r.asset_id, amount })
+        })
+        .collect();
+
+        let virtual_channels = sqlx::query!(
+            "SELECT channel_id, virtual_node_id FROM virtual_channels WHERE user_id = $1",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| ArchivedVirtualChannel {
+            channel_id: r.channel_id,
+            virtual_node_id: r.virtual_node_id,
+        })
+        .collect();
+
+        Ok(UserRows {
+            wallet,
+            transactions,
+            channels,
+            addresses: self.get_user_addresses(user_id).await?,
+            invoices,
+            balances,
+            virtual_channels,
+        })
+    }
+
+    /// Move the exported balances out of the user account, so funds carried by an archive can't
+    /// also be spent on this hub. Fails if the user spent part of them meanwhile.
+    pub async fn debit_exported_balances(
+        &self,
+        user_id: &str,
+        balances: &[ArchivedBalance],
+        reference: &str,
+    ) -> Result<(), LedgerError> {
+        let postings: Vec<LedgerPosting> = balances
+            .iter()
+            .flat_map(|b| LedgerPosting::transfer(user_id, LEDGER_EXPORTED_ACCOUNT, b.asset_id.as_deref(), b.amount))
+            .collect();
+        if !postings.is_empty() {
+            self.post_journal(reference, &postings).await?;
+        }
+        Ok(())
+    }
+
+    /// Import the rows of a user moved from another hub, in one transaction.
+    /// Balances are credited from the external account with the provided journal reference.
+    pub async fn import_user_rows(&self, user_id: &str, rows: &UserRows, reference: &str) -> Result<(), UserImportError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Serialize imports of the same user until the transaction ends
+        sqlx::query!("SELECT pg_advisory_xact_lock(hashtext('user_import'), hashtext($1))", user_id)
+            .execute(&mut *tx)
+            .await?;
+        let exists = sqlx::query!(
+            "SELECT EXISTS(SELECT 1 FROM ln_user_wallets WHERE user_id = $1)
+                 OR EXISTS(SELECT 1 FROM ln_ledger_entries WHERE user_id = $1) AS exists",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .exists
+        .unwrap_or(false);
+        if exists {
+            return Err(UserImportError::UserExists(user_id.to_string()));
+        }
+
+        if let Some(wallet) = &rows.wallet {
+            sqlx::query!(
+                "INSERT INTO ln_user_wallets (user_id, mnemonic_encrypted, derivation_path, virtual_node_id, created_at) VALUES ($1, $2, $3, $4, COALESCE($5, NOW()))",
+                user_id, wallet.mnemonic_encrypted, wallet.derivation_path, wallet.virtual_node_id, wallet.created_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for t in &rows.transactions {
+            sqlx::query!(
+                "INSERT INTO ln_user_transactions (id, user_id, txid, amount, asset_id, status, virtual_node_id, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, COALESCE($8, NOW())) ON CONFLICT DO NOTHING",
+                t.id, user_id, t.txid, t.amount, t.asset_id, t.status, t.virtual_node_id, t.created_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for c in &rows.channels {
+            sqlx::query!(
+                "INSERT INTO ln_user_channels (id, user_id, channel_id, peer_pubkey, capacity_sats, status) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
+                c.id, user_id, c.channel_id, c.peer_pubkey, c.capacity_sats, c.status
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for address in &rows.addresses {
+            sqlx::query!(
+                "INSERT INTO ln_user_addresses (user_id, address, created_at) VALUES ($1, $2, NOW()) ON CONFLICT (user_id, address) DO NOTHING",
+                user_id, address
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for i in &rows.invoices {
+            sqlx::query!(
+                "INSERT INTO ln_user_invoices (payment_hash, user_id, invoice, amount_msat, status, expires_at, paid_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (payment_hash) DO NOTHING",
+                i.payment_hash, user_id, i.invoice, i.amount_msat, i.status, i.expires_at, i.paid_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for c in &rows.virtual_channels {
+            sqlx::query!(
+                "INSERT INTO virtual_channels (channel_id, virtual_node_id, user_id) VALUES ($1, $2, $3) ON CONFLICT (channel_id) DO NOTHING",
+                c.channel_id, c.virtual_node_id, user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let postings: Vec<LedgerPosting> = rows
+            .balances
+            .iter()
+            .filter(|b| b.amount > 0)
+            .flat_map(|b| LedgerPosting::transfer(LEDGER_EXTERNAL_ACCOUNT, user_id, b.asset_id.as_deref(), b.amount))
+            .collect();
+        if !postings.is_empty() {
+            Self::post_journal_in_tx(&mut tx, reference, &postings).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
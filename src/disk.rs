@@ -194,6 +194,7 @@ pub(crate) fn read_swaps_info(path: &Path) -> SwapMap {
     }
     SwapMap {
         swaps: HashMap::new(),
+        offers: HashMap::new(),
     }
 }
 
@@ -26,7 +26,7 @@ use std::{
     sync::{Arc, Mutex, MutexGuard},
     time::{Duration, SystemTime},
 };
-use tokio::sync::{Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
+use tokio::sync::{broadcast, Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
 use tokio_util::sync::CancellationToken;
 
 use crate::ldk::{ChannelIdsMap, Router};
@@ -42,9 +42,13 @@ use crate::{
         LdkBackgroundServices, NetworkGraph, OnionMessenger, OutboundPaymentInfoStorage,
         OutputSweeper, PeerManager, SwapMap,
     },
+    metrics::Metrics,
+    utils::{UserPaymentEvent, USER_PAYMENT_EVENTS_CAPACITY},
+    rate_limit::{rate_limit_config_from_env, RateLimiter},
     user_manager_enhanced::UserManager,
     multi_user_rgb::MultiUserRgbManager,
     auth::AuthService,
+    telegram_login::LoginReplayCache,
 };
 
 // Load environment variables
@@ -71,6 +75,9 @@ pub(crate) struct AppState {
     pub(crate) user_manager: Arc<TokioMutex<Option<UserManager>>>,
     pub(crate) multi_user_rgb: Arc<TokioMutex<Option<MultiUserRgbManager>>>,
     pub(crate) auth_service: Arc<TokioMutex<Option<AuthService>>>,
+    /// Telegram logins already used, to reject replays
+    pub(crate) telegram_logins: Arc<LoginReplayCache>,
+    pub(crate) rate_limiter: Arc<RateLimiter>,
 }
 
 impl AppState {
@@ -112,6 +119,8 @@ pub(crate) struct StaticState {
     pub(crate) ldk_data_dir: PathBuf,
     pub(crate) logger: Arc<FilesystemLogger>,
     pub(crate) max_media_upload_size_mb: u16,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) user_payments: broadcast::Sender<UserPaymentEvent>,
 }
 
 pub(crate) struct UnlockedAppState {
@@ -382,6 +391,8 @@ pub(crate) async fn start_daemon(args: &LdkUserInfo) -> Result<Arc<AppState>, Ap
         ldk_data_dir: ldk_data_dir.clone(),
         logger,
         max_media_upload_size_mb: args.max_media_upload_size_mb,
+        metrics: Arc::new(Metrics::default()),
+        user_payments: broadcast::channel(USER_PAYMENT_EVENTS_CAPACITY).0,
     });
 
     // Load environment variables
@@ -413,6 +424,10 @@ pub(crate) async fn start_daemon(args: &LdkUserInfo) -> Result<Arc<AppState>, Ap
         tracing::info!("No DATABASE_URL found, running in single-user mode");
     }
 
+    let rate_limit_config = rate_limit_config_from_env()
+        .map_err(|e| AppError::InvalidRateLimitConfig(e.to_string()))?;
+    let rate_limiter = Arc::new(RateLimiter::new(rate_limit_config, database.clone()));
+
     Ok(Arc::new(AppState {
         static_state,
         cancel_token,
@@ -423,6 +438,8 @@ pub(crate) async fn start_daemon(args: &LdkUserInfo) -> Result<Arc<AppState>, Ap
         user_manager,
         multi_user_rgb,
         auth_service,
+        telegram_logins: Arc::new(LoginReplayCache::default()),
+        rate_limiter,
     }))
 }
 
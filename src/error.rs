@@ -1,13 +1,15 @@
 use amplify::s;
 use axum::{
     extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use rgb_lib::{BitcoinNetwork, Error as RgbLibError};
 use serde::{Deserialize, Serialize};
 
+use crate::virtual_balance::BalanceError;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct APIErrorResponse {
     pub(crate) error: String,
@@ -30,6 +32,9 @@ pub enum APIError {
     #[error("Node has already been unlocked")]
     AlreadyUnlocked,
 
+    #[error("Backup snapshot not found: {0}")]
+    BackupSnapshotNotFound(String),
+
     #[error("Batch transfer not found")]
     BatchTransferNotFound,
 
@@ -69,6 +74,9 @@ pub enum APIError {
     #[error("Unable to create keys seed file {0}: {1}")]
     FailedKeysCreation(String, String),
 
+    #[error("Failed to set up the monitor mirror: {0}")]
+    FailedMonitorMirror(String),
+
     #[error("Failed to open channel: {0}")]
     FailedOpenChannel(String),
 
@@ -84,6 +92,15 @@ pub enum APIError {
     #[error("Failed to send onion message: {0}")]
     FailedSendingOnionMessage(String),
 
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Idempotency key already used for a different request")]
+    IdempotencyKeyReused,
+
+    #[error("A request with this idempotency key is still in progress")]
+    IdempotentRequestInProgress,
+
     #[error("For an RGB operation both asset_id and asset_amount must be set")]
     IncompleteRGBInfo,
 
@@ -132,6 +149,9 @@ pub enum APIError {
     #[error("Invalid fee rate: {0}")]
     InvalidFeeRate(String),
 
+    #[error("Invalid idempotency key: {0}")]
+    InvalidIdempotencyKey(String),
+
     #[error("Invalid indexer: {0}")]
     InvalidIndexer(String),
 
@@ -180,6 +200,9 @@ pub enum APIError {
     #[error("The provided recipient ID is for a different network than the wallet's one")]
     InvalidRecipientNetwork,
 
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     #[error("Invalid swap: {0}")]
     InvalidSwap(String),
 
@@ -198,6 +221,9 @@ pub enum APIError {
     #[error("Invalid transport endpoints: {0}")]
     InvalidTransportEndpoints(String),
 
+    #[error("Invalid user archive: {0}")]
+    InvalidUserArchive(String),
+
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
 
@@ -231,6 +257,9 @@ pub enum APIError {
     #[error("No uncolored UTXOs are available (hint: call createutxos)")]
     NoAvailableUtxos,
 
+    #[error("No swap offer matches the requested quote")]
+    NoMatchingSwapOffer,
+
     #[error("No route found")]
     NoRoute,
 
@@ -249,18 +278,39 @@ pub enum APIError {
     #[error("Payment not found: {0}")]
     PaymentNotFound(String),
 
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Too many requests, retry in {retry_after_secs} seconds")]
+    RateLimited { retry_after_secs: u64 },
+
     #[error("Recipient ID already used")]
     RecipientIDAlreadyUsed,
 
     #[error("Swap not found: {0}")]
     SwapNotFound(String),
 
+    #[error("Swap price rejected: {0}")]
+    SwapPriceRejected(String),
+
+    #[error("Swap price unavailable: {0}")]
+    SwapPriceUnavailable(String),
+
+    #[error("Swap offer not found: {0}")]
+    SwapOfferNotFound(String),
+
     #[error("Temporary channel ID already used")]
     TemporaryChannelIdAlreadyUsed,
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
     #[error("Unexpected error: {0}")]
     Unexpected(String),
 
+    #[error("Swap not offered by the expected maker: {0}")]
+    UnexpectedSwapMaker(String),
+
     #[error("Unknown RGB contract ID")]
     UnknownContractId,
 
@@ -282,6 +332,9 @@ pub enum APIError {
     #[error("Transport type is not supported")]
     UnsupportedTransportType,
 
+    #[error("User {0} already exists on this node")]
+    UserAlreadyExists(String),
+
     #[error("The provided password is incorrect")]
     WrongPassword,
 }
@@ -374,8 +427,28 @@ impl From<RgbLibError> for APIError {
     }
 }
 
+impl From<BalanceError> for APIError {
+    fn from(error: BalanceError) -> Self {
+        match error {
+            BalanceError::InsufficientBtc {
+                required,
+                available,
+            } => APIError::InsufficientFunds(required - available),
+            BalanceError::InsufficientRgb { .. } => APIError::InsufficientAssets,
+            BalanceError::InvalidInvoice(e) => APIError::InvalidInvoice(e),
+            BalanceError::TransferFailed(e) => APIError::FailedBroadcast(e),
+            BalanceError::PaymentInProgress(_) => APIError::DuplicatePayment(s!("Pending")),
+            e => APIError::Unexpected(e.to_string()),
+        }
+    }
+}
+
 impl IntoResponse for APIError {
     fn into_response(self) -> Response {
+        let retry_after = match self {
+            APIError::RateLimited { retry_after_secs } => Some(retry_after_secs),
+            _ => None,
+        };
         let (status, error, name) = match self {
             APIError::JsonExtractorRejection(ref json_rejection) => (
                 json_rejection.status(),
@@ -386,6 +459,7 @@ impl IntoResponse for APIError {
             | APIError::FailedInvoiceCreation(_)
             | APIError::FailedIssuingAsset(_)
             | APIError::FailedKeysCreation(_, _)
+            | APIError::FailedMonitorMirror(_)
             | APIError::FailedOpenChannel(_)
             | APIError::FailedPayment(_)
             | APIError::FailedPeerDisconnection(_)
@@ -398,6 +472,7 @@ impl IntoResponse for APIError {
             ),
             APIError::AnchorsRequired
             | APIError::ExpiredSwapOffer
+            | APIError::IdempotencyKeyReused
             | APIError::IncompleteRGBInfo
             | APIError::InvalidAddress(_)
             | APIError::InvalidAmount(_)
@@ -411,6 +486,7 @@ impl IntoResponse for APIError {
             | APIError::InvalidDetails(_)
             | APIError::InvalidEstimationBlocks
             | APIError::InvalidFeeRate(_)
+            | APIError::InvalidIdempotencyKey(_)
             | APIError::InvalidInvoice(_)
             | APIError::InvalidMediaDigest
             | APIError::InvalidName(_)
@@ -424,23 +500,36 @@ impl IntoResponse for APIError {
             | APIError::InvalidPubkey
             | APIError::InvalidRecipientID
             | APIError::InvalidRecipientNetwork
+            | APIError::InvalidRequest(_)
             | APIError::InvalidSwap(_)
             | APIError::InvalidSwapString(_, _)
             | APIError::InvalidTicker(_)
             | APIError::InvalidTlvType(_)
             | APIError::InvalidTransportEndpoint(_)
             | APIError::InvalidTransportEndpoints(_)
+            | APIError::InvalidUserArchive(_)
             | APIError::MediaFileEmpty
             | APIError::MediaFileNotProvided
             | APIError::MissingSwapPaymentPreimage
             | APIError::OutputBelowDustLimit
+            | APIError::SwapPriceRejected(_)
+            | APIError::UnexpectedSwapMaker(_)
             | APIError::UnsupportedBackupVersion { .. } => {
                 (StatusCode::BAD_REQUEST, self.to_string(), self.name())
             }
-            APIError::WrongPassword => (StatusCode::UNAUTHORIZED, self.to_string(), self.name()),
+            APIError::Unauthorized(_) | APIError::WrongPassword => {
+                (StatusCode::UNAUTHORIZED, self.to_string(), self.name())
+            }
+            APIError::IdempotentRequestInProgress => {
+                (StatusCode::CONFLICT, self.to_string(), self.name())
+            }
+            APIError::QuotaExceeded(_) | APIError::RateLimited { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, self.to_string(), self.name())
+            }
             APIError::AllocationsAlreadyAvailable
             | APIError::AlreadyInitialized
             | APIError::AlreadyUnlocked
+            | APIError::BackupSnapshotNotFound(_)
             | APIError::BatchTransferNotFound
             | APIError::CannotEstimateFees
             | APIError::CannotFailBatchTransfer
@@ -450,6 +539,7 @@ impl IntoResponse for APIError {
             | APIError::FailedBitcoindConnection(_)
             | APIError::FailedBroadcast(_)
             | APIError::FailedPeerConnection
+            | APIError::Forbidden(_)
             | APIError::InsufficientAssets
             | APIError::InsufficientCapacity(_)
             | APIError::InsufficientFunds(_)
@@ -461,22 +551,27 @@ impl IntoResponse for APIError {
             | APIError::MinFeeNotMet(_)
             | APIError::NetworkMismatch(_, _)
             | APIError::NoAvailableUtxos
+            | APIError::NoMatchingSwapOffer
             | APIError::NoRoute
             | APIError::NotInitialized
             | APIError::OpenChannelInProgress
             | APIError::PaymentNotFound(_)
             | APIError::RecipientIDAlreadyUsed
             | APIError::SwapNotFound(_)
+            | APIError::SwapOfferNotFound(_)
             | APIError::TemporaryChannelIdAlreadyUsed
             | APIError::UnknownContractId
             | APIError::UnknownLNInvoice
             | APIError::UnknownTemporaryChannelId
             | APIError::UnlockedNode
             | APIError::UnsupportedLayer1(_)
-            | APIError::UnsupportedTransportType => {
+            | APIError::UnsupportedTransportType
+            | APIError::UserAlreadyExists(_) => {
                 (StatusCode::FORBIDDEN, self.to_string(), self.name())
             }
-            APIError::Network(_) | APIError::NoValidTransportEndpoint => (
+            APIError::Network(_)
+            | APIError::NoValidTransportEndpoint
+            | APIError::SwapPriceUnavailable(_) => (
                 StatusCode::SERVICE_UNAVAILABLE,
                 self.to_string(),
                 self.name(),
@@ -496,6 +591,14 @@ impl IntoResponse for APIError {
             .unwrap(),
         );
 
+        if let Some(retry_after_secs) = retry_after {
+            return (
+                status,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                body,
+            )
+                .into_response();
+        }
         (status, body).into_response()
     }
 }
@@ -513,6 +616,14 @@ pub enum AppError {
     InvalidRequest(String),
     #[error("Insufficient balance")]
     InsufficientBalance,
+    #[error("Invalid pricing config: {0}")]
+    InvalidPricingConfig(String),
+    #[error("Invalid monitor mirror config: {0}")]
+    InvalidMonitorMirrorConfig(String),
+    #[error("Invalid rate limit config: {0}")]
+    InvalidRateLimitConfig(String),
+    #[error("Invalid hub auth config: {0}")]
+    InvalidHubAuthConfig(String),
     #[error("User not found")]
     UserNotFound,
 }
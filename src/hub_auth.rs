@@ -0,0 +1,213 @@
+//! Authentication of the multi-user hub API.
+//!
+//! The operator calls the hub routes with the admin token set in [`HUB_ADMIN_TOKEN_ENV`]. Users
+//! get a session token when they log in, binding their user ID to an expiry with an HMAC keyed by
+//! the hub session secret. Both are sent as `Authorization: Bearer <token>`.
+
+use axum::extract::{Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::{engine::general_purpose, Engine as _};
+use bitcoin::hex::{DisplayHex, FromHex};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::error::APIError;
+use crate::rate_limit::RateLimitRole;
+use crate::utils::get_current_timestamp;
+
+/// Environment variable holding the operator token, admin routes are disabled when not set
+pub(crate) const HUB_ADMIN_TOKEN_ENV: &str = "HUB_ADMIN_TOKEN";
+
+/// Environment variable holding the hex encoded 32 bytes secret signing user sessions
+///
+/// When not set a random secret is used, so sessions don't survive restarts and aren't shared
+/// by hub replicas.
+pub(crate) const HUB_SESSION_SECRET_ENV: &str = "HUB_SESSION_SECRET";
+
+/// How long a user session is valid for
+pub(crate) const HUB_SESSION_TTL_SECS: u64 = 86400;
+
+/// Minimum length of the admin token
+const MIN_ADMIN_TOKEN_LEN: usize = 32;
+
+/// User ID the operator is identified by, for rate limiting and logs
+pub(crate) const HUB_ADMIN_USER_ID: &str = "@admin";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum HubAuthError {
+    #[error("Invalid hub auth config: {0}")]
+    InvalidConfig(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HubRole {
+    Admin,
+    User,
+}
+
+impl HubRole {
+    pub(crate) fn rate_limit_role(&self) -> RateLimitRole {
+        match self {
+            Self::Admin => RateLimitRole::Admin,
+            Self::User => RateLimitRole::User,
+        }
+    }
+}
+
+/// Authenticated caller of a hub route, added to the request extensions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct HubCaller {
+    pub(crate) user_id: String,
+    pub(crate) role: HubRole,
+}
+
+impl HubCaller {
+    pub(crate) fn require_admin(&self) -> Result<(), APIError> {
+        match self.role {
+            HubRole::Admin => Ok(()),
+            HubRole::User => Err(APIError::Forbidden(
+                "only the hub operator can call this API".to_string(),
+            )),
+        }
+    }
+
+    /// The user acting on their own account, the operator has no user account
+    pub(crate) fn require_user(&self) -> Result<&str, APIError> {
+        match self.role {
+            HubRole::User => Ok(&self.user_id),
+            HubRole::Admin => Err(APIError::Forbidden(
+                "this API needs a user session".to_string(),
+            )),
+        }
+    }
+}
+
+pub(crate) struct HubAuth {
+    admin_token_hash: Option<[u8; 32]>,
+    session_secret: [u8; 32],
+}
+
+impl HubAuth {
+    pub(crate) fn new(
+        admin_token: Option<&str>,
+        session_secret: [u8; 32],
+    ) -> Result<Self, HubAuthError> {
+        if admin_token.is_some_and(|t| t.len() < MIN_ADMIN_TOKEN_LEN) {
+            return Err(HubAuthError::InvalidConfig(format!(
+                "the admin token needs at least {MIN_ADMIN_TOKEN_LEN} chars"
+            )));
+        }
+        Ok(Self {
+            admin_token_hash: admin_token.map(|t| Sha256::digest(t.as_bytes()).into()),
+            session_secret,
+        })
+    }
+
+    /// Load the admin token from [`HUB_ADMIN_TOKEN_ENV`] and the session secret from
+    /// [`HUB_SESSION_SECRET_ENV`]
+    pub(crate) fn from_env() -> Result<Self, HubAuthError> {
+        let admin_token = std::env::var(HUB_ADMIN_TOKEN_ENV).ok();
+        let session_secret = match std::env::var(HUB_SESSION_SECRET_ENV) {
+            Ok(secret) => <[u8; 32]>::from_hex(secret.trim()).map_err(|_| {
+                HubAuthError::InvalidConfig(format!(
+                    "{HUB_SESSION_SECRET_ENV} must be 32 hex encoded bytes"
+                ))
+            })?,
+            Err(_) => {
+                tracing::warn!(
+                    "{HUB_SESSION_SECRET_ENV} not set, user sessions won't survive a restart"
+                );
+                let mut secret = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                secret
+            }
+        };
+        Self::new(admin_token.as_deref(), session_secret)
+    }
+
+    fn session_mac(&self, encoded_user_id: &str, expires_at: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.session_secret)
+            .expect("HMAC accepts keys of any size");
+        mac.update(format!("session|{encoded_user_id}|{expires_at}").as_bytes());
+        mac
+    }
+
+    /// Issue a session token for the user, valid for [`HUB_SESSION_TTL_SECS`] from `now`
+    pub(crate) fn issue_session(&self, user_id: &str, now: u64) -> String {
+        let encoded_user_id = general_purpose::URL_SAFE_NO_PAD.encode(user_id);
+        let expires_at = now + HUB_SESSION_TTL_SECS;
+        let mac = self
+            .session_mac(&encoded_user_id, expires_at)
+            .finalize()
+            .into_bytes();
+        format!(
+            "{encoded_user_id}.{expires_at}.{}",
+            mac.to_lower_hex_string()
+        )
+    }
+
+    /// Identify the caller presenting the bearer `token`
+    pub(crate) fn authenticate(&self, token: &str, now: u64) -> Result<HubCaller, APIError> {
+        let invalid = || APIError::Unauthorized("invalid token".to_string());
+        if let Some(admin_token_hash) = &self.admin_token_hash {
+            // compare digests, so the comparison time doesn't depend on the token
+            if Sha256::digest(token.as_bytes()).as_slice() == admin_token_hash {
+                return Ok(HubCaller {
+                    user_id: HUB_ADMIN_USER_ID.to_string(),
+                    role: HubRole::Admin,
+                });
+            }
+        }
+
+        let mut parts = token.splitn(3, '.');
+        let (Some(encoded_user_id), Some(expires_at), Some(mac)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let expires_at: u64 = expires_at.parse().map_err(|_| invalid())?;
+        let mac = Vec::<u8>::from_hex(mac).map_err(|_| invalid())?;
+        self.session_mac(encoded_user_id, expires_at)
+            .verify_slice(&mac)
+            .map_err(|_| invalid())?;
+        if expires_at <= now {
+            return Err(APIError::Unauthorized("session expired".to_string()));
+        }
+        let user_id = general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded_user_id)
+            .ok()
+            .and_then(|id| String::from_utf8(id).ok())
+            .ok_or_else(invalid)?;
+        Ok(HubCaller {
+            user_id,
+            role: HubRole::User,
+        })
+    }
+}
+
+/// Authenticate requests to hub routes, adding the [`HubCaller`] to their extensions
+pub(crate) async fn hub_auth_middleware(
+    State(auth): State<Arc<HubAuth>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(token) = token else {
+        return APIError::Unauthorized("missing bearer token".to_string()).into_response();
+    };
+    match auth.authenticate(token.trim(), get_current_timestamp()) {
+        Ok(caller) => {
+            request.extensions_mut().insert(caller);
+            next.run(request).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
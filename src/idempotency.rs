@@ -0,0 +1,163 @@
+use amplify::s;
+use axum::{http::HeaderMap, Json};
+use lightning::ln::channelmanager::PaymentId;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::error::APIError;
+use crate::utils::{hex_str, AppState};
+
+/// Header carrying the client supplied idempotency key
+pub(crate) const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+const STATUS_COMPLETED: &str = "completed";
+
+/// Age after which an unfinished claim is taken to belong to a request interrupted by a crash,
+/// and can be claimed again by a retry of the same request
+pub(crate) const IDEMPOTENCY_CLAIM_TIMEOUT_SECS: i64 = 600;
+
+/// Get the idempotency key from the request header, falling back to the body field
+pub(crate) fn extract_idempotency_key(
+    headers: &HeaderMap,
+    body_key: Option<&str>,
+) -> Result<Option<String>, APIError> {
+    let header_key = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| APIError::InvalidIdempotencyKey(s!("not valid ASCII")))?
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    let key = match (header_key, body_key) {
+        (Some(header_key), Some(body_key)) if header_key != body_key => {
+            return Err(APIError::InvalidIdempotencyKey(s!(
+                "header and body keys differ"
+            )))
+        }
+        (Some(key), _) => key,
+        (None, Some(key)) => key.to_string(),
+        (None, None) => return Ok(None),
+    };
+
+    let key = key.trim().to_string();
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(APIError::InvalidIdempotencyKey(format!(
+            "must be between 1 and {MAX_IDEMPOTENCY_KEY_LEN} characters"
+        )));
+    }
+    Ok(Some(key))
+}
+
+/// Fingerprint of a request, used to detect a key being reused for a different request
+pub(crate) fn request_hash<T: Serialize>(request: &T) -> Result<String, APIError> {
+    let body = serde_json::to_vec(request)
+        .map_err(|e| APIError::Unexpected(format!("Failed to serialize request: {e}")))?;
+    Ok(hex_str(&Sha256::digest(body)))
+}
+
+fn idempotent_hash(tag: &[u8], endpoint: &str, key: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(tag);
+    for part in [endpoint, key] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// Payment ID for the payment made by a request, the same for every retry of the request
+pub(crate) fn idempotent_payment_id(endpoint: &str, key: &str) -> PaymentId {
+    PaymentId(idempotent_hash(b"idempotent_payment", endpoint, key))
+}
+
+/// Ledger reference for the funds moved by a request, the same for every retry of the request
+pub(crate) fn idempotent_reference(prefix: &str, endpoint: &str, key: &str) -> String {
+    let hash = idempotent_hash(b"idempotent_reference", endpoint, key);
+    format!("{prefix}_{}", hex_str(&hash))
+}
+
+/// Run a handler at most once per idempotency key.
+///
+/// The first request claiming a key runs the handler and stores its response, later requests
+/// with the same key and body get the stored response back. Requests failing with an
+/// [`APIError`] release the key so they can be retried, so handlers must only fail before
+/// moving any funds. A claim left unfinished by a crash can be taken over by a retry after
+/// [`IDEMPOTENCY_CLAIM_TIMEOUT_SECS`], handlers sending payments must use an ID derived from
+/// the key, see [`idempotent_payment_id`], so the node rejects a payment sent before the crash,
+/// and handlers moving ledger funds a reference derived from it, see [`idempotent_reference`].
+pub(crate) async fn run_idempotent<Req, Resp, F, Fut>(
+    state: &Arc<AppState>,
+    endpoint: &str,
+    key: Option<String>,
+    request: &Req,
+    handler: F,
+) -> Result<Json<Resp>, APIError>
+where
+    Req: Serialize,
+    Resp: Serialize + DeserializeOwned,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Json<Resp>, APIError>>,
+{
+    let Some(key) = key else {
+        return handler().await;
+    };
+
+    if state.database.lock().await.is_none() && std::env::var("DATABASE_URL").is_ok() {
+        if let Err(e) = crate::utils::initialize_database_after_unlock(state).await {
+            tracing::error!("Failed to initialize multi-user database: {}", e);
+        }
+    }
+    // clone the database out of the lock, the handler may need to lock it again
+    let db = state
+        .database
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| APIError::Unexpected(s!("Idempotency keys require the database")))?;
+
+    let hash = request_hash(request)?;
+    let existing = db
+        .claim_idempotency_key(endpoint, &key, &hash, IDEMPOTENCY_CLAIM_TIMEOUT_SECS)
+        .await
+        .map_err(|e| APIError::Unexpected(format!("Failed to claim idempotency key: {e}")))?;
+
+    if let Some(record) = existing {
+        if record.request_hash != hash {
+            return Err(APIError::IdempotencyKeyReused);
+        }
+        return match (record.status.as_str(), record.response) {
+            (STATUS_COMPLETED, Some(response)) => {
+                tracing::info!("Replaying stored response for idempotency key {key} on {endpoint}");
+                let response = serde_json::from_str(&response).map_err(|e| {
+                    APIError::Unexpected(format!("Failed to parse stored response: {e}"))
+                })?;
+                Ok(Json(response))
+            }
+            _ => Err(APIError::IdempotentRequestInProgress),
+        };
+    }
+
+    match handler().await {
+        Ok(Json(response)) => {
+            let stored = serde_json::to_string(&response)
+                .map_err(|e| APIError::Unexpected(format!("Failed to serialize response: {e}")))?;
+            if let Err(e) = db.complete_idempotency_key(endpoint, &key, &stored).await {
+                tracing::error!("Failed to store response for idempotency key {key}: {e}");
+            }
+            Ok(Json(response))
+        }
+        Err(e) => {
+            if let Err(release_err) = db.release_idempotency_key(endpoint, &key).await {
+                tracing::error!("Failed to release idempotency key {key}: {release_err}");
+            }
+            Err(e)
+        }
+    }
+}
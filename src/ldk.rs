@@ -8,8 +8,8 @@ use bitcoin_bech32::WitnessProgram;
 use lightning::chain::{chainmonitor, ChannelMonitorUpdateStatus};
 use lightning::chain::{BestBlock, Filter, Watch};
 use lightning::events::bump_transaction::{BumpTransactionEventHandler, Wallet};
-use lightning::events::{Event, PaymentFailureReason, PaymentPurpose};
-use lightning::ln::channelmanager::{self, PaymentId, RecentPaymentDetails};
+use lightning::events::{Event, PaymentFailureReason, PaymentPurpose, ReplayEvent};
+use lightning::ln::channelmanager::{self, InterceptId, PaymentId, RecentPaymentDetails};
 use lightning::ln::channelmanager::{
     ChainParameters, ChannelManagerReadArgs, SimpleArcChannelManager,
 };
@@ -80,27 +80,39 @@ use std::time::{Duration, SystemTime};
 use time::OffsetDateTime;
 use tokio::runtime::Handle;
 use tokio::sync::watch::Sender;
+use tokio::sync::Mutex as TokioMutex;
 use tokio::task::JoinHandle;
 
 use crate::bitcoind::BitcoindClient;
+use crate::database::Database;
 use crate::disk::{
     self, FilesystemLogger, CHANNEL_IDS_FNAME, CHANNEL_PEER_DATA, INBOUND_PAYMENTS_FNAME,
     MAKER_SWAPS_FNAME, OUTBOUND_PAYMENTS_FNAME, OUTPUT_SPENDER_TXES, TAKER_SWAPS_FNAME,
 };
 use crate::error::APIError;
+use crate::metrics::{PaymentDirection, PaymentOutcome};
+use crate::monitor_mirror::{MirroringPersister, MonitorMirror, MONITOR_MIRROR_RETRY_INTERVAL};
 use crate::rgb::{check_rgb_proxy_endpoint, get_rgb_channel_info_optional, RgbLibWalletWrapper};
 use crate::routes::{HTLCStatus, SwapStatus, UnlockRequest, DUST_LIMIT_MSAT};
-use crate::swap::SwapData;
+use crate::swap::{OfferFillError, SwapData, SwapOfferData, MAX_OPEN_QUOTES_PER_OFFER};
 use crate::utils::{
     check_port_is_available, connect_peer_if_necessary, do_connect_peer, get_current_timestamp,
-    hex_str, AppState, StaticState, UnlockedAppState, ELECTRUM_URL_MAINNET, ELECTRUM_URL_REGTEST,
-    ELECTRUM_URL_SIGNET, ELECTRUM_URL_TESTNET, PROXY_ENDPOINT_LOCAL, PROXY_ENDPOINT_PUBLIC,
+    hex_str, AppState, StaticState, UnlockedAppState, UserPaymentEvent, ELECTRUM_URL_MAINNET,
+    ELECTRUM_URL_REGTEST, ELECTRUM_URL_SIGNET, ELECTRUM_URL_TESTNET, PROXY_ENDPOINT_LOCAL,
+    PROXY_ENDPOINT_PUBLIC,
 };
+use crate::virtual_balance::VirtualBalanceManager;
 
 pub(crate) const FEE_RATE: u64 = 7;
 pub(crate) const UTXO_SIZE_SAT: u32 = 1000;
 pub(crate) const MIN_CHANNEL_CONFIRMATIONS: u8 = 6;
 
+/// How often timed out swaps are expired
+const SWAP_SWEEP_INTERVAL_SECS: u64 = 60;
+/// Environment variable setting how long completed swaps are kept
+const SWAP_RETENTION_ENV: &str = "SWAP_RETENTION_SECS";
+const DEFAULT_SWAP_RETENTION_SECS: u64 = 30 * 86400;
+
 pub(crate) struct LdkBackgroundServices {
     stop_processing: Arc<AtomicBool>,
     peer_manager: Arc<PeerManager>,
@@ -147,12 +159,118 @@ impl_writeable_tlv_based!(OutboundPaymentInfoStorage, {
 
 pub(crate) struct SwapMap {
     pub(crate) swaps: HashMap<PaymentHash, SwapData>,
+    pub(crate) offers: HashMap<String, SwapOfferData>,
 }
 
 impl_writeable_tlv_based!(SwapMap, {
     (0, swaps, required),
+    (1, offers, (default_value, HashMap::new())),
 });
 
+/// Result of sweeping a [`SwapMap`]
+pub(crate) struct SwapSweep {
+    /// Swaps moved to Expired or Failed, with their new status
+    pub(crate) timed_out: Vec<(PaymentHash, SwapData)>,
+    pub(crate) pruned_swaps: usize,
+    pub(crate) pruned_offers: usize,
+}
+
+impl SwapSweep {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.timed_out.is_empty() && self.pruned_swaps == 0 && self.pruned_offers == 0
+    }
+}
+
+impl SwapMap {
+    /// Move timed out swaps to their final status and prune swaps completed, and offers expired,
+    /// more than `retention_secs` before `now`
+    ///
+    /// Succeeded swaps of a kept offer are never pruned, as they count towards its filled size.
+    pub(crate) fn sweep(&mut self, now: u64, retention_secs: u64) -> SwapSweep {
+        let mut timed_out = vec![];
+        for (payment_hash, swap) in self.swaps.iter_mut() {
+            if let Some(status) = swap.timed_out_status(now) {
+                swap.status = status;
+                swap.completed_at = Some(now);
+                timed_out.push((*payment_hash, swap.clone()));
+            }
+        }
+
+        let retained = |timestamp: Option<u64>| {
+            timestamp.is_none_or(|timestamp| now.saturating_sub(timestamp) <= retention_secs)
+        };
+
+        let offers_before = self.offers.len();
+        self.offers.retain(|_, offer| retained(offer.expires_at));
+        let pruned_offers = offers_before - self.offers.len();
+
+        let swaps_before = self.swaps.len();
+        let offers = &self.offers;
+        self.swaps.retain(|_, swap| {
+            let fills_offer = swap.status == SwapStatus::Succeeded
+                && swap.offer_id.as_ref().is_some_and(|id| offers.contains_key(id));
+            !swap.is_completed() || fills_offer || retained(swap.completed_at)
+        });
+        let pruned_swaps = swaps_before - self.swaps.len();
+
+        SwapSweep {
+            timed_out,
+            pruned_swaps,
+            pruned_offers,
+        }
+    }
+
+    /// Size of an offer not yet taken by waiting, pending or succeeded swaps
+    ///
+    /// Returns `None` for unknown offers and offers without a total size.
+    pub(crate) fn offer_remaining_qty_from(&self, offer_id: &str) -> Option<u64> {
+        let total_qty_from = self.offers.get(offer_id)?.total_qty_from?;
+        let now = get_current_timestamp();
+        let taken: u64 = self
+            .swaps
+            .values()
+            .filter(|s| s.offer_id.as_deref() == Some(offer_id))
+            .filter(|s| match s.status {
+                SwapStatus::Waiting => now <= s.swap_info.expiry,
+                SwapStatus::Pending | SwapStatus::Succeeded => true,
+                SwapStatus::Expired | SwapStatus::Failed => false,
+            })
+            .map(|s| s.swap_info.qty_from)
+            .sum();
+        Some(total_qty_from.saturating_sub(taken))
+    }
+
+    /// Number of waiting, not yet expired, swaps quoted from an offer
+    pub(crate) fn offer_open_quotes(&self, offer_id: &str) -> usize {
+        let now = get_current_timestamp();
+        self.swaps
+            .values()
+            .filter(|s| s.offer_id.as_deref() == Some(offer_id))
+            .filter(|s| s.status == SwapStatus::Waiting && now <= s.swap_info.expiry)
+            .count()
+    }
+
+    /// Add a swap filling part of an offer, if it fits in the offer size and the offer doesn't
+    /// have too many open quotes
+    pub(crate) fn add_offer_swap(
+        &mut self,
+        payment_hash: PaymentHash,
+        swap: SwapData,
+        offer_id: &str,
+    ) -> Result<(), OfferFillError> {
+        if let Some(remaining) = self.offer_remaining_qty_from(offer_id) {
+            if swap.swap_info.qty_from > remaining {
+                return Err(OfferFillError::Exhausted { remaining });
+            }
+        }
+        if self.offer_open_quotes(offer_id) >= MAX_OPEN_QUOTES_PER_OFFER {
+            return Err(OfferFillError::TooManyOpenQuotes);
+        }
+        self.swaps.insert(payment_hash, swap);
+        Ok(())
+    }
+}
+
 pub(crate) struct ChannelIdsMap {
     pub(crate) channel_ids: HashMap<ChannelId, ChannelId>,
 }
@@ -182,6 +300,46 @@ impl UnlockedAppState {
         self.save_maker_swaps(maker_swaps);
     }
 
+    /// Add a swap filling part of an offer, see [`SwapMap::add_offer_swap`]
+    pub(crate) fn add_offer_maker_swap(
+        &self,
+        payment_hash: PaymentHash,
+        swap: SwapData,
+        offer_id: &str,
+    ) -> Result<(), OfferFillError> {
+        let mut maker_swaps = self.get_maker_swaps();
+        maker_swaps.add_offer_swap(payment_hash, swap, offer_id)?;
+        self.save_maker_swaps(maker_swaps);
+        Ok(())
+    }
+
+    pub(crate) fn offer_open_quotes(&self, offer_id: &str) -> usize {
+        self.get_maker_swaps().offer_open_quotes(offer_id)
+    }
+
+    pub(crate) fn offer_remaining_qty_from(&self, offer_id: &str) -> Option<u64> {
+        self.get_maker_swaps().offer_remaining_qty_from(offer_id)
+    }
+
+    pub(crate) fn add_swap_offer(&self, offer_id: String, offer: SwapOfferData) {
+        let mut maker_swaps = self.get_maker_swaps();
+        maker_swaps.offers.insert(offer_id, offer);
+        self.save_maker_swaps(maker_swaps);
+    }
+
+    pub(crate) fn remove_swap_offer(&self, offer_id: &str) -> Option<SwapOfferData> {
+        let mut maker_swaps = self.get_maker_swaps();
+        let offer = maker_swaps.offers.remove(offer_id);
+        if offer.is_some() {
+            self.save_maker_swaps(maker_swaps);
+        }
+        offer
+    }
+
+    pub(crate) fn swap_offers(&self) -> HashMap<String, SwapOfferData> {
+        self.get_maker_swaps().offers.clone()
+    }
+
     pub(crate) fn is_maker_swap(&self, payment_hash: &PaymentHash) -> bool {
         self.maker_swaps().contains_key(payment_hash)
     }
@@ -206,6 +364,65 @@ impl UnlockedAppState {
         self.save_taker_swaps(taker_swaps);
     }
 
+    pub(crate) fn set_taker_swap_intercept_id(
+        &self,
+        payment_hash: &PaymentHash,
+        intercept_id: InterceptId,
+    ) {
+        let mut taker_swaps = self.get_taker_swaps();
+        let taker_swap = taker_swaps.swaps.get_mut(payment_hash).unwrap();
+        taker_swap.intercept_id = Some(intercept_id);
+        self.save_taker_swaps(taker_swaps);
+    }
+
+    /// Expire timed out swaps, failing the HTLCs still linked to them, and prune old ones
+    pub(crate) fn sweep_swaps(&self, retention_secs: u64) {
+        let now = get_current_timestamp();
+
+        let mut maker_swaps = self.get_maker_swaps();
+        let maker_sweep = maker_swaps.sweep(now, retention_secs);
+        if !maker_sweep.is_empty() {
+            self.save_maker_swaps(maker_swaps);
+        } else {
+            drop(maker_swaps);
+        }
+        for (payment_hash, _) in &maker_sweep.timed_out {
+            // the maker side of a swap is a payment to us, fail it if it's already claimable
+            self.channel_manager.fail_htlc_backwards(payment_hash);
+        }
+
+        let mut taker_swaps = self.get_taker_swaps();
+        let taker_sweep = taker_swaps.sweep(now, retention_secs);
+        if !taker_sweep.is_empty() {
+            self.save_taker_swaps(taker_swaps);
+        } else {
+            drop(taker_swaps);
+        }
+        for (payment_hash, swap) in &taker_sweep.timed_out {
+            if let Some(intercept_id) = swap.intercept_id {
+                // fails only if the HTLC has already been forwarded or failed
+                if self
+                    .channel_manager
+                    .fail_intercepted_htlc(intercept_id)
+                    .is_ok()
+                {
+                    tracing::info!("Failed intercepted HTLC of timed out swap {payment_hash}");
+                }
+            }
+        }
+
+        for (side, sweep) in [("maker", &maker_sweep), ("taker", &taker_sweep)] {
+            if !sweep.is_empty() {
+                tracing::info!(
+                    "Swept {side} swaps: {} timed out, {} swaps and {} offers pruned",
+                    sweep.timed_out.len(),
+                    sweep.pruned_swaps,
+                    sweep.pruned_offers
+                );
+            }
+        }
+    }
+
     pub(crate) fn is_taker_swap(&self, payment_hash: &PaymentHash) -> bool {
         self.taker_swaps().contains_key(payment_hash)
     }
@@ -293,6 +510,7 @@ impl UnlockedAppState {
             .unwrap();
     }
 
+    /// Insert or update an inbound payment, returning its previous status
     fn upsert_inbound_payment(
         &self,
         payment_hash: PaymentHash,
@@ -301,15 +519,17 @@ impl UnlockedAppState {
         secret: Option<PaymentSecret>,
         amt_msat: Option<u64>,
         payee_pubkey: PublicKey,
-    ) {
+    ) -> Option<HTLCStatus> {
         let mut inbound = self.get_inbound_payments();
-        match inbound.payments.entry(payment_hash) {
+        let previous_status = match inbound.payments.entry(payment_hash) {
             Entry::Occupied(mut e) => {
                 let payment_info = e.get_mut();
+                let previous_status = payment_info.status;
                 payment_info.status = status;
                 payment_info.preimage = preimage;
                 payment_info.secret = secret;
                 payment_info.updated_at = get_current_timestamp();
+                Some(previous_status)
             }
             Entry::Vacant(e) => {
                 let created_at = get_current_timestamp();
@@ -322,9 +542,11 @@ impl UnlockedAppState {
                     updated_at: created_at,
                     payee_pubkey,
                 });
+                None
             }
-        }
+        };
         self.save_inbound_payments(inbound);
+        previous_status
     }
 
     pub(crate) fn update_outbound_payment(
@@ -399,16 +621,16 @@ pub(crate) type ChainMonitor = chainmonitor::ChainMonitor<
     Arc<BitcoindClient>,
     Arc<BitcoindClient>,
     Arc<FilesystemLogger>,
-    Arc<
-        MonitorUpdatingPersister<
-            Arc<FilesystemStore>,
-            Arc<FilesystemLogger>,
-            Arc<KeysManager>,
-            Arc<KeysManager>,
-            Arc<BitcoindClient>,
-            Arc<BitcoindClient>,
-        >,
-    >,
+    Arc<MirroringPersister<Arc<Persister>>>,
+>;
+
+pub(crate) type Persister = MonitorUpdatingPersister<
+    Arc<FilesystemStore>,
+    Arc<FilesystemLogger>,
+    Arc<KeysManager>,
+    Arc<KeysManager>,
+    Arc<BitcoindClient>,
+    Arc<BitcoindClient>,
 >;
 
 pub(crate) type GossipVerifier = lightning_block_sync::gossip::GossipVerifier<
@@ -501,11 +723,42 @@ fn _update_rgb_channel_amount(ldk_data_dir: &Path, payment_hash: &PaymentHash, r
     }
 }
 
+/// Whether an inbound payment may be claimed.
+///
+/// With the multi-user database enabled, only payments for invoices issued to a user or for
+/// invoices and swaps of the node itself are accepted. Keysend payments have no invoice and
+/// always go to the node.
+async fn is_claimable_payment(
+    payment_hash: &PaymentHash,
+    purpose: &PaymentPurpose,
+    unlocked_state: &UnlockedAppState,
+    database: &TokioMutex<Option<Database>>,
+) -> bool {
+    if matches!(purpose, PaymentPurpose::SpontaneousPayment(_)) {
+        return true;
+    }
+    let Some(db) = database.lock().await.clone() else {
+        return true;
+    };
+
+    match db.get_user_invoice(&hex_str(&payment_hash.0)).await {
+        Ok(Some(invoice)) => return invoice.status == "pending",
+        Ok(None) => {}
+        // the payment may still be for the node itself, which doesn't need the database
+        Err(e) => tracing::error!("Failed to look up user invoice for {}: {}", payment_hash, e),
+    }
+
+    unlocked_state.get_inbound_payments().payments.contains_key(payment_hash)
+        || unlocked_state.is_maker_swap(payment_hash)
+}
+
+/// Handle an LDK event, asking LDK to replay it when it couldn't be fully handled
 async fn handle_ldk_events(
     event: Event,
     unlocked_state: Arc<UnlockedAppState>,
     static_state: Arc<StaticState>,
-) {
+    database: Arc<TokioMutex<Option<Database>>>,
+) -> Result<(), ReplayEvent> {
     match event {
         Event::FundingGenerationReady {
             temporary_channel_id,
@@ -608,7 +861,7 @@ async fn handle_ldk_events(
 
                 if let Err(e) = res {
                     tracing::error!("cannot post consignment: {e}");
-                    return;
+                    return Ok(());
                 }
             }
 
@@ -647,6 +900,8 @@ async fn handle_ldk_events(
                 payment_hash,
                 amount_msat,
             );
+            let claimable =
+                is_claimable_payment(&payment_hash, &purpose, &unlocked_state, &database).await;
             let payment_preimage = match purpose {
                 PaymentPurpose::Bolt11InvoicePayment {
                     payment_preimage, ..
@@ -659,6 +914,16 @@ async fn handle_ldk_events(
                 } => payment_preimage,
                 PaymentPurpose::SpontaneousPayment(preimage) => Some(preimage),
             };
+            if !claimable {
+                tracing::warn!("Rejecting payment with unknown payment hash {}", payment_hash);
+                static_state
+                    .metrics
+                    .record_payment(PaymentDirection::Inbound, PaymentOutcome::Failed);
+                unlocked_state
+                    .channel_manager
+                    .fail_htlc_backwards(&payment_hash);
+                return Ok(());
+            }
             unlocked_state
                 .channel_manager
                 .claim_funds(payment_preimage.unwrap());
@@ -698,8 +963,15 @@ async fn handle_ldk_events(
 
             _update_rgb_channel_amount(&static_state.ldk_data_dir, &payment_hash, true);
 
-            if unlocked_state.is_maker_swap(&payment_hash) {
+            // LDK replays the event if it wasn't handled before a restart, count the claim once
+            let already_claimed = if unlocked_state.is_maker_swap(&payment_hash) {
+                let already_claimed = unlocked_state
+                    .get_maker_swaps()
+                    .swaps
+                    .get(&payment_hash)
+                    .is_some_and(|swap| swap.status == SwapStatus::Succeeded);
                 unlocked_state.update_maker_swap_status(&payment_hash, SwapStatus::Succeeded);
+                already_claimed
             } else {
                 unlocked_state.upsert_inbound_payment(
                     payment_hash,
@@ -708,7 +980,43 @@ async fn handle_ldk_events(
                     payment_secret,
                     Some(amount_msat),
                     receiver_node_id.unwrap(),
-                );
+                ) == Some(HTLCStatus::Succeeded)
+            };
+            if !already_claimed {
+                static_state
+                    .metrics
+                    .record_payment(PaymentDirection::Inbound, PaymentOutcome::Succeeded);
+            }
+
+            // Credit the user owning the invoice, if any
+            let db = database.lock().await.clone();
+            if let Some(db) = db {
+                match db.credit_user_invoice(&hex_str(&payment_hash.0), amount_msat).await {
+                    Ok(Some(user_id)) => {
+                        tracing::info!(
+                            "Credited {} msat for payment hash {} to user {}",
+                            amount_msat,
+                            payment_hash,
+                            user_id
+                        );
+                        // no subscribers is fine, notifications are best effort
+                        let _ = static_state.user_payments.send(UserPaymentEvent {
+                            user_id,
+                            amount_msat,
+                            payment_hash: hex_str(&payment_hash.0),
+                        });
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        // the funds are claimed, replay the event until the user is credited
+                        tracing::error!(
+                            "Failed to credit user for payment hash {}, replaying: {}",
+                            payment_hash,
+                            e
+                        );
+                        return Err(ReplayEvent());
+                    }
+                }
             }
         }
         Event::PaymentSent {
@@ -719,6 +1027,9 @@ async fn handle_ldk_events(
             ..
         } => {
             _update_rgb_channel_amount(&static_state.ldk_data_dir, &payment_hash, false);
+            static_state
+                .metrics
+                .record_payment(PaymentDirection::Outbound, PaymentOutcome::Succeeded);
 
             if unlocked_state.is_maker_swap(&payment_hash) {
                 tracing::info!(
@@ -745,6 +1056,22 @@ async fn handle_ldk_events(
                     payment_hash,
                     payment_preimage
                 );
+
+                // Credit back the unused routing fee reserved from the user who paid, if any
+                let db = database.lock().await.clone();
+                if let Some(db) = db {
+                    let balance_manager = VirtualBalanceManager::new(Arc::new(db));
+                    if let Err(e) = balance_manager
+                        .settle_lightning_payment(&hex_str(&payment_hash.0), fee_paid_msat)
+                        .await
+                    {
+                        tracing::error!(
+                            "Failed to settle user payment hash {}: {}",
+                            payment_hash,
+                            e
+                        );
+                    }
+                }
             }
         }
         Event::OpenChannelRequest {
@@ -787,6 +1114,9 @@ async fn handle_ldk_events(
             payment_id,
             ..
         } => {
+            static_state
+                .metrics
+                .record_payment(PaymentDirection::Outbound, PaymentOutcome::Failed);
             if let Some(hash) = payment_hash {
                 tracing::error!(
                     "EVENT: Failed to send payment to payment ID {}, payment hash {}: {:?}",
@@ -803,6 +1133,21 @@ async fn handle_ldk_events(
                 } else {
                     unlocked_state.update_outbound_payment_status(payment_id, HTLCStatus::Failed);
                 }
+
+                // Refund the user who paid from their balance, if any
+                let db = database.lock().await.clone();
+                if let Some(db) = db {
+                    let balance_manager = VirtualBalanceManager::new(Arc::new(db));
+                    match balance_manager.refund_lightning_payment(&hex_str(&hash.0)).await {
+                        Ok(true) => tracing::info!("Refunded failed payment {} to its user", hash),
+                        Ok(false) => {}
+                        Err(e) => tracing::error!(
+                            "Failed to refund user for payment hash {}: {}",
+                            hash,
+                            e
+                        ),
+                    }
+                }
             } else {
                 tracing::error!(
                     "EVENT: Failed fetch invoice for payment ID {}: {:?}",
@@ -958,7 +1303,20 @@ async fn handle_ldk_events(
                 hex_str(&counterparty_node_id.serialize()),
             );
 
-            unlocked_state.add_channel_id(former_temporary_channel_id.unwrap(), channel_id);
+            let temporary_channel_id = former_temporary_channel_id.unwrap();
+            unlocked_state.add_channel_id(temporary_channel_id, channel_id);
+
+            // channels opened for users are recorded under their temporary ID until funded
+            let db = database.lock().await.clone();
+            if let Some(db) = db {
+                let temporary_channel_id = hex_str(&temporary_channel_id.0);
+                if let Err(e) = db
+                    .update_user_channel_id(&temporary_channel_id, &hex_str(&channel_id.0))
+                    .await
+                {
+                    tracing::error!("Failed to update the ID of user channel {channel_id}: {e}");
+                }
+            }
 
             let funding_txid = funding_txo.txid.to_string();
             let psbt_path = static_state
@@ -987,7 +1345,7 @@ async fn handle_ldk_events(
                     .ldk_data_dir
                     .join(format!("consignment_{funding_txid}"));
                 if !consignment_path.exists() {
-                    return;
+                    return Ok(());
                 }
                 let consignment =
                     RgbTransfer::load_file(consignment_path).expect("successful consignment load");
@@ -1037,6 +1395,16 @@ async fn handle_ldk_events(
             );
 
             unlocked_state.delete_channel_id(channel_id);
+
+            // Credit back the user who funded the channel, if any
+            let db = database.lock().await.clone();
+            if let Some(db) = db {
+                let balance_manager = VirtualBalanceManager::new(Arc::new(db));
+                let closed_channel_id = hex_str(&channel_id.0);
+                if let Err(e) = balance_manager.credit_closed_channel(&closed_channel_id).await {
+                    tracing::error!("Failed to credit user for closed channel {channel_id}: {e}");
+                }
+            }
         }
         Event::DiscardFunding { channel_id, .. } => {
             // A "real" node should probably "lock" the UTXOs spent in funding transactions until
@@ -1066,6 +1434,7 @@ async fn handle_ldk_events(
                     .channel_manager
                     .fail_intercepted_htlc(intercept_id)
                     .unwrap();
+                return Ok(());
             }
 
             let get_rgb_info = |channel_id| {
@@ -1109,11 +1478,32 @@ async fn handle_ldk_events(
                         .channel_manager
                         .fail_intercepted_htlc(intercept_id)
                         .unwrap();
-                    return;
+                    return Ok(());
                 }
                 Some(x) => x,
             };
 
+            if whitelist_swap.status != SwapStatus::Waiting
+                || get_current_timestamp() > whitelist_swap.swap_info.expiry
+            {
+                tracing::error!("ERROR: rejecting swap that is not waiting or has expired");
+                let expired = whitelist_swap.status == SwapStatus::Waiting;
+                drop(swaps_lock);
+                if expired {
+                    unlocked_state.update_taker_swap_status(&payment_hash, SwapStatus::Expired);
+                }
+                unlocked_state
+                    .channel_manager
+                    .fail_intercepted_htlc(intercept_id)
+                    .unwrap();
+                return Ok(());
+            }
+            let whitelist_swap = whitelist_swap.clone();
+            drop(swaps_lock);
+            // from now on the HTLC is held until forwarded or failed, record it so the sweeper
+            // fails it if the swap times out in between
+            unlocked_state.set_taker_swap_intercept_id(&payment_hash, intercept_id);
+
             let mut fail = false;
             if whitelist_swap.swap_info.is_from_btc() {
                 let net_msat_diff = expected_outbound_amount_msat.checked_sub(inbound_amount_msat);
@@ -1147,31 +1537,36 @@ async fn handle_ldk_events(
                 }
             }
 
-            drop(swaps_lock);
-
             if fail {
                 tracing::error!("ERROR: swap doesn't match the whitelisted info, rejecting it");
                 unlocked_state.update_taker_swap_status(&payment_hash, SwapStatus::Failed);
-                unlocked_state
+                // the sweeper may have failed the HTLC already, now that it's recorded
+                if let Err(e) = unlocked_state
                     .channel_manager
                     .fail_intercepted_htlc(intercept_id)
-                    .unwrap();
-                return;
+                {
+                    tracing::warn!("Failed to fail intercepted HTLC of swap {payment_hash}: {e:?}");
+                }
+                return Ok(());
             }
 
             tracing::debug!("Swap is whitelisted, forwarding the htlc...");
             unlocked_state.update_taker_swap_status(&payment_hash, SwapStatus::Pending);
 
-            unlocked_state
-                .channel_manager
-                .forward_intercepted_htlc(
-                    intercept_id,
-                    channelmanager::NextHopForward::ShortChannelId(requested_next_hop_scid),
-                    outbound_channel.counterparty.node_id,
-                    expected_outbound_amount_msat,
-                    expected_outbound_rgb_amount,
-                )
-                .expect("Forward should be valid");
+            if let Err(e) = unlocked_state.channel_manager.forward_intercepted_htlc(
+                intercept_id,
+                channelmanager::NextHopForward::ShortChannelId(requested_next_hop_scid),
+                outbound_channel.counterparty.node_id,
+                expected_outbound_amount_msat,
+                expected_outbound_rgb_amount,
+            ) {
+                tracing::error!("ERROR: failed to forward swap HTLC: {e:?}");
+                unlocked_state.update_taker_swap_status(&payment_hash, SwapStatus::Failed);
+                // if this fails too the HTLC is left to the sweeper
+                let _ = unlocked_state
+                    .channel_manager
+                    .fail_intercepted_htlc(intercept_id);
+            }
         }
         Event::OnionMessageIntercepted { .. } => {
             // We don't use the onion message interception feature, so this event should never be
@@ -1197,6 +1592,7 @@ async fn handle_ldk_events(
             });
         }
     }
+    Ok(())
 }
 
 impl OutputSpender for RgbOutputSpender {
@@ -1405,6 +1801,21 @@ impl OutputSpender for RgbOutputSpender {
     }
 }
 
+/// Derive the seed of the LDK `KeysManager` from the wallet mnemonic
+pub(crate) fn derive_ldk_seed(mnemonic: &Mnemonic, network: Network) -> [u8; 32] {
+    let xkey: ExtendedKey = mnemonic
+        .clone()
+        .into_extended_key()
+        .expect("a valid key should have been provided");
+    let master_xprv = &xkey
+        .into_xprv(network)
+        .expect("should be possible to get an extended private key");
+    let xprv: Xpriv = master_xprv
+        .derive_priv(&Secp256k1_30::new(), &ChildNumber::Hardened { index: 535 })
+        .unwrap();
+    xprv.private_key.secret_bytes()
+}
+
 pub(crate) async fn start_ldk(
     app_state: Arc<AppState>,
     mnemonic: Mnemonic,
@@ -1499,17 +1910,7 @@ pub(crate) async fn start_ldk(
     // Initialize the KeysManager
     // The key seed that we use to derive the node privkey (that corresponds to the node pubkey) and
     // other secret key material.
-    let xkey: ExtendedKey = mnemonic
-        .clone()
-        .into_extended_key()
-        .expect("a valid key should have been provided");
-    let master_xprv = &xkey
-        .into_xprv(network)
-        .expect("should be possible to get an extended private key");
-    let xprv: Xpriv = master_xprv
-        .derive_priv(&Secp256k1_30::new(), &ChildNumber::Hardened { index: 535 })
-        .unwrap();
-    let ldk_seed: [u8; 32] = xprv.private_key.secret_bytes();
+    let ldk_seed = derive_ldk_seed(&mnemonic, network);
     let cur = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
@@ -1533,13 +1934,28 @@ pub(crate) async fn start_ldk(
         ldk_data_dir_path.clone(),
     ));
 
+    // Mirror monitors to the secondary store, if configured
+    let monitor_mirror = match &app_state.monitor_mirror {
+        Some(config) => {
+            let mirror = MonitorMirror::open(config, &unlock_request.password)
+                .map_err(|e| APIError::FailedMonitorMirror(e.to_string()))?;
+            tracing::info!("Channel monitors are mirrored to the configured store");
+            Some(mirror)
+        }
+        None => None,
+    };
+    let mirroring_persister = Arc::new(MirroringPersister::new(
+        Arc::clone(&persister),
+        monitor_mirror,
+    ));
+
     // Initialize the ChainMonitor
     let chain_monitor: Arc<ChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
         None,
         Arc::clone(&broadcaster),
         Arc::clone(&logger),
         Arc::clone(&fee_estimator),
-        Arc::clone(&persister),
+        Arc::clone(&mirroring_persister),
     ));
 
     // Read ChannelMonitor state from disk
@@ -1986,15 +2402,29 @@ pub(crate) async fn start_ldk(
         .collect::<Vec<PaymentId>>();
     unlocked_state.fail_outbound_pending_payments(recent_payments_payment_ids);
 
+    // The multi-user database must be up before events are processed, or payments to users
+    // replayed on startup would be claimed without crediting them
+    if app_state.database.lock().await.is_none() {
+        crate::utils::initialize_database(&app_state, Some(&unlocked_state))
+            .await
+            .map_err(|e| APIError::Unexpected(format!("Failed to initialize multi-user database: {e}")))?;
+    } else if let Some(virtual_htlc_mgr) = app_state.virtual_htlc_manager.lock().await.as_ref() {
+        // the database survived a previous lock, reload the HTLCs it has pending
+        if let Err(e) = virtual_htlc_mgr.load_pending_htlcs().await {
+            tracing::error!("Failed to reload pending virtual HTLCs: {}", e);
+        }
+    }
+
     // Handle LDK Events
     let unlocked_state_copy = Arc::clone(&unlocked_state);
     let static_state_copy = Arc::clone(static_state);
+    let database_copy = Arc::clone(&app_state.database);
     let event_handler = move |event: Event| {
         let unlocked_state_copy = Arc::clone(&unlocked_state_copy);
         let static_state_copy = Arc::clone(&static_state_copy);
+        let database_copy = Arc::clone(&database_copy);
         async move {
-            handle_ldk_events(event, unlocked_state_copy, static_state_copy).await;
-            Ok(())
+            handle_ldk_events(event, unlocked_state_copy, static_state_copy, database_copy).await
         }
     };
 
@@ -2067,6 +2497,60 @@ pub(crate) async fn start_ldk(
         }
     });
 
+    // Regularly expire timed out swaps and prune old ones.
+    let swap_retention_secs = std::env::var(SWAP_RETENTION_ENV)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SWAP_RETENTION_SECS);
+    let sweep_state = Arc::clone(&unlocked_state);
+    let stop_sweep = Arc::clone(&stop_processing);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(SWAP_SWEEP_INTERVAL_SECS));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if stop_sweep.load(Ordering::Acquire) {
+                return;
+            }
+            sweep_state.sweep_swaps(swap_retention_secs);
+        }
+    });
+
+    // Regularly retry the required mirror writes channel updates are waiting for, completing the
+    // updates once their monitor is mirrored.
+    let retry_persister = Arc::clone(&mirroring_persister);
+    let retry_chain_monitor = Arc::clone(&chain_monitor);
+    let stop_mirror_retry = Arc::clone(&stop_processing);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MONITOR_MIRROR_RETRY_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if stop_mirror_retry.load(Ordering::Acquire) {
+                return;
+            }
+            let persister = Arc::clone(&retry_persister);
+            let completed = match tokio::task::spawn_blocking(move || persister.retry_pending())
+                .await
+            {
+                Ok(completed) => completed,
+                Err(e) => {
+                    tracing::error!("Monitor mirror retry task failed: {e}");
+                    continue;
+                }
+            };
+            for (funding_outpoint, update_id) in completed {
+                if let Err(e) =
+                    retry_chain_monitor.channel_monitor_updated(funding_outpoint, update_id)
+                {
+                    tracing::error!(
+                        "Failed to complete monitor update {update_id} of {funding_outpoint:?}: {e:?}"
+                    );
+                }
+            }
+        }
+    });
+
     // Regularly broadcast our node_announcement. This is only required (or possible) if we have
     // some public channels.
     let mut ldk_announced_listen_addr = Vec::new();
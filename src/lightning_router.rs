@@ -1,5 +1,6 @@
 use crate::error::AppError;
 use crate::utils::AppState;
+use lightning::ln::channelmanager::PaymentId;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -26,13 +27,15 @@ impl LightningRouter {
     }
 
     /// Route external payment through master Lightning node
+    ///
+    /// `offer_payment_id` sets the payment ID of offer payments, see
+    /// [`crate::routes::send_payment_with_id`].
     pub async fn send_lightning_payment(
         &self,
         invoice: String,
         amount_msat: Option<u64>,
+        offer_payment_id: Option<PaymentId>,
     ) -> Result<LightningPaymentResponse, AppError> {
-        use axum::extract::State;
-        use axum_extra::extract::WithRejection;
         use axum::response::Json;
         
         let request = crate::routes::SendPaymentRequest {
@@ -41,10 +44,7 @@ impl LightningRouter {
         };
 
         // Call the actual Lightning Network payment via the existing route
-        match crate::routes::send_payment(
-            State(self.app_state.clone()),
-            WithRejection(Json(request), std::marker::PhantomData)
-        ).await {
+        match crate::routes::send_payment_with_id(self.app_state.clone(), request, offer_payment_id).await {
             Ok(Json(response)) => Ok(LightningPaymentResponse {
                 payment_id: response.payment_id,
                 payment_hash: response.payment_hash,
@@ -1,5 +1,6 @@
 mod args;
 mod backup;
+mod backup_verify;
 mod bitcoind;
 mod blockchain_balance;
 mod database;
@@ -7,16 +8,28 @@ mod disk;
 mod error;
 mod hsm;
 mod hsm_provider;
+mod hub_auth;
+mod idempotency;
 mod ldk;
+mod metrics;
+mod monitor_mirror;
+mod pricing;
+mod quota;
+mod rate_limit;
+mod remote_signer;
 mod rgb;
 mod rgb_db_adapter;
 mod rgb_db_fix;
 mod routes;
 mod sqlite_proxy;
 mod swap;
+mod user_archive;
 mod user_manager;
 mod utils;
+mod telegram_bot;
 mod telegram_integration;
+mod telegram_login;
+mod telegram_routes;
 mod user_api;
 mod virtual_node;
 mod virtual_context;
@@ -26,11 +39,29 @@ mod virtual_balance;
 mod virtual_router;
 mod lightning_router;
 mod virtual_api;
-mod inbound_payment_handler;
 mod test_utils;
 
 #[cfg(test)]
 mod test {
+    mod backup_incremental;
+    mod backup_verify;
+    mod hsm_pkcs11;
+    mod hsm_signing;
+    mod hub_auth;
+    mod idempotency;
+    mod ledger;
+    mod metrics;
+    mod monitor_mirror;
+    mod pricing;
+    mod quota;
+    mod rate_limit;
+    mod remote_signer;
+    mod swap_offers;
+    mod swap_sweep;
+    mod swapstring;
+    mod telegram_bot;
+    mod telegram_login;
+    mod user_archive;
     mod virtual_node_isolation;
     mod integration_virtual_nodes;
     mod virtual_node_simple;
@@ -40,11 +71,13 @@ use anyhow::Result;
 use axum::{
     extract::DefaultBodyLimit,
     http::Request,
+    middleware,
     response::Response,
     routing::{get, post},
     Router,
 };
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use rgb_lib::BitcoinNetwork;
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
 use tokio::signal;
 #[cfg(unix)]
 use tokio::signal::unix;
@@ -61,22 +94,30 @@ use tracing_subscriber::{
     prelude::*,
 };
 
-use crate::args::LdkUserInfo;
+use crate::args::{LdkUserInfo, VerifyBackupArgs, BACKUP_PASSWORD_ENV};
+use crate::backup_verify::do_verify_backup;
 use crate::error::AppError;
+use crate::hub_auth::hub_auth_middleware;
 use crate::ldk::stop_ldk;
+use crate::metrics::track_http_metrics;
+use crate::rate_limit::rate_limit_middleware;
 use crate::routes::{
-    address, asset_balance, asset_metadata, backup, btc_balance, change_password,
+    address, asset_balance, asset_metadata, backup, btc_balance, cancel_swap_offer, change_password,
     check_indexer_url, check_proxy_endpoint, close_channel, connect_peer, create_utxos,
-    decode_ln_invoice, decode_rgb_invoice, disconnect_peer, estimate_fee, fail_transfers,
-    get_asset_media, get_channel_id, get_payment, get_swap, init, invoice_status, issue_asset_cfa,
-    issue_asset_nia, issue_asset_uda, keysend, list_assets, list_channels, list_payments,
-    list_peers, list_swaps, list_transactions, list_transfers, list_unspents, ln_invoice, lock,
-    maker_execute, maker_init, network_info, node_info, open_channel, post_asset_media,
-    refresh_transfers, restore, rgb_invoice, send_asset, send_btc, send_onion_message,
-    send_payment, shutdown, sign_message, sync, taker, unlock, virtual_transfer, payment_webhook,
+    decode_ln_invoice, decode_rgb_invoice, disconnect_peer, estimate_fee, export_user,
+    fail_transfers, get_asset_media, get_channel_id, get_payment, get_swap, import_user, init,
+    invoice_status, issue_asset_cfa, issue_asset_nia, issue_asset_uda, keysend, list_assets,
+    list_channels, list_payments, list_peers, list_swap_offers, list_swaps, list_transactions,
+    list_transfers, list_unspents, ln_invoice, lock, maker_execute, maker_init, metrics,
+    network_info, node_info, open_channel, post_asset_media, post_swap_offer, refresh_transfers,
+    restore, rgb_invoice, send_asset, send_btc, send_onion_message, send_payment, shutdown,
+    sign_message, swap_quote, sync, taker, unlock, update_user_quotas, user_quotas, verify_backup,
+    virtual_transfer,
 };
 use crate::utils::{start_daemon, AppState, LOGS_DIR};
+use crate::telegram_bot::run_telegram_bot;
 use crate::telegram_integration::TelegramIntegration;
+use crate::telegram_routes::{bot_service_proxy, telegram_login};
 use crate::user_api::user_api_routes;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 8)]
@@ -86,6 +127,10 @@ async fn main() -> Result<()> {
     
     let args = args::parse_startup_args()?;
 
+    if let Some(verify_args) = &args.verify_backup {
+        return verify_backup_cli(verify_args, args.network, &args.storage_dir_path).await;
+    }
+
     // stdout logger
     let stdout_log = tracing_subscriber::fmt::layer().fmt_fields(TypedFields::default());
 
@@ -120,6 +165,48 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Verify a backup from the command line, printing the report and failing if it's not valid
+async fn verify_backup_cli(
+    verify_args: &VerifyBackupArgs,
+    network: BitcoinNetwork,
+    storage_dir_path: &Path,
+) -> Result<()> {
+    let password = match std::env::var(BACKUP_PASSWORD_ENV) {
+        Ok(password) => password,
+        Err(_) => {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            password.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    let backup_path = verify_args.backup_path.clone();
+    let snapshot_id = verify_args.snapshot_id.clone();
+    // unpack next to the backup when no node storage dir is given
+    let data_dir = if storage_dir_path.as_os_str().is_empty() {
+        backup_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_path_buf()
+    } else {
+        storage_dir_path.to_path_buf()
+    };
+    let report = tokio::task::spawn_blocking(move || {
+        do_verify_backup(
+            &backup_path,
+            &password,
+            snapshot_id.as_deref(),
+            network,
+            &data_dir,
+        )
+    })
+    .await??;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.valid {
+        anyhow::bail!("backup verification found {} issues", report.issues.len());
+    }
+    Ok(())
+}
+
 pub(crate) async fn app(args: LdkUserInfo) -> Result<(Router, Arc<AppState>), AppError> {
     // Note: RGB database configuration disabled to avoid initialization conflicts
     // RGB library will initialize its own database during unlock
@@ -133,6 +220,28 @@ pub(crate) async fn app(args: LdkUserInfo) -> Result<(Router, Arc<AppState>), Ap
     let database_url = std::env::var("DATABASE_URL")
         .unwrap_or_else(|_| "postgresql://localhost:5432/rgb_lightning".to_string());
     let _telegram = Arc::new(TelegramIntegration::new(database_url));
+    if let Some(config) = app_state.telegram_bot.clone() {
+        tokio::spawn(run_telegram_bot(config, app_state.clone()));
+    }
+
+    // multi-user hub routes, called by users with a session or by the operator
+    let hub_router = Router::new()
+        .route("/botservice", post(bot_service_proxy))
+        .route("/exportuser", post(export_user))
+        .route("/importuser", post(import_user))
+        .route("/updateuserquotas", post(update_user_quotas))
+        .route("/userquotas", post(user_quotas))
+        // layers run bottom to top, so callers are known when rate limiting
+        .route_layer(middleware::from_fn_with_state(
+            app_state.rate_limiter.clone(),
+            rate_limit_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.hub_auth.clone(),
+            hub_auth_middleware,
+        ))
+        // added after the route layers, as users log in to get a session
+        .route("/login", post(telegram_login));
 
     let router = Router::new()
         .route(
@@ -148,6 +257,7 @@ pub(crate) async fn app(args: LdkUserInfo) -> Result<(Router, Arc<AppState>), Ap
         .route("/assetmetadata", post(asset_metadata))
         .route("/backup", post(backup))
         .route("/btcbalance", post(btc_balance))
+        .route("/cancelswapoffer", post(cancel_swap_offer))
         .route("/changepassword", post(change_password))
         .route("/checkindexerurl", post(check_indexer_url))
         .route("/checkproxyendpoint", post(check_proxy_endpoint))
@@ -173,6 +283,7 @@ pub(crate) async fn app(args: LdkUserInfo) -> Result<(Router, Arc<AppState>), Ap
         .route("/listchannels", get(list_channels))
         .route("/listpayments", get(list_payments))
         .route("/listpeers", get(list_peers))
+        .route("/listswapoffers", get(list_swap_offers))
         .route("/listswaps", get(list_swaps))
         .route("/listtransactions", post(list_transactions))
         .route("/listtransfers", post(list_transfers))
@@ -181,9 +292,11 @@ pub(crate) async fn app(args: LdkUserInfo) -> Result<(Router, Arc<AppState>), Ap
         .route("/lock", post(lock))
         .route("/makerexecute", post(maker_execute))
         .route("/makerinit", post(maker_init))
+        .route("/metrics", get(metrics))
         .route("/networkinfo", get(network_info))
         .route("/nodeinfo", get(node_info))
         .route("/openchannel", post(open_channel))
+        .route("/postswapoffer", post(post_swap_offer))
         .route("/refreshtransfers", post(refresh_transfers))
         .route("/restore", post(restore))
         .route("/rgbinvoice", post(rgb_invoice))
@@ -193,20 +306,28 @@ pub(crate) async fn app(args: LdkUserInfo) -> Result<(Router, Arc<AppState>), Ap
         .route("/sendpayment", post(send_payment))
         .route("/shutdown", post(shutdown))
         .route("/signmessage", post(sign_message))
+        .route("/swapquote", post(swap_quote))
         .route("/sync", post(sync))
         .route("/taker", post(taker))
         .route("/unlock", post(unlock))
+        .route("/verifybackup", post(verify_backup))
         // Virtual node API routes for bitMaskRGB integration
         .route("/virtual_rgbinvoice", post(crate::virtual_api::virtual_rgbinvoice))
         .route("/virtual_sendpayment", post(crate::virtual_api::virtual_sendpayment))
+        .route("/virtual_lninvoice", post(crate::virtual_api::virtual_lninvoice))
         .route("/virtual_assetbalance", post(crate::virtual_api::virtual_assetbalance))
         .route("/virtual_transfer", post(crate::routes::virtual_transfer))
-        .route("/webhook/payment", post(crate::routes::payment_webhook))
+        .route("/virtual_withdraw", post(crate::routes::virtual_withdraw))
         // Test endpoints (for development/testing only)
         .route("/test/add_utxo", post(crate::test_utils::add_test_utxo))
         .route("/test/add_address", post(crate::test_utils::add_test_address))
         // Telegram bot integration routes
         .nest("/telegram", user_api_routes())
+        .nest("/hub", hub_router)
+        .layer(middleware::from_fn_with_state(
+            app_state.static_state.metrics.clone(),
+            track_http_metrics,
+        ))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|request: &Request<_>| {
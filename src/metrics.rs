@@ -0,0 +1,511 @@
+//! Prometheus metrics.
+//!
+//! Counters and histograms updated while the node runs (payments, HTTP requests) are kept in
+//! [`Metrics`], while channel, RGB asset and swap gauges are read from the unlocked node when
+//! `/metrics` is scraped. Everything is rendered in the Prometheus text exposition format.
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use lightning::ln::channel_state::ChannelShutdownState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::rgb::get_rgb_channel_info_optional;
+use crate::routes::SwapStatus;
+use crate::utils::UnlockedAppState;
+
+/// Content type of the Prometheus text exposition format
+pub(crate) const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds, in seconds, of the HTTP request latency histogram buckets
+const HTTP_LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const SWAP_STATUSES: [SwapStatus; 5] = [
+    SwapStatus::Waiting,
+    SwapStatus::Pending,
+    SwapStatus::Succeeded,
+    SwapStatus::Expired,
+    SwapStatus::Failed,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PaymentDirection {
+    Inbound,
+    Outbound,
+}
+
+impl PaymentDirection {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Inbound => "inbound",
+            Self::Outbound => "outbound",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PaymentOutcome {
+    Succeeded,
+    Failed,
+}
+
+impl PaymentOutcome {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SwapRole {
+    Maker,
+    Taker,
+}
+
+impl SwapRole {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Maker => "maker",
+            Self::Taker => "taker",
+        }
+    }
+}
+
+fn swap_status_label(status: &SwapStatus) -> &'static str {
+    match status {
+        SwapStatus::Waiting => "waiting",
+        SwapStatus::Pending => "pending",
+        SwapStatus::Succeeded => "succeeded",
+        SwapStatus::Expired => "expired",
+        SwapStatus::Failed => "failed",
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// Cumulative count for each of [`HTTP_LATENCY_BUCKETS`]
+    buckets: [u64; HTTP_LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, upper_bound) in self.buckets.iter_mut().zip(HTTP_LATENCY_BUCKETS) {
+            if value <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Metrics updated while the node runs
+#[derive(Default)]
+pub(crate) struct Metrics {
+    payments: Mutex<BTreeMap<(PaymentDirection, PaymentOutcome), u64>>,
+    /// Latency histograms by method and matched route
+    http_latency: Mutex<HashMap<(String, String), Histogram>>,
+    /// Request counts by method, matched route and status code
+    http_requests: Mutex<HashMap<(String, String, u16), u64>>,
+}
+
+/// Channel state read when metrics are scraped
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ChannelMetrics {
+    pub(crate) status: &'static str,
+    pub(crate) usable: bool,
+    pub(crate) capacity_sat: u64,
+    pub(crate) outbound_balance_msat: u64,
+    pub(crate) inbound_balance_msat: u64,
+    pub(crate) pending_inbound_htlcs: usize,
+    pub(crate) pending_outbound_htlcs: usize,
+    pub(crate) rgb: Option<RgbChannelMetrics>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct RgbChannelMetrics {
+    pub(crate) contract_id: String,
+    pub(crate) local_amount: u64,
+    pub(crate) remote_amount: u64,
+}
+
+/// Node state read when metrics are scraped, only available while the node is unlocked
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NodeMetrics {
+    pub(crate) channels: Vec<ChannelMetrics>,
+    pub(crate) swaps: BTreeMap<(SwapRole, &'static str), u64>,
+}
+
+impl NodeMetrics {
+    pub(crate) fn collect(unlocked_state: &UnlockedAppState, ldk_data_dir: &Path) -> Self {
+        let channels = unlocked_state
+            .channel_manager
+            .list_channels()
+            .into_iter()
+            .map(|chan_info| {
+                let status = match chan_info.channel_shutdown_state {
+                    Some(ChannelShutdownState::NotShuttingDown) | None => {
+                        if chan_info.is_channel_ready {
+                            "opened"
+                        } else {
+                            "opening"
+                        }
+                    }
+                    _ => "closing",
+                };
+                let rgb = get_rgb_channel_info_optional(&chan_info.channel_id, ldk_data_dir, false)
+                    .map(|(rgb_info, _)| RgbChannelMetrics {
+                        contract_id: rgb_info.contract_id.to_string(),
+                        local_amount: rgb_info.local_rgb_amount,
+                        remote_amount: rgb_info.remote_rgb_amount,
+                    });
+                ChannelMetrics {
+                    status,
+                    usable: chan_info.is_usable,
+                    capacity_sat: chan_info.channel_value_satoshis,
+                    outbound_balance_msat: chan_info.outbound_capacity_msat,
+                    inbound_balance_msat: chan_info.inbound_capacity_msat,
+                    pending_inbound_htlcs: chan_info.pending_inbound_htlcs.len(),
+                    pending_outbound_htlcs: chan_info.pending_outbound_htlcs.len(),
+                    rgb,
+                }
+            })
+            .collect();
+
+        let mut node_metrics = NodeMetrics {
+            channels,
+            ..Default::default()
+        };
+        for swap in unlocked_state.get_maker_swaps().swaps.values() {
+            node_metrics.add_swap(SwapRole::Maker, &swap.status);
+        }
+        for swap in unlocked_state.get_taker_swaps().swaps.values() {
+            node_metrics.add_swap(SwapRole::Taker, &swap.status);
+        }
+        node_metrics
+    }
+
+    pub(crate) fn add_swap(&mut self, role: SwapRole, status: &SwapStatus) {
+        *self
+            .swaps
+            .entry((role, swap_status_label(status)))
+            .or_default() += 1;
+    }
+}
+
+impl Metrics {
+    pub(crate) fn record_payment(&self, direction: PaymentDirection, outcome: PaymentOutcome) {
+        *self
+            .payments
+            .lock()
+            .unwrap()
+            .entry((direction, outcome))
+            .or_default() += 1;
+    }
+
+    pub(crate) fn record_http_request(
+        &self,
+        method: &str,
+        route: &str,
+        status: u16,
+        latency_secs: f64,
+    ) {
+        self.http_latency
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(latency_secs);
+        *self
+            .http_requests
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub(crate) fn render(&self, node: Option<&NodeMetrics>) -> String {
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "rln_unlocked",
+            "gauge",
+            "Whether the node is unlocked",
+        );
+        writeln!(out, "rln_unlocked {}", node.is_some() as u8).unwrap();
+
+        if let Some(node) = node {
+            render_node(&mut out, node);
+        }
+
+        write_header(
+            &mut out,
+            "rln_payments_total",
+            "counter",
+            "Lightning payments by direction and outcome",
+        );
+        let payments = self.payments.lock().unwrap();
+        for direction in [PaymentDirection::Inbound, PaymentDirection::Outbound] {
+            for outcome in [PaymentOutcome::Succeeded, PaymentOutcome::Failed] {
+                let count = payments
+                    .get(&(direction, outcome))
+                    .copied()
+                    .unwrap_or_default();
+                writeln!(
+                    out,
+                    "rln_payments_total{{direction=\"{}\",outcome=\"{}\"}} {count}",
+                    direction.label(),
+                    outcome.label()
+                )
+                .unwrap();
+            }
+        }
+        drop(payments);
+
+        write_header(
+            &mut out,
+            "rln_http_requests_total",
+            "counter",
+            "HTTP requests by method, route and status code",
+        );
+        let http_requests = self.http_requests.lock().unwrap();
+        let mut requests: Vec<_> = http_requests.iter().collect();
+        requests.sort();
+        for ((method, route, status), count) in requests {
+            writeln!(
+                out,
+                "rln_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{status}\"}} {count}",
+                escape_label(method),
+                escape_label(route)
+            )
+            .unwrap();
+        }
+        drop(http_requests);
+
+        write_header(
+            &mut out,
+            "rln_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by method and route",
+        );
+        let http_latency = self.http_latency.lock().unwrap();
+        let mut latencies: Vec<_> = http_latency.iter().collect();
+        latencies.sort_by(|a, b| a.0.cmp(b.0));
+        for ((method, route), histogram) in latencies {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\"",
+                escape_label(method),
+                escape_label(route)
+            );
+            for (upper_bound, count) in HTTP_LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                writeln!(
+                    out,
+                    "rln_http_request_duration_seconds_bucket{{{labels},le=\"{upper_bound}\"}} {count}"
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "rln_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "rln_http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "rln_http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+fn render_node(out: &mut String, node: &NodeMetrics) {
+    write_header(out, "rln_channels", "gauge", "Channels by status");
+    for status in ["opening", "opened", "closing"] {
+        let count = node.channels.iter().filter(|c| c.status == status).count();
+        writeln!(out, "rln_channels{{status=\"{status}\"}} {count}").unwrap();
+    }
+    write_header(
+        out,
+        "rln_channels_usable",
+        "gauge",
+        "Channels usable to send payments",
+    );
+    writeln!(
+        out,
+        "rln_channels_usable {}",
+        node.channels.iter().filter(|c| c.usable).count()
+    )
+    .unwrap();
+
+    let channel_sums: [(&str, &str, fn(&ChannelMetrics) -> u64); 3] = [
+        (
+            "rln_channel_capacity_sat",
+            "Total capacity of the channels in satoshis",
+            |c| c.capacity_sat,
+        ),
+        (
+            "rln_channel_outbound_balance_msat",
+            "Total outbound balance of the channels in millisatoshis",
+            |c| c.outbound_balance_msat,
+        ),
+        (
+            "rln_channel_inbound_balance_msat",
+            "Total inbound balance of the channels in millisatoshis",
+            |c| c.inbound_balance_msat,
+        ),
+    ];
+    for (name, help, value) in channel_sums {
+        write_header(out, name, "gauge", help);
+        writeln!(
+            out,
+            "{name} {}",
+            node.channels.iter().map(value).sum::<u64>()
+        )
+        .unwrap();
+    }
+
+    write_header(
+        out,
+        "rln_pending_htlcs",
+        "gauge",
+        "HTLCs pending in the channels",
+    );
+    for (direction, count) in [
+        (
+            PaymentDirection::Inbound,
+            node.channels
+                .iter()
+                .map(|c| c.pending_inbound_htlcs)
+                .sum::<usize>(),
+        ),
+        (
+            PaymentDirection::Outbound,
+            node.channels
+                .iter()
+                .map(|c| c.pending_outbound_htlcs)
+                .sum::<usize>(),
+        ),
+    ] {
+        writeln!(
+            out,
+            "rln_pending_htlcs{{direction=\"{}\"}} {count}",
+            direction.label()
+        )
+        .unwrap();
+    }
+
+    // (channels, local amount, remote amount) by contract ID
+    let mut rgb_amounts: BTreeMap<&str, (u64, u64, u64)> = BTreeMap::new();
+    for rgb in node.channels.iter().filter_map(|c| c.rgb.as_ref()) {
+        let amounts = rgb_amounts.entry(&rgb.contract_id).or_default();
+        amounts.0 += 1;
+        amounts.1 += rgb.local_amount;
+        amounts.2 += rgb.remote_amount;
+    }
+    write_header(out, "rln_rgb_channels", "gauge", "RGB channels by asset");
+    for (contract_id, (channels, _, _)) in &rgb_amounts {
+        writeln!(
+            out,
+            "rln_rgb_channels{{contract_id=\"{contract_id}\"}} {channels}"
+        )
+        .unwrap();
+    }
+    write_header(
+        out,
+        "rln_rgb_channel_local_amount",
+        "gauge",
+        "Local RGB amount in the channels by asset",
+    );
+    for (contract_id, (_, local, _)) in &rgb_amounts {
+        writeln!(
+            out,
+            "rln_rgb_channel_local_amount{{contract_id=\"{contract_id}\"}} {local}"
+        )
+        .unwrap();
+    }
+    write_header(
+        out,
+        "rln_rgb_channel_remote_amount",
+        "gauge",
+        "Remote RGB amount in the channels by asset",
+    );
+    for (contract_id, (_, _, remote)) in &rgb_amounts {
+        writeln!(
+            out,
+            "rln_rgb_channel_remote_amount{{contract_id=\"{contract_id}\"}} {remote}"
+        )
+        .unwrap();
+    }
+
+    write_header(out, "rln_swaps", "gauge", "Swaps by role and status");
+    for role in [SwapRole::Maker, SwapRole::Taker] {
+        for status in &SWAP_STATUSES {
+            let status = swap_status_label(status);
+            let count = node.swaps.get(&(role, status)).copied().unwrap_or_default();
+            writeln!(
+                out,
+                "rln_swaps{{role=\"{}\",status=\"{status}\"}} {count}",
+                role.label()
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {metric_type}").unwrap();
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Middleware recording the latency of each request, labeled with its matched route
+pub(crate) async fn track_http_metrics(
+    State(metrics): State<Arc<Metrics>>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started_at = Instant::now();
+    let method = request.method().to_string();
+    let route = matched_path
+        .as_ref()
+        .map(MatchedPath::as_str)
+        .unwrap_or("unmatched")
+        .to_string();
+    let response = next.run(request).await;
+    metrics.record_http_request(
+        &method,
+        &route,
+        response.status().as_u16(),
+        started_at.elapsed().as_secs_f64(),
+    );
+    response
+}
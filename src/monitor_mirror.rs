@@ -0,0 +1,643 @@
+//! Mirroring of channel monitors to a secondary store.
+//!
+//! [`MirroringPersister`] wraps the LDK persister and, after every successful local write of a
+//! `ChannelMonitor`, synchronously writes a full copy of the monitor to a
+//! [`MonitorMirrorStore`], so the latest state survives the loss of the node data dir. Mirrored
+//! copies are encrypted with a key derived from the node password as done for backups.
+//!
+//! When the mirror is required, updates whose mirror write fails are reported to LDK as in
+//! progress, which pauses the channel, and their writes are retried until the store is back.
+
+use amplify::s;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lightning::chain::chainmonitor::Persist;
+use lightning::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
+use lightning::chain::transaction::OutPoint;
+use lightning::chain::ChannelMonitorUpdateStatus;
+use lightning::sign::ecdsa::EcdsaChannelSigner;
+use lightning::util::ser::Writeable;
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use crate::backup::BackupCipher;
+use crate::utils::hex_str;
+
+/// Environment variable holding the directory monitors are mirrored to
+pub(crate) const MONITOR_MIRROR_DIR_ENV: &str = "MONITOR_MIRROR_DIR";
+/// Environment variable holding the S3 endpoint monitors are mirrored to
+pub(crate) const MONITOR_MIRROR_S3_ENDPOINT_ENV: &str = "MONITOR_MIRROR_S3_ENDPOINT";
+/// Environment variable holding the S3 bucket name
+pub(crate) const MONITOR_MIRROR_S3_BUCKET_ENV: &str = "MONITOR_MIRROR_S3_BUCKET";
+/// Environment variable holding the S3 region, `us-east-1` if not set
+pub(crate) const MONITOR_MIRROR_S3_REGION_ENV: &str = "MONITOR_MIRROR_S3_REGION";
+/// Environment variable holding the S3 access key ID
+pub(crate) const MONITOR_MIRROR_S3_ACCESS_KEY_ENV: &str = "MONITOR_MIRROR_S3_ACCESS_KEY";
+/// Environment variable holding the S3 secret access key
+pub(crate) const MONITOR_MIRROR_S3_SECRET_KEY_ENV: &str = "MONITOR_MIRROR_S3_SECRET_KEY";
+/// Environment variable holding the prefix of the S3 object keys
+pub(crate) const MONITOR_MIRROR_S3_PREFIX_ENV: &str = "MONITOR_MIRROR_S3_PREFIX";
+/// Environment variable that, when `true`, makes mirroring failures pause the channels
+pub(crate) const MONITOR_MIRROR_REQUIRED_ENV: &str = "MONITOR_MIRROR_REQUIRED";
+
+/// Interval between retries of the mirror writes channel updates wait for
+pub(crate) const MONITOR_MIRROR_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+const DEFAULT_S3_REGION: &str = "us-east-1";
+const S3_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+const MIRROR_SALT_KEY: &str = "mirror.salt";
+const MIRROR_CHECK_KEY: &str = "mirror.check";
+const MIRROR_CHECK_PLAINTEXT: &[u8] = b"rln-monitor-mirror";
+pub(crate) const MONITORS_PREFIX: &str = "monitors/";
+pub(crate) const ARCHIVED_MONITORS_PREFIX: &str = "archived_monitors/";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MirrorError {
+    #[error("Monitor mirror encryption error: {0}")]
+    Encryption(String),
+    #[error("Invalid monitor mirror config: {0}")]
+    InvalidConfig(String),
+    #[error("Monitor mirror I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Monitor mirror request failed: {0}")]
+    Request(String),
+}
+
+/// Secondary store holding mirrored channel monitors
+///
+/// Calls are blocking, as LDK persists monitors synchronously.
+pub(crate) trait MonitorMirrorStore: Send + Sync {
+    /// Write an object, replacing any existing one
+    fn write(&self, key: &str, data: &[u8]) -> Result<(), MirrorError>;
+
+    /// Read an object, `None` if it doesn't exist
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, MirrorError>;
+
+    /// Remove an object, doing nothing if it doesn't exist
+    fn remove(&self, key: &str) -> Result<(), MirrorError>;
+}
+
+/// Store keeping mirrored monitors in a local directory, e.g. on a separate disk or mount
+pub(crate) struct LocalDirMirrorStore {
+    dir: PathBuf,
+}
+
+impl LocalDirMirrorStore {
+    pub(crate) fn new(dir: PathBuf) -> Result<Self, MirrorError> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, MirrorError> {
+        let mut path = self.dir.clone();
+        for part in key.split('/') {
+            if part.is_empty() || part == "." || part == ".." {
+                return Err(MirrorError::InvalidConfig(format!("invalid key '{key}'")));
+            }
+            path.push(part);
+        }
+        Ok(path)
+    }
+}
+
+impl MonitorMirrorStore for LocalDirMirrorStore {
+    fn write(&self, key: &str, data: &[u8]) -> Result<(), MirrorError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, MirrorError> {
+        match fs::read(self.path(key)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn remove(&self, key: &str) -> Result<(), MirrorError> {
+        match fs::remove_file(self.path(key)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Connection details of an S3-compatible bucket
+#[derive(Clone, Debug)]
+pub(crate) struct S3Config {
+    pub(crate) endpoint: String,
+    pub(crate) bucket: String,
+    pub(crate) region: String,
+    pub(crate) access_key: String,
+    pub(crate) secret_key: String,
+    pub(crate) prefix: String,
+}
+
+/// Store keeping mirrored monitors in an S3-compatible bucket (AWS S3, MinIO, ...)
+///
+/// Objects are addressed path-style (`<endpoint>/<bucket>/<prefix><key>`) and requests are
+/// signed with AWS signature version 4. Requests run on a dedicated runtime, so the store can be
+/// called from synchronous code running on the node runtime.
+pub(crate) struct S3MirrorStore {
+    config: S3Config,
+    client: reqwest::Client,
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl S3MirrorStore {
+    pub(crate) fn new(config: S3Config) -> Result<Self, MirrorError> {
+        Url::parse(&config.endpoint)
+            .map_err(|e| MirrorError::InvalidConfig(format!("invalid S3 endpoint: {e}")))?;
+        if config.bucket.is_empty() {
+            return Err(MirrorError::InvalidConfig(s!("missing S3 bucket")));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(S3_REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| MirrorError::InvalidConfig(e.to_string()))?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("monitor-mirror")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            config,
+            client,
+            runtime: Some(runtime),
+        })
+    }
+
+    fn object_url(&self, key: &str) -> Result<Url, MirrorError> {
+        let object = format!("{}{}", self.config.prefix, key);
+        let path = std::iter::once(self.config.bucket.as_str())
+            .chain(object.split('/'))
+            .map(uri_encode)
+            .collect::<Vec<_>>()
+            .join("/");
+        Url::parse(&format!(
+            "{}/{}",
+            self.config.endpoint.trim_end_matches('/'),
+            path
+        ))
+        .map_err(|e| MirrorError::InvalidConfig(format!("invalid S3 object URL: {e}")))
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Vec<u8>,
+    ) -> Result<(StatusCode, Vec<u8>), MirrorError> {
+        let url = self.object_url(key)?;
+        let payload_hash = hex_str(&Sha256::digest(&body));
+        let amz_date = Utc::now();
+        let authorization = s3_authorization(
+            &self.config,
+            method.as_str(),
+            &url,
+            &[],
+            &payload_hash,
+            amz_date,
+        );
+        let request = self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date.format("%Y%m%dT%H%M%SZ").to_string())
+            .header("authorization", authorization)
+            .body(body);
+
+        let (tx, rx) = mpsc::channel();
+        self.runtime
+            .as_ref()
+            .expect("runtime is only taken on drop")
+            .spawn(async move {
+                let res = async {
+                    let response = request.send().await?;
+                    let status = response.status();
+                    let body = response.bytes().await?;
+                    Ok::<_, reqwest::Error>((status, body.to_vec()))
+                }
+                .await;
+                let _ = tx.send(res);
+            });
+        rx.recv()
+            .map_err(|e| MirrorError::Request(e.to_string()))?
+            .map_err(|e| MirrorError::Request(e.to_string()))
+    }
+}
+
+impl Drop for S3MirrorStore {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl MonitorMirrorStore for S3MirrorStore {
+    fn write(&self, key: &str, data: &[u8]) -> Result<(), MirrorError> {
+        let (status, body) = self.send(Method::PUT, key, data.to_vec())?;
+        if !status.is_success() {
+            return Err(MirrorError::Request(format!(
+                "PUT {key} returned {status}: {}",
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        Ok(())
+    }
+
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, MirrorError> {
+        let (status, body) = self.send(Method::GET, key, vec![])?;
+        match status {
+            StatusCode::NOT_FOUND => Ok(None),
+            s if s.is_success() => Ok(Some(body)),
+            s => Err(MirrorError::Request(format!(
+                "GET {key} returned {s}: {}",
+                String::from_utf8_lossy(&body)
+            ))),
+        }
+    }
+
+    fn remove(&self, key: &str) -> Result<(), MirrorError> {
+        let (status, body) = self.send(Method::DELETE, key, vec![])?;
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            return Err(MirrorError::Request(format!(
+                "DELETE {key} returned {status}: {}",
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Encode a URI path segment as required by AWS signature version 4
+fn uri_encode(segment: &str) -> String {
+    let mut res = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{b:02X}"));
+        }
+    }
+    res
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Compute the AWS signature version 4 `Authorization` header of an S3 request
+///
+/// `host`, `x-amz-content-sha256` and `x-amz-date` are always signed, `extra_headers` (lowercase
+/// names) are signed as well. The URL must not have a query string.
+pub(crate) fn s3_authorization(
+    config: &S3Config,
+    method: &str,
+    url: &Url,
+    extra_headers: &[(&str, &str)],
+    payload_hash: &str,
+    amz_date: DateTime<Utc>,
+) -> String {
+    let date = amz_date.format("%Y%m%d").to_string();
+    let timestamp = amz_date.format("%Y%m%dT%H%M%SZ").to_string();
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => String::new(),
+    };
+
+    let mut headers = vec![
+        ("host", host.as_str()),
+        ("x-amz-content-sha256", payload_hash),
+        ("x-amz-date", timestamp.as_str()),
+    ];
+    headers.extend_from_slice(extra_headers);
+    headers.sort_by_key(|(name, _)| *name);
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{method}\n{}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+        url.path()
+    );
+
+    let scope = format!("{date}/{}/s3/aws4_request", config.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        hex_str(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signing_key = [config.region.as_bytes(), b"s3", b"aws4_request"]
+        .iter()
+        .fold(
+            hmac_sha256(
+                format!("AWS4{}", config.secret_key).as_bytes(),
+                date.as_bytes(),
+            ),
+            |key, part| hmac_sha256(&key, part),
+        );
+    let signature = hex_str(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        config.access_key
+    )
+}
+
+/// Mirror store configuration loaded at startup
+#[derive(Clone)]
+pub(crate) struct MonitorMirrorConfig {
+    pub(crate) store: Arc<dyn MonitorMirrorStore>,
+    pub(crate) required: bool,
+}
+
+/// Load the mirror store configuration from the environment, if a store is configured
+pub(crate) fn monitor_mirror_config_from_env() -> Result<Option<MonitorMirrorConfig>, MirrorError> {
+    let required = std::env::var(MONITOR_MIRROR_REQUIRED_ENV).is_ok_and(|v| v == "true");
+    let dir = std::env::var(MONITOR_MIRROR_DIR_ENV).ok();
+    let endpoint = std::env::var(MONITOR_MIRROR_S3_ENDPOINT_ENV).ok();
+    let store: Arc<dyn MonitorMirrorStore> = match (dir, endpoint) {
+        (Some(_), Some(_)) => return Err(MirrorError::InvalidConfig(format!(
+            "only one of {MONITOR_MIRROR_DIR_ENV} and {MONITOR_MIRROR_S3_ENDPOINT_ENV} can be set"
+        ))),
+        (Some(dir), None) => Arc::new(LocalDirMirrorStore::new(PathBuf::from(dir))?),
+        (None, Some(endpoint)) => {
+            let var = |name: &str| {
+                std::env::var(name)
+                    .map_err(|_| MirrorError::InvalidConfig(format!("{name} not set")))
+            };
+            Arc::new(S3MirrorStore::new(S3Config {
+                endpoint,
+                bucket: var(MONITOR_MIRROR_S3_BUCKET_ENV)?,
+                region: std::env::var(MONITOR_MIRROR_S3_REGION_ENV)
+                    .unwrap_or_else(|_| DEFAULT_S3_REGION.to_string()),
+                access_key: var(MONITOR_MIRROR_S3_ACCESS_KEY_ENV)?,
+                secret_key: var(MONITOR_MIRROR_S3_SECRET_KEY_ENV)?,
+                prefix: std::env::var(MONITOR_MIRROR_S3_PREFIX_ENV).unwrap_or_default(),
+            })?)
+        }
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(MonitorMirrorConfig { store, required }))
+}
+
+/// Mirror store opened with the key derived from the node password
+pub(crate) struct MonitorMirror {
+    store: Arc<dyn MonitorMirrorStore>,
+    cipher: BackupCipher,
+    required: bool,
+}
+
+impl MonitorMirror {
+    /// Open the mirror, deriving the key from the password and the salt kept in the store
+    ///
+    /// A new salt is generated when the store is empty. A store written with a different password
+    /// can't be read with this one: a required mirror fails to open, so the node doesn't start
+    /// without a usable copy of its monitors, otherwise a new salt is generated. Live monitors
+    /// are mirrored again when they're loaded at startup, archived copies stay under the old key.
+    pub(crate) fn open(config: &MonitorMirrorConfig, password: &str) -> Result<Self, MirrorError> {
+        let store = config.store.clone();
+        if let Some(salt) = store.read(MIRROR_SALT_KEY)? {
+            let salt = String::from_utf8(salt)
+                .map_err(|e| MirrorError::Encryption(format!("invalid salt: {e}")))?;
+            let cipher = BackupCipher::new(password, &salt)
+                .map_err(|e| MirrorError::Encryption(e.to_string()))?;
+            let check = store.read(MIRROR_CHECK_KEY)?;
+            if check.is_some_and(|c| {
+                cipher
+                    .decrypt(&c)
+                    .is_ok_and(|plaintext| plaintext == MIRROR_CHECK_PLAINTEXT)
+            }) {
+                return Ok(Self {
+                    store,
+                    cipher,
+                    required: config.required,
+                });
+            }
+            if config.required {
+                return Err(MirrorError::Encryption(s!(
+                    "the mirror was written with a different password, clear the store to start \
+                     a new mirror"
+                )));
+            }
+            tracing::warn!(
+                "Monitor mirror was written with a different password, rotating key, archived \
+                 monitors in the store are left encrypted with the old key"
+            );
+        }
+
+        let salt = BackupCipher::generate_salt();
+        let cipher = BackupCipher::new(password, &salt)
+            .map_err(|e| MirrorError::Encryption(e.to_string()))?;
+        let check = cipher
+            .encrypt(MIRROR_CHECK_PLAINTEXT)
+            .map_err(|e| MirrorError::Encryption(e.to_string()))?;
+        store.write(MIRROR_SALT_KEY, salt.as_bytes())?;
+        store.write(MIRROR_CHECK_KEY, &check)?;
+        Ok(Self {
+            store,
+            cipher,
+            required: config.required,
+        })
+    }
+
+    /// Encrypt and write the serialized monitor of the given channel
+    pub(crate) fn mirror_monitor(
+        &self,
+        funding_outpoint: OutPoint,
+        monitor: &[u8],
+    ) -> Result<(), MirrorError> {
+        let encrypted = self
+            .cipher
+            .encrypt(monitor)
+            .map_err(|e| MirrorError::Encryption(e.to_string()))?;
+        self.store
+            .write(&monitor_key(MONITORS_PREFIX, funding_outpoint), &encrypted)
+    }
+
+    /// Move the mirrored monitor of a closed channel to the archive
+    pub(crate) fn archive_monitor(&self, funding_outpoint: OutPoint) -> Result<(), MirrorError> {
+        let key = monitor_key(MONITORS_PREFIX, funding_outpoint);
+        if let Some(encrypted) = self.store.read(&key)? {
+            self.store.write(
+                &monitor_key(ARCHIVED_MONITORS_PREFIX, funding_outpoint),
+                &encrypted,
+            )?;
+            self.store.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Key of a mirrored monitor, named as LDK names persisted monitors
+pub(crate) fn monitor_key(prefix: &str, funding_outpoint: OutPoint) -> String {
+    format!(
+        "{prefix}{}_{}",
+        funding_outpoint.txid, funding_outpoint.index
+    )
+}
+
+/// Latest monitor of a channel whose updates wait for a required mirror write
+struct PendingMirror {
+    monitor: Vec<u8>,
+    update_id: u64,
+}
+
+/// Persister mirroring every monitor written by the wrapped persister
+pub(crate) struct MirroringPersister<P: Deref> {
+    inner: P,
+    mirror: Option<MonitorMirror>,
+    pending: Mutex<HashMap<OutPoint, PendingMirror>>,
+}
+
+impl<P: Deref> MirroringPersister<P> {
+    pub(crate) fn new(inner: P, mirror: Option<MonitorMirror>) -> Self {
+        Self {
+            inner,
+            mirror,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn mirror<ChannelSigner: EcdsaChannelSigner>(
+        &self,
+        funding_outpoint: OutPoint,
+        monitor: &ChannelMonitor<ChannelSigner>,
+        status: ChannelMonitorUpdateStatus,
+    ) -> ChannelMonitorUpdateStatus {
+        let Some(mirror) = &self.mirror else {
+            return status;
+        };
+        if status != ChannelMonitorUpdateStatus::Completed {
+            return status;
+        }
+        let encoded = monitor.encode();
+        let mut pending = self.pending.lock().unwrap();
+        // later updates of a waiting channel complete with it, once its latest monitor is written
+        if let Some(waiting) = pending.get_mut(&funding_outpoint) {
+            *waiting = PendingMirror {
+                monitor: encoded,
+                update_id: monitor.get_latest_update_id(),
+            };
+            return ChannelMonitorUpdateStatus::InProgress;
+        }
+        match mirror.mirror_monitor(funding_outpoint, &encoded) {
+            Ok(()) => status,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to mirror monitor {}: {e}",
+                    monitor_key("", funding_outpoint)
+                );
+                if mirror.required {
+                    pending.insert(
+                        funding_outpoint,
+                        PendingMirror {
+                            monitor: encoded,
+                            update_id: monitor.get_latest_update_id(),
+                        },
+                    );
+                    ChannelMonitorUpdateStatus::InProgress
+                } else {
+                    status
+                }
+            }
+        }
+    }
+
+    /// Retry the mirror writes channel updates wait for
+    ///
+    /// Returns the channels whose latest monitor got written, with the ID of the update to
+    /// complete through `ChainMonitor::channel_monitor_updated`. Blocks on the store.
+    pub(crate) fn retry_pending(&self) -> Vec<(OutPoint, u64)> {
+        let Some(mirror) = &self.mirror else {
+            return vec![];
+        };
+        let mut completed = vec![];
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|funding_outpoint, waiting| {
+                match mirror.mirror_monitor(*funding_outpoint, &waiting.monitor) {
+                    Ok(()) => {
+                        completed.push((*funding_outpoint, waiting.update_id));
+                        false
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed again to mirror monitor {}: {e}",
+                            monitor_key("", *funding_outpoint)
+                        );
+                        true
+                    }
+                }
+            });
+        completed
+    }
+}
+
+impl<ChannelSigner: EcdsaChannelSigner, P: Deref> Persist<ChannelSigner> for MirroringPersister<P>
+where
+    P::Target: Persist<ChannelSigner>,
+{
+    fn persist_new_channel(
+        &self,
+        funding_outpoint: OutPoint,
+        monitor: &ChannelMonitor<ChannelSigner>,
+    ) -> ChannelMonitorUpdateStatus {
+        let status = self.inner.persist_new_channel(funding_outpoint, monitor);
+        self.mirror(funding_outpoint, monitor, status)
+    }
+
+    fn update_persisted_channel(
+        &self,
+        funding_outpoint: OutPoint,
+        monitor_update: Option<&ChannelMonitorUpdate>,
+        monitor: &ChannelMonitor<ChannelSigner>,
+    ) -> ChannelMonitorUpdateStatus {
+        let status = self
+            .inner
+            .update_persisted_channel(funding_outpoint, monitor_update, monitor);
+        self.mirror(funding_outpoint, monitor, status)
+    }
+
+    fn archive_persisted_channel(&self, funding_outpoint: OutPoint) {
+        self.inner.archive_persisted_channel(funding_outpoint);
+        self.pending.lock().unwrap().remove(&funding_outpoint);
+        if let Some(mirror) = &self.mirror {
+            if let Err(e) = mirror.archive_monitor(funding_outpoint) {
+                tracing::error!(
+                    "Failed to archive mirrored monitor {}: {e}",
+                    monitor_key("", funding_outpoint)
+                );
+            }
+        }
+    }
+}
@@ -112,24 +112,6 @@ impl MultiUserRgbManager {
         // Would implement cleanup logic based on last access time
         tracing::info!("Cleaning up inactive user wallets older than {} minutes", max_idle_minutes);
     }
-
-    /// Backup user wallet data
-    pub async fn backup_user_wallet(&self, user_id: &str, backup_path: &str) -> Result<(), AppError> {
-        let user_wallet_dir = self.base_data_dir.join("users").join(user_id);
-        
-        // Would implement backup logic
-        tracing::info!("Backing up wallet for user {} to {}", user_id, backup_path);
-        Ok(())
-    }
-
-    /// Restore user wallet from backup
-    pub async fn restore_user_wallet(&self, user_id: &str, backup_path: &str) -> Result<(), AppError> {
-        let user_wallet_dir = self.base_data_dir.join("users").join(user_id);
-        
-        // Would implement restore logic
-        tracing::info!("Restoring wallet for user {} from {}", user_id, backup_path);
-        Ok(())
-    }
 }
 
 #[derive(Debug, Clone)]
@@ -125,35 +125,6 @@ pub async fn multi_user_asset_balance(
     }))
 }
 
-/// User quota check middleware
-pub async fn quota_check_middleware(
-    State(state): State<Arc<AppState>>,
-    request: Request,
-    next: Next,
-) -> Result<Response, StatusCode> {
-    if let Some(claims) = request.extensions().get::<Claims>() {
-        let user_manager = state.user_manager.lock().await;
-        if let Some(user_manager) = user_manager.as_ref() {
-            // Determine action from request path
-            let action = match request.uri().path() {
-                "/openchannel" => "create_channel",
-                "/issueassetnia" | "/issueassetuda" | "/issueassetcfa" => "create_asset",
-                "/sendpayment" | "/sendasset" => "transaction",
-                _ => "general",
-            };
-
-            // Check quota
-            match user_manager.check_user_quota(&claims.user_id, action).await {
-                Ok(true) => {}, // Quota check passed
-                Ok(false) => return Err(StatusCode::TOO_MANY_REQUESTS),
-                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-            }
-        }
-    }
-
-    Ok(next.run(request).await)
-}
-
 /// Admin-only endpoint for user management
 pub async fn admin_list_users(
     State(state): State<Arc<AppState>>,
@@ -204,18 +175,3 @@ pub struct UserSummary {
     pub total_transactions: u32,
     pub wallet_size_bytes: u64,
 }
-
-/// Rate limiting middleware per user
-pub async fn rate_limit_middleware(
-    State(state): State<Arc<AppState>>,
-    request: Request,
-    next: Next,
-) -> Result<Response, StatusCode> {
-    if let Some(claims) = request.extensions().get::<Claims>() {
-        // Implement rate limiting logic per user
-        // This would typically use Redis or in-memory store
-        tracing::debug!("Rate limiting check for user: {}", claims.user_id);
-    }
-
-    Ok(next.run(request).await)
-}
\ No newline at end of file
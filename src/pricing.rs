@@ -0,0 +1,358 @@
+//! Price sources and the maker price policy applied to maker swaps.
+//!
+//! Prices are expressed in the raw units swaps use: the amount of `to_asset` per unit of
+//! `from_asset`, with BTC counted in msat.
+
+use async_trait::async_trait;
+use rgb_lib::ContractId;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::swap::SwapInfo;
+
+/// Environment variable holding the path of the JSON pricing config
+pub(crate) const PRICING_CONFIG_ENV: &str = "SWAP_PRICING_CONFIG";
+
+const HTTP_PRICE_TIMEOUT_SECS: u64 = 10;
+
+const BPS_DENOMINATOR: f64 = 10_000.0;
+
+/// One side of a swap, `None` for BTC
+pub(crate) type SwapAsset = Option<ContractId>;
+
+fn asset_str(asset: &SwapAsset) -> String {
+    asset
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| "btc".to_string())
+}
+
+fn parse_asset(asset: &str) -> Result<SwapAsset, PricingError> {
+    if asset == "btc" {
+        return Ok(None);
+    }
+    ContractId::from_str(asset)
+        .map(Some)
+        .map_err(|_| PricingError::InvalidConfig(format!("invalid asset '{asset}'")))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum PricingError {
+    #[error("Invalid pricing config: {0}")]
+    InvalidConfig(String),
+    #[error("No price available for {0}")]
+    NoPrice(String),
+    #[error("Swap price {price} is outside the allowed range {min_price} - {max_price}")]
+    OutOfRange {
+        price: f64,
+        min_price: f64,
+        max_price: f64,
+    },
+    #[error("Price source error: {0}")]
+    Source(String),
+}
+
+/// Source of mid prices for swap pairs
+#[async_trait]
+pub(crate) trait PriceSource: Send + Sync {
+    /// Mid price in units of `to_asset` per unit of `from_asset`, `None` if the pair is unknown
+    async fn mid_price(
+        &self,
+        from_asset: SwapAsset,
+        to_asset: SwapAsset,
+    ) -> Result<Option<f64>, PricingError>;
+}
+
+/// Prices fixed in the config, inverse pairs are derived automatically
+pub(crate) struct StaticPriceSource {
+    prices: HashMap<(SwapAsset, SwapAsset), f64>,
+}
+
+impl StaticPriceSource {
+    pub(crate) fn new(prices: HashMap<(SwapAsset, SwapAsset), f64>) -> Self {
+        Self { prices }
+    }
+}
+
+#[async_trait]
+impl PriceSource for StaticPriceSource {
+    async fn mid_price(
+        &self,
+        from_asset: SwapAsset,
+        to_asset: SwapAsset,
+    ) -> Result<Option<f64>, PricingError> {
+        if let Some(price) = self.prices.get(&(from_asset, to_asset)) {
+            return Ok(Some(*price));
+        }
+        Ok(self
+            .prices
+            .get(&(to_asset, from_asset))
+            .map(|price| 1.0 / price))
+    }
+}
+
+#[derive(Deserialize)]
+struct HttpPriceResponse {
+    price: Option<f64>,
+}
+
+/// Prices fetched from an HTTP endpoint
+///
+/// The source GETs `<url>?from=<asset>&to=<asset>`, with assets given as contract IDs or `btc`,
+/// and expects a `{"price": <number or null>}` JSON body. A 404 means the pair is unknown.
+pub(crate) struct HttpPriceSource {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpPriceSource {
+    pub(crate) fn new(url: String) -> Result<Self, PricingError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(HTTP_PRICE_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| PricingError::Source(e.to_string()))?;
+        Ok(Self { url, client })
+    }
+}
+
+#[async_trait]
+impl PriceSource for HttpPriceSource {
+    async fn mid_price(
+        &self,
+        from_asset: SwapAsset,
+        to_asset: SwapAsset,
+    ) -> Result<Option<f64>, PricingError> {
+        let response = self
+            .client
+            .get(&self.url)
+            .query(&[("from", asset_str(&from_asset)), ("to", asset_str(&to_asset))])
+            .send()
+            .await
+            .map_err(|e| PricingError::Source(e.to_string()))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response
+            .error_for_status()
+            .map_err(|e| PricingError::Source(e.to_string()))?
+            .json::<HttpPriceResponse>()
+            .await
+            .map_err(|e| PricingError::Source(format!("invalid price response: {e}")))?;
+        match response.price {
+            Some(price) if !price.is_finite() || price <= 0.0 => Err(PricingError::Source(
+                format!("invalid price {price}"),
+            )),
+            price => Ok(price),
+        }
+    }
+}
+
+/// What to do with a swap priced outside the allowed spread
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PolicyMode {
+    /// Refuse the swap
+    #[default]
+    Reject,
+    /// Change `qty_to` so the swap price is at the nearest end of the allowed range
+    AutoPrice,
+}
+
+/// Spread the maker keeps below the mid price on a pair, in basis points
+///
+/// The maker gives `to_asset`, so the swap price must be between `max_spread_bps` and
+/// `min_spread_bps` below the mid price.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PairSpread {
+    pub(crate) min_spread_bps: u32,
+    pub(crate) max_spread_bps: u32,
+}
+
+/// Checks the price of maker swaps against a price source
+///
+/// Pairs without a configured spread are not checked.
+pub(crate) struct MakerPricePolicy {
+    source: Arc<dyn PriceSource>,
+    mode: PolicyMode,
+    spreads: HashMap<(SwapAsset, SwapAsset), PairSpread>,
+}
+
+impl MakerPricePolicy {
+    pub(crate) fn new(
+        source: Arc<dyn PriceSource>,
+        mode: PolicyMode,
+        spreads: HashMap<(SwapAsset, SwapAsset), PairSpread>,
+    ) -> Self {
+        Self {
+            source,
+            mode,
+            spreads,
+        }
+    }
+
+    /// Allowed price range of a pair, `None` if the pair is not checked
+    async fn price_range(
+        &self,
+        pair: (SwapAsset, SwapAsset),
+    ) -> Result<Option<(f64, f64)>, PricingError> {
+        let Some(spread) = self.spreads.get(&pair) else {
+            return Ok(None);
+        };
+        let mid_price = self
+            .source
+            .mid_price(pair.0, pair.1)
+            .await?
+            .ok_or_else(|| {
+                PricingError::NoPrice(format!("{}/{}", asset_str(&pair.0), asset_str(&pair.1)))
+            })?;
+
+        let min_price = mid_price * (1.0 - spread.max_spread_bps as f64 / BPS_DENOMINATOR);
+        let max_price = mid_price * (1.0 - spread.min_spread_bps as f64 / BPS_DENOMINATOR);
+        Ok(Some((min_price, max_price)))
+    }
+
+    /// Check a swap without ever repricing it, whatever the mode
+    ///
+    /// Used for swaps whose price has already been agreed, like quotes against a standing offer.
+    pub(crate) async fn check(&self, swap_info: &SwapInfo) -> Result<(), PricingError> {
+        let pair = (swap_info.from_asset, swap_info.to_asset);
+        let Some((min_price, max_price)) = self.price_range(pair).await? else {
+            return Ok(());
+        };
+        let price = swap_info.qty_to as f64 / swap_info.qty_from as f64;
+        if (min_price..=max_price).contains(&price) {
+            return Ok(());
+        }
+        Err(PricingError::OutOfRange {
+            price,
+            min_price,
+            max_price,
+        })
+    }
+
+    /// Check a swap, returning it unchanged or auto-priced
+    pub(crate) async fn apply(&self, swap_info: SwapInfo) -> Result<SwapInfo, PricingError> {
+        let pair = (swap_info.from_asset, swap_info.to_asset);
+        let Some((min_price, max_price)) = self.price_range(pair).await? else {
+            return Ok(swap_info);
+        };
+        let price = swap_info.qty_to as f64 / swap_info.qty_from as f64;
+        if (min_price..=max_price).contains(&price) {
+            return Ok(swap_info);
+        }
+
+        let out_of_range = PricingError::OutOfRange {
+            price,
+            min_price,
+            max_price,
+        };
+        match self.mode {
+            PolicyMode::Reject => Err(out_of_range),
+            PolicyMode::AutoPrice => {
+                let target_price = price.clamp(min_price, max_price);
+                let qty_to = (swap_info.qty_from as f64 * target_price).floor();
+                if qty_to < 1.0 || qty_to > u64::MAX as f64 {
+                    return Err(out_of_range);
+                }
+                tracing::info!(
+                    "Auto-pricing swap {}/{} from qty_to {} to {}",
+                    asset_str(&pair.0),
+                    asset_str(&pair.1),
+                    swap_info.qty_to,
+                    qty_to
+                );
+                Ok(SwapInfo {
+                    qty_to: qty_to as u64,
+                    ..swap_info
+                })
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PricingConfig {
+    source: PriceSourceConfig,
+    #[serde(default)]
+    mode: PolicyMode,
+    pairs: Vec<PairConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PriceSourceConfig {
+    Static { prices: Vec<StaticPriceConfig> },
+    Http { url: String },
+}
+
+#[derive(Deserialize)]
+struct StaticPriceConfig {
+    from_asset: String,
+    to_asset: String,
+    price: f64,
+}
+
+#[derive(Deserialize)]
+struct PairConfig {
+    from_asset: String,
+    to_asset: String,
+    min_spread_bps: u32,
+    max_spread_bps: u32,
+}
+
+/// Build the maker price policy from a JSON config
+pub(crate) fn maker_price_policy_from_json(config: &str) -> Result<MakerPricePolicy, PricingError> {
+    let config: PricingConfig =
+        serde_json::from_str(config).map_err(|e| PricingError::InvalidConfig(e.to_string()))?;
+
+    let source: Arc<dyn PriceSource> = match config.source {
+        PriceSourceConfig::Static { prices } => {
+            let mut static_prices = HashMap::new();
+            for p in prices {
+                if !p.price.is_finite() || p.price <= 0.0 {
+                    return Err(PricingError::InvalidConfig(format!(
+                        "invalid price {} for {}/{}",
+                        p.price, p.from_asset, p.to_asset
+                    )));
+                }
+                let pair = (parse_asset(&p.from_asset)?, parse_asset(&p.to_asset)?);
+                static_prices.insert(pair, p.price);
+            }
+            Arc::new(StaticPriceSource::new(static_prices))
+        }
+        PriceSourceConfig::Http { url } => Arc::new(HttpPriceSource::new(url)?),
+    };
+
+    let mut spreads = HashMap::new();
+    for pair in config.pairs {
+        if pair.min_spread_bps > pair.max_spread_bps
+            || pair.max_spread_bps > BPS_DENOMINATOR as u32
+        {
+            return Err(PricingError::InvalidConfig(format!(
+                "invalid spread for {}/{}",
+                pair.from_asset, pair.to_asset
+            )));
+        }
+        spreads.insert(
+            (parse_asset(&pair.from_asset)?, parse_asset(&pair.to_asset)?),
+            PairSpread {
+                min_spread_bps: pair.min_spread_bps,
+                max_spread_bps: pair.max_spread_bps,
+            },
+        );
+    }
+
+    Ok(MakerPricePolicy::new(source, config.mode, spreads))
+}
+
+/// Load the maker price policy from the file at [`PRICING_CONFIG_ENV`], if set
+pub(crate) fn maker_price_policy_from_env() -> Result<Option<MakerPricePolicy>, PricingError> {
+    let Ok(path) = std::env::var(PRICING_CONFIG_ENV) else {
+        return Ok(None);
+    };
+    let config = std::fs::read_to_string(&path)
+        .map_err(|e| PricingError::InvalidConfig(format!("cannot read {path}: {e}")))?;
+    maker_price_policy_from_json(&config).map(Some)
+}
@@ -0,0 +1,292 @@
+//! Per-user quotas of the multi-user API.
+//!
+//! Quotas cap the number of channels and assets of a user, and how much they can send over a
+//! rolling day and a rolling month: transactions, sats and units of each RGB asset. Usage is
+//! recorded in the database when a request is let through, and released if the request fails.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use crate::database::{Database, QuotaError};
+use crate::error::APIError;
+
+/// Length of the daily quota window, in seconds
+pub(crate) const QUOTA_DAY_SECS: i64 = 86_400;
+/// Length of the monthly quota window, in seconds
+pub(crate) const QUOTA_MONTH_SECS: i64 = 30 * QUOTA_DAY_SECS;
+
+/// Limits of a user, `u64::MAX` and `u32::MAX` meaning unlimited
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UserQuotas {
+    pub max_channels: u32,
+    pub max_assets: u32,
+    pub max_transactions_per_day: u32,
+    pub max_transactions_per_month: u32,
+    pub max_balance_btc: u64,
+    pub max_sats_per_day: u64,
+    pub max_sats_per_month: u64,
+    /// Applies to each RGB asset separately
+    pub max_asset_units_per_day: u64,
+    /// Applies to each RGB asset separately
+    pub max_asset_units_per_month: u64,
+}
+
+impl Default for UserQuotas {
+    fn default() -> Self {
+        Self {
+            max_channels: 10,
+            max_assets: 100,
+            max_transactions_per_day: 1000,
+            max_transactions_per_month: 10_000,
+            max_balance_btc: 1_000_000, // 0.01 BTC in sats
+            max_sats_per_day: 1_000_000,
+            max_sats_per_month: 10_000_000,
+            // asset precisions differ, so asset limits are opt-in
+            max_asset_units_per_day: u64::MAX,
+            max_asset_units_per_month: u64::MAX,
+        }
+    }
+}
+
+/// Changes to the quotas of a user, fields left out are kept as they are
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct UserQuotasUpdate {
+    pub max_channels: Option<u32>,
+    pub max_assets: Option<u32>,
+    pub max_transactions_per_day: Option<u32>,
+    pub max_transactions_per_month: Option<u32>,
+    pub max_balance_btc: Option<u64>,
+    pub max_sats_per_day: Option<u64>,
+    pub max_sats_per_month: Option<u64>,
+    pub max_asset_units_per_day: Option<u64>,
+    pub max_asset_units_per_month: Option<u64>,
+}
+
+impl UserQuotasUpdate {
+    pub fn apply(&self, quotas: &mut UserQuotas) {
+        macro_rules! update {
+            ($($field:ident),*) => {
+                $(if let Some(value) = self.$field {
+                    quotas.$field = value;
+                })*
+            };
+        }
+        update!(
+            max_channels,
+            max_assets,
+            max_transactions_per_day,
+            max_transactions_per_month,
+            max_balance_btc,
+            max_sats_per_day,
+            max_sats_per_month,
+            max_asset_units_per_day,
+            max_asset_units_per_month
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QuotaAction {
+    CreateChannel,
+    CreateAsset,
+    Transaction,
+}
+
+impl QuotaAction {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Self::CreateChannel => "create_channel",
+            Self::CreateAsset => "create_asset",
+            Self::Transaction => "transaction",
+        }
+    }
+
+    pub(crate) fn from_label(label: &str) -> Option<Self> {
+        match label {
+            "create_channel" => Some(Self::CreateChannel),
+            "create_asset" => Some(Self::CreateAsset),
+            "transaction" => Some(Self::Transaction),
+            _ => None,
+        }
+    }
+}
+
+/// What a request would use of the quotas of a user
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct QuotaSpend {
+    pub(crate) action: QuotaAction,
+    pub(crate) sats: u64,
+    /// Asset ID and units sent
+    pub(crate) asset: Option<(String, u64)>,
+}
+
+/// Usage recorded for a request that was let through
+#[derive(Clone, Debug)]
+pub(crate) struct QuotaUsageRecord {
+    pub(crate) action: QuotaAction,
+    pub(crate) sats: u64,
+    pub(crate) asset_id: Option<String>,
+    pub(crate) asset_amount: u64,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WindowUsage {
+    pub transactions: u64,
+    pub sats: u64,
+    /// Units sent by asset ID
+    pub asset_units: BTreeMap<String, u64>,
+}
+
+impl WindowUsage {
+    fn add(&mut self, record: &QuotaUsageRecord) {
+        if record.action == QuotaAction::Transaction {
+            self.transactions += 1;
+        }
+        self.sats = self.sats.saturating_add(record.sats);
+        if let Some(asset_id) = &record.asset_id {
+            let units = self.asset_units.entry(asset_id.clone()).or_default();
+            *units = units.saturating_add(record.asset_amount);
+        }
+    }
+}
+
+/// Current usage of the quotas of a user
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub channels: u64,
+    pub assets: u64,
+    pub day: WindowUsage,
+    pub month: WindowUsage,
+}
+
+impl QuotaUsage {
+    /// Sum the usage records of the last month into the daily and monthly windows
+    pub(crate) fn new(
+        channels: u64,
+        assets: u64,
+        records: &[QuotaUsageRecord],
+        now: DateTime<Utc>,
+    ) -> Self {
+        let day_start = now - Duration::seconds(QUOTA_DAY_SECS);
+        let month_start = now - Duration::seconds(QUOTA_MONTH_SECS);
+        let mut usage = Self {
+            channels,
+            assets,
+            ..Default::default()
+        };
+        for record in records.iter().filter(|r| r.created_at > month_start) {
+            usage.month.add(record);
+            if record.created_at > day_start {
+                usage.day.add(record);
+            }
+        }
+        usage
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub(crate) struct QuotaExceeded(pub(crate) String);
+
+/// Check that `spend` fits in what's left of the quotas of a user
+pub(crate) fn check_quota(
+    quotas: &UserQuotas,
+    usage: &QuotaUsage,
+    spend: &QuotaSpend,
+) -> Result<(), QuotaExceeded> {
+    match spend.action {
+        QuotaAction::CreateChannel => {
+            if usage.channels >= quotas.max_channels as u64 {
+                return Err(QuotaExceeded(format!(
+                    "channel limit of {} reached",
+                    quotas.max_channels
+                )));
+            }
+        }
+        QuotaAction::CreateAsset => {
+            if usage.assets >= quotas.max_assets as u64 {
+                return Err(QuotaExceeded(format!(
+                    "asset limit of {} reached",
+                    quotas.max_assets
+                )));
+            }
+        }
+        QuotaAction::Transaction => {
+            for (window, used, max) in [
+                ("daily", &usage.day, quotas.max_transactions_per_day),
+                ("monthly", &usage.month, quotas.max_transactions_per_month),
+            ] {
+                if used.transactions >= max as u64 {
+                    return Err(QuotaExceeded(format!(
+                        "{window} transaction limit of {max} reached"
+                    )));
+                }
+            }
+        }
+    }
+
+    for (window, used, max_sats, max_units) in [
+        (
+            "daily",
+            &usage.day,
+            quotas.max_sats_per_day,
+            quotas.max_asset_units_per_day,
+        ),
+        (
+            "monthly",
+            &usage.month,
+            quotas.max_sats_per_month,
+            quotas.max_asset_units_per_month,
+        ),
+    ] {
+        if spend.sats > 0 && used.sats.saturating_add(spend.sats) > max_sats {
+            return Err(QuotaExceeded(format!(
+                "{window} limit of {max_sats} sats exceeded, {} sats left",
+                max_sats.saturating_sub(used.sats)
+            )));
+        }
+        if let Some((asset_id, units)) = &spend.asset {
+            let used_units = used.asset_units.get(asset_id).copied().unwrap_or_default();
+            if *units > 0 && used_units.saturating_add(*units) > max_units {
+                return Err(QuotaExceeded(format!(
+                    "{window} limit of {max_units} units of asset {asset_id} exceeded, {} units left",
+                    max_units.saturating_sub(used_units)
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run an action within the quotas of a user, releasing its usage if it fails
+pub(crate) async fn with_quota<T, Fut>(
+    database: &Database,
+    user_id: &str,
+    spend: &QuotaSpend,
+    action: Fut,
+) -> Result<T, APIError>
+where
+    Fut: Future<Output = Result<T, APIError>>,
+{
+    let usage_id = database
+        .reserve_quota_usage(user_id, spend)
+        .await
+        .map_err(quota_api_error)?;
+    let result = action.await;
+    if result.is_err() {
+        if let Err(e) = database.release_quota_usage(usage_id).await {
+            tracing::error!("Failed to release quota {usage_id} of user {user_id}: {e}");
+        }
+    }
+    result
+}
+
+fn quota_api_error(e: QuotaError) -> APIError {
+    match e {
+        QuotaError::Exceeded(e) => APIError::QuotaExceeded(e.to_string()),
+        e => APIError::Unexpected(e.to_string()),
+    }
+}
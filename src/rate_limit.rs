@@ -0,0 +1,370 @@
+//! Per-user rate limiting of the multi-user API.
+//!
+//! Every user gets two token buckets sized by their role: one for every request and a stricter
+//! one for expensive bot commands (opening channels and sending assets). Buckets are kept in
+//! memory, or in Postgres so hub replicas behind a load balancer share them.
+
+use async_trait::async_trait;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as TokioMutex;
+
+use crate::database::Database;
+use crate::error::APIError;
+use crate::hub_auth::HubCaller;
+
+/// Environment variable holding the path of the JSON rate limit config
+pub(crate) const RATE_LIMIT_CONFIG_ENV: &str = "RATE_LIMIT_CONFIG";
+
+const EXPENSIVE_BOT_COMMANDS: [&str; 2] = ["create_channel", "send_asset"];
+
+/// Above this many buckets, buckets that refilled completely are dropped
+const MAX_IN_MEMORY_BUCKETS: usize = 100_000;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RateLimitError {
+    #[error("Invalid rate limit config: {0}")]
+    InvalidConfig(String),
+}
+
+/// Role of the user, mirroring the roles in their auth token
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RateLimitRole {
+    Admin,
+    User,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RouteClass {
+    Default,
+    Expensive,
+}
+
+impl RouteClass {
+    /// Class of a command of the bot service, see [`crate::telegram_routes::bot_service_proxy`]
+    pub(crate) fn for_bot_command(command: &str) -> Self {
+        if EXPENSIVE_BOT_COMMANDS.contains(&command) {
+            Self::Expensive
+        } else {
+            Self::Default
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Expensive => "expensive",
+        }
+    }
+}
+
+/// Size of a token bucket
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub(crate) struct BucketLimit {
+    /// Maximum number of requests in a burst
+    pub(crate) capacity: u32,
+    /// Requests allowed per second once the burst is used
+    pub(crate) refill_per_sec: f64,
+}
+
+impl BucketLimit {
+    const fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        Self {
+            capacity,
+            refill_per_sec,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub(crate) struct RoleLimits {
+    pub(crate) default: BucketLimit,
+    pub(crate) expensive: BucketLimit,
+}
+
+impl RoleLimits {
+    fn defaults(role: RateLimitRole) -> Self {
+        match role {
+            RateLimitRole::Admin => Self {
+                default: BucketLimit::new(100, 20.0),
+                expensive: BucketLimit::new(20, 1.0),
+            },
+            RateLimitRole::User => Self {
+                default: BucketLimit::new(30, 5.0),
+                expensive: BucketLimit::new(5, 0.1),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RateLimitBackend {
+    /// Buckets local to this process
+    #[default]
+    Memory,
+    /// Buckets in the hub database, shared by replicas
+    Postgres,
+}
+
+#[derive(Deserialize)]
+struct RateLimitConfigFile {
+    #[serde(default)]
+    backend: RateLimitBackend,
+    #[serde(default)]
+    limits: HashMap<RateLimitRole, RoleLimits>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RateLimitConfig {
+    pub(crate) backend: RateLimitBackend,
+    limits: HashMap<RateLimitRole, RoleLimits>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            backend: RateLimitBackend::default(),
+            limits: [RateLimitRole::Admin, RateLimitRole::User]
+                .into_iter()
+                .map(|role| (role, RoleLimits::defaults(role)))
+                .collect(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub(crate) fn limit(&self, role: RateLimitRole, class: RouteClass) -> BucketLimit {
+        let limits = self
+            .limits
+            .get(&role)
+            .copied()
+            .unwrap_or_else(|| RoleLimits::defaults(role));
+        match class {
+            RouteClass::Default => limits.default,
+            RouteClass::Expensive => limits.expensive,
+        }
+    }
+}
+
+/// Parse a JSON rate limit config, roles it doesn't mention keep the default limits
+pub(crate) fn rate_limit_config_from_json(json: &str) -> Result<RateLimitConfig, RateLimitError> {
+    let file: RateLimitConfigFile =
+        serde_json::from_str(json).map_err(|e| RateLimitError::InvalidConfig(e.to_string()))?;
+    let mut config = RateLimitConfig {
+        backend: file.backend,
+        ..Default::default()
+    };
+    for (role, limits) in file.limits {
+        for limit in [limits.default, limits.expensive] {
+            if limit.capacity == 0
+                || limit.refill_per_sec <= 0.0
+                || !limit.refill_per_sec.is_finite()
+            {
+                return Err(RateLimitError::InvalidConfig(format!(
+                    "limits for {role:?} need a positive capacity and refill rate"
+                )));
+            }
+        }
+        config.limits.insert(role, limits);
+    }
+    Ok(config)
+}
+
+/// Load the rate limit config from the file at [`RATE_LIMIT_CONFIG_ENV`], defaults if not set
+pub(crate) fn rate_limit_config_from_env() -> Result<RateLimitConfig, RateLimitError> {
+    let Ok(path) = std::env::var(RATE_LIMIT_CONFIG_ENV) else {
+        return Ok(RateLimitConfig::default());
+    };
+    let config = std::fs::read_to_string(&path)
+        .map_err(|e| RateLimitError::InvalidConfig(format!("cannot read {path}: {e}")))?;
+    rate_limit_config_from_json(&config)
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn full(limit: BucketLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, limit: BucketLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.refill_per_sec).min(limit.capacity as f64);
+        self.updated_at = now;
+    }
+
+    /// Take a token, returning how long to wait for one if the bucket is empty
+    pub(crate) fn take(&mut self, limit: BucketLimit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.refill_per_sec,
+            ))
+        }
+    }
+
+    fn is_full(&self, limit: BucketLimit, now: Instant) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(limit, now);
+        bucket.tokens >= limit.capacity as f64
+    }
+}
+
+/// Storage of the token buckets
+#[async_trait]
+pub(crate) trait RateLimitStore: Send + Sync {
+    /// Take a token from the bucket with the given key, returning how long to wait if it's empty
+    async fn take(&self, key: &str, limit: BucketLimit) -> Result<(), Duration>;
+}
+
+#[derive(Default)]
+pub(crate) struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, (BucketLimit, TokenBucket)>>,
+}
+
+impl InMemoryRateLimitStore {
+    pub(crate) fn take_at(
+        &self,
+        key: &str,
+        limit: BucketLimit,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_IN_MEMORY_BUCKETS && !buckets.contains_key(key) {
+            buckets.retain(|_, (limit, bucket)| !bucket.is_full(*limit, now));
+        }
+        let (bucket_limit, bucket) = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| (limit, TokenBucket::full(limit, now)));
+        *bucket_limit = limit;
+        bucket.take(limit, now)
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn take(&self, key: &str, limit: BucketLimit) -> Result<(), Duration> {
+        self.take_at(key, limit, Instant::now())
+    }
+}
+
+/// Buckets stored in the hub database
+///
+/// Until the database is connected, or when it can't be reached, buckets are kept in memory so
+/// requests are still limited per replica.
+pub(crate) struct PostgresRateLimitStore {
+    database: Arc<TokioMutex<Option<Database>>>,
+    fallback: InMemoryRateLimitStore,
+}
+
+impl PostgresRateLimitStore {
+    pub(crate) fn new(database: Arc<TokioMutex<Option<Database>>>) -> Self {
+        Self {
+            database,
+            fallback: InMemoryRateLimitStore::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresRateLimitStore {
+    async fn take(&self, key: &str, limit: BucketLimit) -> Result<(), Duration> {
+        let database = self.database.lock().await.clone();
+        let Some(database) = database else {
+            return self.fallback.take(key, limit).await;
+        };
+        match database
+            .take_rate_limit_token(key, limit.capacity as f64, limit.refill_per_sec)
+            .await
+        {
+            Ok(None) => Ok(()),
+            Ok(Some(wait_secs)) => Err(Duration::from_secs_f64(wait_secs.max(0.0))),
+            Err(e) => {
+                tracing::warn!("Failed to use rate limit bucket {key} in the database: {e}");
+                self.fallback.take(key, limit).await
+            }
+        }
+    }
+}
+
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter using the store selected by the config backend
+    pub(crate) fn new(
+        config: RateLimitConfig,
+        database: Arc<TokioMutex<Option<Database>>>,
+    ) -> Self {
+        let store: Arc<dyn RateLimitStore> = match config.backend {
+            RateLimitBackend::Memory => Arc::new(InMemoryRateLimitStore::default()),
+            RateLimitBackend::Postgres => Arc::new(PostgresRateLimitStore::new(database)),
+        };
+        Self::with_store(config, store)
+    }
+
+    pub(crate) fn with_store(config: RateLimitConfig, store: Arc<dyn RateLimitStore>) -> Self {
+        Self { config, store }
+    }
+
+    /// Take a token for a request of the user, from their bucket of the class sized by their role
+    pub(crate) async fn check(
+        &self,
+        user_id: &str,
+        role: RateLimitRole,
+        class: RouteClass,
+    ) -> Result<(), APIError> {
+        let key = format!("{user_id}:{}", class.label());
+        self.store
+            .take(&key, self.config.limit(role, class))
+            .await
+            .map_err(|wait| APIError::RateLimited {
+                retry_after_secs: wait.as_secs_f64().ceil().max(1.0) as u64,
+            })
+    }
+}
+
+/// Take a token from the caller's default bucket, sized by their role
+///
+/// Runs after [`crate::hub_auth::hub_auth_middleware`], requests over the limit get a 429 with a
+/// `Retry-After` header. Expensive bot commands also take a token from the caller's expensive
+/// bucket when they run.
+pub(crate) async fn rate_limit_middleware(
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(caller) = request.extensions().get::<HubCaller>() {
+        if let Err(e) = rate_limiter
+            .check(
+                &caller.user_id,
+                caller.role.rate_limit_role(),
+                RouteClass::Default,
+            )
+            .await
+        {
+            tracing::debug!("Rate limited request of user {}: {}", caller.user_id, e);
+            return e.into_response();
+        }
+    }
+    next.run(request).await
+}
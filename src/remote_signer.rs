@@ -0,0 +1,161 @@
+//! Request/response protocol between the node and a remote signer holding virtual node keys.
+//!
+//! Every request and response is a JSON body POSTed to [`SIGNER_PATH`], authenticated with an
+//! HMAC-SHA256 keyed by a secret shared between node and signer. Requests carry a timestamp and a
+//! random nonce covered by their MAC, the signer rejects stale timestamps and repeated nonces.
+//! The response MAC covers the request timestamp, nonce and body hash, so a response can't be
+//! replayed against a different request.
+//!
+//! This module is shared with the reference signer binary, so it must not depend on the rest of
+//! the crate.
+
+use bitcoin::hex::{DisplayHex, FromHex};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Path of the signer endpoint
+pub const SIGNER_PATH: &str = "/v1/signer";
+
+/// Header carrying the UNIX timestamp (seconds) of a request
+pub const SIGNER_TIMESTAMP_HEADER: &str = "x-signer-timestamp";
+
+/// Header carrying the hex encoded random nonce of a request
+pub const SIGNER_NONCE_HEADER: &str = "x-signer-nonce";
+
+/// Header carrying the hex encoded HMAC of a request or response
+pub const SIGNER_MAC_HEADER: &str = "x-signer-mac";
+
+/// Maximum clock difference accepted between node and signer
+pub const MAX_CLOCK_SKEW_SECS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SignerRequest {
+    /// Get the node ID of a user's virtual node
+    DeriveNodeId { user_id: String },
+    /// Sign a 32 byte hash with a user's virtual node key
+    SignHash { user_id: String, hash: String },
+    /// ECDH between a user's virtual node key and the given public key
+    Ecdh { user_id: String, public_key: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum SignerResponse {
+    NodeId { node_id: String },
+    /// Compact recoverable signature
+    Signature { signature: String, recovery_id: i32 },
+    SharedSecret { secret: String },
+    Error { message: String },
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum SignerAuthError {
+    #[error("Invalid MAC")]
+    InvalidMac,
+    #[error("Invalid request nonce")]
+    InvalidNonce,
+    #[error("Request timestamp outside the accepted window")]
+    StaleRequest,
+}
+
+fn request_mac(secret: &[u8], timestamp: u64, nonce: &[u8; 32], body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(b"request");
+    mac.update(&timestamp.to_be_bytes());
+    mac.update(nonce);
+    mac.update(body);
+    mac
+}
+
+fn response_mac(
+    secret: &[u8],
+    timestamp: u64,
+    nonce: &[u8; 32],
+    request_body: &[u8],
+    body: &[u8],
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(b"response");
+    mac.update(&timestamp.to_be_bytes());
+    mac.update(nonce);
+    mac.update(&Sha256::digest(request_body));
+    mac.update(body);
+    mac
+}
+
+fn check_mac(mac: Hmac<Sha256>, mac_hex: &str) -> Result<(), SignerAuthError> {
+    let expected = Vec::<u8>::from_hex(mac_hex).map_err(|_| SignerAuthError::InvalidMac)?;
+    mac.verify_slice(&expected)
+        .map_err(|_| SignerAuthError::InvalidMac)
+}
+
+/// New random request nonce
+pub fn new_nonce() -> [u8; 32] {
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Parse the hex encoded nonce of a request
+pub fn parse_nonce(nonce_hex: &str) -> Result<[u8; 32], SignerAuthError> {
+    <[u8; 32]>::from_hex(nonce_hex).map_err(|_| SignerAuthError::InvalidNonce)
+}
+
+/// Hex encoded MAC of a request body with the given timestamp and nonce
+pub fn compute_request_mac(secret: &[u8], timestamp: u64, nonce: &[u8; 32], body: &[u8]) -> String {
+    request_mac(secret, timestamp, nonce, body)
+        .finalize()
+        .into_bytes()
+        .to_lower_hex_string()
+}
+
+/// Check a request MAC, in constant time, and that its timestamp is within
+/// [`MAX_CLOCK_SKEW_SECS`] of `now`
+///
+/// Nonce reuse is not tracked here, the signer must reject nonces it has already seen within the
+/// accepted window.
+pub fn verify_request(
+    secret: &[u8],
+    timestamp: u64,
+    nonce: &[u8; 32],
+    body: &[u8],
+    mac_hex: &str,
+    now: u64,
+) -> Result<(), SignerAuthError> {
+    if now.abs_diff(timestamp) > MAX_CLOCK_SKEW_SECS {
+        return Err(SignerAuthError::StaleRequest);
+    }
+    check_mac(request_mac(secret, timestamp, nonce, body), mac_hex)
+}
+
+/// Hex encoded MAC of a response body, bound to the request it answers
+pub fn compute_response_mac(
+    secret: &[u8],
+    timestamp: u64,
+    nonce: &[u8; 32],
+    request_body: &[u8],
+    body: &[u8],
+) -> String {
+    response_mac(secret, timestamp, nonce, request_body, body)
+        .finalize()
+        .into_bytes()
+        .to_lower_hex_string()
+}
+
+/// Check the MAC of a response to the given request, in constant time
+pub fn verify_response(
+    secret: &[u8],
+    timestamp: u64,
+    nonce: &[u8; 32],
+    request_body: &[u8],
+    body: &[u8],
+    mac_hex: &str,
+) -> Result<(), SignerAuthError> {
+    check_mac(
+        response_mac(secret, timestamp, nonce, request_body, body),
+        mac_hex,
+    )
+}
@@ -1,9 +1,12 @@
 use amplify::{map, s, Display};
 use axum::{
     extract::{Multipart, State},
-    Json,
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Extension, Json,
 };
 use axum_extra::extract::WithRejection;
+use base64::{engine::general_purpose, Engine as _};
 use bitcoin::hashes::sha256::{self, Hash as Sha256};
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::PublicKey;
@@ -77,15 +80,24 @@ use tokio::{
     sync::MutexGuard as TokioMutexGuard,
 };
 
+use crate::database::{Database, UserImportError};
+use crate::hub_auth::HubCaller;
+use crate::idempotency::{extract_idempotency_key, idempotent_reference, run_idempotent};
 use crate::ldk::{start_ldk, stop_ldk, LdkBackgroundServices, MIN_CHANNEL_CONFIRMATIONS};
-use crate::swap::{SwapData, SwapInfo, SwapString};
+use crate::metrics::{NodeMetrics, METRICS_CONTENT_TYPE};
+use crate::pricing::PricingError;
+use crate::quota::{with_quota, QuotaAction, QuotaSpend, QuotaUsage, UserQuotas, UserQuotasUpdate};
+use crate::swap::{SwapData, SwapInfo, SwapOfferData, SwapString, MAX_OPEN_QUOTES_PER_OFFER};
+use crate::user_archive::{open_user_archive, pack_user_archive, user_wallet_dir, UserArchiveData};
+use crate::virtual_balance::{BalanceError, VirtualTransferMode};
 use crate::utils::{
     check_already_initialized, check_channel_id, check_password_strength, check_password_validity,
     encrypt_and_save_mnemonic, get_max_local_rgb_amount, get_mnemonic_path, get_route, hex_str,
     hex_str_to_compressed_pubkey, hex_str_to_vec, UnlockedAppState, UserOnionMessageContents,
 };
 use crate::{
-    backup::{do_backup, restore_backup},
+    backup::{do_backup, do_incremental_backup, restore_backup},
+    backup_verify::do_verify_backup,
     rgb::{check_rgb_proxy_endpoint, get_rgb_channel_info_optional},
 };
 use crate::{
@@ -113,6 +125,12 @@ const INVOICE_MIN_MSAT: u64 = HTLC_MIN_MSAT;
 
 pub(crate) const DEFAULT_FINAL_CLTV_EXPIRY_DELTA: u32 = 14;
 
+/// Most the node pays in routing fees for a BOLT11 payment of `amt_msat`, LDK's default of 1%
+/// plus 50 sats
+pub(crate) fn max_routing_fee_msat(amt_msat: u64) -> u64 {
+    amt_msat / 100 + 50_000
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct AddressResponse {
     pub(crate) address: String,
@@ -314,10 +332,41 @@ impl From<Assignment> for RgbLibAssignment {
     }
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct BackupAsset {
+    pub(crate) asset_id: String,
+    pub(crate) schema: AssetSchema,
+    pub(crate) name: String,
+    pub(crate) settled_balance: u64,
+    pub(crate) spendable_balance: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct BackupChannel {
+    pub(crate) channel_id: String,
+    pub(crate) funding_txid: String,
+    pub(crate) funding_output_index: u16,
+    pub(crate) peer_pubkey: Option<String>,
+    pub(crate) latest_update_id: u64,
+    pub(crate) claimable_balance_sat: u64,
+    pub(crate) asset_id: Option<String>,
+    pub(crate) asset_local_amount: Option<u64>,
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct BackupRequest {
     pub(crate) backup_path: String,
     pub(crate) password: String,
+    #[serde(default)]
+    pub(crate) incremental: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct BackupResponse {
+    pub(crate) version: u8,
+    pub(crate) snapshot_id: Option<String>,
+    pub(crate) new_chunks: Option<usize>,
+    pub(crate) reused_chunks: Option<usize>,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
@@ -375,6 +424,11 @@ pub(crate) struct BtcBalanceResponse {
     pub(crate) colored: BtcBalance,
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct CancelSwapOfferRequest {
+    pub(crate) offer_id: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct ChangePasswordRequest {
     pub(crate) old_password: String,
@@ -514,6 +568,19 @@ pub(crate) struct EstimateFeeResponse {
     pub(crate) fee_rate: f64,
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ExportUserRequest {
+    pub(crate) password: String,
+    pub(crate) destination_hub: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ExportUserResponse {
+    pub(crate) archive_id: String,
+    /// Base64 encoded encrypted archive
+    pub(crate) archive: String,
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct FailTransfersRequest {
     pub(crate) batch_transfer_idx: Option<i32>,
@@ -596,6 +663,20 @@ impl From<RgbLibIndexerProtocol> for IndexerProtocol {
     }
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ImportUserRequest {
+    /// Base64 encoded encrypted archive
+    pub(crate) archive: String,
+    pub(crate) password: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ImportUserResponse {
+    pub(crate) archive_id: String,
+    pub(crate) user_id: String,
+    pub(crate) virtual_node_id: Option<String>,
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct InitRequest {
     pub(crate) password: String,
@@ -708,6 +789,11 @@ pub(crate) struct ListPeersResponse {
     pub(crate) peers: Vec<Peer>,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ListSwapOffersResponse {
+    pub(crate) offers: Vec<SwapOffer>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct ListSwapsResponse {
     pub(crate) maker: Vec<Swap>,
@@ -780,9 +866,11 @@ pub(crate) struct MakerInitRequest {
 
 #[derive(Deserialize, Serialize)]
 pub(crate) struct MakerInitResponse {
+    pub(crate) qty_to: u64,
     pub(crate) payment_hash: String,
     pub(crate) payment_secret: String,
     pub(crate) swapstring: String,
+    pub(crate) signed_swapstring: String,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -872,6 +960,24 @@ pub(crate) struct PostAssetMediaResponse {
     pub(crate) digest: String,
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct PostSwapOfferRequest {
+    pub(crate) from_asset: Option<String>,
+    pub(crate) to_asset: Option<String>,
+    pub(crate) price_qty_from: u64,
+    pub(crate) price_qty_to: u64,
+    pub(crate) min_qty_from: u64,
+    pub(crate) max_qty_from: u64,
+    pub(crate) swap_timeout_sec: u32,
+    pub(crate) expiry_sec: Option<u32>,
+    pub(crate) total_qty_from: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct PostSwapOfferResponse {
+    pub(crate) offer_id: String,
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct ProofOfReserves {
     pub(crate) utxo: String,
@@ -896,6 +1002,8 @@ pub(crate) struct RefreshRequest {
 pub(crate) struct RestoreRequest {
     pub(crate) backup_path: String,
     pub(crate) password: String,
+    #[serde(default)]
+    pub(crate) snapshot_id: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -974,6 +1082,9 @@ pub(crate) struct SendPaymentResponse {
 #[derive(Deserialize, Serialize)]
 pub(crate) struct SignMessageRequest {
     pub(crate) message: String,
+    /// Sign with this user's virtual node key instead of the node key
+    #[serde(default)]
+    pub(crate) user_id: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -993,6 +1104,43 @@ pub(crate) struct Swap {
     pub(crate) initiated_at: Option<u64>,
     pub(crate) expires_at: u64,
     pub(crate) completed_at: Option<u64>,
+    pub(crate) offer_id: Option<String>,
+    pub(crate) offer_remaining_qty_from: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct SwapOffer {
+    pub(crate) offer_id: String,
+    pub(crate) from_asset: Option<String>,
+    pub(crate) to_asset: Option<String>,
+    pub(crate) price_qty_from: u64,
+    pub(crate) price_qty_to: u64,
+    pub(crate) min_qty_from: u64,
+    pub(crate) max_qty_from: u64,
+    pub(crate) swap_timeout_sec: u32,
+    pub(crate) created_at: u64,
+    pub(crate) expires_at: Option<u64>,
+    pub(crate) total_qty_from: Option<u64>,
+    pub(crate) remaining_qty_from: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct SwapQuoteRequest {
+    pub(crate) from_asset: Option<String>,
+    pub(crate) to_asset: Option<String>,
+    pub(crate) qty_from: u64,
+    pub(crate) offer_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct SwapQuoteResponse {
+    pub(crate) offer_id: String,
+    pub(crate) qty_from: u64,
+    pub(crate) qty_to: u64,
+    pub(crate) payment_hash: String,
+    pub(crate) payment_secret: String,
+    pub(crate) swapstring: String,
+    pub(crate) signed_swapstring: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -1015,6 +1163,12 @@ impl_writeable_tlv_based_enum!(SwapStatus,
 #[derive(Deserialize, Serialize)]
 pub(crate) struct TakerRequest {
     pub(crate) swapstring: String,
+    /// Only accept swapstrings signed by this maker node
+    #[serde(default)]
+    pub(crate) maker_pubkey: Option<String>,
+    /// Accept a swapstring without a maker signature
+    #[serde(default)]
+    pub(crate) allow_unsigned: bool,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -1161,6 +1315,26 @@ pub(crate) struct Unspent {
     pub(crate) rgb_allocations: Vec<RgbAllocation>,
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct UpdateUserQuotasRequest {
+    pub(crate) user_id: String,
+    /// Quotas to change, the ones left out keep their value
+    #[serde(flatten)]
+    pub(crate) quotas: UserQuotasUpdate,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct UserQuotasRequest {
+    pub(crate) user_id: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct UserQuotasResponse {
+    pub(crate) user_id: String,
+    pub(crate) quotas: UserQuotas,
+    pub(crate) usage: QuotaUsage,
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Utxo {
     pub(crate) outpoint: String,
@@ -1168,6 +1342,27 @@ pub(crate) struct Utxo {
     pub(crate) colorable: bool,
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct VerifyBackupRequest {
+    pub(crate) backup_path: String,
+    pub(crate) password: String,
+    #[serde(default)]
+    pub(crate) snapshot_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct VerifyBackupResponse {
+    pub(crate) valid: bool,
+    pub(crate) version: u8,
+    pub(crate) snapshot_id: Option<String>,
+    pub(crate) network: BitcoinNetwork,
+    pub(crate) node_pubkey: String,
+    pub(crate) best_block_height: Option<u32>,
+    pub(crate) channels: Vec<BackupChannel>,
+    pub(crate) assets: Vec<BackupAsset>,
+    pub(crate) issues: Vec<String>,
+}
+
 impl AppState {
     fn check_changing_state(&self) -> Result<(), APIError> {
         if *self.get_changing_state() {
@@ -1346,20 +1541,39 @@ pub(crate) async fn asset_metadata(
 pub(crate) async fn backup(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<BackupRequest>, APIError>,
-) -> Result<Json<EmptyResponse>, APIError> {
+) -> Result<Json<BackupResponse>, APIError> {
     no_cancel(async move {
         let _unlocked_state = state.check_locked().await?;
 
         let _mnemonic =
             check_password_validity(&payload.password, &state.static_state.storage_dir_path)?;
 
+        if payload.incremental {
+            let snapshot = do_incremental_backup(
+                &state.static_state.storage_dir_path,
+                Path::new(&payload.backup_path),
+                &payload.password,
+            )?;
+            return Ok(Json(BackupResponse {
+                version: 2,
+                snapshot_id: Some(snapshot.snapshot_id),
+                new_chunks: Some(snapshot.new_chunks),
+                reused_chunks: Some(snapshot.reused_chunks),
+            }));
+        }
+
         do_backup(
             &state.static_state.storage_dir_path,
             Path::new(&payload.backup_path),
             &payload.password,
         )?;
 
-        Ok(Json(EmptyResponse {}))
+        Ok(Json(BackupResponse {
+            version: 1,
+            snapshot_id: None,
+            new_chunks: None,
+            reused_chunks: None,
+        }))
     })
     .await
 }
@@ -1428,6 +1642,23 @@ pub(crate) async fn btc_balance(
     Ok(Json(BtcBalanceResponse { vanilla, colored }))
 }
 
+pub(crate) async fn cancel_swap_offer(
+    State(state): State<Arc<AppState>>,
+    WithRejection(Json(payload), _): WithRejection<Json<CancelSwapOfferRequest>, APIError>,
+) -> Result<Json<EmptyResponse>, APIError> {
+    no_cancel(async move {
+        let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+
+        // swaps already quoted from the offer stay valid until they expire
+        unlocked_state
+            .remove_swap_offer(&payload.offer_id)
+            .ok_or(APIError::SwapOfferNotFound(payload.offer_id))?;
+
+        Ok(Json(EmptyResponse {}))
+    })
+    .await
+}
+
 pub(crate) async fn change_password(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<ChangePasswordRequest>, APIError>,
@@ -1669,6 +1900,63 @@ pub(crate) async fn estimate_fee(
     Ok(Json(EstimateFeeResponse { fee_rate }))
 }
 
+/// Export the caller's RGB wallet and hub rows into an archive signed with the node key
+///
+/// The balances carried by the archive are moved out of the user account, so they can only be
+/// spent on the hub importing it.
+pub(crate) async fn export_user(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<HubCaller>,
+    WithRejection(Json(payload), _): WithRejection<Json<ExportUserRequest>, APIError>,
+) -> Result<Json<ExportUserResponse>, APIError> {
+    let user_id = caller.require_user()?.to_string();
+    check_password_strength(payload.password.clone())?;
+    let destination_hub =
+        PublicKey::from_str(&payload.destination_hub).map_err(|_| APIError::InvalidPubkey)?;
+    let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+    let database = hub_database(&state).await?;
+    let wallet_dir = user_wallet_dir(&state.static_state.storage_dir_path, &user_id)?;
+
+    let rows = database
+        .export_user_rows(&user_id)
+        .await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?;
+    let data = UserArchiveData::new(&user_id, &destination_hub, rows);
+    let archive_id = data.archive_id.to_string();
+    let reference = format!("user_export_{archive_id}");
+    database
+        .debit_exported_balances(&user_id, &data.rows.balances, &reference)
+        .await
+        .map_err(|e| APIError::from(BalanceError::from(e)))?;
+
+    let hub_secret = unlocked_state.keys_manager.get_node_secret_key();
+    let packed = tokio::task::spawn_blocking(move || {
+        pack_user_archive(&data, &wallet_dir, &payload.password, &hub_secret)
+    })
+    .await
+    .unwrap();
+    let archive = match packed {
+        Ok(archive) => archive,
+        Err(e) => {
+            if let Err(refund_err) = database
+                .reverse_journal(&reference, &format!("{reference}_refund"))
+                .await
+            {
+                tracing::error!(
+                    "Failed to refund export {archive_id} of user {user_id}: {refund_err}"
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    tracing::info!("Exported archive {} for user {}", archive_id, user_id);
+    Ok(Json(ExportUserResponse {
+        archive_id,
+        archive: general_purpose::STANDARD.encode(archive),
+    }))
+}
+
 pub(crate) async fn fail_transfers(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<FailTransfersRequest>, APIError>,
@@ -1730,6 +2018,71 @@ pub(crate) async fn get_channel_id(
     Ok(Json(GetChannelIdResponse { channel_id }))
 }
 
+/// Import a user exported from a trusted hub, only the hub operator can credit their balances
+pub(crate) async fn import_user(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<HubCaller>,
+    WithRejection(Json(payload), _): WithRejection<Json<ImportUserRequest>, APIError>,
+) -> Result<Json<ImportUserResponse>, APIError> {
+    caller.require_admin()?;
+    let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+    let database = hub_database(&state).await?;
+
+    let archive = general_purpose::STANDARD
+        .decode(&payload.archive)
+        .map_err(|e| APIError::InvalidUserArchive(e.to_string()))?;
+    let trusted_hubs = state.user_archive_trusted_hubs.clone();
+    let local_hub = unlocked_state.channel_manager.get_our_node_id();
+    let opened = tokio::task::spawn_blocking(move || {
+        open_user_archive(&archive, &payload.password, &trusted_hubs, &local_hub)
+    })
+    .await
+    .unwrap()?;
+    let user_id = opened.data.user_id.clone();
+    let archive_id = opened.data.archive_id.to_string();
+    let wallet_dir = user_wallet_dir(&state.static_state.storage_dir_path, &user_id)?;
+
+    // virtual node IDs are derived from the keys of this hub
+    let mut rows = opened.data.rows.clone();
+    let virtual_node_manager = state.virtual_node_manager.lock().await.clone();
+    let virtual_node_id = match virtual_node_manager {
+        Some(virtual_node_manager) => {
+            let virtual_node_id = virtual_node_manager
+                .get_virtual_node_id(&user_id)
+                .await
+                .map_err(|e| APIError::Unexpected(e.to_string()))?
+                .to_string();
+            rows.set_virtual_node_id(&virtual_node_id);
+            Some(virtual_node_id)
+        }
+        None => None,
+    };
+
+    let wallet_dir_copy = wallet_dir.clone();
+    tokio::task::spawn_blocking(move || opened.extract_wallet(&wallet_dir_copy))
+        .await
+        .unwrap()?;
+    let reference = format!("user_import_{archive_id}");
+    if let Err(e) = database.import_user_rows(&user_id, &rows, &reference).await {
+        if let Err(rm_err) = tokio::fs::remove_dir_all(&wallet_dir).await {
+            tracing::error!(
+                "Failed to remove wallet of user {user_id} after a failed import: {rm_err}"
+            );
+        }
+        return Err(match e {
+            UserImportError::UserExists(user_id) => APIError::UserAlreadyExists(user_id),
+            e => APIError::Unexpected(e.to_string()),
+        });
+    }
+
+    tracing::info!("Imported archive {} for user {}", archive_id, user_id);
+    Ok(Json(ImportUserResponse {
+        archive_id,
+        user_id,
+        virtual_node_id,
+    }))
+}
+
 pub(crate) async fn init(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<InitRequest>, APIError>,
@@ -2062,7 +2415,7 @@ pub(crate) async fn list_channels(
     
     // Check for virtual node context
     let payload = serde_json::json!({});
-    let virtual_ctx = crate::virtual_context::VirtualNodeContext::from_request(&payload, &headers, &state).await;
+    let virtual_ctx = crate::virtual_context::VirtualNodeContext::from_request(&payload, &headers, &state).await?;
 
     let mut channels = vec![];
     
@@ -2159,7 +2512,7 @@ pub(crate) async fn list_payments(
     
     // Check for virtual node context
     let payload = serde_json::json!({});
-    let virtual_ctx = crate::virtual_context::VirtualNodeContext::from_request(&payload, &headers, &state).await;
+    let virtual_ctx = crate::virtual_context::VirtualNodeContext::from_request(&payload, &headers, &state).await?;
     
     // Get owned payments for virtual node
     let owned_payments = if let Some(ref ctx) = virtual_ctx {
@@ -2332,20 +2685,55 @@ pub(crate) async fn list_peers(
     Ok(Json(ListPeersResponse { peers }))
 }
 
+fn map_swap_offer(
+    offer_id: &str,
+    offer: &SwapOfferData,
+    remaining_qty_from: Option<u64>,
+) -> SwapOffer {
+    SwapOffer {
+        offer_id: offer_id.to_string(),
+        from_asset: offer.from_asset.map(|c| c.to_string()),
+        to_asset: offer.to_asset.map(|c| c.to_string()),
+        price_qty_from: offer.price_qty_from,
+        price_qty_to: offer.price_qty_to,
+        min_qty_from: offer.min_qty_from,
+        max_qty_from: offer.max_qty_from,
+        swap_timeout_sec: offer.swap_timeout_sec,
+        created_at: offer.created_at,
+        expires_at: offer.expires_at,
+        total_qty_from: offer.total_qty_from,
+        remaining_qty_from,
+    }
+}
+
+pub(crate) async fn list_swap_offers(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ListSwapOffersResponse>, APIError> {
+    let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+
+    let mut offers: Vec<SwapOffer> = unlocked_state
+        .swap_offers()
+        .iter()
+        .filter(|(_, offer)| !offer.is_expired())
+        .map(|(offer_id, offer)| {
+            let remaining = unlocked_state.offer_remaining_qty_from(offer_id);
+            map_swap_offer(offer_id, offer, remaining)
+        })
+        .collect();
+    offers.sort_by_key(|o| o.created_at);
+
+    Ok(Json(ListSwapOffersResponse { offers }))
+}
+
 pub(crate) async fn list_swaps(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<ListSwapsResponse>, APIError> {
     let unlocked_state = state.check_unlocked().await?.clone().unwrap();
 
     let map_swap = |payment_hash: &PaymentHash, swap_data: &SwapData, taker: bool| {
-        let mut status = swap_data.status.clone();
-        if status == SwapStatus::Waiting && get_current_timestamp() > swap_data.swap_info.expiry {
-            status = SwapStatus::Expired;
-        } else if status == SwapStatus::Pending
-            && get_current_timestamp() > swap_data.initiated_at.unwrap() + 86400
-        {
-            status = SwapStatus::Failed;
-        }
+        let status = swap_data
+            .timed_out_status(get_current_timestamp())
+            .unwrap_or_else(|| swap_data.status.clone());
         if status != swap_data.status {
             if taker {
                 unlocked_state.update_taker_swap_status(payment_hash, status.clone());
@@ -2364,6 +2752,11 @@ pub(crate) async fn list_swaps(
             initiated_at: swap_data.initiated_at,
             expires_at: swap_data.swap_info.expiry,
             completed_at: swap_data.completed_at,
+            offer_id: swap_data.offer_id.clone(),
+            offer_remaining_qty_from: swap_data
+                .offer_id
+                .as_ref()
+                .and_then(|offer_id| unlocked_state.offer_remaining_qty_from(offer_id)),
         }
     };
 
@@ -2395,14 +2788,9 @@ pub(crate) async fn get_swap(
     let requested_ph = PaymentHash(payment_hash_vec.unwrap().try_into().unwrap());
 
     let map_swap = |payment_hash: &PaymentHash, swap_data: &SwapData, taker: bool| {
-        let mut status = swap_data.status.clone();
-        if status == SwapStatus::Waiting && get_current_timestamp() > swap_data.swap_info.expiry {
-            status = SwapStatus::Expired;
-        } else if status == SwapStatus::Pending
-            && get_current_timestamp() > swap_data.initiated_at.unwrap() + 86400
-        {
-            status = SwapStatus::Failed;
-        }
+        let status = swap_data
+            .timed_out_status(get_current_timestamp())
+            .unwrap_or_else(|| swap_data.status.clone());
         if status != swap_data.status {
             if taker {
                 unlocked_state.update_taker_swap_status(payment_hash, status.clone());
@@ -2421,6 +2809,11 @@ pub(crate) async fn get_swap(
             initiated_at: swap_data.initiated_at,
             expires_at: swap_data.swap_info.expiry,
             completed_at: swap_data.completed_at,
+            offer_id: swap_data.offer_id.clone(),
+            offer_remaining_qty_from: swap_data
+                .offer_id
+                .as_ref()
+                .and_then(|offer_id| unlocked_state.offer_remaining_qty_from(offer_id)),
         }
     };
 
@@ -2906,81 +3299,155 @@ pub(crate) async fn maker_execute(
     .await
 }
 
-pub(crate) async fn maker_init(
-    State(state): State<Arc<AppState>>,
-    WithRejection(Json(payload), _): WithRejection<Json<MakerInitRequest>, APIError>,
-) -> Result<Json<MakerInitResponse>, APIError> {
-    no_cancel(async move {
-        let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+fn parse_swap_asset(asset: &Option<String>) -> Result<Option<ContractId>, APIError> {
+    match asset {
+        None => Ok(None),
+        Some(asset) => Ok(Some(
+            ContractId::from_str(asset).map_err(|_| APIError::InvalidAssetID(asset.clone()))?,
+        )),
+    }
+}
 
-        let from_asset = match &payload.from_asset {
-            None => None,
-            Some(asset) => Some(
-                ContractId::from_str(asset).map_err(|_| APIError::InvalidAssetID(asset.clone()))?,
-            ),
-        };
+fn check_swap_assets(
+    from_asset: Option<ContractId>,
+    to_asset: Option<ContractId>,
+) -> Result<(), APIError> {
+    // prevent BTC-to-BTC swaps
+    if from_asset.is_none() && to_asset.is_none() {
+        return Err(APIError::InvalidSwap(s!("cannot swap BTC for BTC")));
+    }
 
-        let to_asset = match &payload.to_asset {
-            None => None,
-            Some(asset) => Some(
-                ContractId::from_str(asset).map_err(|_| APIError::InvalidAssetID(asset.clone()))?,
-            ),
-        };
+    // prevent swaps of same assets
+    if from_asset == to_asset {
+        return Err(APIError::InvalidSwap(s!("cannot swap the same asset")));
+    }
+
+    Ok(())
+}
+
+/// Check a maker swap against the pricing policy, which may auto-price it
+async fn apply_maker_price_policy(
+    state: &AppState,
+    swap_info: SwapInfo,
+) -> Result<SwapInfo, APIError> {
+    let Some(policy) = &state.maker_price_policy else {
+        return Ok(swap_info);
+    };
+    policy.apply(swap_info).await.map_err(map_pricing_error)
+}
+
+/// Check a swap priced by an offer against the pricing policy, rejecting it if out of range
+/// even when the policy auto-prices
+async fn check_maker_price_policy(state: &AppState, swap_info: &SwapInfo) -> Result<(), APIError> {
+    let Some(policy) = &state.maker_price_policy else {
+        return Ok(());
+    };
+    policy.check(swap_info).await.map_err(map_pricing_error)
+}
 
-        // prevent BTC-to-BTC swaps
-        if from_asset.is_none() && to_asset.is_none() {
-            return Err(APIError::InvalidSwap(s!("cannot swap BTC for BTC")));
+fn map_pricing_error(e: PricingError) -> APIError {
+    match e {
+        PricingError::OutOfRange { .. } => APIError::SwapPriceRejected(e.to_string()),
+        _ => APIError::SwapPriceUnavailable(e.to_string()),
+    }
+}
+
+/// Register a maker swap and create the payment hash and swapstring the taker needs
+fn init_maker_swap(
+    state: &AppState,
+    unlocked_state: &UnlockedAppState,
+    swap_info: SwapInfo,
+    timeout_sec: u32,
+    offer_id: Option<&str>,
+) -> Result<MakerInitResponse, APIError> {
+    // Check that we have enough assets to send
+    if let Some(to_asset) = swap_info.to_asset {
+        let max_balance = get_max_local_rgb_amount(
+            to_asset,
+            &state.static_state.ldk_data_dir,
+            unlocked_state.channel_manager.list_channels().iter(),
+        );
+        if swap_info.qty_to > max_balance {
+            return Err(APIError::InsufficientAssets);
         }
+    }
 
-        // prevent swaps of same assets
-        if from_asset == to_asset {
-            return Err(APIError::InvalidSwap(s!("cannot swap the same asset")));
+    let (payment_hash, payment_secret) = unlocked_state
+        .channel_manager
+        .create_inbound_payment(Some(DUST_LIMIT_MSAT), timeout_sec, None)
+        .unwrap();
+    match offer_id {
+        Some(offer_id) => {
+            let swap_data = SwapData::create_from_offer(&swap_info, offer_id);
+            unlocked_state
+                .add_offer_maker_swap(payment_hash, swap_data, offer_id)
+                .map_err(|e| APIError::InvalidSwap(e.to_string()))?;
+        }
+        None => {
+            let swap_data = SwapData::create_from_swap_info(&swap_info);
+            unlocked_state.add_maker_swap(payment_hash, swap_data);
         }
+    }
 
-        let qty_from = payload.qty_from;
-        let qty_to = payload.qty_to;
+    let swapstring = SwapString::from_swap_info(&swap_info, payment_hash);
+    let signed_swapstring = SwapString::from_swap_info(&swap_info, payment_hash)
+        .signed(&unlocked_state.keys_manager.get_node_secret_key())
+        .to_string();
+
+    let payment_secret = payment_secret.0.as_hex().to_string();
+    let payment_hash = payment_hash.0.as_hex().to_string();
+    Ok(MakerInitResponse {
+        qty_to: swap_info.qty_to,
+        payment_hash,
+        payment_secret,
+        swapstring: swapstring.to_string(),
+        signed_swapstring,
+    })
+}
+
+pub(crate) async fn maker_init(
+    State(state): State<Arc<AppState>>,
+    WithRejection(Json(payload), _): WithRejection<Json<MakerInitRequest>, APIError>,
+) -> Result<Json<MakerInitResponse>, APIError> {
+    no_cancel(async move {
+        let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+
+        let from_asset = parse_swap_asset(&payload.from_asset)?;
+        let to_asset = parse_swap_asset(&payload.to_asset)?;
+        check_swap_assets(from_asset, to_asset)?;
 
         let expiry = get_current_timestamp() + payload.timeout_sec as u64;
         let swap_info = SwapInfo {
             from_asset,
             to_asset,
-            qty_from,
-            qty_to,
+            qty_from: payload.qty_from,
+            qty_to: payload.qty_to,
             expiry,
         };
-        let swap_data = SwapData::create_from_swap_info(&swap_info);
+        let swap_info = apply_maker_price_policy(&state, swap_info).await?;
 
-        // Check that we have enough assets to send
-        if let Some(to_asset) = to_asset {
-            let max_balance = get_max_local_rgb_amount(
-                to_asset,
-                &state.static_state.ldk_data_dir,
-                unlocked_state.channel_manager.list_channels().iter(),
-            );
-            if swap_info.qty_to > max_balance {
-                return Err(APIError::InsufficientAssets);
-            }
-        }
-
-        let (payment_hash, payment_secret) = unlocked_state
-            .channel_manager
-            .create_inbound_payment(Some(DUST_LIMIT_MSAT), payload.timeout_sec, None)
-            .unwrap();
-        unlocked_state.add_maker_swap(payment_hash, swap_data);
-
-        let swapstring = SwapString::from_swap_info(&swap_info, payment_hash).to_string();
-
-        let payment_secret = payment_secret.0.as_hex().to_string();
-        let payment_hash = payment_hash.0.as_hex().to_string();
-        Ok(Json(MakerInitResponse {
-            payment_hash,
-            payment_secret,
-            swapstring,
-        }))
+        Ok(Json(init_maker_swap(
+            &state,
+            &unlocked_state,
+            swap_info,
+            payload.timeout_sec,
+            None,
+        )?))
     })
     .await
 }
 
+pub(crate) async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let unlocked_state = state.get_unlocked_app_state().await.clone();
+    let node_metrics = unlocked_state
+        .map(|u| NodeMetrics::collect(&u, &state.static_state.ldk_data_dir));
+
+    (
+        [(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+        state.static_state.metrics.render(node_metrics.as_ref()),
+    )
+}
+
 pub(crate) async fn network_info(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<NetworkInfoResponse>, APIError> {
@@ -3002,7 +3469,7 @@ pub(crate) async fn node_info(
     
     // Check for virtual node context
     let payload = serde_json::json!({});
-    if let Some(virtual_ctx) = crate::virtual_context::VirtualNodeContext::from_request(&payload, &headers, &state).await {
+    if let Some(virtual_ctx) = crate::virtual_context::VirtualNodeContext::from_request(&payload, &headers, &state).await? {
         // Return virtual node info
         return Ok(Json(NodeInfoResponse {
             pubkey: virtual_ctx.virtual_node_id.to_string(),
@@ -3363,6 +3830,73 @@ pub(crate) async fn post_asset_media(
     .await
 }
 
+pub(crate) async fn post_swap_offer(
+    State(state): State<Arc<AppState>>,
+    WithRejection(Json(payload), _): WithRejection<Json<PostSwapOfferRequest>, APIError>,
+) -> Result<Json<PostSwapOfferResponse>, APIError> {
+    no_cancel(async move {
+        let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+
+        let from_asset = parse_swap_asset(&payload.from_asset)?;
+        let to_asset = parse_swap_asset(&payload.to_asset)?;
+        check_swap_assets(from_asset, to_asset)?;
+
+        if payload.price_qty_from == 0 || payload.price_qty_to == 0 {
+            return Err(APIError::InvalidSwap(s!("price quantities should be positive")));
+        }
+        if payload.min_qty_from == 0 || payload.min_qty_from > payload.max_qty_from {
+            return Err(APIError::InvalidSwap(s!(
+                "min_qty_from should be positive and not above max_qty_from"
+            )));
+        }
+        if payload.swap_timeout_sec == 0 {
+            return Err(APIError::InvalidSwap(s!("swap_timeout_sec should be positive")));
+        }
+        if payload
+            .total_qty_from
+            .is_some_and(|total| total < payload.min_qty_from)
+        {
+            return Err(APIError::InvalidSwap(s!(
+                "total_qty_from should not be below min_qty_from"
+            )));
+        }
+
+        let created_at = get_current_timestamp();
+        let offer = SwapOfferData {
+            from_asset,
+            to_asset,
+            price_qty_from: payload.price_qty_from,
+            price_qty_to: payload.price_qty_to,
+            min_qty_from: payload.min_qty_from,
+            max_qty_from: payload.max_qty_from,
+            swap_timeout_sec: payload.swap_timeout_sec,
+            created_at,
+            expires_at: payload.expiry_sec.map(|e| created_at + e as u64),
+            total_qty_from: payload.total_qty_from,
+        };
+        // both ends of the range must produce a valid quote
+        offer
+            .quote(offer.min_qty_from)
+            .and_then(|_| offer.quote(offer.max_qty_from))
+            .map_err(|e| APIError::InvalidSwap(e.to_string()))?;
+        // reject offers priced outside the policy upfront instead of failing every quote
+        let offer_price = SwapInfo {
+            from_asset,
+            to_asset,
+            qty_from: offer.price_qty_from,
+            qty_to: offer.price_qty_to,
+            expiry: created_at,
+        };
+        check_maker_price_policy(&state, &offer_price).await?;
+
+        let offer_id = hex_str(&unlocked_state.keys_manager.get_secure_random_bytes()[..16]);
+        unlocked_state.add_swap_offer(offer_id.clone(), offer);
+
+        Ok(Json(PostSwapOfferResponse { offer_id }))
+    })
+    .await
+}
+
 pub(crate) async fn refresh_transfers(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<RefreshRequest>, APIError>,
@@ -3394,6 +3928,7 @@ pub(crate) async fn restore(
             Path::new(&payload.backup_path),
             &payload.password,
             &state.static_state.storage_dir_path,
+            payload.snapshot_id.as_deref(),
         )?;
 
         let _mnemonic =
@@ -3558,6 +4093,18 @@ pub(crate) async fn send_onion_message(
 pub(crate) async fn send_payment(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<SendPaymentRequest>, APIError>,
+) -> Result<Json<SendPaymentResponse>, APIError> {
+    send_payment_with_id(state, payload, None).await
+}
+
+/// Send a payment, using `offer_payment_id` as the payment ID when paying an offer
+///
+/// Bolt11 payments are always identified by their payment hash, so sending the same invoice
+/// twice is rejected as a duplicate. Offer payments get a random ID unless one is given.
+pub(crate) async fn send_payment_with_id(
+    state: Arc<AppState>,
+    payload: SendPaymentRequest,
+    offer_payment_id: Option<PaymentId>,
 ) -> Result<Json<SendPaymentResponse>, APIError> {
     no_cancel(async move {
         let unlocked_state = state.check_unlocked().await?.clone().unwrap();
@@ -3566,8 +4113,9 @@ pub(crate) async fn send_payment(
         let created_at = get_current_timestamp();
 
         let (payment_id, payment_hash, payment_secret) = if let Ok(offer) = Offer::from_str(&payload.invoice) {
-            let random_bytes = unlocked_state.keys_manager.get_secure_random_bytes();
-            let payment_id = PaymentId(random_bytes);
+            let payment_id = offer_payment_id.unwrap_or_else(|| {
+                PaymentId(unlocked_state.keys_manager.get_secure_random_bytes())
+            });
 
             let amt_msat = match (offer.amount(), payload.amt_msat) {
                 (Some(offer::Amount::Bitcoin { amount_msats }), _) => amount_msats,
@@ -3639,7 +4187,7 @@ pub(crate) async fn send_payment(
                 }
                 (payment_parameters_from_invoice(&invoice), invoice.amount_milli_satoshis().unwrap_or(0))
             };
-            let (payment_hash, recipient_onion, route_params) = match pay_params_opt {
+            let (payment_hash, recipient_onion, mut route_params) = match pay_params_opt {
                 Ok(res) => res,
                 Err(e) => {
                     return Err(APIError::InvalidInvoice(format!(
@@ -3648,6 +4196,9 @@ pub(crate) async fn send_payment(
                 },
             };
 
+            // explicit, as payments from user balances reserve the fee up front
+            route_params.max_total_routing_fee_msat = Some(max_routing_fee_msat(amt_msat));
+
             let rgb_payment = match (invoice.rgb_contract_id(), invoice.rgb_amount()) {
                 (Some(rgb_contract_id), Some(rgb_amount)) => {
                     if amt_msat < INVOICE_MIN_MSAT {
@@ -3750,6 +4301,21 @@ pub(crate) async fn sign_message(
     let unlocked_state = state.check_unlocked().await?.clone().unwrap();
 
     let message = payload.message.trim();
+
+    if let Some(user_id) = payload.user_id {
+        let hsm_provider = state
+            .hsm_service
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| APIError::Unexpected(s!("HSM provider not available")))?;
+        let signed_message = hsm_provider
+            .sign_message(&user_id, message.as_bytes())
+            .await
+            .map_err(|e| APIError::Unexpected(format!("Failed to sign message: {e}")))?;
+        return Ok(Json(SignMessageResponse { signed_message }));
+    }
+
     let signed_message = lightning::util::message_signing::sign(
         &message.as_bytes()[message.len()..],
         &unlocked_state.keys_manager.get_node_secret_key(),
@@ -3758,6 +4324,75 @@ pub(crate) async fn sign_message(
     Ok(Json(SignMessageResponse { signed_message }))
 }
 
+pub(crate) async fn swap_quote(
+    State(state): State<Arc<AppState>>,
+    WithRejection(Json(payload), _): WithRejection<Json<SwapQuoteRequest>, APIError>,
+) -> Result<Json<SwapQuoteResponse>, APIError> {
+    no_cancel(async move {
+        let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+
+        let from_asset = parse_swap_asset(&payload.from_asset)?;
+        let to_asset = parse_swap_asset(&payload.to_asset)?;
+        check_swap_assets(from_asset, to_asset)?;
+
+        let offers = unlocked_state.swap_offers();
+        let (offer_id, offer, qty_to) = match &payload.offer_id {
+            Some(offer_id) => {
+                let offer = offers
+                    .get(offer_id)
+                    .filter(|o| !o.is_expired() && o.is_pair(from_asset, to_asset))
+                    .ok_or_else(|| APIError::SwapOfferNotFound(offer_id.clone()))?;
+                let qty_to = offer
+                    .quote(payload.qty_from)
+                    .map_err(|e| APIError::InvalidSwap(e.to_string()))?;
+                (offer_id.clone(), offer, qty_to)
+            }
+            // pick the offer giving the taker the most
+            None => offers
+                .iter()
+                .filter(|(_, o)| !o.is_expired() && o.is_pair(from_asset, to_asset))
+                .filter(|(id, _)| {
+                    unlocked_state
+                        .offer_remaining_qty_from(id)
+                        .is_none_or(|remaining| remaining >= payload.qty_from)
+                        && unlocked_state.offer_open_quotes(id) < MAX_OPEN_QUOTES_PER_OFFER
+                })
+                .filter_map(|(id, o)| o.quote(payload.qty_from).ok().map(|q| (id.clone(), o, q)))
+                .max_by_key(|(_, _, qty_to)| *qty_to)
+                .ok_or(APIError::NoMatchingSwapOffer)?,
+        };
+
+        let expiry = get_current_timestamp() + offer.swap_timeout_sec as u64;
+        let swap_info = SwapInfo {
+            from_asset,
+            to_asset,
+            qty_from: payload.qty_from,
+            qty_to,
+            expiry,
+        };
+        // the offer fixes the price, a quote the policy doesn't accept is rejected as is
+        check_maker_price_policy(&state, &swap_info).await?;
+        let maker_init = init_maker_swap(
+            &state,
+            &unlocked_state,
+            swap_info,
+            offer.swap_timeout_sec,
+            Some(&offer_id),
+        )?;
+
+        Ok(Json(SwapQuoteResponse {
+            offer_id,
+            qty_from: payload.qty_from,
+            qty_to: maker_init.qty_to,
+            payment_hash: maker_init.payment_hash,
+            payment_secret: maker_init.payment_secret,
+            swapstring: maker_init.swapstring,
+            signed_swapstring: maker_init.signed_swapstring,
+        }))
+    })
+    .await
+}
+
 pub(crate) async fn sync(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<EmptyResponse>, APIError> {
@@ -3780,6 +4415,31 @@ pub(crate) async fn taker(
         let swapstring = SwapString::from_str(&payload.swapstring)
             .map_err(|e| APIError::InvalidSwapString(payload.swapstring.clone(), e.to_string()))?;
 
+        // a signature, when present, has been verified when parsing the swapstring
+        match (&payload.maker_pubkey, swapstring.maker_pubkey()) {
+            (Some(expected_maker), maker) => {
+                let expected_maker =
+                    PublicKey::from_str(expected_maker).map_err(|_| APIError::InvalidPubkey)?;
+                match maker {
+                    Some(maker) if maker == expected_maker => {}
+                    Some(maker) => {
+                        return Err(APIError::UnexpectedSwapMaker(format!(
+                            "signed by {maker} instead of {expected_maker}"
+                        )))
+                    }
+                    None => {
+                        return Err(APIError::UnexpectedSwapMaker(s!("swapstring is not signed")))
+                    }
+                }
+            }
+            (None, None) if !payload.allow_unsigned => {
+                return Err(APIError::UnexpectedSwapMaker(s!(
+                    "swapstring is not signed, set allow_unsigned to take it anyway"
+                )))
+            }
+            (None, _) => {}
+        }
+
         if get_current_timestamp() > swapstring.swap_info.expiry {
             return Err(APIError::ExpiredSwapOffer);
         }
@@ -3804,6 +4464,91 @@ pub(crate) async fn taker(
     .await
 }
 
+/// Change the quotas of a user, only the hub operator can call this
+pub(crate) async fn update_user_quotas(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<HubCaller>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateUserQuotasRequest>, APIError>,
+) -> Result<Json<UserQuotasResponse>, APIError> {
+    caller.require_admin()?;
+    let database = hub_database(&state).await?;
+
+    let mut quotas = database
+        .get_user_quotas(&payload.user_id)
+        .await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?
+        .unwrap_or_default();
+    payload.quotas.apply(&mut quotas);
+    database
+        .set_user_quotas(&payload.user_id, &quotas)
+        .await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?;
+    tracing::info!("Updated quotas of user {}", payload.user_id);
+
+    user_quotas_response(&database, payload.user_id).await
+}
+
+/// Get the quotas of a user and their usage, only the hub operator can call this
+pub(crate) async fn user_quotas(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<HubCaller>,
+    WithRejection(Json(payload), _): WithRejection<Json<UserQuotasRequest>, APIError>,
+) -> Result<Json<UserQuotasResponse>, APIError> {
+    caller.require_admin()?;
+    let database = hub_database(&state).await?;
+    user_quotas_response(&database, payload.user_id).await
+}
+
+async fn hub_database(state: &AppState) -> Result<Database, APIError> {
+    state
+        .database
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| APIError::Unexpected(s!("Database not available")))
+}
+
+async fn user_quotas_response(
+    database: &Database,
+    user_id: String,
+) -> Result<Json<UserQuotasResponse>, APIError> {
+    let quotas = database
+        .get_user_quotas(&user_id)
+        .await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?
+        .unwrap_or_default();
+    let usage = database
+        .get_quota_usage(&user_id)
+        .await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?;
+    Ok(Json(UserQuotasResponse {
+        user_id,
+        quotas,
+        usage,
+    }))
+}
+
+pub(crate) async fn verify_backup(
+    State(state): State<Arc<AppState>>,
+    WithRejection(Json(payload), _): WithRejection<Json<VerifyBackupRequest>, APIError>,
+) -> Result<Json<VerifyBackupResponse>, APIError> {
+    let bitcoin_network = state.static_state.network;
+    let storage_dir_path = state.static_state.storage_dir_path.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        do_verify_backup(
+            Path::new(&payload.backup_path),
+            &payload.password,
+            payload.snapshot_id.as_deref(),
+            bitcoin_network,
+            &storage_dir_path,
+        )
+    })
+    .await
+    .unwrap()?;
+
+    Ok(Json(report))
+}
+
 pub(crate) async fn virtual_node_id(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<serde_json::Value>, APIError>,
@@ -3822,14 +4567,16 @@ pub(crate) async fn virtual_node_id(
     })))
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub(crate) struct VirtualTransferRequest {
     pub(crate) from_user_id: i64,  // telegram_id
     pub(crate) to_user_id: i64,    // telegram_id
     pub(crate) amount_sats: u64,
+    #[serde(default, skip_serializing)]
+    pub(crate) idempotency_key: Option<String>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub(crate) struct VirtualTransferResponse {
     pub(crate) success: bool,
     pub(crate) transaction_id: Option<String>,
@@ -3838,7 +4585,25 @@ pub(crate) struct VirtualTransferResponse {
 
 pub(crate) async fn virtual_transfer(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     WithRejection(Json(payload), _): WithRejection<Json<VirtualTransferRequest>, APIError>,
+) -> Result<Json<VirtualTransferResponse>, APIError> {
+    let idempotency_key =
+        extract_idempotency_key(&headers, payload.idempotency_key.as_deref())?;
+    // retries of a request share its ledger reference, so the transfer is made once
+    let reference = idempotency_key
+        .as_deref()
+        .map(|key| idempotent_reference("vt", "virtual_transfer", key));
+    run_idempotent(&state, "virtual_transfer", idempotency_key, &payload, || {
+        execute_virtual_transfer_request(&state, &payload, reference)
+    })
+    .await
+}
+
+async fn execute_virtual_transfer_request(
+    state: &Arc<AppState>,
+    payload: &VirtualTransferRequest,
+    reference: Option<String>,
 ) -> Result<Json<VirtualTransferResponse>, APIError> {
     let unlocked_state = state.check_unlocked().await?.clone().unwrap();
     
@@ -3849,7 +4614,7 @@ pub(crate) async fn virtual_transfer(
     if std::env::var("DATABASE_URL").is_ok() {
         if state.database.lock().await.is_none() {
             tracing::info!("Initializing multi-user database on-demand for virtual transfer...");
-            if let Err(e) = crate::utils::initialize_database_after_unlock(&state).await {
+            if let Err(e) = crate::utils::initialize_database_after_unlock(state).await {
                 tracing::error!("Failed to initialize multi-user database: {}", e);
                 return Ok(Json(VirtualTransferResponse {
                     success: false,
@@ -3863,9 +4628,15 @@ pub(crate) async fn virtual_transfer(
     // Get database connection
     let database = state.database.lock().await;
     if let Some(db) = database.as_ref() {
-        // Check sender's balance using blockchain service if available
+        // Create virtual balance manager
+        let balance_manager = crate::virtual_balance::VirtualBalanceManager::new(Arc::new(db.clone()));
+        
+        // Check sender's on-chain balance using blockchain service if available, ledger
+        // transfers are checked against the ledger balance when posted
         let user_manager = state.user_manager.lock().await;
-        if let Some(user_mgr) = user_manager.as_ref() {
+        if let (VirtualTransferMode::OnChain, Some(user_mgr)) =
+            (balance_manager.transfer_mode(), user_manager.as_ref())
+        {
             let sender_balance = user_mgr.get_user_balance(&payload.from_user_id.to_string(), None).await
                 .unwrap_or(0);
             
@@ -3880,18 +4651,29 @@ pub(crate) async fn virtual_transfer(
             tracing::info!("Balance check passed: user {} has {} sats, transferring {} sats", 
                 payload.from_user_id, sender_balance, payload.amount_sats);
         }
+        drop(user_manager);
         
-        // Create virtual balance manager
-        let balance_manager = crate::virtual_balance::VirtualBalanceManager::new(Arc::new(db.clone()));
-        
-        // Execute virtual transfer
-        match balance_manager.execute_virtual_transfer(
-            payload.from_user_id,
-            payload.to_user_id,
-            payload.amount_sats,
-            &unlocked_state,
-            &state,
-        ).await {
+        // Execute virtual transfer within the sender's quotas
+        let spend = QuotaSpend {
+            action: QuotaAction::Transaction,
+            sats: payload.amount_sats,
+            asset: None,
+        };
+        let transfer = with_quota(db, &payload.from_user_id.to_string(), &spend, async {
+            balance_manager
+                .execute_virtual_transfer(
+                    payload.from_user_id,
+                    payload.to_user_id,
+                    payload.amount_sats,
+                    reference,
+                    &unlocked_state,
+                    state,
+                )
+                .await
+                .map_err(APIError::from)
+        })
+        .await;
+        match transfer {
             Ok(transaction_id) => {
                 tracing::info!("Virtual transfer completed: {} -> {} ({} sats), tx: {}", 
                     payload.from_user_id, payload.to_user_id, payload.amount_sats, transaction_id);
@@ -3920,6 +4702,87 @@ pub(crate) async fn virtual_transfer(
     }
 }
 
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct VirtualWithdrawRequest {
+    pub(crate) user_id: i64,  // telegram_id
+    pub(crate) address: String,
+    pub(crate) amount_sats: u64,
+    pub(crate) fee_rate: Option<u64>,
+    #[serde(default, skip_serializing)]
+    pub(crate) idempotency_key: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct VirtualWithdrawResponse {
+    pub(crate) txid: String,
+    pub(crate) fee_sats: u64,
+}
+
+pub(crate) async fn virtual_withdraw(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    WithRejection(Json(payload), _): WithRejection<Json<VirtualWithdrawRequest>, APIError>,
+) -> Result<Json<VirtualWithdrawResponse>, APIError> {
+    let idempotency_key =
+        extract_idempotency_key(&headers, payload.idempotency_key.as_deref())?;
+    // retries of a request share its ledger reference, so the withdrawal is debited once
+    let reference = idempotency_key
+        .as_deref()
+        .map(|key| idempotent_reference("withdraw", "virtual_withdraw", key));
+    run_idempotent(&state, "virtual_withdraw", idempotency_key, &payload, || {
+        execute_virtual_withdraw_request(&state, &payload, reference)
+    })
+    .await
+}
+
+async fn execute_virtual_withdraw_request(
+    state: &Arc<AppState>,
+    payload: &VirtualWithdrawRequest,
+    reference: Option<String>,
+) -> Result<Json<VirtualWithdrawResponse>, APIError> {
+    let unlocked_state = state.check_unlocked().await?.clone().unwrap();
+
+    tracing::info!("Virtual withdraw request: user {} -> {} ({} sats)",
+        payload.user_id, payload.address, payload.amount_sats);
+
+    let db = state
+        .database
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| APIError::Unexpected(s!("Database not available")))?;
+    let balance_manager = crate::virtual_balance::VirtualBalanceManager::new(Arc::new(db.clone()));
+
+    // the chain fee isn't known yet, so only the amount counts against the quotas
+    let spend = QuotaSpend {
+        action: QuotaAction::Transaction,
+        sats: payload.amount_sats,
+        asset: None,
+    };
+    let (txid, fee_sats) = with_quota(&db, &payload.user_id.to_string(), &spend, async {
+        balance_manager
+            .withdraw(
+                payload.user_id,
+                payload.address.clone(),
+                payload.amount_sats,
+                payload.fee_rate.unwrap_or(crate::virtual_balance::DEFAULT_ONCHAIN_FEE_RATE),
+                reference,
+                unlocked_state,
+            )
+            .await
+            .map_err(|e| match e {
+                BalanceError::InsufficientBtc { required, available } => {
+                    APIError::InsufficientFunds(required - available)
+                }
+                BalanceError::PaymentInProgress(_) => e.into(),
+                _ => APIError::Unexpected(e.to_string()),
+            })
+    })
+    .await?;
+
+    Ok(Json(VirtualWithdrawResponse { txid, fee_sats }))
+}
+
 pub(crate) async fn unlock(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<UnlockRequest>, APIError>,
@@ -3969,10 +4832,6 @@ pub(crate) async fn unlock(
 
         state.update_ldk_background_services(Some(new_ldk_background_services));
 
-        // Note: Multi-user database initialization disabled during unlock to avoid conflicts
-        // Multi-user features will be initialized on-demand when first accessed
-        tracing::debug!("RGB library initialized successfully. Multi-user database will be initialized on-demand.");
-
         state.update_changing_state(false);
 
         tracing::info!("Unlock completed");
@@ -3980,38 +4839,3 @@ pub(crate) async fn unlock(
     })
     .await
 }
-
-#[derive(serde::Deserialize)]
-pub(crate) struct PaymentWebhookRequest {
-    pub(crate) payment_hash: String,
-    pub(crate) amount_msat: u64,
-    pub(crate) metadata: Option<String>,
-}
-
-pub(crate) async fn payment_webhook(
-    State(state): State<Arc<AppState>>,
-    WithRejection(Json(payload), _): WithRejection<Json<PaymentWebhookRequest>, APIError>,
-) -> Result<Json<EmptyResponse>, APIError> {
-    let _unlocked_state = state.check_unlocked().await?.clone().unwrap();
-    
-    tracing::info!("Received payment webhook: hash={}, amount={} msat", 
-        payload.payment_hash, payload.amount_msat);
-    
-    // Process inbound payment
-    let handler = crate::inbound_payment_handler::InboundPaymentHandler::new(state);
-    
-    match handler.handle_payment_webhook(
-        payload.payment_hash,
-        payload.amount_msat,
-        payload.metadata,
-    ).await {
-        Ok(_) => {
-            tracing::info!("Inbound payment processed successfully");
-            Ok(Json(EmptyResponse {}))
-        }
-        Err(e) => {
-            tracing::error!("Failed to process inbound payment: {}", e);
-            Err(APIError::Unexpected(e.to_string()))
-        }
-    }
-}
@@ -1,3 +1,6 @@
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use lightning::ln::channelmanager::InterceptId;
+use lightning::util::message_signing;
 use lightning::{impl_writeable_tlv_based, ln::PaymentHash};
 use rgb_lib::ContractId;
 use std::convert::TryInto;
@@ -9,6 +12,13 @@ use crate::{
     utils::{get_current_timestamp, hex_str_to_vec},
 };
 
+/// Time after which a pending swap is considered failed
+pub(crate) const PENDING_SWAP_TIMEOUT_SECS: u64 = 86400;
+
+/// Maximum number of waiting quotes an offer can have at once, so quote requests can't pile up
+/// maker swaps until they expire
+pub(crate) const MAX_OPEN_QUOTES_PER_OFFER: usize = 20;
+
 #[derive(Debug, Clone)]
 pub(crate) struct SwapData {
     pub(crate) swap_info: SwapInfo,
@@ -16,6 +26,10 @@ pub(crate) struct SwapData {
     pub(crate) requested_at: u64,
     pub(crate) initiated_at: Option<u64>,
     pub(crate) completed_at: Option<u64>,
+    pub(crate) offer_id: Option<String>,
+    /// The intercepted HTLC of a taker swap, recorded as soon as it's intercepted so the sweeper
+    /// can fail it if the swap times out before it's forwarded
+    pub(crate) intercept_id: Option<InterceptId>,
 }
 
 impl_writeable_tlv_based!(SwapData, {
@@ -24,6 +38,8 @@ impl_writeable_tlv_based!(SwapData, {
     (2, requested_at, required),
     (3, initiated_at, option),
     (4, completed_at, option),
+    (5, offer_id, option),
+    (7, intercept_id, option),
 });
 
 impl SwapData {
@@ -34,6 +50,115 @@ impl SwapData {
             requested_at: get_current_timestamp(),
             initiated_at: None,
             completed_at: None,
+            offer_id: None,
+            intercept_id: None,
+        }
+    }
+
+    pub(crate) fn create_from_offer(swap_info: &SwapInfo, offer_id: &str) -> Self {
+        Self {
+            offer_id: Some(offer_id.to_string()),
+            ..Self::create_from_swap_info(swap_info)
+        }
+    }
+
+    /// The status a waiting or pending swap moves to once it has timed out at `now`
+    pub(crate) fn timed_out_status(&self, now: u64) -> Option<SwapStatus> {
+        match self.status {
+            SwapStatus::Waiting if now > self.swap_info.expiry => Some(SwapStatus::Expired),
+            SwapStatus::Pending
+                if now > self.initiated_at.unwrap_or_default() + PENDING_SWAP_TIMEOUT_SECS =>
+            {
+                Some(SwapStatus::Failed)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_completed(&self) -> bool {
+        matches!(
+            self.status,
+            SwapStatus::Succeeded | SwapStatus::Expired | SwapStatus::Failed
+        )
+    }
+}
+
+/// A standing maker offer in the local orderbook
+///
+/// Takers request quotes against it for any `qty_from` in the offer range, each accepted quote
+/// becoming a regular maker swap. An offer with a total size can be filled by several swaps until
+/// the size is used up.
+#[derive(Debug, Clone)]
+pub(crate) struct SwapOfferData {
+    pub(crate) from_asset: Option<ContractId>,
+    pub(crate) to_asset: Option<ContractId>,
+    /// The maker gives `price_qty_to` of `to_asset` for every `price_qty_from` of `from_asset`
+    pub(crate) price_qty_from: u64,
+    pub(crate) price_qty_to: u64,
+    pub(crate) min_qty_from: u64,
+    pub(crate) max_qty_from: u64,
+    /// Timeout of the swaps created from this offer
+    pub(crate) swap_timeout_sec: u32,
+    pub(crate) created_at: u64,
+    pub(crate) expires_at: Option<u64>,
+    /// Total `qty_from` that can be filled across all swaps, unlimited if not set
+    pub(crate) total_qty_from: Option<u64>,
+}
+
+impl_writeable_tlv_based!(SwapOfferData, {
+    (0, from_asset, required),
+    (1, to_asset, required),
+    (2, price_qty_from, required),
+    (3, price_qty_to, required),
+    (4, min_qty_from, required),
+    (5, max_qty_from, required),
+    (6, swap_timeout_sec, required),
+    (7, created_at, required),
+    (8, expires_at, option),
+    (9, total_qty_from, option),
+});
+
+impl SwapOfferData {
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| get_current_timestamp() > expires_at)
+    }
+
+    pub(crate) fn is_pair(&self, from_asset: Option<ContractId>, to_asset: Option<ContractId>) -> bool {
+        self.from_asset == from_asset && self.to_asset == to_asset
+    }
+
+    /// Amount of `to_asset` the maker gives for `qty_from`, rounded down
+    pub(crate) fn quote(&self, qty_from: u64) -> Result<u64, &'static str> {
+        if qty_from < self.min_qty_from || qty_from > self.max_qty_from {
+            return Err("Quantity outside the offer range");
+        }
+        let qty_to = qty_from as u128 * self.price_qty_to as u128 / self.price_qty_from as u128;
+        match u64::try_from(qty_to) {
+            Ok(0) => Err("Quantity too small for the offer price"),
+            Ok(qty_to) => Ok(qty_to),
+            Err(_) => Err("Quantity too large for the offer price"),
+        }
+    }
+}
+
+/// Why a swap can't be added to an offer
+#[derive(Debug, PartialEq)]
+pub(crate) enum OfferFillError {
+    /// The swap is larger than the offer size left
+    Exhausted { remaining: u64 },
+    /// The offer already has [`MAX_OPEN_QUOTES_PER_OFFER`] waiting quotes
+    TooManyOpenQuotes,
+}
+
+impl fmt::Display for OfferFillError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Exhausted { remaining } => write!(f, "only {remaining} left in the offer"),
+            Self::TooManyOpenQuotes => write!(
+                f,
+                "the offer already has {MAX_OPEN_QUOTES_PER_OFFER} open quotes, retry later"
+            ),
         }
     }
 }
@@ -93,10 +218,23 @@ impl SwapInfo {
     }
 }
 
+/// Prefix of the message signed by the maker, so a swap signature can't be a valid signature of
+/// anything else made with the node key
+const SWAP_SIGNATURE_DOMAIN: &str = "rln-swap:";
+
+/// The maker's signature over the swap terms, made with its node key
+#[derive(Debug, Clone)]
+pub(crate) struct SwapSignature {
+    pub(crate) maker_pubkey: PublicKey,
+    /// zbase32 encoded LN message signature
+    pub(crate) signature: String,
+}
+
 #[derive(Debug)]
 pub(crate) struct SwapString {
     pub(crate) swap_info: SwapInfo,
     pub(crate) payment_hash: PaymentHash,
+    pub(crate) signature: Option<SwapSignature>,
 }
 
 impl SwapString {
@@ -104,14 +242,28 @@ impl SwapString {
         Self {
             swap_info: swap_info.clone(),
             payment_hash,
+            signature: None,
         }
     }
-}
 
-impl fmt::Display for SwapString {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
+    /// Sign the swap terms with the maker's node key
+    pub(crate) fn signed(mut self, node_secret: &SecretKey) -> Self {
+        let maker_pubkey = PublicKey::from_secret_key(&Secp256k1::signing_only(), node_secret);
+        let signature = message_signing::sign(self.signed_message().as_bytes(), node_secret);
+        self.signature = Some(SwapSignature {
+            maker_pubkey,
+            signature,
+        });
+        self
+    }
+
+    /// The node ID of the maker, if the swapstring is signed
+    pub(crate) fn maker_pubkey(&self) -> Option<PublicKey> {
+        self.signature.as_ref().map(|s| s.maker_pubkey)
+    }
+
+    fn terms(&self) -> String {
+        format!(
             "{}/{}/{}/{}/{}/{}",
             self.swap_info.qty_from,
             self.swap_info
@@ -127,11 +279,26 @@ impl fmt::Display for SwapString {
             self.payment_hash,
         )
     }
+
+    fn signed_message(&self) -> String {
+        format!("{SWAP_SIGNATURE_DOMAIN}{}", self.terms())
+    }
+}
+
+impl fmt::Display for SwapString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.terms())?;
+        if let Some(signature) = &self.signature {
+            write!(f, "/{}/{}", signature.maker_pubkey, signature.signature)?;
+        }
+        Ok(())
+    }
 }
 
 impl FromStr for SwapString {
     type Err = &'static str;
 
+    /// Parse a swapstring, verifying the maker signature when it's signed
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut iter = s.split('/');
         let qty_from = iter.next();
@@ -140,8 +307,13 @@ impl FromStr for SwapString {
         let to_asset = iter.next();
         let expiry = iter.next();
         let payment_hash = iter.next();
+        let maker_pubkey = iter.next();
+        let signature = iter.next();
 
-        if payment_hash.is_none() || iter.next().is_some() {
+        if payment_hash.is_none()
+            || maker_pubkey.is_some() != signature.is_some()
+            || iter.next().is_some()
+        {
             return Err("Wrong number of parts");
         }
 
@@ -195,9 +367,28 @@ impl FromStr for SwapString {
             return Err("From and to assets should be different");
         }
 
-        Ok(SwapString {
+        let mut swapstring = SwapString {
             swap_info,
             payment_hash,
-        })
+            signature: None,
+        };
+
+        if let (Some(maker_pubkey), Some(signature)) = (maker_pubkey, signature) {
+            let maker_pubkey =
+                PublicKey::from_str(maker_pubkey).map_err(|_| "Invalid maker pubkey")?;
+            if !message_signing::verify(
+                swapstring.signed_message().as_bytes(),
+                signature,
+                &maker_pubkey,
+            ) {
+                return Err("Invalid maker signature");
+            }
+            swapstring.signature = Some(SwapSignature {
+                maker_pubkey,
+                signature: signature.to_string(),
+            });
+        }
+
+        Ok(swapstring)
     }
 }
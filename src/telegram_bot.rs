@@ -0,0 +1,545 @@
+//! Built-in Telegram bot.
+//!
+//! When a bot token is configured the node long-polls the Telegram Bot API, runs chat commands
+//! for the Telegram user sending them and notifies users of the payments they receive. Users are
+//! identified by their Telegram ID, as in the virtual node API. The Bot API URL is configurable,
+//! so a local Bot API server or a mock can stand in for Telegram.
+
+use axum::{extract::State, Json};
+use axum_extra::extract::WithRejection;
+use lightning_invoice::Bolt11Invoice;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::database::Database;
+use crate::error::APIError;
+use crate::lightning_router::LightningRouter;
+use crate::quota::{with_quota, QuotaAction, QuotaSpend};
+use crate::routes::{max_routing_fee_msat, DecodeRGBInvoiceRequest};
+use crate::utils::{hex_str, AppState, UserPaymentEvent};
+use crate::virtual_api::create_user_ln_invoice;
+use crate::virtual_balance::{
+    BalanceError, UserAssetSend, VirtualBalanceManager, DEFAULT_ONCHAIN_FEE_RATE,
+};
+
+pub(crate) const TELEGRAM_BOT_TOKEN_ENV: &str = "TELEGRAM_BOT_TOKEN";
+pub(crate) const TELEGRAM_BOT_API_URL_ENV: &str = "TELEGRAM_BOT_API_URL";
+const DEFAULT_TELEGRAM_BOT_API_URL: &str = "https://api.telegram.org";
+
+/// How long a getUpdates call waits for new messages
+const POLL_TIMEOUT_SECS: u64 = 30;
+/// Wait before polling again after a failed getUpdates call
+const POLL_RETRY_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const INVOICE_EXPIRY_SECS: u32 = 3600;
+
+pub(crate) const HELP: &str = "Commands:\n\
+/balance [asset_id] - show your balances\n\
+/invoice <amount_sats> - create a Lightning invoice paying you\n\
+/pay <bolt11_invoice> [amount_msat] - pay a Lightning invoice\n\
+/send <rgb_invoice> [amount] - send an RGB asset";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TelegramBotError {
+    #[error("Bot API request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Bot API error: {0}")]
+    Api(String),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct TelegramBotConfig {
+    pub(crate) token: String,
+    pub(crate) api_url: String,
+}
+
+impl TelegramBotConfig {
+    /// Read the bot config from the environment, `None` when no bot token is set
+    pub(crate) fn from_env() -> Option<Self> {
+        let token = std::env::var(TELEGRAM_BOT_TOKEN_ENV)
+            .ok()
+            .filter(|token| !token.is_empty())?;
+        let api_url = std::env::var(TELEGRAM_BOT_API_URL_ENV)
+            .unwrap_or_else(|_| DEFAULT_TELEGRAM_BOT_API_URL.to_string());
+        Some(Self { token, api_url })
+    }
+}
+
+#[derive(Deserialize)]
+struct BotApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Update {
+    pub(crate) update_id: i64,
+    pub(crate) message: Option<Message>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Message {
+    pub(crate) chat: Chat,
+    pub(crate) from: Option<User>,
+    pub(crate) text: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Chat {
+    pub(crate) id: i64,
+    #[serde(rename = "type")]
+    pub(crate) kind: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct User {
+    pub(crate) id: i64,
+}
+
+/// Client for the Telegram Bot API
+pub(crate) struct BotApi {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl BotApi {
+    pub(crate) fn new(config: &TelegramBotConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: format!(
+                "{}/bot{}",
+                config.api_url.trim_end_matches('/'),
+                config.token
+            ),
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<T, TelegramBotError> {
+        // the Bot API describes failures in the body, whatever the HTTP status
+        let response: BotApiResponse<T> = self
+            .client
+            .post(format!("{}/{}", self.base_url, method))
+            .json(&params)
+            .timeout(timeout)
+            .send()
+            .await?
+            .json()
+            .await?;
+        match response {
+            BotApiResponse {
+                ok: true,
+                result: Some(result),
+                ..
+            } => Ok(result),
+            response => Err(TelegramBotError::Api(
+                response
+                    .description
+                    .unwrap_or_else(|| format!("{method} failed")),
+            )),
+        }
+    }
+
+    /// Long-poll for messages with an update ID of at least `offset`
+    pub(crate) async fn get_updates(
+        &self,
+        offset: i64,
+        timeout_secs: u64,
+    ) -> Result<Vec<Update>, TelegramBotError> {
+        self.call(
+            "getUpdates",
+            serde_json::json!({
+                "offset": offset,
+                "timeout": timeout_secs,
+                "allowed_updates": ["message"],
+            }),
+            REQUEST_TIMEOUT + Duration::from_secs(timeout_secs),
+        )
+        .await
+    }
+
+    pub(crate) async fn send_message(
+        &self,
+        chat_id: i64,
+        text: &str,
+    ) -> Result<(), TelegramBotError> {
+        let _: serde_json::Value = self
+            .call(
+                "sendMessage",
+                serde_json::json!({ "chat_id": chat_id, "text": text }),
+                REQUEST_TIMEOUT,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum BotCommand {
+    Help,
+    Balance {
+        asset_id: Option<String>,
+    },
+    Invoice {
+        amount_sats: u64,
+    },
+    Pay {
+        invoice: String,
+        amount_msat: Option<u64>,
+    },
+    Send {
+        invoice: String,
+        amount: Option<u64>,
+    },
+}
+
+impl BotCommand {
+    /// Parse a chat message, on failure returning the text to reply with
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let mut words = text.split_whitespace();
+        // in groups commands can be addressed as /command@BotName
+        let command = words
+            .next()
+            .and_then(|word| word.split('@').next())
+            .unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let amount = |arg: Option<&&str>| {
+            arg.map(|arg| {
+                arg.parse::<u64>()
+                    .ok()
+                    .filter(|amount| *amount > 0)
+                    .ok_or_else(|| format!("Invalid amount: {arg}"))
+            })
+            .transpose()
+        };
+
+        match (command, args.as_slice()) {
+            ("/start" | "/help", _) => Ok(Self::Help),
+            ("/balance", []) => Ok(Self::Balance { asset_id: None }),
+            ("/balance", [asset_id]) => Ok(Self::Balance {
+                asset_id: Some(asset_id.to_string()),
+            }),
+            ("/invoice", [arg]) => match amount(Some(arg))? {
+                Some(amount_sats) if amount_sats.checked_mul(1000).is_some() => {
+                    Ok(Self::Invoice { amount_sats })
+                }
+                _ => Err(format!("Invalid amount: {arg}")),
+            },
+            ("/pay", [invoice, rest @ ..]) if rest.len() <= 1 => Ok(Self::Pay {
+                invoice: invoice.to_string(),
+                amount_msat: amount(rest.first())?,
+            }),
+            ("/send", [invoice, rest @ ..]) if rest.len() <= 1 => Ok(Self::Send {
+                invoice: invoice.to_string(),
+                amount: amount(rest.first())?,
+            }),
+            ("/balance" | "/invoice" | "/pay" | "/send", _) => {
+                Err(format!("Invalid arguments for {command}\n\n{HELP}"))
+            }
+            _ => Err(format!("Unknown command {command}\n\n{HELP}")),
+        }
+    }
+}
+
+/// Describe a user's ledger balances, or the one of `asset_id`
+pub(crate) fn balance_message(balances: &HashMap<String, i64>, asset_id: Option<&str>) -> String {
+    let balance = |key: &str| balances.get(key).copied().unwrap_or(0).max(0);
+    if let Some(asset_id) = asset_id {
+        return format!("{asset_id}: {}", balance(asset_id));
+    }
+    let mut assets: Vec<(&String, &i64)> = balances
+        .iter()
+        .filter(|(key, balance)| key.as_str() != "BTC" && **balance > 0)
+        .collect();
+    assets.sort();
+    let mut message = format!("BTC: {} sats", balance("BTC"));
+    for (asset_id, balance) in assets {
+        message.push_str(&format!("\n{asset_id}: {balance}"));
+    }
+    message
+}
+
+/// Chat and text notifying a user of a received payment, `None` for non-Telegram users
+pub(crate) fn payment_notification(event: &UserPaymentEvent) -> Option<(i64, String)> {
+    let chat_id = event.user_id.parse::<i64>().ok()?;
+    let amount = if event.amount_msat % 1000 == 0 {
+        format!("{} sats", event.amount_msat / 1000)
+    } else {
+        format!("{} msat", event.amount_msat)
+    };
+    Some((
+        chat_id,
+        format!("Received {amount}\nPayment hash: {}", event.payment_hash),
+    ))
+}
+
+/// RGB asset send made from a user's ledger balance
+pub(crate) struct UserAssetSent {
+    pub(crate) txid: String,
+    pub(crate) asset_id: String,
+    pub(crate) amount: u64,
+    /// On-chain fee debited from the user
+    pub(crate) fee_sats: u64,
+}
+
+/// Pay an RGB invoice from a user's ledger balance, within the user's quotas
+pub(crate) async fn send_user_asset(
+    app_state: &Arc<AppState>,
+    user_id: &str,
+    invoice: String,
+    asset_id: Option<String>,
+    amount: Option<u64>,
+    fee_rate: Option<u64>,
+    min_confirmations: Option<u8>,
+) -> Result<UserAssetSent, APIError> {
+    let unlocked_state = app_state.check_unlocked().await?.clone().unwrap();
+    let decoded = crate::routes::decode_rgb_invoice(
+        State(app_state.clone()),
+        WithRejection(Json(DecodeRGBInvoiceRequest { invoice }), PhantomData),
+    )
+    .await?
+    .0;
+    let send = UserAssetSend::new(decoded, asset_id, amount)?;
+    let asset_id = send.asset_id.clone();
+    let amount = send.units();
+
+    let database = database(app_state).await?;
+    let spend = QuotaSpend {
+        action: QuotaAction::Transaction,
+        sats: 0,
+        asset: Some((asset_id.clone(), amount)),
+    };
+    let balance_manager = VirtualBalanceManager::new(Arc::new(database.clone()));
+    let (txid, fee_sats) = with_quota(&database, user_id, &spend, async {
+        balance_manager
+            .send_asset(
+                user_id,
+                send,
+                fee_rate.unwrap_or(DEFAULT_ONCHAIN_FEE_RATE),
+                min_confirmations.unwrap_or(1),
+                unlocked_state,
+            )
+            .await
+            .map_err(APIError::from)
+    })
+    .await?;
+
+    Ok(UserAssetSent {
+        txid,
+        asset_id,
+        amount,
+        fee_sats,
+    })
+}
+
+/// Pay a Lightning invoice from a user's ledger balance, within the user's quotas
+///
+/// The most the node may pay in routing fees is reserved with the amount, the unused part is
+/// credited back once the payment succeeds. Returns the payment hash and status.
+pub(crate) async fn pay_user_invoice(
+    app_state: &Arc<AppState>,
+    user_id: &str,
+    invoice: String,
+    amount_msat: Option<u64>,
+) -> Result<(String, String), APIError> {
+    let bolt11 =
+        Bolt11Invoice::from_str(&invoice).map_err(|e| APIError::InvalidInvoice(e.to_string()))?;
+    let amt_msat = amount_msat
+        .or(bolt11.amount_milli_satoshis())
+        .ok_or_else(|| APIError::InvalidAmount("The invoice has no amount".to_string()))?;
+    let sats = amt_msat.div_ceil(1000);
+    let max_fee_sats = max_routing_fee_msat(amt_msat).div_ceil(1000);
+    let asset = bolt11
+        .rgb_contract_id()
+        .map(|contract_id| contract_id.to_string())
+        .zip(bolt11.rgb_amount());
+    let payment_hash = hex_str(bolt11.payment_hash().as_ref());
+
+    let database = database(app_state).await?;
+    let spend = QuotaSpend {
+        action: QuotaAction::Transaction,
+        sats,
+        asset: asset.clone(),
+    };
+    let balance_manager = VirtualBalanceManager::new(Arc::new(database.clone()));
+    let payment = with_quota(&database, user_id, &spend, async {
+        balance_manager
+            .fund_lightning_payment(
+                user_id,
+                &payment_hash,
+                sats,
+                max_fee_sats,
+                asset
+                    .as_ref()
+                    .map(|(asset_id, amount)| (asset_id.as_str(), *amount)),
+                async {
+                    let payment = LightningRouter::new(app_state.clone())
+                        .send_lightning_payment(invoice, amount_msat, None)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    if payment.status == "Failed" {
+                        return Err("Payment failed".to_string());
+                    }
+                    Ok(payment)
+                },
+            )
+            .await
+            .map_err(|e| match e {
+                BalanceError::TransferFailed(e) => APIError::FailedPayment(e),
+                e => e.into(),
+            })
+    })
+    .await?;
+
+    Ok((payment_hash, payment.status))
+}
+
+async fn database(app_state: &AppState) -> Result<Database, APIError> {
+    app_state
+        .database
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| APIError::Unexpected("Database not available".to_string()))
+}
+
+async fn run_command(
+    app_state: &Arc<AppState>,
+    user_id: &str,
+    command: BotCommand,
+) -> Result<String, APIError> {
+    match command {
+        BotCommand::Help => Ok(HELP.to_string()),
+        BotCommand::Balance { asset_id } => {
+            let balances = database(app_state)
+                .await?
+                .get_user_balances(user_id)
+                .await
+                .map_err(|e| APIError::Unexpected(e.to_string()))?;
+            Ok(balance_message(&balances, asset_id.as_deref()))
+        }
+        BotCommand::Invoice { amount_sats } => {
+            let invoice = create_user_ln_invoice(
+                app_state,
+                user_id,
+                Some(amount_sats * 1000),
+                INVOICE_EXPIRY_SECS,
+            )
+            .await?;
+            Ok(invoice.to_string())
+        }
+        BotCommand::Pay {
+            invoice,
+            amount_msat,
+        } => {
+            let (payment_hash, status) =
+                pay_user_invoice(app_state, user_id, invoice, amount_msat).await?;
+            Ok(format!(
+                "Payment {}\nPayment hash: {payment_hash}",
+                status.to_lowercase()
+            ))
+        }
+        BotCommand::Send { invoice, amount } => {
+            let sent =
+                send_user_asset(app_state, user_id, invoice, None, amount, None, None).await?;
+            Ok(format!(
+                "Sent {} of {}\nTransaction: {}\nFee: {} sats",
+                sent.amount, sent.asset_id, sent.txid, sent.fee_sats
+            ))
+        }
+    }
+}
+
+async fn handle_message(api: Arc<BotApi>, app_state: Arc<AppState>, message: Message) {
+    let Some(text) = message.text.filter(|text| text.starts_with('/')) else {
+        return;
+    };
+    let reply = match (message.chat.kind.as_str(), message.from) {
+        // balances and invoices must not be shown to a whole group
+        ("private", Some(user)) => match BotCommand::parse(&text) {
+            Ok(command) => run_command(&app_state, &user.id.to_string(), command)
+                .await
+                .unwrap_or_else(|e| format!("Error: {e}")),
+            Err(reply) => reply,
+        },
+        _ => "Commands are only available in a private chat with the bot".to_string(),
+    };
+    if let Err(e) = api.send_message(message.chat.id, &reply).await {
+        tracing::warn!(
+            "Failed to reply in Telegram chat {}: {}",
+            message.chat.id,
+            e
+        );
+    }
+}
+
+async fn notify_payments(
+    api: Arc<BotApi>,
+    app_state: Arc<AppState>,
+    mut payments: broadcast::Receiver<UserPaymentEvent>,
+) {
+    loop {
+        let event = tokio::select! {
+            _ = app_state.cancel_token.cancelled() => return,
+            event = payments.recv() => event,
+        };
+        match event {
+            Ok(event) => {
+                let Some((chat_id, text)) = payment_notification(&event) else {
+                    continue;
+                };
+                if let Err(e) = api.send_message(chat_id, &text).await {
+                    tracing::warn!("Failed to notify Telegram user {}: {}", chat_id, e);
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!("Telegram bot missed {} payment notifications", missed)
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Answer bot commands and send payment notifications until the node shuts down
+pub(crate) async fn run_telegram_bot(config: TelegramBotConfig, app_state: Arc<AppState>) {
+    tracing::info!("Starting Telegram bot using {}", config.api_url);
+    let api = Arc::new(BotApi::new(&config));
+    let payments = app_state.static_state.user_payments.subscribe();
+    tokio::spawn(notify_payments(api.clone(), app_state.clone(), payments));
+
+    let mut offset = 0;
+    loop {
+        let updates = tokio::select! {
+            _ = app_state.cancel_token.cancelled() => break,
+            updates = api.get_updates(offset, POLL_TIMEOUT_SECS) => updates,
+        };
+        match updates {
+            Ok(updates) => {
+                for update in updates {
+                    offset = offset.max(update.update_id + 1);
+                    if let Some(message) = update.message {
+                        tokio::spawn(handle_message(api.clone(), app_state.clone(), message));
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to get Telegram updates: {}", e);
+                tokio::select! {
+                    _ = app_state.cancel_token.cancelled() => break,
+                    _ = tokio::time::sleep(POLL_RETRY_DELAY) => {}
+                }
+            }
+        }
+    }
+    tracing::info!("Telegram bot stopped");
+}
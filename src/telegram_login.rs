@@ -0,0 +1,241 @@
+//! Verification of Telegram login data.
+//!
+//! Both the Login Widget payload and Mini App `initData` are signed by Telegram with an
+//! HMAC-SHA256 over their sorted fields, keyed by a secret derived from the bot token. Besides
+//! the signature, a login must be recent and each signed payload is accepted only once, so a
+//! leaked login URL can't be used to open sessions later on.
+
+use bitcoin::hex::{DisplayHex, FromHex};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Environment variable setting how long login data is accepted after Telegram signed it
+pub(crate) const TELEGRAM_AUTH_MAX_AGE_ENV: &str = "TELEGRAM_AUTH_MAX_AGE_SECS";
+pub(crate) const DEFAULT_TELEGRAM_AUTH_MAX_AGE_SECS: u64 = 600;
+
+/// Maximum clock difference accepted for an `auth_date` in the future
+pub(crate) const MAX_CLOCK_SKEW_SECS: i64 = 30;
+
+/// Key used to derive the Mini App secret from the bot token
+const MINI_APP_KEY: &[u8] = b"WebAppData";
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub(crate) enum TelegramLoginError {
+    #[error("Invalid Telegram login data: {0}")]
+    Malformed(String),
+    #[error("Invalid Telegram login hash")]
+    InvalidHash,
+    #[error("Telegram login data expired")]
+    Expired,
+    #[error("Telegram login data was already used")]
+    Replayed,
+}
+
+fn malformed(reason: &str) -> TelegramLoginError {
+    TelegramLoginError::Malformed(reason.to_string())
+}
+
+/// Telegram user from verified login data
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub(crate) struct TelegramLoginUser {
+    pub(crate) id: i64,
+    #[serde(default)]
+    pub(crate) first_name: String,
+    pub(crate) last_name: Option<String>,
+    pub(crate) username: Option<String>,
+}
+
+/// Data sent by the Login Widget
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct TelegramAuthData {
+    pub(crate) id: i64,
+    pub(crate) first_name: String,
+    pub(crate) last_name: Option<String>,
+    pub(crate) username: Option<String>,
+    pub(crate) photo_url: Option<String>,
+    pub(crate) auth_date: i64,
+    pub(crate) hash: String,
+}
+
+impl TelegramAuthData {
+    /// Fields signed by Telegram, as sent by the Login Widget
+    pub(crate) fn fields(&self) -> BTreeMap<String, String> {
+        let mut fields = BTreeMap::new();
+        fields.insert("id".to_string(), self.id.to_string());
+        fields.insert("first_name".to_string(), self.first_name.clone());
+        if let Some(last_name) = &self.last_name {
+            fields.insert("last_name".to_string(), last_name.clone());
+        }
+        if let Some(username) = &self.username {
+            fields.insert("username".to_string(), username.clone());
+        }
+        if let Some(photo_url) = &self.photo_url {
+            fields.insert("photo_url".to_string(), photo_url.clone());
+        }
+        fields.insert("auth_date".to_string(), self.auth_date.to_string());
+        fields.insert("hash".to_string(), self.hash.clone());
+        fields
+    }
+}
+
+/// Hashes of the logins accepted within the max age, shared by all login requests
+#[derive(Default)]
+pub(crate) struct LoginReplayCache {
+    used: Mutex<HashMap<String, i64>>,
+}
+
+impl LoginReplayCache {
+    /// Record a login hash, returning false if it was already used
+    ///
+    /// Logins signed before `expired_before` are dropped, they're rejected for their age anyway.
+    fn record(&self, hash: String, auth_date: i64, expired_before: i64) -> bool {
+        let mut used = self.used.lock().unwrap();
+        used.retain(|_, date| *date >= expired_before);
+        used.insert(hash, auth_date).is_none()
+    }
+}
+
+pub(crate) struct TelegramLoginVerifier<'a> {
+    bot_token: &'a str,
+    max_age_secs: i64,
+    replay_cache: &'a LoginReplayCache,
+}
+
+impl<'a> TelegramLoginVerifier<'a> {
+    pub(crate) fn new(
+        bot_token: &'a str,
+        max_age_secs: u64,
+        replay_cache: &'a LoginReplayCache,
+    ) -> Self {
+        Self {
+            bot_token,
+            max_age_secs: i64::try_from(max_age_secs).unwrap_or(i64::MAX),
+            replay_cache,
+        }
+    }
+
+    /// Verifier with the max age set by [`TELEGRAM_AUTH_MAX_AGE_ENV`]
+    pub(crate) fn from_env(bot_token: &'a str, replay_cache: &'a LoginReplayCache) -> Self {
+        let max_age_secs = std::env::var(TELEGRAM_AUTH_MAX_AGE_ENV)
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_TELEGRAM_AUTH_MAX_AGE_SECS);
+        Self::new(bot_token, max_age_secs, replay_cache)
+    }
+
+    /// Verify the fields sent by the Login Widget, including `hash`
+    pub(crate) fn verify_login_widget(
+        &self,
+        fields: &BTreeMap<String, String>,
+        now: i64,
+    ) -> Result<TelegramLoginUser, TelegramLoginError> {
+        let secret_key = Sha256::digest(self.bot_token.as_bytes());
+        self.verify(fields, &secret_key, now)?;
+
+        let field = |name: &str| fields.get(name).cloned();
+        Ok(TelegramLoginUser {
+            id: field("id")
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(|| malformed("missing or invalid id"))?,
+            first_name: field("first_name").unwrap_or_default(),
+            last_name: field("last_name"),
+            username: field("username"),
+        })
+    }
+
+    /// Verify the `initData` query string a Mini App is launched with
+    pub(crate) fn verify_init_data(
+        &self,
+        init_data: &str,
+        now: i64,
+    ) -> Result<TelegramLoginUser, TelegramLoginError> {
+        let fields = parse_query(init_data)?;
+        let mut secret_key =
+            Hmac::<Sha256>::new_from_slice(MINI_APP_KEY).expect("HMAC accepts keys of any size");
+        secret_key.update(self.bot_token.as_bytes());
+        self.verify(&fields, &secret_key.finalize().into_bytes(), now)?;
+
+        let user = fields
+            .get("user")
+            .ok_or_else(|| malformed("missing user"))?;
+        serde_json::from_str(user).map_err(|e| TelegramLoginError::Malformed(e.to_string()))
+    }
+
+    /// Check the signature, age and uniqueness of login data
+    fn verify(
+        &self,
+        fields: &BTreeMap<String, String>,
+        secret_key: &[u8],
+        now: i64,
+    ) -> Result<(), TelegramLoginError> {
+        let hash = fields
+            .get("hash")
+            .ok_or_else(|| malformed("missing hash"))?;
+        let hash = Vec::<u8>::from_hex(hash).map_err(|_| TelegramLoginError::InvalidHash)?;
+        let data_check_string = fields
+            .iter()
+            .filter(|(name, _)| name.as_str() != "hash")
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret_key).expect("HMAC accepts keys of any size");
+        mac.update(data_check_string.as_bytes());
+        mac.verify_slice(&hash)
+            .map_err(|_| TelegramLoginError::InvalidHash)?;
+
+        let auth_date: i64 = fields
+            .get("auth_date")
+            .and_then(|date| date.parse().ok())
+            .ok_or_else(|| malformed("missing or invalid auth_date"))?;
+        if auth_date > now.saturating_add(MAX_CLOCK_SKEW_SECS) {
+            return Err(malformed("auth_date is in the future"));
+        }
+        let expired_before = now.saturating_sub(self.max_age_secs);
+        if auth_date < expired_before {
+            return Err(TelegramLoginError::Expired);
+        }
+
+        // keyed by the decoded hash, so changing the case of the hex can't bypass the cache
+        if !self
+            .replay_cache
+            .record(hash.to_lower_hex_string(), auth_date, expired_before)
+        {
+            return Err(TelegramLoginError::Replayed);
+        }
+        Ok(())
+    }
+}
+
+/// Parse a URL encoded query string, rejecting repeated fields
+fn parse_query(query: &str) -> Result<BTreeMap<String, String>, TelegramLoginError> {
+    // the decoder keeps malformed escapes as they are, reject them rather than check a signature
+    // over data Telegram didn't send
+    let bytes = query.as_bytes();
+    let valid_escapes = bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'%')
+        .all(|(i, _)| {
+            bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+        });
+    if !valid_escapes {
+        return Err(malformed("invalid percent encoding"));
+    }
+
+    let mut fields = BTreeMap::new();
+    for (name, value) in form_urlencoded::parse(bytes) {
+        if fields
+            .insert(name.into_owned(), value.into_owned())
+            .is_some()
+        {
+            return Err(malformed("repeated field"));
+        }
+    }
+    Ok(fields)
+}
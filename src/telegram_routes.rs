@@ -1,25 +1,39 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Extension, State},
+    Json,
+};
 use axum_extra::extract::WithRejection;
 use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
 use std::sync::Arc;
 
 use crate::{
-    auth::Claims,
+    database::Database,
     error::APIError,
-    telegram_auth::{TelegramAuthData, TelegramAuthService, TelegramUser},
-    utils::AppState,
+    hub_auth::{HubCaller, HUB_SESSION_TTL_SECS},
+    quota::{with_quota, QuotaAction, QuotaSpend},
+    rate_limit::RouteClass,
+    routes::{ListAssetsRequest, OpenChannelRequest},
+    telegram_bot::send_user_asset,
+    telegram_login::{TelegramAuthData, TelegramLoginVerifier},
+    utils::{get_current_timestamp, AppState},
+    virtual_balance::{BalanceError, UserAssetBalance, VirtualBalanceManager},
 };
 
+/// Login with either Login Widget data or Mini App `initData`
 #[derive(Deserialize)]
 pub struct TelegramLoginRequest {
-    pub auth_data: TelegramAuthData,
+    pub auth_data: Option<TelegramAuthData>,
+    pub init_data: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct TelegramLoginResponse {
+    /// Hub session, sent as `Authorization: Bearer <token>`
     pub token: String,
     pub user_id: String,
     pub telegram_id: i64,
+    pub expires_at: u64,
 }
 
 #[derive(Deserialize)]
@@ -37,53 +51,92 @@ pub struct BotServiceResponse {
     pub message: Option<String>,
 }
 
-/// Telegram user login endpoint
+#[derive(Deserialize)]
+struct BotSendAssetData {
+    invoice: String,
+    /// Required when the invoice doesn't set the asset
+    asset_id: Option<String>,
+    /// Required when the invoice doesn't set the amount
+    amount: Option<u64>,
+    fee_rate: Option<u64>,
+    min_confirmations: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct BotCreateChannelData {
+    peer_pubkey_and_opt_addr: String,
+    capacity_sat: u64,
+    asset_id: Option<String>,
+    asset_amount: Option<u64>,
+    #[serde(default)]
+    public: bool,
+}
+
+/// Telegram user login endpoint, opening a hub session
+///
+/// The login data must be signed for the hub bot, recent and not used before.
 pub async fn telegram_login(
     State(state): State<Arc<AppState>>,
     WithRejection(Json(payload), _): WithRejection<Json<TelegramLoginRequest>, APIError>,
 ) -> Result<Json<TelegramLoginResponse>, APIError> {
-    let bot_token = std::env::var("BOT_TOKEN")
-        .map_err(|_| APIError::Unexpected("Bot token not configured".to_string()))?;
-
-    let auth_service = state.auth_service.lock().await;
-    let auth_service = auth_service.as_ref()
-        .ok_or(APIError::Unexpected("Auth service not initialized".to_string()))?;
-
-    let telegram_auth = TelegramAuthService::new(bot_token, auth_service.clone());
-    let token = telegram_auth.authenticate_telegram_user(payload.auth_data.clone())?;
-
-    // Initialize user in database if needed
-    let user_id = format!("tg_{}", payload.auth_data.id);
-    if let Some(user_manager) = state.user_manager.lock().await.as_ref() {
-        let _ = user_manager.get_user_context(&user_id).await;
+    let bot = state
+        .telegram_bot
+        .as_ref()
+        .ok_or_else(|| APIError::Forbidden("Telegram login is not enabled".to_string()))?;
+    let verifier = TelegramLoginVerifier::from_env(&bot.token, &state.telegram_logins);
+    let now = get_current_timestamp();
+    let telegram_user = match (payload.auth_data, payload.init_data) {
+        (Some(auth_data), None) => verifier.verify_login_widget(&auth_data.fields(), now as i64),
+        (None, Some(init_data)) => verifier.verify_init_data(&init_data, now as i64),
+        _ => {
+            return Err(APIError::InvalidRequest(
+                "Exactly one of auth_data and init_data is required".to_string(),
+            ))
+        }
     }
+    .map_err(|e| APIError::Unauthorized(e.to_string()))?;
 
+    let user_id = telegram_user.id.to_string();
+    tracing::info!("Telegram user {} logged in", user_id);
     Ok(Json(TelegramLoginResponse {
-        token,
+        token: state.hub_auth.issue_session(&user_id, now),
         user_id,
-        telegram_id: payload.auth_data.id,
+        telegram_id: telegram_user.id,
+        expires_at: now + HUB_SESSION_TTL_SECS,
     }))
 }
 
 /// Bot service proxy endpoint for bitMaskRGB bot
+///
+/// Users are identified by their Telegram user ID, which must be the one of the session.
 pub async fn bot_service_proxy(
     State(state): State<Arc<AppState>>,
-    claims: Claims,
+    Extension(caller): Extension<HubCaller>,
     WithRejection(Json(payload), _): WithRejection<Json<BotServiceRequest>, APIError>,
 ) -> Result<Json<BotServiceResponse>, APIError> {
     // Verify the request is from the correct Telegram user
-    let expected_user_id = format!("tg_{}", payload.telegram_user_id);
-    if claims.user_id != expected_user_id {
+    let user_id = caller.require_user()?;
+    if user_id != payload.telegram_user_id.to_string() {
         return Err(APIError::Forbidden("User ID mismatch".to_string()));
     }
+    // every request took a default token already, expensive commands also take an expensive one
+    let class = RouteClass::for_bot_command(&payload.command);
+    if class == RouteClass::Expensive {
+        state.rate_limiter.check(user_id, caller.role.rate_limit_role(), class).await?;
+    }
+
+    tracing::debug!(
+        "Bot command {} for user {} ({:?})",
+        payload.command, user_id, payload.telegram_username
+    );
 
     // Route command to appropriate handler
     let result = match payload.command.as_str() {
-        "get_balance" => handle_get_balance(state, &claims, payload.data).await,
-        "generate_address" => handle_generate_address(state, &claims, payload.data).await,
-        "send_asset" => handle_send_asset(state, &claims, payload.data).await,
-        "list_assets" => handle_list_assets(state, &claims, payload.data).await,
-        "create_channel" => handle_create_channel(state, &claims, payload.data).await,
+        "get_balance" => handle_get_balance(state, user_id, payload.data).await,
+        "generate_address" => handle_generate_address(state, user_id, payload.data).await,
+        "send_asset" => handle_send_asset(state, user_id, payload.data).await,
+        "list_assets" => handle_list_assets(state, user_id, payload.data).await,
+        "create_channel" => handle_create_channel(state, user_id, payload.data).await,
         _ => Err(APIError::InvalidRequest(format!("Unknown command: {}", payload.command))),
     };
 
@@ -103,7 +156,7 @@ pub async fn bot_service_proxy(
 
 async fn handle_get_balance(
     state: Arc<AppState>,
-    claims: &Claims,
+    user_id: &str,
     data: serde_json::Value,
 ) -> Result<serde_json::Value, APIError> {
     let asset_id = data.get("asset_id").and_then(|v| v.as_str());
@@ -112,7 +165,8 @@ async fn handle_get_balance(
     let user_manager = user_manager.as_ref()
         .ok_or(APIError::Unexpected("User manager not initialized".to_string()))?;
 
-    let balance = user_manager.get_user_balance(&claims.user_id, asset_id).await?;
+    let balance = user_manager.get_user_balance(user_id, asset_id).await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?;
     
     Ok(serde_json::json!({
         "balance": balance,
@@ -122,7 +176,7 @@ async fn handle_get_balance(
 
 async fn handle_generate_address(
     state: Arc<AppState>,
-    claims: &Claims,
+    user_id: &str,
     _data: serde_json::Value,
 ) -> Result<serde_json::Value, APIError> {
     let unlocked_state = state.check_unlocked().await?.clone().unwrap();
@@ -132,7 +186,8 @@ async fn handle_generate_address(
     let user_manager = user_manager.as_ref()
         .ok_or(APIError::Unexpected("User manager not initialized".to_string()))?;
 
-    user_manager.save_user_address(&claims.user_id, &address).await?;
+    user_manager.save_user_address(user_id, &address).await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?;
 
     Ok(serde_json::json!({
         "address": address
@@ -140,37 +195,111 @@ async fn handle_generate_address(
 }
 
 async fn handle_send_asset(
-    _state: Arc<AppState>,
-    _claims: &Claims,
-    _data: serde_json::Value,
+    state: Arc<AppState>,
+    user_id: &str,
+    data: serde_json::Value,
 ) -> Result<serde_json::Value, APIError> {
-    // Implement asset sending logic
+    let data: BotSendAssetData = serde_json::from_value(data)
+        .map_err(|e| APIError::InvalidRequest(e.to_string()))?;
+    let sent = send_user_asset(
+        &state,
+        user_id,
+        data.invoice,
+        data.asset_id,
+        data.amount,
+        data.fee_rate,
+        data.min_confirmations,
+    )
+    .await?;
+
     Ok(serde_json::json!({
-        "status": "pending",
-        "message": "Asset send functionality to be implemented"
+        "txid": sent.txid,
+        "asset_id": sent.asset_id,
+        "amount": sent.amount,
+        "fee_sats": sent.fee_sats
     }))
 }
 
 async fn handle_list_assets(
-    _state: Arc<AppState>,
-    _claims: &Claims,
+    state: Arc<AppState>,
+    user_id: &str,
     _data: serde_json::Value,
 ) -> Result<serde_json::Value, APIError> {
-    // Implement asset listing logic
+    let database = user_database(&state).await?;
+    let balances = database.get_user_balances(user_id).await
+        .map_err(|e| APIError::Unexpected(e.to_string()))?;
+    let node_assets = crate::routes::list_assets(
+        State(state.clone()),
+        WithRejection(Json(ListAssetsRequest { filter_asset_schemas: vec![] }), PhantomData),
+    )
+    .await?
+    .0;
+
     Ok(serde_json::json!({
-        "assets": [],
-        "message": "Asset listing functionality to be implemented"
+        "assets": UserAssetBalance::from_balances(&balances, &node_assets),
+        "btc_balance": balances.get("BTC").copied().unwrap_or_default().max(0)
     }))
 }
 
+/// Ask the hub to open a channel for the user, funded from their balance
 async fn handle_create_channel(
-    _state: Arc<AppState>,
-    _claims: &Claims,
-    _data: serde_json::Value,
+    state: Arc<AppState>,
+    user_id: &str,
+    data: serde_json::Value,
 ) -> Result<serde_json::Value, APIError> {
-    // Implement channel creation logic
+    let data: BotCreateChannelData = serde_json::from_value(data)
+        .map_err(|e| APIError::InvalidRequest(e.to_string()))?;
+    let peer_pubkey = data.peer_pubkey_and_opt_addr
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    let database = user_database(&state).await?;
+    let spend = QuotaSpend {
+        action: QuotaAction::CreateChannel,
+        sats: data.capacity_sat,
+        asset: data.asset_id.clone().zip(data.asset_amount),
+    };
+    let request = OpenChannelRequest {
+        peer_pubkey_and_opt_addr: data.peer_pubkey_and_opt_addr,
+        capacity_sat: data.capacity_sat,
+        push_msat: 0,
+        asset_amount: data.asset_amount,
+        asset_id: data.asset_id.clone(),
+        public: data.public,
+        with_anchors: true,
+        fee_base_msat: None,
+        fee_proportional_millionths: None,
+        temporary_channel_id: None,
+    };
+    let asset = data.asset_id.as_deref().zip(data.asset_amount);
+    let balance_manager = VirtualBalanceManager::new(Arc::new(database.clone()));
+    let temporary_channel_id = with_quota(&database, user_id, &spend, async {
+        balance_manager
+            .fund_channel_open(user_id, &peer_pubkey, data.capacity_sat, asset, async {
+                let response = crate::routes::open_channel(
+                    State(state.clone()),
+                    WithRejection(Json(request), PhantomData),
+                )
+                .await?;
+                Ok(response.0.temporary_channel_id)
+            })
+            .await
+            .map_err(|e| match e {
+                BalanceError::TransferFailed(e) => APIError::FailedOpenChannel(e),
+                e => e.into(),
+            })
+    })
+    .await?;
+
     Ok(serde_json::json!({
         "status": "pending",
-        "message": "Channel creation functionality to be implemented"
+        "temporary_channel_id": temporary_channel_id
     }))
-}
\ No newline at end of file
+}
+
+async fn user_database(state: &AppState) -> Result<Database, APIError> {
+    state.database.lock().await.clone()
+        .ok_or_else(|| APIError::Unexpected("Database not available".to_string()))
+}
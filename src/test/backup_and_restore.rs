@@ -53,6 +53,7 @@ async fn backup_and_restore() {
     let payload = BackupRequest {
         backup_path: node1_backup_path.clone(),
         password: node1_password.clone(),
+        incremental: false,
     };
     let res = reqwest::Client::new()
         .post(format!("http://{node1_addr}/backup"))
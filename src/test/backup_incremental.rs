@@ -0,0 +1,118 @@
+use crate::backup::{do_backup, do_incremental_backup, restore_backup};
+use crate::error::APIError;
+use std::fs::{create_dir_all, read, read_dir, write};
+use std::path::Path;
+
+const PASSWORD: &str = "backup-password";
+
+fn populate_wallet_dir(wallet_dir: &Path) {
+    create_dir_all(wallet_dir.join("monitors")).unwrap();
+    create_dir_all(wallet_dir.join("rgb").join("empty")).unwrap();
+    create_dir_all(wallet_dir.join("logs")).unwrap();
+    write(wallet_dir.join("mnemonic"), b"encrypted mnemonic").unwrap();
+    write(wallet_dir.join("monitors").join("monitor_1"), b"monitor v1").unwrap();
+    // bigger than a chunk, so the file is split
+    let stash: Vec<u8> = (0..(3 * 1024 * 1024 + 17))
+        .map(|i| (i % 251) as u8)
+        .collect();
+    write(wallet_dir.join("rgb").join("stash.db"), stash).unwrap();
+    write(wallet_dir.join("logs").join("log"), b"not backed up").unwrap();
+}
+
+fn chunk_count(backup_dir: &Path) -> usize {
+    read_dir(backup_dir.join("chunks")).unwrap().count()
+}
+
+#[test]
+fn incremental_backup_stores_only_changed_chunks() {
+    let tmp = tempfile::tempdir().unwrap();
+    let wallet_dir = tmp.path().join("wallet");
+    let backup_dir = tmp.path().join("backup");
+    populate_wallet_dir(&wallet_dir);
+
+    let first = do_incremental_backup(&wallet_dir, &backup_dir, PASSWORD).unwrap();
+    assert_eq!(first.snapshot_id, "00000001");
+    // mnemonic + monitor + 4 stash chunks
+    assert_eq!(first.new_chunks, 6);
+    assert_eq!(first.reused_chunks, 0);
+    assert_eq!(chunk_count(&backup_dir), 6);
+
+    write(
+        wallet_dir.join("monitors").join("monitor_1"),
+        b"monitor v2, updated",
+    )
+    .unwrap();
+    let second = do_incremental_backup(&wallet_dir, &backup_dir, PASSWORD).unwrap();
+    assert_eq!(second.snapshot_id, "00000002");
+    assert_eq!(second.new_chunks, 1);
+    assert_eq!(second.reused_chunks, 5);
+    assert_eq!(chunk_count(&backup_dir), 7);
+
+    // replay the first snapshot
+    let restored_first = tmp.path().join("restored_first");
+    restore_backup(&backup_dir, PASSWORD, &restored_first, Some("00000001")).unwrap();
+    assert_eq!(
+        read(restored_first.join("monitors").join("monitor_1")).unwrap(),
+        b"monitor v1"
+    );
+    assert_eq!(
+        read(restored_first.join("rgb").join("stash.db")).unwrap(),
+        read(wallet_dir.join("rgb").join("stash.db")).unwrap()
+    );
+    assert!(restored_first.join("rgb").join("empty").is_dir());
+    assert!(!restored_first.join("logs").exists());
+
+    // latest snapshot by default
+    let restored_latest = tmp.path().join("restored_latest");
+    restore_backup(&backup_dir, PASSWORD, &restored_latest, None).unwrap();
+    assert_eq!(
+        read(restored_latest.join("monitors").join("monitor_1")).unwrap(),
+        b"monitor v2, updated"
+    );
+    assert_eq!(
+        read(restored_latest.join("mnemonic")).unwrap(),
+        b"encrypted mnemonic"
+    );
+}
+
+#[test]
+fn incremental_backup_errors() {
+    let tmp = tempfile::tempdir().unwrap();
+    let wallet_dir = tmp.path().join("wallet");
+    let backup_dir = tmp.path().join("backup");
+    populate_wallet_dir(&wallet_dir);
+    do_incremental_backup(&wallet_dir, &backup_dir, PASSWORD).unwrap();
+
+    let err = do_incremental_backup(&wallet_dir, &backup_dir, "wrong-password").unwrap_err();
+    assert!(matches!(err, APIError::WrongPassword));
+    let err =
+        restore_backup(&backup_dir, "wrong-password", &tmp.path().join("r1"), None).unwrap_err();
+    assert!(matches!(err, APIError::WrongPassword));
+
+    let err = restore_backup(
+        &backup_dir,
+        PASSWORD,
+        &tmp.path().join("r2"),
+        Some("00000009"),
+    )
+    .unwrap_err();
+    assert!(matches!(err, APIError::BackupSnapshotNotFound(_)));
+
+    // a non-empty directory that is not a backup is never written to
+    let err = do_incremental_backup(&wallet_dir, &wallet_dir, PASSWORD).unwrap_err();
+    assert!(matches!(err, APIError::InvalidBackupPath));
+
+    // version 1 backups are single files without snapshots
+    let v1_backup = tmp.path().join("backup_v1");
+    do_backup(&wallet_dir, &v1_backup, PASSWORD).unwrap();
+    let err = do_incremental_backup(&wallet_dir, &v1_backup, PASSWORD).unwrap_err();
+    assert!(matches!(err, APIError::InvalidBackupPath));
+    let err = restore_backup(
+        &v1_backup,
+        PASSWORD,
+        &tmp.path().join("r3"),
+        Some("00000001"),
+    )
+    .unwrap_err();
+    assert!(matches!(err, APIError::BackupSnapshotNotFound(_)));
+}
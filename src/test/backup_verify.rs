@@ -0,0 +1,165 @@
+use crate::backup::{do_backup, do_incremental_backup};
+use crate::backup_verify::do_verify_backup;
+use crate::error::APIError;
+use crate::utils::{encrypt_and_save_mnemonic, get_mnemonic_path, LDK_DIR};
+use bitcoin::constants::ChainHash;
+use bitcoin::Network;
+use lightning::rgb_utils::BITCOIN_NETWORK_FNAME;
+use rgb_lib::{generate_keys, BitcoinNetwork};
+use std::fs::{create_dir_all, read_dir, write};
+use std::path::Path;
+
+const PASSWORD: &str = "backup-password";
+
+fn populate_node_dir(storage_dir: &Path) {
+    create_dir_all(storage_dir.join(LDK_DIR)).unwrap();
+    let keys = generate_keys(BitcoinNetwork::Regtest);
+    encrypt_and_save_mnemonic(
+        PASSWORD.to_string(),
+        keys.mnemonic,
+        &get_mnemonic_path(storage_dir),
+    )
+    .unwrap();
+    write(
+        storage_dir.join(BITCOIN_NETWORK_FNAME),
+        BitcoinNetwork::Regtest.to_string(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn verify_backup_reports_issues() {
+    let tmp = tempfile::tempdir().unwrap();
+    let storage_dir = tmp.path().join("node");
+    populate_node_dir(&storage_dir);
+    let data_dir = tmp.path().join("verifier");
+    let backup_file = tmp.path().join("backup.zip");
+    do_backup(&storage_dir, &backup_file, PASSWORD).unwrap();
+
+    let report = do_verify_backup(
+        &backup_file,
+        PASSWORD,
+        None,
+        BitcoinNetwork::Regtest,
+        &data_dir,
+    )
+    .unwrap();
+    assert_eq!(report.version, 1);
+    assert!(report.snapshot_id.is_none());
+    assert!(!report.node_pubkey.is_empty());
+    assert!(report.channels.is_empty());
+    assert!(report.best_block_height.is_none());
+    // the node has never been unlocked, so there's no RGB database
+    assert!(!report.valid);
+    assert_eq!(report.issues.len(), 1);
+    assert!(report.issues[0].starts_with("RGB database for wallet"));
+
+    let report = do_verify_backup(
+        &backup_file,
+        PASSWORD,
+        None,
+        BitcoinNetwork::Testnet,
+        &data_dir,
+    )
+    .unwrap();
+    assert!(report
+        .issues
+        .iter()
+        .any(|i| i.starts_with("backup is for network")));
+
+    let err = do_verify_backup(
+        &backup_file,
+        "wrong-password",
+        None,
+        BitcoinNetwork::Regtest,
+        &data_dir,
+    )
+    .unwrap_err();
+    assert!(matches!(err, APIError::WrongPassword));
+
+    // the node dir is left untouched and the backup is unpacked next to the data dir, so backups
+    // of the data dir can't pick it up, then removed
+    assert!(!storage_dir.join(LDK_DIR).join("logs").exists());
+    assert_eq!(read_dir(&data_dir).unwrap().count(), 0);
+    assert!(!read_dir(tmp.path()).unwrap().any(|e| e
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with(".verifier")));
+}
+
+#[test]
+fn verify_backup_loads_channel_manager() {
+    let tmp = tempfile::tempdir().unwrap();
+    let storage_dir = tmp.path().join("node");
+    populate_node_dir(&storage_dir);
+    let data_dir = tmp.path().join("verifier");
+
+    // a channel manager with a valid header that doesn't deserialize
+    let mut manager = vec![1, 1];
+    manager.extend_from_slice(ChainHash::using_genesis_block(Network::Regtest).as_bytes());
+    manager.extend_from_slice(&100u32.to_be_bytes());
+    manager.extend_from_slice(&[0; 32]);
+    manager.extend_from_slice(&0u64.to_be_bytes());
+    write(storage_dir.join(LDK_DIR).join("manager"), manager).unwrap();
+    let backup_file = tmp.path().join("backup.zip");
+    do_backup(&storage_dir, &backup_file, PASSWORD).unwrap();
+
+    let report = do_verify_backup(
+        &backup_file,
+        PASSWORD,
+        None,
+        BitcoinNetwork::Regtest,
+        &data_dir,
+    )
+    .unwrap();
+    assert_eq!(report.best_block_height, Some(100));
+    assert!(report
+        .issues
+        .iter()
+        .any(|i| i.starts_with("channel manager doesn't load against the channel monitors")));
+}
+
+#[test]
+fn verify_incremental_backup_snapshot() {
+    let tmp = tempfile::tempdir().unwrap();
+    let storage_dir = tmp.path().join("node");
+    populate_node_dir(&storage_dir);
+    let data_dir = tmp.path().join("verifier");
+    let backup_dir = tmp.path().join("backup");
+    do_incremental_backup(&storage_dir, &backup_dir, PASSWORD).unwrap();
+    do_incremental_backup(&storage_dir, &backup_dir, PASSWORD).unwrap();
+
+    let first = do_verify_backup(
+        &backup_dir,
+        PASSWORD,
+        Some("00000001"),
+        BitcoinNetwork::Regtest,
+        &data_dir,
+    )
+    .unwrap();
+    assert_eq!(first.version, 2);
+    assert_eq!(first.snapshot_id.as_deref(), Some("00000001"));
+
+    let latest = do_verify_backup(
+        &backup_dir,
+        PASSWORD,
+        None,
+        BitcoinNetwork::Regtest,
+        &data_dir,
+    )
+    .unwrap();
+    assert_eq!(latest.snapshot_id.as_deref(), Some("00000002"));
+    // the node keys only depend on the mnemonic
+    assert_eq!(latest.node_pubkey, first.node_pubkey);
+
+    let err = do_verify_backup(
+        &backup_dir,
+        PASSWORD,
+        Some("00000009"),
+        BitcoinNetwork::Regtest,
+        &data_dir,
+    )
+    .unwrap_err();
+    assert!(matches!(err, APIError::BackupSnapshotNotFound(_)));
+}
@@ -0,0 +1,48 @@
+use crate::hsm_provider::{decode_ec_point, HardwareHsmProvider, HsmError, HsmProvider};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+fn test_public_key() -> PublicKey {
+    let secp = Secp256k1::new();
+    PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[7u8; 32]).unwrap())
+}
+
+#[test]
+fn test_decode_ec_point() {
+    let public_key = test_public_key();
+    let uncompressed = public_key.serialize_uncompressed();
+
+    // raw point
+    assert_eq!(decode_ec_point(&uncompressed).unwrap(), public_key);
+
+    // DER OCTET STRING wrapped point, as returned by SoftHSM
+    let mut wrapped = vec![0x04, uncompressed.len() as u8];
+    wrapped.extend_from_slice(&uncompressed);
+    assert_eq!(decode_ec_point(&wrapped).unwrap(), public_key);
+
+    assert!(decode_ec_point(&[0x04, 0x01, 0x02]).is_err());
+}
+
+/// Runs against SoftHSM when SOFTHSM2_MODULE points to libsofthsm2.so and a token labelled
+/// SOFTHSM2_TOKEN_LABEL has been initialized with user PIN SOFTHSM2_PIN
+#[tokio::test]
+async fn test_softhsm_virtual_node_keys() {
+    let Ok(module) = std::env::var("SOFTHSM2_MODULE") else {
+        println!("SOFTHSM2_MODULE not set, skipping");
+        return;
+    };
+    let token_label = std::env::var("SOFTHSM2_TOKEN_LABEL").unwrap_or_else(|_| "rln-test".to_string());
+    let pin = std::env::var("SOFTHSM2_PIN").unwrap_or_else(|_| "1234".to_string());
+
+    let hsm = HardwareHsmProvider::new(module, &token_label, &pin).unwrap();
+
+    let user1_node_id = hsm.derive_virtual_node_id("user1").await.unwrap();
+    let user2_node_id = hsm.derive_virtual_node_id("user2").await.unwrap();
+    assert_eq!(user1_node_id, hsm.derive_virtual_node_id("user1").await.unwrap());
+    assert_ne!(user1_node_id, user2_node_id);
+
+    // the token can't produce the hashed compressed shared point
+    assert!(matches!(
+        hsm.ecdh("user1", &user2_node_id).await,
+        Err(HsmError::Unsupported(_))
+    ));
+}
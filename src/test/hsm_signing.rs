@@ -0,0 +1,74 @@
+use crate::hsm_provider::{zbase32_encode, HsmProvider, LocalHsmProvider};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use lightning::ln::PaymentSecret;
+use lightning::sign::KeysManager;
+use lightning_invoice::{Currency, InvoiceBuilder};
+use std::sync::Arc;
+
+fn local_provider() -> LocalHsmProvider {
+    LocalHsmProvider::new(Arc::new(KeysManager::new(
+        &[1u8; 32],
+        42,
+        42,
+        std::path::PathBuf::from("/tmp"),
+    )))
+}
+
+#[test]
+fn test_zbase32_encode() {
+    assert_eq!(zbase32_encode(b""), "");
+    assert_eq!(zbase32_encode(&[0x00]), "yy");
+    assert_eq!(zbase32_encode(&[0xff, 0xff]), "999o");
+}
+
+#[tokio::test]
+async fn test_sign_message_with_virtual_node_key() {
+    let hsm = local_provider();
+    let node_id = hsm.derive_virtual_node_id("user1").await.unwrap();
+    let other_node_id = hsm.derive_virtual_node_id("user2").await.unwrap();
+
+    let signature = hsm.sign_message("user1", b"hello").await.unwrap();
+    assert!(lightning::util::message_signing::verify(b"hello", &signature, &node_id));
+    assert!(!lightning::util::message_signing::verify(b"hello", &signature, &other_node_id));
+    assert!(!lightning::util::message_signing::verify(b"goodbye", &signature, &node_id));
+}
+
+#[tokio::test]
+async fn test_ecdh_with_virtual_node_key() {
+    let hsm = local_provider();
+    let node_id = hsm.derive_virtual_node_id("user1").await.unwrap();
+
+    let secp = Secp256k1::new();
+    let peer_secret = SecretKey::from_slice(&[9u8; 32]).unwrap();
+    let peer_public = PublicKey::from_secret_key(&secp, &peer_secret);
+
+    let shared = hsm.ecdh("user1", &peer_public).await.unwrap();
+    assert_eq!(shared, SharedSecret::new(&node_id, &peer_secret).secret_bytes());
+}
+
+#[tokio::test]
+async fn test_sign_invoice_with_virtual_node_key() {
+    let hsm = local_provider();
+    let node_id = hsm.derive_virtual_node_id("user1").await.unwrap();
+
+    let raw_invoice = InvoiceBuilder::new(Currency::Regtest)
+        .description("test".to_string())
+        .payment_hash(sha256::Hash::hash(&[1u8; 32]))
+        .payment_secret(PaymentSecret([2u8; 32]))
+        .current_timestamp()
+        .min_final_cltv_expiry_delta(18)
+        .amount_milli_satoshis(1000)
+        .build_raw()
+        .unwrap();
+    let invoice = hsm.sign_invoice("user1", raw_invoice).await.unwrap();
+
+    assert_eq!(invoice.recover_payee_pub_key(), node_id);
+
+    // a raw hash signature recovers to the same key
+    let hash = Message::from_digest([3u8; 32]);
+    let signature = hsm.sign_hash("user1", &hash).await.unwrap();
+    let secp = Secp256k1::new();
+    assert_eq!(secp.recover_ecdsa(&hash, &signature).unwrap(), node_id);
+}
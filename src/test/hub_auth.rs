@@ -0,0 +1,104 @@
+use crate::error::APIError;
+use crate::hub_auth::{
+    hub_auth_middleware, HubAuth, HubCaller, HubRole, HUB_ADMIN_USER_ID, HUB_SESSION_TTL_SECS,
+};
+use axum::{middleware, routing::get, Extension, Router};
+use reqwest::StatusCode;
+use std::sync::Arc;
+
+const ADMIN_TOKEN: &str = "0123456789abcdef0123456789abcdef";
+const NOW: u64 = 1_700_000_000;
+
+fn hub_auth() -> HubAuth {
+    HubAuth::new(Some(ADMIN_TOKEN), [7; 32]).unwrap()
+}
+
+#[test]
+fn user_sessions() {
+    let auth = hub_auth();
+    let token = auth.issue_session("user.1", NOW);
+    let caller = auth.authenticate(&token, NOW).unwrap();
+    assert_eq!(
+        caller,
+        HubCaller {
+            user_id: "user.1".to_string(),
+            role: HubRole::User,
+        }
+    );
+    assert_eq!(caller.require_user().unwrap(), "user.1");
+    assert!(matches!(
+        caller.require_admin(),
+        Err(APIError::Forbidden(_))
+    ));
+
+    let err = auth
+        .authenticate(&token, NOW + HUB_SESSION_TTL_SECS)
+        .unwrap_err();
+    assert!(matches!(err, APIError::Unauthorized(e) if e == "session expired"));
+
+    // sessions can't be moved to another user or extended
+    let (_, rest) = token.split_once('.').unwrap();
+    let other_user = format!(
+        "{}.{rest}",
+        auth.issue_session("user2", NOW).split_once('.').unwrap().0
+    );
+    assert!(auth.authenticate(&other_user, NOW).is_err());
+    let mut parts: Vec<&str> = token.split('.').collect();
+    let extended = (NOW + 2 * HUB_SESSION_TTL_SECS).to_string();
+    parts[1] = &extended;
+    assert!(auth.authenticate(&parts.join("."), NOW).is_err());
+
+    // sessions of another hub are rejected
+    let other_hub = HubAuth::new(None, [8; 32]).unwrap();
+    assert!(auth
+        .authenticate(&other_hub.issue_session("user.1", NOW), NOW)
+        .is_err());
+    assert!(auth.authenticate("garbage", NOW).is_err());
+}
+
+#[test]
+fn admin_token() {
+    let caller = hub_auth().authenticate(ADMIN_TOKEN, NOW).unwrap();
+    assert_eq!(caller.user_id, HUB_ADMIN_USER_ID);
+    assert!(caller.require_admin().is_ok());
+    assert!(matches!(caller.require_user(), Err(APIError::Forbidden(_))));
+
+    // without a configured token nobody is admin
+    let auth = HubAuth::new(None, [7; 32]).unwrap();
+    assert!(auth.authenticate(ADMIN_TOKEN, NOW).is_err());
+    assert!(HubAuth::new(Some("short"), [7; 32]).is_err());
+}
+
+#[tokio::test]
+async fn hub_auth_middleware_sets_caller() {
+    let auth = Arc::new(hub_auth());
+    let router = Router::new()
+        .route(
+            "/whoami",
+            get(|Extension(caller): Extension<HubCaller>| async move { caller.user_id }),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            auth.clone(),
+            hub_auth_middleware,
+        ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/whoami", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    let client = reqwest::Client::new();
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let session = auth.issue_session("user1", crate::utils::get_current_timestamp());
+    let res = client.get(&url).bearer_auth(session).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await.unwrap(), "user1");
+
+    let res = client
+        .get(&url)
+        .bearer_auth("not a session")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
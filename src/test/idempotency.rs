@@ -0,0 +1,71 @@
+use axum::http::{HeaderMap, HeaderValue};
+
+use crate::error::APIError;
+use crate::idempotency::{
+    extract_idempotency_key, idempotent_payment_id, request_hash, IDEMPOTENCY_KEY_HEADER,
+};
+use crate::routes::VirtualTransferRequest;
+
+fn transfer_request(amount_sats: u64, idempotency_key: Option<&str>) -> VirtualTransferRequest {
+    VirtualTransferRequest {
+        from_user_id: 1,
+        to_user_id: 2,
+        amount_sats,
+        idempotency_key: idempotency_key.map(|k| k.to_string()),
+    }
+}
+
+#[test]
+fn test_idempotency_key_from_header_or_body() {
+    let mut headers = HeaderMap::new();
+    assert!(extract_idempotency_key(&headers, None).unwrap().is_none());
+    assert_eq!(
+        extract_idempotency_key(&headers, Some("body-key")).unwrap(),
+        Some("body-key".to_string())
+    );
+
+    headers.insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_static("header-key"));
+    assert_eq!(
+        extract_idempotency_key(&headers, None).unwrap(),
+        Some("header-key".to_string())
+    );
+    assert_eq!(
+        extract_idempotency_key(&headers, Some("header-key")).unwrap(),
+        Some("header-key".to_string())
+    );
+    assert!(matches!(
+        extract_idempotency_key(&headers, Some("body-key")),
+        Err(APIError::InvalidIdempotencyKey(_))
+    ));
+}
+
+#[test]
+fn test_invalid_idempotency_key() {
+    let headers = HeaderMap::new();
+    assert!(matches!(
+        extract_idempotency_key(&headers, Some("  ")),
+        Err(APIError::InvalidIdempotencyKey(_))
+    ));
+    let long_key = "k".repeat(256);
+    assert!(matches!(
+        extract_idempotency_key(&headers, Some(&long_key)),
+        Err(APIError::InvalidIdempotencyKey(_))
+    ));
+}
+
+#[test]
+fn test_request_hash_ignores_idempotency_key() {
+    let hash = request_hash(&transfer_request(1000, None)).unwrap();
+    assert_eq!(hash, request_hash(&transfer_request(1000, Some("key"))).unwrap());
+    assert_ne!(hash, request_hash(&transfer_request(1001, Some("key"))).unwrap());
+}
+
+#[test]
+fn test_idempotent_payment_id() {
+    let payment_id = idempotent_payment_id("virtual_sendpayment", "key");
+    assert_eq!(payment_id, idempotent_payment_id("virtual_sendpayment", "key"));
+    assert_ne!(payment_id, idempotent_payment_id("virtual_sendpayment", "key2"));
+    assert_ne!(payment_id, idempotent_payment_id("virtual_transfer", "key"));
+    // the parts are length prefixed, so they can't be shifted into each other
+    assert_ne!(idempotent_payment_id("ab", "c"), idempotent_payment_id("a", "bc"));
+}
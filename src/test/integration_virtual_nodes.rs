@@ -13,6 +13,7 @@ async fn setup_test_node() -> Arc<AppState> {
         ldk_peer_listening_port: 9735,
         network: rgb_lib::BitcoinNetwork::Regtest,
         max_media_upload_size_mb: 10,
+        verify_backup: None,
     };
     
     start_daemon(&args).await.unwrap()
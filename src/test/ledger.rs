@@ -0,0 +1,202 @@
+use crate::database::{
+    msat_transfer_postings, validate_journal, LedgerError, LedgerPosting, LEDGER_EXTERNAL_ACCOUNT,
+    LEDGER_MSAT_ASSET,
+};
+use crate::routes::{Assignment, BitcoinNetwork, DecodeRGBInvoiceResponse, ListAssetsResponse};
+use crate::virtual_balance::{BalanceError, UserAssetBalance, UserAssetSend};
+use std::collections::HashMap;
+
+const ASSET_ID: &str = "rgb1qyfe883hey6jrgj2xvk5g3dfmfqfzm7a4wez4pd2krf7ltsxffd6u6nrvjvvnc8vt02v7";
+
+#[test]
+fn test_transfer_journal_is_balanced() {
+    let postings = LedgerPosting::transfer("user1", "user2", None, 1000);
+    assert_eq!(postings[0].amount, -1000);
+    assert_eq!(postings[1].amount, 1000);
+    assert!(validate_journal(&postings).is_ok());
+
+    let mut postings = LedgerPosting::transfer(LEDGER_EXTERNAL_ACCOUNT, "user1", None, 500);
+    postings.extend(LedgerPosting::transfer("user1", "user2", Some(ASSET_ID), 10));
+    assert!(validate_journal(&postings).is_ok());
+}
+
+#[test]
+fn test_unbalanced_journal_is_rejected() {
+    let postings = vec![
+        LedgerPosting::new("user1", None, -1000),
+        LedgerPosting::new("user2", None, 999),
+    ];
+    assert!(matches!(validate_journal(&postings), Err(LedgerError::Unbalanced(a)) if a == "BTC"));
+
+    // Amounts must net to zero per asset, not across assets
+    let postings = vec![
+        LedgerPosting::new("user1", None, -10),
+        LedgerPosting::new("user2", Some(ASSET_ID), 10),
+    ];
+    assert!(matches!(validate_journal(&postings), Err(LedgerError::Unbalanced(_))));
+}
+
+#[test]
+fn test_empty_or_zero_journal_is_rejected() {
+    assert!(matches!(validate_journal(&[]), Err(LedgerError::InvalidPosting)));
+    assert!(matches!(
+        validate_journal(&LedgerPosting::transfer("user1", "user2", None, 0)),
+        Err(LedgerError::InvalidPosting)
+    ));
+}
+
+/// Net change of an account in a journal
+fn net(postings: &[LedgerPosting], user_id: &str, asset_id: Option<&str>) -> i64 {
+    postings
+        .iter()
+        .filter(|p| p.user_id == user_id && p.asset_id.as_deref() == asset_id)
+        .map(|p| p.amount)
+        .sum()
+}
+
+#[test]
+fn test_msat_transfer_carries_remainder() {
+    let msat = Some(LEDGER_MSAT_ASSET);
+
+    // whole sats only move BTC
+    let postings = msat_transfer_postings("user1", "user2", 5000, 0, 0);
+    assert!(validate_journal(&postings).is_ok());
+    assert_eq!(postings.len(), 2);
+    assert_eq!(net(&postings, "user2", None), 5);
+
+    // the sub-sat part is kept instead of being truncated
+    let postings = msat_transfer_postings(LEDGER_EXTERNAL_ACCOUNT, "user1", 1500, 0, 0);
+    assert!(validate_journal(&postings).is_ok());
+    assert_eq!((net(&postings, "user1", None), net(&postings, "user1", msat)), (1, 500));
+    let postings = msat_transfer_postings(LEDGER_EXTERNAL_ACCOUNT, "user1", 999, 0, 0);
+    assert_eq!((net(&postings, "user1", None), net(&postings, "user1", msat)), (0, 999));
+
+    // remainders adding up to a sat are credited as one
+    let postings = msat_transfer_postings(LEDGER_EXTERNAL_ACCOUNT, "user1", 700, 0, 500);
+    assert!(validate_journal(&postings).is_ok());
+    assert_eq!((net(&postings, "user1", None), net(&postings, "user1", msat)), (1, -300));
+
+    // a payer without enough remainder borrows a sat
+    let postings = msat_transfer_postings("user1", "user2", 2300, 100, 800);
+    assert!(validate_journal(&postings).is_ok());
+    assert_eq!((net(&postings, "user1", None), net(&postings, "user1", msat)), (-3, 700));
+    assert_eq!((net(&postings, "user2", None), net(&postings, "user2", msat)), (3, -700));
+    assert_eq!(net(&postings, LEDGER_EXTERNAL_ACCOUNT, None), 0);
+    assert_eq!(net(&postings, LEDGER_EXTERNAL_ACCOUNT, msat), 0);
+
+    assert!(msat_transfer_postings("user1", "user2", 0, 0, 0).is_empty());
+}
+
+#[test]
+fn test_virtual_transfer_mode_parsing() {
+    use crate::virtual_balance::VirtualTransferMode;
+
+    assert_eq!(VirtualTransferMode::default(), VirtualTransferMode::Ledger);
+    assert_eq!("ledger".parse::<VirtualTransferMode>().unwrap(), VirtualTransferMode::Ledger);
+    assert_eq!("OnChain".parse::<VirtualTransferMode>().unwrap(), VirtualTransferMode::OnChain);
+    assert_eq!("on_chain".parse::<VirtualTransferMode>().unwrap(), VirtualTransferMode::OnChain);
+    assert!("lightning".parse::<VirtualTransferMode>().is_err());
+}
+
+fn decoded_invoice(asset_id: Option<&str>, assignment: Assignment) -> DecodeRGBInvoiceResponse {
+    DecodeRGBInvoiceResponse {
+        recipient_id: "utxob:recipient".to_string(),
+        asset_schema: None,
+        asset_id: asset_id.map(|a| a.to_string()),
+        assignment,
+        network: BitcoinNetwork::Regtest,
+        expiration_timestamp: None,
+        transport_endpoints: vec!["rpc://127.0.0.1:3000/json-rpc".to_string()],
+    }
+}
+
+#[test]
+fn test_user_asset_send_from_invoice() {
+    let send = UserAssetSend::new(decoded_invoice(Some(ASSET_ID), Assignment::Fungible(10)), None, None).unwrap();
+    assert_eq!(send.asset_id, ASSET_ID);
+    assert_eq!(send.assignment, Assignment::Fungible(10));
+    assert_eq!(send.recipient_id, "utxob:recipient");
+    assert_eq!(send.units(), 10);
+
+    // open invoices take the asset and amount from the request
+    let send = UserAssetSend::new(
+        decoded_invoice(None, Assignment::Any),
+        Some(ASSET_ID.to_string()),
+        Some(25),
+    )
+    .unwrap();
+    assert_eq!((send.asset_id.as_str(), send.units()), (ASSET_ID, 25));
+    let send = UserAssetSend::new(decoded_invoice(Some(ASSET_ID), Assignment::NonFungible), None, None).unwrap();
+    assert_eq!(send.units(), 1);
+
+    for (invoice, asset_id, amount) in [
+        (decoded_invoice(Some(ASSET_ID), Assignment::Fungible(10)), Some("rgb:other"), None),
+        (decoded_invoice(Some(ASSET_ID), Assignment::Fungible(10)), None, Some(11)),
+        (decoded_invoice(None, Assignment::Fungible(10)), None, None),
+        (decoded_invoice(Some(ASSET_ID), Assignment::Any), None, None),
+        (decoded_invoice(Some(ASSET_ID), Assignment::Any), None, Some(0)),
+        (decoded_invoice(Some(ASSET_ID), Assignment::ReplaceRight), None, Some(1)),
+    ] {
+        assert!(matches!(
+            UserAssetSend::new(invoice, asset_id.map(|a| a.to_string()), amount),
+            Err(BalanceError::InvalidInvoice(_))
+        ));
+    }
+}
+
+#[test]
+fn test_user_asset_balances() {
+    let balance = serde_json::json!({
+        "settled": 1000, "future": 1000, "spendable": 1000,
+        "offchain_outbound": 0, "offchain_inbound": 0
+    });
+    let node_assets: ListAssetsResponse = serde_json::from_value(serde_json::json!({
+        "nia": [{
+            "asset_id": ASSET_ID, "ticker": "USDT", "name": "Tether", "details": null,
+            "precision": 6, "issued_supply": 1000, "timestamp": 0, "added_at": 0,
+            "balance": balance, "media": null
+        }],
+        "uda": null,
+        "cfa": [{
+            "asset_id": "rgb:cfa", "name": "Collectible", "details": null,
+            "precision": 0, "issued_supply": 1000, "timestamp": 0, "added_at": 0,
+            "balance": balance, "media": null
+        }]
+    }))
+    .unwrap();
+    let balances = HashMap::from([
+        ("BTC".to_string(), 5000),
+        (ASSET_ID.to_string(), 40),
+        ("rgb:cfa".to_string(), 3),
+        ("rgb:spent".to_string(), 0),
+        ("rgb:unknown".to_string(), 7),
+    ]);
+
+    let assets = UserAssetBalance::from_balances(&balances, &node_assets);
+    assert_eq!(
+        assets,
+        vec![
+            UserAssetBalance {
+                asset_id: ASSET_ID.to_string(),
+                ticker: Some("USDT".to_string()),
+                name: Some("Tether".to_string()),
+                precision: Some(6),
+                balance: 40,
+            },
+            UserAssetBalance {
+                asset_id: "rgb:cfa".to_string(),
+                ticker: None,
+                name: Some("Collectible".to_string()),
+                precision: Some(0),
+                balance: 3,
+            },
+            UserAssetBalance {
+                asset_id: "rgb:unknown".to_string(),
+                ticker: None,
+                name: None,
+                precision: None,
+                balance: 7,
+            },
+        ]
+    );
+}
@@ -0,0 +1,139 @@
+use crate::metrics::{
+    track_http_metrics, ChannelMetrics, Metrics, NodeMetrics, PaymentDirection, PaymentOutcome,
+    RgbChannelMetrics, SwapRole,
+};
+use crate::routes::SwapStatus;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
+
+const CONTRACT_ID: &str = "rgb:2dkSTbr-jFhznbPmo-TQafzswCN-av4gTsJjX-ttx6CNou5-M98k8Zd";
+
+fn rgb_channel(local_amount: u64, remote_amount: u64) -> ChannelMetrics {
+    ChannelMetrics {
+        status: "opened",
+        usable: true,
+        capacity_sat: 100_000,
+        outbound_balance_msat: 30_000_000,
+        inbound_balance_msat: 60_000_000,
+        pending_inbound_htlcs: 1,
+        pending_outbound_htlcs: 2,
+        rgb: Some(RgbChannelMetrics {
+            contract_id: CONTRACT_ID.to_string(),
+            local_amount,
+            remote_amount,
+        }),
+    }
+}
+
+#[test]
+fn render_metrics() {
+    let metrics = Metrics::default();
+    let output = metrics.render(None);
+    assert!(output.contains("# TYPE rln_unlocked gauge\nrln_unlocked 0\n"));
+    assert!(!output.contains("rln_channels"));
+    assert!(output.contains("rln_payments_total{direction=\"outbound\",outcome=\"failed\"} 0\n"));
+
+    metrics.record_payment(PaymentDirection::Outbound, PaymentOutcome::Succeeded);
+    metrics.record_payment(PaymentDirection::Outbound, PaymentOutcome::Succeeded);
+    metrics.record_payment(PaymentDirection::Inbound, PaymentOutcome::Failed);
+
+    let mut node = NodeMetrics {
+        channels: vec![
+            rgb_channel(400, 600),
+            rgb_channel(100, 0),
+            ChannelMetrics {
+                status: "opening",
+                capacity_sat: 50_000,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    node.add_swap(SwapRole::Maker, &SwapStatus::Succeeded);
+    node.add_swap(SwapRole::Maker, &SwapStatus::Succeeded);
+    node.add_swap(SwapRole::Taker, &SwapStatus::Expired);
+
+    let output = metrics.render(Some(&node));
+    for line in [
+        "rln_unlocked 1",
+        "rln_channels{status=\"opened\"} 2",
+        "rln_channels{status=\"opening\"} 1",
+        "rln_channels{status=\"closing\"} 0",
+        "rln_channels_usable 2",
+        "rln_channel_capacity_sat 250000",
+        "rln_channel_outbound_balance_msat 60000000",
+        "rln_channel_inbound_balance_msat 120000000",
+        "rln_pending_htlcs{direction=\"inbound\"} 2",
+        "rln_pending_htlcs{direction=\"outbound\"} 4",
+        &format!("rln_rgb_channels{{contract_id=\"{CONTRACT_ID}\"}} 2"),
+        &format!("rln_rgb_channel_local_amount{{contract_id=\"{CONTRACT_ID}\"}} 500"),
+        &format!("rln_rgb_channel_remote_amount{{contract_id=\"{CONTRACT_ID}\"}} 600"),
+        "rln_swaps{role=\"maker\",status=\"succeeded\"} 2",
+        "rln_swaps{role=\"maker\",status=\"expired\"} 0",
+        "rln_swaps{role=\"taker\",status=\"expired\"} 1",
+        "rln_payments_total{direction=\"outbound\",outcome=\"succeeded\"} 2",
+        "rln_payments_total{direction=\"inbound\",outcome=\"failed\"} 1",
+        "rln_payments_total{direction=\"inbound\",outcome=\"succeeded\"} 0",
+    ] {
+        assert!(
+            output.lines().any(|l| l == line),
+            "missing {line} in:\n{output}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn track_http_request_latency() {
+    let metrics = Arc::new(Metrics::default());
+    let app = Router::new()
+        .route(
+            "/channel/:id",
+            get(|Path(id): Path<String>| async move { id }),
+        )
+        .route("/fail", post(|| async { StatusCode::BAD_REQUEST }))
+        .layer(middleware::from_fn_with_state(
+            metrics.clone(),
+            track_http_metrics,
+        ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let client = reqwest::Client::new();
+    for id in ["a", "b"] {
+        let res = client
+            .get(format!("http://{addr}/channel/{id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), id);
+    }
+    let res = client
+        .post(format!("http://{addr}/fail"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let output = metrics.render(None);
+    // requests are labeled with the matched route, not the requested path
+    for line in [
+        "rln_http_requests_total{method=\"GET\",route=\"/channel/:id\",status=\"200\"} 2",
+        "rln_http_requests_total{method=\"POST\",route=\"/fail\",status=\"400\"} 1",
+        "rln_http_request_duration_seconds_bucket{method=\"GET\",route=\"/channel/:id\",le=\"+Inf\"} 2",
+        "rln_http_request_duration_seconds_count{method=\"GET\",route=\"/channel/:id\"} 2",
+        "rln_http_request_duration_seconds_count{method=\"POST\",route=\"/fail\"} 1",
+    ] {
+        assert!(
+            output.lines().any(|l| l == line),
+            "missing {line} in:\n{output}"
+        );
+    }
+    assert!(!output.contains("/channel/a"));
+}
@@ -0,0 +1,148 @@
+use crate::pricing::{
+    maker_price_policy_from_json, HttpPriceSource, MakerPricePolicy, PairSpread, PolicyMode,
+    PriceSource, PricingError, StaticPriceSource,
+};
+use crate::swap::SwapInfo;
+use axum::{extract::Query, routing::get, Json, Router};
+use rgb_lib::ContractId;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const CONTRACT_ID: &str = "rgb:CJkb4YZw-jRiz2sk-~PARPio-wtVYI1c-XAEYCqO-wTfvRZ8";
+
+fn asset() -> Option<ContractId> {
+    Some(ContractId::from_str(CONTRACT_ID).unwrap())
+}
+
+fn swap_info(qty_from: u64, qty_to: u64) -> SwapInfo {
+    SwapInfo {
+        qty_from,
+        qty_to,
+        from_asset: None,
+        to_asset: asset(),
+        expiry: 100,
+    }
+}
+
+fn policy(mode: PolicyMode) -> MakerPricePolicy {
+    // 1 asset unit per 1000 msat, the maker keeps between 1% and 5%
+    let source = StaticPriceSource::new(HashMap::from([((None, asset()), 0.001)]));
+    let spreads = HashMap::from([(
+        (None, asset()),
+        PairSpread {
+            min_spread_bps: 100,
+            max_spread_bps: 500,
+        },
+    )]);
+    MakerPricePolicy::new(Arc::new(source), mode, spreads)
+}
+
+#[tokio::test]
+async fn test_static_price_source_inverse() {
+    let source = StaticPriceSource::new(HashMap::from([((None, asset()), 0.001)]));
+    assert_eq!(source.mid_price(None, asset()).await.unwrap(), Some(0.001));
+    assert_eq!(source.mid_price(asset(), None).await.unwrap(), Some(1000.0));
+    let other = Some(
+        ContractId::from_str("rgb:icfqnK9y-wObZKTu-XJcDL98-sKbE5Mh-OuDJhiI-brRJrzE").unwrap(),
+    );
+    assert_eq!(source.mid_price(None, other).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_policy_reject() {
+    let policy = policy(PolicyMode::Reject);
+    // 2% spread is accepted unchanged
+    let accepted = policy.apply(swap_info(1_000_000, 980)).await.unwrap();
+    assert_eq!(accepted.qty_to, 980);
+    // giving away more than the mid price minus the minimum spread
+    assert!(matches!(
+        policy.apply(swap_info(1_000_000, 1000)).await,
+        Err(PricingError::OutOfRange { .. })
+    ));
+    // too far below the mid price
+    assert!(matches!(
+        policy.apply(swap_info(1_000_000, 900)).await,
+        Err(PricingError::OutOfRange { .. })
+    ));
+    // pairs without a spread are not checked
+    let unchecked = SwapInfo {
+        from_asset: asset(),
+        to_asset: None,
+        ..swap_info(1, 1_000_000_000)
+    };
+    assert!(policy.apply(unchecked).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_policy_auto_price() {
+    let policy = policy(PolicyMode::AutoPrice);
+    assert_eq!(policy.apply(swap_info(1_000_000, 1000)).await.unwrap().qty_to, 990);
+    assert_eq!(policy.apply(swap_info(1_000_000, 1)).await.unwrap().qty_to, 950);
+    assert_eq!(policy.apply(swap_info(1_000_000, 970)).await.unwrap().qty_to, 970);
+    // too small to price at all
+    assert!(policy.apply(swap_info(10, 1)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_policy_check_never_reprices() {
+    let policy = policy(PolicyMode::AutoPrice);
+    assert!(policy.check(&swap_info(1_000_000, 970)).await.is_ok());
+    assert!(matches!(
+        policy.check(&swap_info(1_000_000, 1000)).await,
+        Err(PricingError::OutOfRange { .. })
+    ));
+    assert!(matches!(
+        policy.check(&swap_info(1_000_000, 900)).await,
+        Err(PricingError::OutOfRange { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_http_price_source() {
+    async fn price(Query(params): Query<HashMap<String, String>>) -> Json<serde_json::Value> {
+        let price = match (params["from"].as_str(), params["to"].as_str()) {
+            ("btc", CONTRACT_ID) => Some(0.001),
+            _ => None,
+        };
+        Json(serde_json::json!({ "price": price }))
+    }
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, Router::new().route("/price", get(price)))
+            .await
+            .unwrap()
+    });
+
+    let source = HttpPriceSource::new(format!("http://{addr}/price")).unwrap();
+    assert_eq!(source.mid_price(None, asset()).await.unwrap(), Some(0.001));
+    assert_eq!(source.mid_price(asset(), None).await.unwrap(), None);
+
+    let missing = HttpPriceSource::new(format!("http://{addr}/missing")).unwrap();
+    assert_eq!(missing.mid_price(None, asset()).await.unwrap(), None);
+}
+
+#[test]
+fn test_pricing_config() {
+    let config = format!(
+        r#"{{
+            "source": {{"type": "static", "prices": [
+                {{"from_asset": "btc", "to_asset": "{CONTRACT_ID}", "price": 0.001}}
+            ]}},
+            "mode": "auto_price",
+            "pairs": [
+                {{"from_asset": "btc", "to_asset": "{CONTRACT_ID}", "min_spread_bps": 100, "max_spread_bps": 500}}
+            ]
+        }}"#
+    );
+    assert!(maker_price_policy_from_json(&config).is_ok());
+
+    let inverted_spread = config.replace("\"min_spread_bps\": 100", "\"min_spread_bps\": 600");
+    assert!(matches!(
+        maker_price_policy_from_json(&inverted_spread),
+        Err(PricingError::InvalidConfig(_))
+    ));
+    let bad_asset = config.replace("\"from_asset\": \"btc\"", "\"from_asset\": \"eth\"");
+    assert!(maker_price_policy_from_json(&bad_asset).is_err());
+}
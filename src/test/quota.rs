@@ -0,0 +1,165 @@
+use crate::quota::{
+    check_quota, QuotaAction, QuotaSpend, QuotaUsage, QuotaUsageRecord, UserQuotas,
+};
+use crate::routes::UpdateUserQuotasRequest;
+use chrono::{Duration, Utc};
+
+const ASSET_ID: &str = "rgb:CJkb4YZw-jRiz2sk-~PARPio-wtVYI1c-XAEYCqO-wTfvRZ8";
+
+fn record(action: QuotaAction, sats: u64, asset_amount: u64, hours_ago: i64) -> QuotaUsageRecord {
+    QuotaUsageRecord {
+        action,
+        sats,
+        asset_id: (asset_amount > 0).then(|| ASSET_ID.to_string()),
+        asset_amount,
+        created_at: Utc::now() - Duration::hours(hours_ago),
+    }
+}
+
+fn send(sats: u64, asset_units: Option<u64>) -> QuotaSpend {
+    QuotaSpend {
+        action: QuotaAction::Transaction,
+        sats,
+        asset: asset_units.map(|units| (ASSET_ID.to_string(), units)),
+    }
+}
+
+#[test]
+fn quota_usage_windows() {
+    let records = [
+        record(QuotaAction::Transaction, 1_000, 0, 1),
+        record(QuotaAction::Transaction, 0, 5, 2),
+        record(QuotaAction::CreateChannel, 20_000, 0, 3),
+        record(QuotaAction::Transaction, 4_000, 10, 48),
+        // out of both windows
+        record(QuotaAction::Transaction, 100_000, 100, 24 * 31),
+    ];
+    let usage = QuotaUsage::new(2, 1, &records, Utc::now());
+
+    assert_eq!(usage.channels, 2);
+    assert_eq!(usage.assets, 1);
+    assert_eq!(usage.day.transactions, 2);
+    assert_eq!(usage.day.sats, 21_000);
+    assert_eq!(usage.day.asset_units[ASSET_ID], 5);
+    assert_eq!(usage.month.transactions, 3);
+    assert_eq!(usage.month.sats, 25_000);
+    assert_eq!(usage.month.asset_units[ASSET_ID], 15);
+}
+
+#[test]
+fn quota_limits() {
+    let quotas = UserQuotas {
+        max_channels: 2,
+        max_assets: 1,
+        max_transactions_per_day: 3,
+        max_transactions_per_month: 10,
+        max_sats_per_day: 10_000,
+        max_sats_per_month: 15_000,
+        max_asset_units_per_day: 100,
+        max_asset_units_per_month: 150,
+        ..Default::default()
+    };
+    let records = [
+        record(QuotaAction::Transaction, 6_000, 60, 1),
+        record(QuotaAction::Transaction, 5_000, 80, 30),
+    ];
+    let usage = QuotaUsage::new(1, 1, &records, Utc::now());
+
+    // daily sats: 6_000 used of 10_000
+    check_quota(&quotas, &usage, &send(4_000, None)).unwrap();
+    let err = check_quota(&quotas, &usage, &send(4_001, None)).unwrap_err();
+    assert!(
+        err.to_string().contains("daily limit of 10000 sats"),
+        "{err}"
+    );
+    // monthly sats: 11_000 used of 15_000
+    let quotas_big_day = UserQuotas {
+        max_sats_per_day: 100_000,
+        ..quotas.clone()
+    };
+    check_quota(&quotas_big_day, &usage, &send(4_000, None)).unwrap();
+    let err = check_quota(&quotas_big_day, &usage, &send(4_001, None)).unwrap_err();
+    assert!(
+        err.to_string().contains("monthly limit of 15000 sats"),
+        "{err}"
+    );
+
+    // daily units: 60 used of 100, monthly units: 140 used of 150
+    check_quota(&quotas, &usage, &send(0, Some(10))).unwrap();
+    let err = check_quota(&quotas, &usage, &send(0, Some(11))).unwrap_err();
+    assert!(
+        err.to_string().contains("monthly limit of 150 units"),
+        "{err}"
+    );
+    // other assets have their own limits
+    let other_asset = QuotaSpend {
+        asset: Some(("rgb:other".to_string(), 100)),
+        ..send(0, None)
+    };
+    check_quota(&quotas, &usage, &other_asset).unwrap();
+
+    // one channel left, no assets left
+    let open_channel = QuotaSpend {
+        action: QuotaAction::CreateChannel,
+        ..send(0, None)
+    };
+    check_quota(&quotas, &usage, &open_channel).unwrap();
+    let full = QuotaUsage::new(2, 1, &records, Utc::now());
+    assert!(check_quota(&quotas, &full, &open_channel).is_err());
+    let issue_asset = QuotaSpend {
+        action: QuotaAction::CreateAsset,
+        ..send(0, None)
+    };
+    assert!(check_quota(&quotas, &usage, &issue_asset).is_err());
+
+    // daily transaction count
+    let records = [
+        record(QuotaAction::Transaction, 0, 0, 1),
+        record(QuotaAction::Transaction, 0, 0, 2),
+        record(QuotaAction::Transaction, 0, 0, 3),
+    ];
+    let usage = QuotaUsage::new(0, 0, &records, Utc::now());
+    let err = check_quota(&quotas, &usage, &send(1, None)).unwrap_err();
+    assert!(
+        err.to_string().contains("daily transaction limit of 3"),
+        "{err}"
+    );
+    check_quota(&quotas, &usage, &open_channel).unwrap();
+
+    // no quotas at all
+    let no_quotas = UserQuotas {
+        max_transactions_per_day: 0,
+        ..UserQuotas::default()
+    };
+    assert!(check_quota(&no_quotas, &QuotaUsage::default(), &send(1, None)).is_err());
+    let unlimited = UserQuotas {
+        max_channels: u32::MAX,
+        max_assets: u32::MAX,
+        max_transactions_per_day: u32::MAX,
+        max_transactions_per_month: u32::MAX,
+        max_balance_btc: u64::MAX,
+        max_sats_per_day: u64::MAX,
+        max_sats_per_month: u64::MAX,
+        max_asset_units_per_day: u64::MAX,
+        max_asset_units_per_month: u64::MAX,
+    };
+    check_quota(&unlimited, &usage, &send(u64::MAX, Some(u64::MAX))).unwrap();
+}
+
+#[test]
+fn quota_update() {
+    let mut quotas = UserQuotas::default();
+    let request: UpdateUserQuotasRequest =
+        serde_json::from_str(r#"{"user_id": "123", "max_sats_per_day": 5000, "max_channels": 3}"#)
+            .unwrap();
+    assert_eq!(request.user_id, "123");
+    request.quotas.apply(&mut quotas);
+    assert_eq!(
+        quotas,
+        UserQuotas {
+            max_sats_per_day: 5000,
+            max_channels: 3,
+            ..Default::default()
+        }
+    );
+}
@@ -0,0 +1,203 @@
+use crate::error::APIError;
+use crate::hub_auth::{hub_auth_middleware, HubAuth};
+use crate::rate_limit::{
+    rate_limit_config_from_json, rate_limit_middleware, BucketLimit, InMemoryRateLimitStore,
+    RateLimitBackend, RateLimitConfig, RateLimitRole, RateLimiter, RouteClass, TokenBucket,
+};
+use crate::utils::get_current_timestamp;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::{middleware, routing::post, Router};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn route_classes() {
+    for command in ["create_channel", "send_asset"] {
+        assert_eq!(
+            RouteClass::for_bot_command(command),
+            RouteClass::Expensive,
+            "{command}"
+        );
+    }
+    for command in ["get_balance", "generate_address", "list_assets", "unknown"] {
+        assert_eq!(
+            RouteClass::for_bot_command(command),
+            RouteClass::Default,
+            "{command}"
+        );
+    }
+}
+
+#[test]
+fn token_bucket_refill() {
+    let limit = BucketLimit {
+        capacity: 2,
+        refill_per_sec: 1.0,
+    };
+    let start = Instant::now();
+    let mut bucket = TokenBucket::full(limit, start);
+    assert!(bucket.take(limit, start).is_ok());
+    assert!(bucket.take(limit, start).is_ok());
+    assert_eq!(bucket.take(limit, start), Err(Duration::from_secs(1)));
+    assert_eq!(
+        bucket.take(limit, start + Duration::from_millis(500)),
+        Err(Duration::from_millis(500))
+    );
+    assert!(bucket
+        .take(limit, start + Duration::from_millis(1000))
+        .is_ok());
+
+    // refills never go above the capacity
+    let later = start + Duration::from_secs(3600);
+    assert!(bucket.take(limit, later).is_ok());
+    assert!(bucket.take(limit, later).is_ok());
+    assert!(bucket.take(limit, later).is_err());
+}
+
+#[test]
+fn rate_limit_config() {
+    let config = rate_limit_config_from_json(
+        r#"{
+            "backend": "postgres",
+            "limits": {
+                "user": {
+                    "default": {"capacity": 2, "refill_per_sec": 1},
+                    "expensive": {"capacity": 1, "refill_per_sec": 0.5}
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.backend, RateLimitBackend::Postgres);
+    assert_eq!(
+        config.limit(RateLimitRole::User, RouteClass::Expensive),
+        BucketLimit {
+            capacity: 1,
+            refill_per_sec: 0.5
+        }
+    );
+    // roles not in the config keep their defaults
+    let defaults = RateLimitConfig::default();
+    assert_eq!(defaults.backend, RateLimitBackend::Memory);
+    assert_eq!(
+        config.limit(RateLimitRole::Admin, RouteClass::Default),
+        defaults.limit(RateLimitRole::Admin, RouteClass::Default)
+    );
+    assert_eq!(rate_limit_config_from_json("{}").unwrap(), defaults);
+
+    for invalid in [
+        r#"{"limits": {"user": {"default": {"capacity": 0, "refill_per_sec": 1}, "expensive": {"capacity": 1, "refill_per_sec": 1}}}}"#,
+        r#"{"limits": {"user": {"default": {"capacity": 1, "refill_per_sec": 0}, "expensive": {"capacity": 1, "refill_per_sec": 1}}}}"#,
+        r#"{"limits": {"superuser": {}}}"#,
+        r#"{"limits": {"read_only": {"default": {"capacity": 1, "refill_per_sec": 1}, "expensive": {"capacity": 1, "refill_per_sec": 1}}}}"#,
+        r#"{"backend": "redis"}"#,
+    ] {
+        assert!(rate_limit_config_from_json(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[tokio::test]
+async fn rate_limiter_per_user_and_route() {
+    let config = rate_limit_config_from_json(
+        r#"{"limits": {"user": {
+            "default": {"capacity": 3, "refill_per_sec": 0.001},
+            "expensive": {"capacity": 1, "refill_per_sec": 0.1}
+        }}}"#,
+    )
+    .unwrap();
+    let limiter = RateLimiter::with_store(config, Arc::new(InMemoryRateLimitStore::default()));
+
+    limiter
+        .check("alice", RateLimitRole::User, RouteClass::Expensive)
+        .await
+        .unwrap();
+    let err = limiter
+        .check("alice", RateLimitRole::User, RouteClass::Expensive)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        APIError::RateLimited {
+            retry_after_secs: 10
+        }
+    ));
+
+    // the default class and other users have their own buckets
+    for _ in 0..3 {
+        limiter
+            .check("alice", RateLimitRole::User, RouteClass::Default)
+            .await
+            .unwrap();
+    }
+    assert!(limiter
+        .check("alice", RateLimitRole::User, RouteClass::Default)
+        .await
+        .is_err());
+    limiter
+        .check("bob", RateLimitRole::User, RouteClass::Expensive)
+        .await
+        .unwrap();
+    // admins get the larger default limits
+    for _ in 0..10 {
+        limiter
+            .check("carol", RateLimitRole::Admin, RouteClass::Expensive)
+            .await
+            .unwrap();
+    }
+}
+
+#[test]
+fn rate_limited_response() {
+    let response = APIError::RateLimited {
+        retry_after_secs: 7,
+    }
+    .into_response();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "7");
+}
+
+#[tokio::test]
+async fn rate_limited_through_router() {
+    let config = rate_limit_config_from_json(
+        r#"{"limits": {"user": {
+            "default": {"capacity": 2, "refill_per_sec": 0.01},
+            "expensive": {"capacity": 1, "refill_per_sec": 0.01}
+        }}}"#,
+    )
+    .unwrap();
+    let limiter = Arc::new(RateLimiter::with_store(
+        config,
+        Arc::new(InMemoryRateLimitStore::default()),
+    ));
+    let auth = Arc::new(HubAuth::new(None, [1; 32]).unwrap());
+    let hub_router = Router::new()
+        .route("/address", post(|| async { "ok" }))
+        .route_layer(middleware::from_fn_with_state(
+            limiter,
+            rate_limit_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            auth.clone(),
+            hub_auth_middleware,
+        ));
+    let router = Router::new().nest("/hub", hub_router);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hub/address", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    let client = reqwest::Client::new();
+    let alice = auth.issue_session("alice", get_current_timestamp());
+    for _ in 0..2 {
+        let res = client.post(&url).bearer_auth(&alice).send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    let res = client.post(&url).bearer_auth(&alice).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()[header::RETRY_AFTER], "100");
+
+    // other users have their own buckets
+    let bob = auth.issue_session("bob", get_current_timestamp());
+    let res = client.post(&url).bearer_auth(&bob).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
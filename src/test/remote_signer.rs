@@ -0,0 +1,96 @@
+use crate::remote_signer::{
+    compute_request_mac, compute_response_mac, new_nonce, parse_nonce, verify_request,
+    verify_response, SignerAuthError, SignerRequest, SignerResponse, MAX_CLOCK_SKEW_SECS,
+};
+use bitcoin::hex::DisplayHex;
+
+const SECRET: &[u8] = b"shared-secret";
+
+#[test]
+fn test_signer_request_mac() {
+    let body = serde_json::to_vec(&SignerRequest::DeriveNodeId {
+        user_id: "user1".to_string(),
+    })
+    .unwrap();
+    let timestamp = 1_700_000_000;
+    let nonce = new_nonce();
+    let mac = compute_request_mac(SECRET, timestamp, &nonce, &body);
+
+    assert!(verify_request(SECRET, timestamp, &nonce, &body, &mac, timestamp + 1).is_ok());
+    assert_eq!(
+        verify_request(b"other-secret", timestamp, &nonce, &body, &mac, timestamp),
+        Err(SignerAuthError::InvalidMac)
+    );
+    assert_eq!(
+        verify_request(SECRET, timestamp + 1, &nonce, &body, &mac, timestamp),
+        Err(SignerAuthError::InvalidMac)
+    );
+    assert_eq!(
+        verify_request(SECRET, timestamp, &new_nonce(), &body, &mac, timestamp),
+        Err(SignerAuthError::InvalidMac)
+    );
+    assert_eq!(
+        verify_request(SECRET, timestamp, &nonce, b"{}", &mac, timestamp),
+        Err(SignerAuthError::InvalidMac)
+    );
+    assert_eq!(
+        verify_request(
+            SECRET,
+            timestamp,
+            &nonce,
+            &body,
+            &mac,
+            timestamp + MAX_CLOCK_SKEW_SECS + 1
+        ),
+        Err(SignerAuthError::StaleRequest)
+    );
+
+    assert_eq!(parse_nonce(&nonce.to_lower_hex_string()), Ok(nonce));
+    assert_eq!(parse_nonce("00"), Err(SignerAuthError::InvalidNonce));
+}
+
+#[test]
+fn test_signer_response_mac() {
+    let request = serde_json::to_vec(&SignerRequest::DeriveNodeId {
+        user_id: "user1".to_string(),
+    })
+    .unwrap();
+    let other_request = serde_json::to_vec(&SignerRequest::DeriveNodeId {
+        user_id: "user2".to_string(),
+    })
+    .unwrap();
+    let body = serde_json::to_vec(&SignerResponse::NodeId {
+        node_id: "02".repeat(33),
+    })
+    .unwrap();
+    let timestamp = 1_700_000_000;
+    let nonce = new_nonce();
+    let mac = compute_response_mac(SECRET, timestamp, &nonce, &request, &body);
+
+    assert!(verify_response(SECRET, timestamp, &nonce, &request, &body, &mac).is_ok());
+    // a response to another request made in the same second doesn't verify
+    assert_eq!(
+        verify_response(SECRET, timestamp, &nonce, &other_request, &body, &mac),
+        Err(SignerAuthError::InvalidMac)
+    );
+    assert_eq!(
+        verify_response(SECRET, timestamp, &new_nonce(), &request, &body, &mac),
+        Err(SignerAuthError::InvalidMac)
+    );
+    // a response MAC can't be passed off as a request MAC for the same bytes
+    assert_eq!(
+        verify_request(SECRET, timestamp, &nonce, &body, &mac, timestamp),
+        Err(SignerAuthError::InvalidMac)
+    );
+}
+
+#[test]
+fn test_signer_request_encoding() {
+    let request = SignerRequest::SignHash {
+        user_id: "user1".to_string(),
+        hash: "00".repeat(32),
+    };
+    let json = serde_json::to_value(&request).unwrap();
+    assert_eq!(json["op"], "sign_hash");
+    assert_eq!(serde_json::from_value::<SignerRequest>(json).unwrap(), request);
+}
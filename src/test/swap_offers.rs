@@ -0,0 +1,189 @@
+use crate::ldk::SwapMap;
+use crate::routes::SwapStatus;
+use crate::swap::{OfferFillError, SwapData, SwapInfo, SwapOfferData, MAX_OPEN_QUOTES_PER_OFFER};
+use crate::utils::get_current_timestamp;
+use lightning::ln::PaymentHash;
+use lightning::util::ser::{Readable, Writeable};
+use rgb_lib::ContractId;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const CONTRACT_ID: &str = "rgb:CJkb4YZw-jRiz2sk-~PARPio-wtVYI1c-XAEYCqO-wTfvRZ8";
+
+fn offer() -> SwapOfferData {
+    SwapOfferData {
+        from_asset: None,
+        to_asset: Some(ContractId::from_str(CONTRACT_ID).unwrap()),
+        price_qty_from: 3,
+        price_qty_to: 2,
+        min_qty_from: 30,
+        max_qty_from: 3000,
+        swap_timeout_sec: 100,
+        created_at: 1,
+        expires_at: None,
+        total_qty_from: None,
+    }
+}
+
+#[test]
+fn test_swap_offer_quote() {
+    let offer = offer();
+    assert_eq!(offer.quote(30), Ok(20));
+    assert_eq!(offer.quote(3000), Ok(2000));
+    // rounded down in the maker's favor
+    assert_eq!(offer.quote(31), Ok(20));
+    assert!(offer.quote(29).is_err());
+    assert!(offer.quote(3001).is_err());
+
+    let offer = SwapOfferData {
+        price_qty_from: 1000,
+        price_qty_to: 1,
+        min_qty_from: 1,
+        ..offer
+    };
+    assert!(offer.quote(999).is_err());
+}
+
+#[test]
+fn test_swap_offer_pair_and_expiry() {
+    let offer = offer();
+    let contract_id = ContractId::from_str(CONTRACT_ID).unwrap();
+    assert!(offer.is_pair(None, Some(contract_id)));
+    assert!(!offer.is_pair(Some(contract_id), None));
+    assert!(!offer.is_expired());
+    assert!(SwapOfferData {
+        expires_at: Some(1),
+        ..offer
+    }
+    .is_expired());
+}
+
+#[test]
+fn test_swap_map_persists_offers() {
+    let swap_info = SwapInfo {
+        qty_from: 30,
+        qty_to: 20,
+        from_asset: None,
+        to_asset: Some(ContractId::from_str(CONTRACT_ID).unwrap()),
+        expiry: 100,
+    };
+    let swap_map = SwapMap {
+        swaps: HashMap::from([(
+            PaymentHash([1; 32]),
+            SwapData::create_from_offer(&swap_info, "offer1"),
+        )]),
+        offers: HashMap::from([("offer1".to_string(), offer())]),
+    };
+
+    let read = SwapMap::read(&mut &swap_map.encode()[..]).unwrap();
+    assert_eq!(read.offers["offer1"].max_qty_from, 3000);
+    assert_eq!(
+        read.swaps[&PaymentHash([1; 32])].offer_id.as_deref(),
+        Some("offer1")
+    );
+}
+
+#[test]
+fn test_swap_offer_partial_fills() {
+    let swap_info = |qty_from, expiry| SwapInfo {
+        qty_from,
+        qty_to: qty_from * 2 / 3,
+        from_asset: None,
+        to_asset: Some(ContractId::from_str(CONTRACT_ID).unwrap()),
+        expiry,
+    };
+    let future = get_current_timestamp() + 100;
+    let fill = |qty_from, expiry, status| SwapData {
+        status,
+        ..SwapData::create_from_offer(&swap_info(qty_from, expiry), "offer1")
+    };
+
+    let mut swap_map = SwapMap {
+        swaps: HashMap::new(),
+        offers: HashMap::from([
+            (
+                "offer1".to_string(),
+                SwapOfferData {
+                    total_qty_from: Some(1000),
+                    ..offer()
+                },
+            ),
+            ("unlimited".to_string(), offer()),
+        ]),
+    };
+    assert_eq!(swap_map.offer_remaining_qty_from("offer1"), Some(1000));
+    assert_eq!(swap_map.offer_remaining_qty_from("unlimited"), None);
+    assert_eq!(swap_map.offer_remaining_qty_from("unknown"), None);
+
+    swap_map.swaps.extend([
+        (PaymentHash([1; 32]), fill(300, future, SwapStatus::Waiting)),
+        (PaymentHash([2; 32]), fill(200, future, SwapStatus::Pending)),
+        (PaymentHash([3; 32]), fill(100, future, SwapStatus::Succeeded)),
+        // failed, expired and timed out swaps release their size
+        (PaymentHash([4; 32]), fill(50, future, SwapStatus::Failed)),
+        (PaymentHash([5; 32]), fill(50, future, SwapStatus::Expired)),
+        (PaymentHash([6; 32]), fill(50, 1, SwapStatus::Waiting)),
+    ]);
+    swap_map.swaps.insert(
+        PaymentHash([7; 32]),
+        SwapData::create_from_swap_info(&swap_info(500, future)),
+    );
+    assert_eq!(swap_map.offer_remaining_qty_from("offer1"), Some(400));
+}
+
+#[test]
+fn test_swap_offer_open_quotes_cap() {
+    let swap_info = |expiry| SwapInfo {
+        qty_from: 30,
+        qty_to: 20,
+        from_asset: None,
+        to_asset: Some(ContractId::from_str(CONTRACT_ID).unwrap()),
+        expiry,
+    };
+    let future = get_current_timestamp() + 100;
+    let mut swap_map = SwapMap {
+        swaps: HashMap::new(),
+        offers: HashMap::from([("unlimited".to_string(), offer())]),
+    };
+
+    // an expired quote doesn't count towards the cap
+    swap_map.swaps.insert(
+        PaymentHash([0xff; 32]),
+        SwapData::create_from_offer(&swap_info(1), "unlimited"),
+    );
+    for i in 0..MAX_OPEN_QUOTES_PER_OFFER {
+        swap_map
+            .add_offer_swap(
+                PaymentHash([i as u8; 32]),
+                SwapData::create_from_offer(&swap_info(future), "unlimited"),
+                "unlimited",
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        swap_map.offer_open_quotes("unlimited"),
+        MAX_OPEN_QUOTES_PER_OFFER
+    );
+    assert_eq!(
+        swap_map.add_offer_swap(
+            PaymentHash([0xfe; 32]),
+            SwapData::create_from_offer(&swap_info(future), "unlimited"),
+            "unlimited",
+        ),
+        Err(OfferFillError::TooManyOpenQuotes)
+    );
+
+    // a quote taken by the taker frees a slot
+    swap_map
+        .swaps
+        .get_mut(&PaymentHash([0; 32]))
+        .unwrap()
+        .status = SwapStatus::Pending;
+    assert!(swap_map
+        .add_offer_swap(
+            PaymentHash([0xfe; 32]),
+            SwapData::create_from_offer(&swap_info(future), "unlimited"),
+            "unlimited",
+        )
+        .is_ok());
+}
@@ -50,7 +50,7 @@ async fn swap_reverse_same_channel() {
     let qty_to = 10;
     let maker_init_response =
         maker_init(maker_addr, qty_from, None, qty_to, Some(&asset_id), 3600).await;
-    taker(taker_addr, maker_init_response.swapstring.clone()).await;
+    taker(taker_addr, maker_init_response.signed_swapstring.clone()).await;
 
     let swaps_maker = list_swaps(maker_addr).await;
     assert!(swaps_maker.taker.is_empty());
@@ -133,7 +133,7 @@ async fn swap_reverse_same_channel() {
     let qty_to = 50000;
     let maker_init_response =
         maker_init(maker_addr, qty_from, Some(&asset_id), qty_to, None, 3600).await;
-    taker(taker_addr, maker_init_response.swapstring.clone()).await;
+    taker(taker_addr, maker_init_response.signed_swapstring.clone()).await;
 
     let swaps_maker = list_swaps(maker_addr).await;
     assert_eq!(swaps_maker.maker.len(), 1);
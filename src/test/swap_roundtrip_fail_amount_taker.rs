@@ -21,5 +21,5 @@ async fn swap_fail_amount_taker() {
 
     let maker_init_response =
         maker_init(node1_addr, 1000, Some(&asset_id), 360000, None, 5000).await;
-    taker(node2_addr, maker_init_response.swapstring.clone()).await;
+    taker(node2_addr, maker_init_response.signed_swapstring.clone()).await;
 }
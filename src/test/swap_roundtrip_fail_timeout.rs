@@ -56,7 +56,9 @@ async fn swap_roundtrip_fail_timeout() {
 
     // try adding an expired swap, which should fail
     let payload = TakerRequest {
-        swapstring: maker_init_response_1.swapstring.clone(),
+        swapstring: maker_init_response_1.signed_swapstring.clone(),
+        maker_pubkey: None,
+        allow_unsigned: false,
     };
     let res = reqwest::Client::new()
         .post(format!("http://{taker_addr}/taker"))
@@ -83,8 +85,23 @@ async fn swap_roundtrip_fail_timeout() {
     let maker_init_response_2 =
         maker_init(maker_addr, qty_from_2, None, qty_to_2, Some(&asset_id), 10).await;
 
+    // an unsigned swapstring is rejected unless explicitly allowed
+    let payload = TakerRequest {
+        swapstring: maker_init_response_2.swapstring.clone(),
+        maker_pubkey: None,
+        allow_unsigned: false,
+    };
+    let res = reqwest::Client::new()
+        .post(format!("http://{taker_addr}/taker"))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert!(res.text().await.unwrap().contains("swapstring is not signed"));
+
     // add the swap
-    taker(taker_addr, maker_init_response_2.swapstring.clone()).await;
+    taker(taker_addr, maker_init_response_2.signed_swapstring.clone()).await;
 
     let swaps_maker = list_swaps(maker_addr).await;
     assert_eq!(swaps_maker.maker.len(), 2);
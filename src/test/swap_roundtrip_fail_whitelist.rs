@@ -62,7 +62,7 @@ async fn swap_fail_whitelist() {
         maker_init(maker_addr, qty_from, None, qty_to, Some(&asset_id), 5000).await;
     // We don't execute the taker command, so the swapstring is not going to be Waiting, and
     // the swap will fail.
-    //let taker_response = taker(taker_addr, maker_init_response.swapstring.clone()).await;
+    //let taker_response = taker(taker_addr, maker_init_response.signed_swapstring.clone()).await;
 
     let swaps_maker = list_swaps(maker_addr).await;
     assert!(swaps_maker.taker.is_empty());
@@ -151,7 +151,7 @@ async fn swap_roundtrip_multihop_asset_asset() {
         500,
     )
     .await;
-    taker(taker_addr, maker_init_response.swapstring.clone()).await;
+    taker(taker_addr, maker_init_response.signed_swapstring.clone()).await;
 
     let swaps_maker = list_swaps(maker_addr).await;
     assert!(swaps_maker.taker.is_empty());
@@ -127,7 +127,7 @@ async fn swap_roundtrip_multihop_buy() {
     let qty_from = 36000;
     let qty_to = 10;
     let maker_init_response = maker_init(maker_addr, 36000, None, 10, Some(&asset_id), 500).await;
-    taker(taker_addr, maker_init_response.swapstring.clone()).await;
+    taker(taker_addr, maker_init_response.signed_swapstring.clone()).await;
 
     let swaps_maker = list_swaps(maker_addr).await;
     assert!(swaps_maker.taker.is_empty());
@@ -128,7 +128,7 @@ async fn swap_roundtrip_multihop_sell() {
     let qty_from = 10;
     let qty_to = 3600;
     let maker_init_response = maker_init(maker_addr, 10, Some(&asset_id), 3600, None, 5000).await;
-    taker(taker_addr, maker_init_response.swapstring.clone()).await;
+    taker(taker_addr, maker_init_response.signed_swapstring.clone()).await;
 
     let swaps_maker = list_swaps(maker_addr).await;
     assert!(swaps_maker.taker.is_empty());
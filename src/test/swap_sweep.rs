@@ -0,0 +1,152 @@
+use crate::ldk::SwapMap;
+use crate::routes::SwapStatus;
+use crate::swap::{SwapData, SwapInfo, SwapOfferData, PENDING_SWAP_TIMEOUT_SECS};
+use lightning::ln::channelmanager::InterceptId;
+use lightning::ln::PaymentHash;
+use lightning::util::ser::{Readable, Writeable};
+use rgb_lib::ContractId;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const NOW: u64 = 1_700_000_000;
+const RETENTION_SECS: u64 = 1000;
+
+fn swap(status: SwapStatus, expiry: u64) -> SwapData {
+    let swap_info = SwapInfo {
+        qty_from: 30,
+        qty_to: 10,
+        from_asset: None,
+        to_asset: Some(
+            ContractId::from_str("rgb:CJkb4YZw-jRiz2sk-~PARPio-wtVYI1c-XAEYCqO-wTfvRZ8").unwrap(),
+        ),
+        expiry,
+    };
+    SwapData {
+        status,
+        ..SwapData::create_from_swap_info(&swap_info)
+    }
+}
+
+fn completed(status: SwapStatus, completed_at: u64) -> SwapData {
+    SwapData {
+        completed_at: Some(completed_at),
+        ..swap(status, completed_at)
+    }
+}
+
+#[test]
+fn test_sweep_times_out_swaps() {
+    let mut swap_map = SwapMap {
+        swaps: HashMap::from([
+            (PaymentHash([1; 32]), swap(SwapStatus::Waiting, NOW - 1)),
+            (PaymentHash([2; 32]), swap(SwapStatus::Waiting, NOW + 1)),
+            (
+                PaymentHash([3; 32]),
+                SwapData {
+                    initiated_at: Some(NOW - PENDING_SWAP_TIMEOUT_SECS - 1),
+                    ..swap(SwapStatus::Pending, NOW - 1)
+                },
+            ),
+            (
+                PaymentHash([4; 32]),
+                SwapData {
+                    initiated_at: Some(NOW - 1),
+                    ..swap(SwapStatus::Pending, NOW - 1)
+                },
+            ),
+        ]),
+        offers: HashMap::new(),
+    };
+
+    let sweep = swap_map.sweep(NOW, RETENTION_SECS);
+    assert_eq!(sweep.timed_out.len(), 2);
+    assert_eq!(sweep.pruned_swaps, 0);
+
+    let status = |byte: u8| swap_map.swaps[&PaymentHash([byte; 32])].status.clone();
+    assert_eq!(status(1), SwapStatus::Expired);
+    assert_eq!(status(2), SwapStatus::Waiting);
+    assert_eq!(status(3), SwapStatus::Failed);
+    assert_eq!(status(4), SwapStatus::Pending);
+    assert_eq!(
+        swap_map.swaps[&PaymentHash([1; 32])].completed_at,
+        Some(NOW)
+    );
+
+    // a second sweep has nothing left to do
+    assert!(swap_map.sweep(NOW, RETENTION_SECS).is_empty());
+}
+
+#[test]
+fn test_sweep_prunes_old_swaps_and_offers() {
+    let offer = |expires_at| SwapOfferData {
+        from_asset: None,
+        to_asset: None,
+        price_qty_from: 1,
+        price_qty_to: 1,
+        min_qty_from: 1,
+        max_qty_from: 10,
+        swap_timeout_sec: 100,
+        created_at: 0,
+        expires_at,
+        total_qty_from: Some(100),
+    };
+    let old = NOW - RETENTION_SECS - 1;
+    let mut swap_map = SwapMap {
+        swaps: HashMap::from([
+            (PaymentHash([1; 32]), completed(SwapStatus::Succeeded, old)),
+            (PaymentHash([2; 32]), completed(SwapStatus::Failed, old)),
+            (PaymentHash([3; 32]), completed(SwapStatus::Expired, NOW - 1)),
+            (
+                PaymentHash([4; 32]),
+                SwapData {
+                    offer_id: Some("live".to_string()),
+                    ..completed(SwapStatus::Succeeded, old)
+                },
+            ),
+            (
+                PaymentHash([5; 32]),
+                SwapData {
+                    offer_id: Some("expired".to_string()),
+                    ..completed(SwapStatus::Succeeded, old)
+                },
+            ),
+        ]),
+        offers: HashMap::from([
+            ("live".to_string(), offer(None)),
+            ("expired".to_string(), offer(Some(old))),
+        ]),
+    };
+
+    let sweep = swap_map.sweep(NOW, RETENTION_SECS);
+    assert_eq!(sweep.pruned_offers, 1);
+    assert_eq!(sweep.pruned_swaps, 3);
+    assert!(swap_map.offers.contains_key("live"));
+    // recent swaps and fills of live offers are kept
+    assert!(swap_map.swaps.contains_key(&PaymentHash([3; 32])));
+    assert!(swap_map.swaps.contains_key(&PaymentHash([4; 32])));
+}
+
+#[test]
+fn test_sweep_fails_held_htlc_of_timed_out_swap() {
+    // the HTLC was intercepted while the swap was waiting and never forwarded
+    let held = SwapData {
+        intercept_id: Some(InterceptId([7; 32])),
+        ..swap(SwapStatus::Waiting, NOW + 1)
+    };
+    let swap_map = SwapMap {
+        swaps: HashMap::from([(PaymentHash([1; 32]), held)]),
+        offers: HashMap::new(),
+    };
+
+    // the intercepted HTLC survives a restart
+    let mut swap_map = SwapMap::read(&mut &swap_map.encode()[..]).unwrap();
+    assert!(swap_map.sweep(NOW, RETENTION_SECS).is_empty());
+
+    let sweep = swap_map.sweep(NOW + 2, RETENTION_SECS);
+    assert_eq!(sweep.timed_out.len(), 1);
+    let (payment_hash, swap) = &sweep.timed_out[0];
+    assert_eq!(*payment_hash, PaymentHash([1; 32]));
+    assert_eq!(swap.status, SwapStatus::Expired);
+    // the sweeper fails the held HTLC through this
+    assert_eq!(swap.intercept_id, Some(InterceptId([7; 32])));
+}
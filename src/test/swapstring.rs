@@ -0,0 +1,56 @@
+use crate::swap::{SwapInfo, SwapString};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use lightning::ln::PaymentHash;
+use rgb_lib::ContractId;
+use std::str::FromStr;
+
+fn swapstring() -> SwapString {
+    let swap_info = SwapInfo {
+        qty_from: 30,
+        qty_to: 10,
+        from_asset: None,
+        to_asset: Some(
+            ContractId::from_str("rgb:CJkb4YZw-jRiz2sk-~PARPio-wtVYI1c-XAEYCqO-wTfvRZ8").unwrap(),
+        ),
+        expiry: 1715896416,
+    };
+    SwapString::from_swap_info(&swap_info, PaymentHash([7; 32]))
+}
+
+#[test]
+fn test_unsigned_swapstring_roundtrip() {
+    let unsigned = swapstring().to_string();
+    let parsed = SwapString::from_str(&unsigned).unwrap();
+    assert!(parsed.maker_pubkey().is_none());
+    assert_eq!(parsed.to_string(), unsigned);
+}
+
+#[test]
+fn test_signed_swapstring() {
+    let node_secret = SecretKey::from_slice(&[3; 32]).unwrap();
+    let node_id = PublicKey::from_secret_key(&Secp256k1::new(), &node_secret);
+
+    let signed = swapstring().signed(&node_secret).to_string();
+    assert!(signed.starts_with(&swapstring().to_string()));
+    let parsed = SwapString::from_str(&signed).unwrap();
+    assert_eq!(parsed.maker_pubkey(), Some(node_id));
+    assert_eq!(parsed.to_string(), signed);
+
+    // tampering with the terms invalidates the signature
+    let tampered = signed.replacen("30/", "31/", 1);
+    assert_eq!(
+        SwapString::from_str(&tampered).unwrap_err(),
+        "Invalid maker signature"
+    );
+
+    // claiming another maker invalidates the signature
+    let other_node_id =
+        PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[4; 32]).unwrap());
+    let impersonated = signed.replace(&node_id.to_string(), &other_node_id.to_string());
+    assert!(SwapString::from_str(&impersonated).is_err());
+
+    // a signature without the maker pubkey is rejected
+    let (without_pubkey, signature) = signed.rsplit_once('/').unwrap();
+    let (terms, _) = without_pubkey.rsplit_once('/').unwrap();
+    assert!(SwapString::from_str(&format!("{terms}/{signature}")).is_err());
+}
@@ -0,0 +1,192 @@
+use crate::telegram_bot::{
+    balance_message, payment_notification, BotApi, BotCommand, TelegramBotConfig, HELP,
+};
+use crate::utils::UserPaymentEvent;
+use axum::{http::StatusCode, routing::post, Json, Router};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// real tokens contain a colon, which axum routes would read as a path parameter
+const TOKEN: &str = "123456-secret";
+
+/// Serve a Bot API mock, returning its URL and the messages sent through it
+async fn mock_bot_api() -> (String, Arc<Mutex<Vec<Value>>>) {
+    let sent = Arc::new(Mutex::new(vec![]));
+    let sent_messages = sent.clone();
+    let app = Router::new()
+        .route(
+            &format!("/bot{TOKEN}/getUpdates"),
+            post(|Json(params): Json<Value>| async move {
+                let updates: Vec<Value> = [
+                    json!({"update_id": 7, "message": {
+                        "message_id": 1,
+                        "chat": {"id": 42, "type": "private"},
+                        "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+                        "text": "/balance"
+                    }}),
+                    json!({"update_id": 8, "edited_message": {}}),
+                ]
+                .into_iter()
+                .filter(|update| update["update_id"].as_i64() >= params["offset"].as_i64())
+                .collect();
+                Json(json!({"ok": true, "result": updates}))
+            }),
+        )
+        .route(
+            &format!("/bot{TOKEN}/sendMessage"),
+            post(move |Json(params): Json<Value>| {
+                let sent_messages = sent_messages.clone();
+                async move {
+                    sent_messages.lock().unwrap().push(params);
+                    Json(json!({"ok": true, "result": {"message_id": 2}}))
+                }
+            }),
+        )
+        .fallback(|| async {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"ok": false, "error_code": 401, "description": "Unauthorized"})),
+            )
+        });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{addr}/"), sent)
+}
+
+#[test]
+fn bot_command_parsing() {
+    assert_eq!(BotCommand::parse("/start"), Ok(BotCommand::Help));
+    assert_eq!(
+        BotCommand::parse("/balance"),
+        Ok(BotCommand::Balance { asset_id: None })
+    );
+    assert_eq!(
+        BotCommand::parse("/balance@rln_bot rgb:asset"),
+        Ok(BotCommand::Balance {
+            asset_id: Some("rgb:asset".to_string())
+        })
+    );
+    assert_eq!(
+        BotCommand::parse("/invoice 1500"),
+        Ok(BotCommand::Invoice { amount_sats: 1500 })
+    );
+    assert_eq!(
+        BotCommand::parse("/pay  lnbcrt1  "),
+        Ok(BotCommand::Pay {
+            invoice: "lnbcrt1".to_string(),
+            amount_msat: None
+        })
+    );
+    assert_eq!(
+        BotCommand::parse("/pay lnbcrt1 3000"),
+        Ok(BotCommand::Pay {
+            invoice: "lnbcrt1".to_string(),
+            amount_msat: Some(3000)
+        })
+    );
+    assert_eq!(
+        BotCommand::parse("/send rgb:~/~/~/bc:utxob:x 10"),
+        Ok(BotCommand::Send {
+            invoice: "rgb:~/~/~/bc:utxob:x".to_string(),
+            amount: Some(10)
+        })
+    );
+
+    assert_eq!(
+        BotCommand::parse("/invoice 0"),
+        Err("Invalid amount: 0".to_string())
+    );
+    assert_eq!(
+        BotCommand::parse("/invoice 18446744073709551615"),
+        Err("Invalid amount: 18446744073709551615".to_string())
+    );
+    assert_eq!(
+        BotCommand::parse("/pay lnbcrt1 -5"),
+        Err("Invalid amount: -5".to_string())
+    );
+    assert_eq!(
+        BotCommand::parse("/invoice"),
+        Err(format!("Invalid arguments for /invoice\n\n{HELP}"))
+    );
+    assert_eq!(
+        BotCommand::parse("/withdraw all"),
+        Err(format!("Unknown command /withdraw\n\n{HELP}"))
+    );
+}
+
+#[test]
+fn bot_messages() {
+    let balances = HashMap::from([
+        ("BTC".to_string(), 2_500),
+        ("rgb:b".to_string(), 10),
+        ("rgb:a".to_string(), 3),
+        ("rgb:spent".to_string(), 0),
+    ]);
+    assert_eq!(
+        balance_message(&balances, None),
+        "BTC: 2500 sats\nrgb:a: 3\nrgb:b: 10"
+    );
+    assert_eq!(balance_message(&balances, Some("rgb:b")), "rgb:b: 10");
+    assert_eq!(balance_message(&balances, Some("rgb:c")), "rgb:c: 0");
+    assert_eq!(balance_message(&HashMap::new(), None), "BTC: 0 sats");
+
+    let event = UserPaymentEvent {
+        user_id: "42".to_string(),
+        amount_msat: 21_000,
+        payment_hash: "ab".repeat(32),
+    };
+    assert_eq!(
+        payment_notification(&event),
+        Some((
+            42,
+            format!("Received 21 sats\nPayment hash: {}", "ab".repeat(32))
+        ))
+    );
+    let event = UserPaymentEvent {
+        amount_msat: 1_500,
+        ..event
+    };
+    assert!(payment_notification(&event)
+        .unwrap()
+        .1
+        .starts_with("Received 1500 msat"));
+    let event = UserPaymentEvent {
+        user_id: "tg_42".to_string(),
+        ..event
+    };
+    assert_eq!(payment_notification(&event), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn bot_api_client() {
+    let (api_url, sent) = mock_bot_api().await;
+    let api = BotApi::new(&TelegramBotConfig {
+        token: TOKEN.to_string(),
+        api_url: api_url.clone(),
+    });
+
+    let updates = api.get_updates(0, 0).await.unwrap();
+    assert_eq!(updates.len(), 2);
+    let message = updates[0].message.as_ref().unwrap();
+    assert_eq!(message.chat.id, 42);
+    assert_eq!(message.chat.kind, "private");
+    assert_eq!(message.from.as_ref().unwrap().id, 42);
+    assert_eq!(message.text.as_deref(), Some("/balance"));
+    assert!(updates[1].message.is_none());
+    assert_eq!(api.get_updates(9, 0).await.unwrap().len(), 0);
+
+    api.send_message(42, "BTC: 0 sats").await.unwrap();
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![json!({"chat_id": 42, "text": "BTC: 0 sats"})]
+    );
+
+    let api = BotApi::new(&TelegramBotConfig {
+        token: "wrong".to_string(),
+        api_url,
+    });
+    let err = api.get_updates(0, 0).await.unwrap_err();
+    assert_eq!(err.to_string(), "Bot API error: Unauthorized");
+}
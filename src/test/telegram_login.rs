@@ -0,0 +1,197 @@
+use crate::telegram_login::{
+    LoginReplayCache, TelegramLoginError, TelegramLoginUser, TelegramLoginVerifier,
+    MAX_CLOCK_SKEW_SECS,
+};
+use bitcoin::hex::DisplayHex;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+const BOT_TOKEN: &str = "123456:bot-token";
+const MAX_AGE_SECS: u64 = 600;
+const NOW: i64 = 1_700_000_000;
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Sign fields as Telegram does, returning them with their hash
+fn sign(fields: &[(&str, &str)], secret_key: &[u8]) -> BTreeMap<String, String> {
+    let mut fields: BTreeMap<String, String> = fields
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let data_check_string = fields
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let hash = hmac(secret_key, data_check_string.as_bytes()).to_lower_hex_string();
+    fields.insert("hash".to_string(), hash);
+    fields
+}
+
+fn widget_login(auth_date: i64) -> BTreeMap<String, String> {
+    sign(
+        &[
+            ("id", "42"),
+            ("first_name", "Alice"),
+            ("username", "alice"),
+            ("auth_date", &auth_date.to_string()),
+        ],
+        &Sha256::digest(BOT_TOKEN.as_bytes()),
+    )
+}
+
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn init_data(fields: &[(&str, &str)], secret_key: &[u8]) -> String {
+    sign(fields, secret_key)
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[test]
+fn telegram_login_widget() {
+    let cache = LoginReplayCache::default();
+    let verifier = TelegramLoginVerifier::new(BOT_TOKEN, MAX_AGE_SECS, &cache);
+
+    let login = widget_login(NOW - 5);
+    assert_eq!(
+        verifier.verify_login_widget(&login, NOW),
+        Ok(TelegramLoginUser {
+            id: 42,
+            first_name: "Alice".to_string(),
+            last_name: None,
+            username: Some("alice".to_string()),
+        })
+    );
+    // the same login can't open a second session
+    assert_eq!(
+        verifier.verify_login_widget(&login, NOW + 1),
+        Err(TelegramLoginError::Replayed)
+    );
+    let mut upper_case = login.clone();
+    upper_case.insert("hash".to_string(), login["hash"].to_uppercase());
+    assert_eq!(
+        verifier.verify_login_widget(&upper_case, NOW + 1),
+        Err(TelegramLoginError::Replayed)
+    );
+
+    let mut tampered = widget_login(NOW - 4);
+    tampered.insert("id".to_string(), "43".to_string());
+    assert_eq!(
+        verifier.verify_login_widget(&tampered, NOW),
+        Err(TelegramLoginError::InvalidHash)
+    );
+    let other_bot = TelegramLoginVerifier::new("654321:other", MAX_AGE_SECS, &cache);
+    assert_eq!(
+        other_bot.verify_login_widget(&widget_login(NOW - 3), NOW),
+        Err(TelegramLoginError::InvalidHash)
+    );
+    let mut no_hash = widget_login(NOW - 3);
+    no_hash.remove("hash");
+    assert!(matches!(
+        verifier.verify_login_widget(&no_hash, NOW),
+        Err(TelegramLoginError::Malformed(_))
+    ));
+
+    // too old, or signed in the future beyond the clock skew
+    let max_age = MAX_AGE_SECS as i64;
+    assert_eq!(
+        verifier.verify_login_widget(&widget_login(NOW - max_age - 1), NOW),
+        Err(TelegramLoginError::Expired)
+    );
+    verifier
+        .verify_login_widget(&widget_login(NOW - max_age), NOW)
+        .unwrap();
+    verifier
+        .verify_login_widget(&widget_login(NOW + MAX_CLOCK_SKEW_SECS), NOW)
+        .unwrap();
+    assert!(matches!(
+        verifier.verify_login_widget(&widget_login(NOW + MAX_CLOCK_SKEW_SECS + 1), NOW),
+        Err(TelegramLoginError::Malformed(_))
+    ));
+
+    // a leaked login is rejected for its age once it has left the replay cache
+    assert_eq!(
+        verifier.verify_login_widget(&login, NOW + max_age),
+        Err(TelegramLoginError::Expired)
+    );
+}
+
+#[test]
+fn telegram_mini_app_init_data() {
+    let cache = LoginReplayCache::default();
+    let verifier = TelegramLoginVerifier::new(BOT_TOKEN, MAX_AGE_SECS, &cache);
+    let secret_key = hmac(b"WebAppData", BOT_TOKEN.as_bytes());
+    let auth_date = (NOW - 10).to_string();
+    let user =
+        r#"{"id":42,"first_name":"Alice Smith","language_code":"en","allows_write_to_pm":true}"#;
+
+    let data = init_data(
+        &[
+            ("query_id", "AAHdF6IQAAAAAN0XohDhrOrc"),
+            ("user", user),
+            ("auth_date", &auth_date),
+        ],
+        &secret_key,
+    );
+    assert!(data.contains("user=%7B%22id%22%3A42"));
+    assert_eq!(
+        verifier.verify_init_data(&data, NOW),
+        Ok(TelegramLoginUser {
+            id: 42,
+            first_name: "Alice Smith".to_string(),
+            last_name: None,
+            username: None,
+        })
+    );
+    assert_eq!(
+        verifier.verify_init_data(&data, NOW),
+        Err(TelegramLoginError::Replayed)
+    );
+
+    // initData signed with the Login Widget secret
+    let widget_secret = Sha256::digest(BOT_TOKEN.as_bytes());
+    let data = init_data(&[("user", user), ("auth_date", &auth_date)], &widget_secret);
+    assert_eq!(
+        verifier.verify_init_data(&data, NOW),
+        Err(TelegramLoginError::InvalidHash)
+    );
+
+    let data = init_data(&[("user", user), ("auth_date", &auth_date)], &secret_key);
+    let tampered = data.replace("%3A42", "%3A43");
+    assert_eq!(
+        verifier.verify_init_data(&tampered, NOW),
+        Err(TelegramLoginError::InvalidHash)
+    );
+
+    let no_user = init_data(&[("auth_date", &auth_date)], &secret_key);
+    assert_eq!(
+        verifier.verify_init_data(&no_user, NOW),
+        Err(TelegramLoginError::Malformed("missing user".to_string()))
+    );
+    assert_eq!(
+        verifier.verify_init_data(&format!("{data}&auth_date=1"), NOW),
+        Err(TelegramLoginError::Malformed("repeated field".to_string()))
+    );
+    assert_eq!(
+        verifier.verify_init_data(&format!("{data}&x=%4"), NOW),
+        Err(TelegramLoginError::Malformed(
+            "invalid percent encoding".to_string()
+        ))
+    );
+}
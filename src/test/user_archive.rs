@@ -0,0 +1,234 @@
+use crate::error::APIError;
+use crate::user_archive::{
+    open_user_archive, pack_user_archive, user_wallet_dir, ArchivedBalance, ArchivedChannel,
+    ArchivedTransaction, ArchivedVirtualChannel, ArchivedWallet, UserArchiveData, UserRows,
+};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use std::fs::{create_dir_all, read, write};
+use std::path::Path;
+use uuid::Uuid;
+
+const PASSWORD: &str = "archive-password";
+const OLD_VIRTUAL_NODE_ID: &str =
+    "02a1633cafcc01ebfb6d78e39f687a1f0995c62fc95f51ead10a02ee0be551b5dc";
+const NEW_VIRTUAL_NODE_ID: &str =
+    "03b79a4bc1ec365524b4fab9a39eb133753646babb5a1da5c4bc94c53110b7795d";
+
+fn user_rows() -> UserRows {
+    UserRows {
+        wallet: Some(ArchivedWallet {
+            mnemonic_encrypted: "encrypted mnemonic".to_string(),
+            derivation_path: "m/84'/1'/0'".to_string(),
+            virtual_node_id: Some(OLD_VIRTUAL_NODE_ID.to_string()),
+            created_at: None,
+        }),
+        transactions: vec![ArchivedTransaction {
+            id: Uuid::new_v4(),
+            txid: "payment_hash".to_string(),
+            amount: 1000,
+            asset_id: None,
+            status: "mapped".to_string(),
+            virtual_node_id: Some(OLD_VIRTUAL_NODE_ID.to_string()),
+            created_at: None,
+        }],
+        channels: vec![ArchivedChannel {
+            id: Uuid::new_v4(),
+            channel_id: "channel".to_string(),
+            peer_pubkey: OLD_VIRTUAL_NODE_ID.to_string(),
+            capacity_sats: 0,
+            status: "mapped".to_string(),
+        }],
+        addresses: vec!["bcrt1qaddress".to_string()],
+        invoices: vec![],
+        balances: vec![ArchivedBalance {
+            asset_id: None,
+            amount: 5000,
+        }],
+        virtual_channels: vec![ArchivedVirtualChannel {
+            channel_id: "channel".to_string(),
+            virtual_node_id: OLD_VIRTUAL_NODE_ID.to_string(),
+        }],
+    }
+}
+
+fn hub_key(byte: u8) -> (SecretKey, PublicKey) {
+    let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+    (
+        secret,
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret),
+    )
+}
+
+fn populate_wallet_dir(wallet_dir: &Path) {
+    create_dir_all(wallet_dir.join("fingerprint").join("empty")).unwrap();
+    create_dir_all(wallet_dir.join("logs")).unwrap();
+    write(
+        wallet_dir.join("fingerprint").join("rgb_db"),
+        b"rgb database",
+    )
+    .unwrap();
+    write(wallet_dir.join("logs").join("log"), b"not archived").unwrap();
+}
+
+#[test]
+fn user_archive_roundtrip() {
+    let tmp = tempfile::tempdir().unwrap();
+    let wallet_dir = tmp.path().join("users").join("user1");
+    populate_wallet_dir(&wallet_dir);
+    let (hub_secret, hub_pubkey) = hub_key(1);
+    let (_, destination) = hub_key(3);
+    let data = UserArchiveData::new("user1", &destination, user_rows());
+    let archive = pack_user_archive(&data, &wallet_dir, PASSWORD, &hub_secret).unwrap();
+    assert!(!archive
+        .windows(b"rgb database".len())
+        .any(|w| w == b"rgb database"));
+
+    let opened = open_user_archive(&archive, PASSWORD, &[hub_pubkey], &destination).unwrap();
+    assert_eq!(opened.hub_pubkey, hub_pubkey);
+    assert_eq!(opened.data, data);
+
+    let restored_dir = tmp.path().join("other_hub").join("users").join("user1");
+    opened.extract_wallet(&restored_dir).unwrap();
+    assert_eq!(
+        read(restored_dir.join("fingerprint").join("rgb_db")).unwrap(),
+        b"rgb database"
+    );
+    assert!(restored_dir.join("fingerprint").join("empty").is_dir());
+    assert!(!restored_dir.join("logs").exists());
+
+    // an existing wallet is never overwritten
+    let err = opened.extract_wallet(&restored_dir).unwrap_err();
+    assert!(matches!(err, APIError::UserAlreadyExists(u) if u == "user1"));
+}
+
+#[test]
+fn user_archive_errors() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (hub_secret, hub_pubkey) = hub_key(1);
+    let (_, destination) = hub_key(3);
+    let data = UserArchiveData::new("user1", &destination, UserRows::default());
+    let trusted = [hub_pubkey];
+    // users without a wallet dir can still be archived
+    let archive =
+        pack_user_archive(&data, &tmp.path().join("missing"), PASSWORD, &hub_secret).unwrap();
+
+    let err = open_user_archive(&archive, "wrong-password", &trusted, &destination)
+        .err()
+        .unwrap();
+    assert!(matches!(err, APIError::WrongPassword));
+
+    // any change breaks the hub signature, before the archive is decrypted
+    for i in [archive.len() - 1, archive.len() / 2, 10] {
+        let mut tampered = archive.clone();
+        tampered[i] ^= 1;
+        let err = open_user_archive(&tampered, PASSWORD, &trusted, &destination)
+            .err()
+            .unwrap();
+        assert!(matches!(err, APIError::InvalidUserArchive(_)), "byte {i}");
+    }
+
+    let err = open_user_archive(b"not an archive", PASSWORD, &trusted, &destination)
+        .err()
+        .unwrap();
+    assert!(matches!(err, APIError::InvalidUserArchive(_)));
+    let err = open_user_archive(&archive[..9], PASSWORD, &trusted, &destination)
+        .err()
+        .unwrap();
+    assert!(matches!(err, APIError::InvalidUserArchive(_)));
+}
+
+#[test]
+fn user_archive_untrusted_hub() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (hub_secret, hub_pubkey) = hub_key(1);
+    let (other_secret, other_pubkey) = hub_key(2);
+    let (_, destination) = hub_key(3);
+    let data = UserArchiveData::new("user1", &destination, user_rows());
+
+    // archives of hubs that aren't trusted can't credit balances
+    let archive = pack_user_archive(&data, tmp.path(), PASSWORD, &other_secret).unwrap();
+    for trusted in [vec![], vec![hub_pubkey]] {
+        let err = open_user_archive(&archive, PASSWORD, &trusted, &destination)
+            .err()
+            .unwrap();
+        assert!(matches!(err, APIError::InvalidUserArchive(e) if e.contains("not trusted")));
+    }
+    assert!(open_user_archive(
+        &archive,
+        PASSWORD,
+        &[hub_pubkey, other_pubkey],
+        &destination
+    )
+    .is_ok());
+
+    // the signer can't be swapped for a trusted hub
+    let mut forged = pack_user_archive(&data, tmp.path(), PASSWORD, &hub_secret).unwrap();
+    let pubkey_start = b"RLNUSER".len() + 1;
+    forged[pubkey_start..pubkey_start + 33].copy_from_slice(&other_pubkey.serialize());
+    let err = open_user_archive(&forged, PASSWORD, &[hub_pubkey, other_pubkey], &destination)
+        .err()
+        .unwrap();
+    assert!(matches!(err, APIError::InvalidUserArchive(e) if e == "invalid signature"));
+}
+
+#[test]
+fn user_archive_other_destination() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (hub_secret, hub_pubkey) = hub_key(1);
+    let (_, destination) = hub_key(3);
+    let (_, other_destination) = hub_key(4);
+    let data = UserArchiveData::new("user1", &destination, user_rows());
+    let archive = pack_user_archive(&data, tmp.path(), PASSWORD, &hub_secret).unwrap();
+
+    // trusting hubs other than the destination can't import the archive and credit it again
+    let err = open_user_archive(&archive, PASSWORD, &[hub_pubkey], &other_destination)
+        .err()
+        .unwrap();
+    assert!(matches!(err, APIError::InvalidUserArchive(e) if e.contains("not this one")));
+    assert!(open_user_archive(&archive, PASSWORD, &[hub_pubkey], &destination).is_ok());
+}
+
+#[test]
+fn user_wallet_dirs() {
+    let storage = Path::new("/data");
+    assert_eq!(
+        user_wallet_dir(storage, "user1").unwrap(),
+        storage.join("users").join("user1")
+    );
+    for user_id in ["", ".", "..", "../user1", "a/b", "a\\b"] {
+        assert!(
+            matches!(
+                user_wallet_dir(storage, user_id),
+                Err(APIError::InvalidUserArchive(_))
+            ),
+            "{user_id}"
+        );
+    }
+}
+
+#[test]
+fn user_rows_rebind_virtual_node() {
+    let mut rows = user_rows();
+    rows.set_virtual_node_id(NEW_VIRTUAL_NODE_ID);
+    assert_eq!(
+        rows.wallet.unwrap().virtual_node_id.as_deref(),
+        Some(NEW_VIRTUAL_NODE_ID)
+    );
+    assert_eq!(
+        rows.transactions[0].virtual_node_id.as_deref(),
+        Some(NEW_VIRTUAL_NODE_ID)
+    );
+    assert_eq!(rows.channels[0].peer_pubkey, NEW_VIRTUAL_NODE_ID);
+    assert_eq!(
+        rows.virtual_channels[0].virtual_node_id,
+        NEW_VIRTUAL_NODE_ID
+    );
+
+    // rows of users without a virtual node are left as they are
+    let mut rows = UserRows {
+        wallet: None,
+        ..user_rows()
+    };
+    rows.set_virtual_node_id(NEW_VIRTUAL_NODE_ID);
+    assert_eq!(rows.channels[0].peer_pubkey, OLD_VIRTUAL_NODE_ID);
+}
@@ -84,4 +84,43 @@ fn test_virtual_settlement_structure() {
     assert!(settlement.has_rgb_transfer());
     assert_eq!(settlement.btc_settled, 1000000);
     assert_eq!(settlement.rgb_amount(), Some(100));
-}
\ No newline at end of file
+}
+#[test]
+fn test_virtual_htlc_status_roundtrip() {
+    for status in [VirtualHtlcStatus::Pending, VirtualHtlcStatus::Settled, VirtualHtlcStatus::Failed] {
+        assert_eq!(VirtualHtlcStatus::from_str(status.as_str()).unwrap(), status);
+    }
+    assert!(VirtualHtlcStatus::from_str("unknown").is_err());
+}
+
+#[test]
+fn test_virtual_htlc_from_persisted_record() {
+    use crate::database::VirtualPaymentRecord;
+
+    let secp = Secp256k1::new();
+    let from_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1u8; 32]).unwrap());
+    let to_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+
+    let record = VirtualPaymentRecord {
+        payment_hash: "2a".repeat(32),
+        from_virtual_node_id: from_key.to_string(),
+        to_virtual_node_id: to_key.to_string(),
+        btc_amount_msat: 1000000,
+        rgb_contract_id: Some("rgb1qyfe883hey6jrgj2xvk5g3dfmfqfzm7a4wez4pd2krf7ltsxffd6u6nrvjvvnc8vt02v7".to_string()),
+        rgb_amount: Some(100),
+        status: "pending".to_string(),
+    };
+
+    let virtual_htlc = VirtualHtlc::try_from(record.clone()).unwrap();
+    assert_eq!(virtual_htlc.payment_hash, PaymentHash([42u8; 32]));
+    assert_eq!(virtual_htlc.from_virtual_node, from_key);
+    assert_eq!(virtual_htlc.to_virtual_node, to_key);
+    assert_eq!(virtual_htlc.status, VirtualHtlcStatus::Pending);
+    assert_eq!(virtual_htlc.rgb_transfer.unwrap().amount, 100);
+
+    let invalid = VirtualPaymentRecord {
+        payment_hash: "not_hex".to_string(),
+        ..record
+    };
+    assert!(VirtualHtlc::try_from(invalid).is_err());
+}
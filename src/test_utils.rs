@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::database::{LedgerPosting, LEDGER_EXTERNAL_ACCOUNT};
 use crate::error::APIError;
 use crate::utils::AppState;
 
@@ -64,14 +65,11 @@ pub async fn add_test_utxo(
         .await
         .map_err(|e| APIError::Unexpected(format!("Failed to add test UTXO: {}", e)))?;
         
-        // Also update ln_user_balances for consistency
-        sqlx::query!(
-            "INSERT INTO ln_user_balances (user_id, balance, updated_at) VALUES ($1, $2, NOW()) 
-             ON CONFLICT (user_id, COALESCE(asset_id, '')) DO UPDATE SET balance = ln_user_balances.balance + $2, updated_at = NOW()",
-            payload.user_id.to_string(),
-            payload.amount_sats as i64
+        // Also credit the ledger so the virtual balance matches the UTXO
+        db.post_journal(
+            &txid,
+            &LedgerPosting::transfer(LEDGER_EXTERNAL_ACCOUNT, &payload.user_id.to_string(), None, payload.amount_sats as i64),
         )
-        .execute(db.pool())
         .await
         .map_err(|e| APIError::Unexpected(format!("Failed to update balance: {}", e)))?;
         
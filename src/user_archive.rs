@@ -0,0 +1,367 @@
+//! Encrypted per-user archives, used to move a user between hub instances.
+//!
+//! An archive holds the RGB wallet dir of a user and their rows in the hub database, zipped and
+//! encrypted with a key derived from a password chosen by the user. The exporting hub signs the
+//! archive with its node key, so the importing hub only credits balances carried by archives of
+//! hubs it trusts, and the archive data names the destination hub, so only that hub can import
+//! it. The layout is
+//! `magic | version | hub node ID | salt length | salt | nonce | ciphertext | signature`, with
+//! the compact ECDSA signature covering everything before it.
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{
+    constants::PUBLIC_KEY_SIZE, ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_dir, rename, File};
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use uuid::Uuid;
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+
+use crate::backup::BackupCipher;
+use crate::error::APIError;
+use crate::utils::LOGS_DIR;
+
+const USER_ARCHIVE_MAGIC: &[u8] = b"RLNUSER";
+const USER_ARCHIVE_VERSION: u8 = 3;
+const USER_ARCHIVE_SIGNATURE_LEN: usize = 64;
+const USER_ARCHIVE_DATA_FNAME: &str = "user.json";
+const USER_ARCHIVE_WALLET_DIR: &str = "wallet";
+
+/// Environment variable listing the node IDs of the hubs whose archives can be imported,
+/// separated by commas
+pub(crate) const USER_ARCHIVE_TRUSTED_HUBS_ENV: &str = "USER_ARCHIVE_TRUSTED_HUBS";
+
+/// Parse the node IDs of the trusted hubs, none if [`USER_ARCHIVE_TRUSTED_HUBS_ENV`] isn't set
+pub(crate) fn trusted_hubs_from_env() -> Result<Vec<PublicKey>, String> {
+    let Ok(hubs) = std::env::var(USER_ARCHIVE_TRUSTED_HUBS_ENV) else {
+        return Ok(vec![]);
+    };
+    hubs.split(',')
+        .map(str::trim)
+        .filter(|hub| !hub.is_empty())
+        .map(|hub| PublicKey::from_str(hub).map_err(|_| format!("invalid hub node ID {hub}")))
+        .collect()
+}
+
+/// Directory holding the RGB wallet of a user
+pub(crate) fn user_wallet_dir(storage_dir_path: &Path, user_id: &str) -> Result<PathBuf, APIError> {
+    // user IDs of imported archives come from another hub, they can't point outside the dir
+    if user_id.is_empty() || user_id == "." || user_id == ".." || user_id.contains(['/', '\\']) {
+        return Err(APIError::InvalidUserArchive(format!(
+            "invalid user ID {user_id}"
+        )));
+    }
+    Ok(storage_dir_path.join("users").join(user_id))
+}
+
+/// Content of a user archive, besides the wallet files
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct UserArchiveData {
+    pub(crate) archive_id: Uuid,
+    pub(crate) user_id: String,
+    /// Node ID of the only hub that can import the archive
+    pub(crate) destination_hub: String,
+    pub(crate) exported_at: DateTime<Utc>,
+    pub(crate) rows: UserRows,
+}
+
+impl UserArchiveData {
+    pub(crate) fn new(user_id: &str, destination_hub: &PublicKey, rows: UserRows) -> Self {
+        Self {
+            archive_id: Uuid::new_v4(),
+            user_id: user_id.to_string(),
+            destination_hub: destination_hub.to_string(),
+            exported_at: Utc::now(),
+            rows,
+        }
+    }
+}
+
+/// Hub database rows belonging to a user
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) struct UserRows {
+    pub(crate) wallet: Option<ArchivedWallet>,
+    pub(crate) transactions: Vec<ArchivedTransaction>,
+    pub(crate) channels: Vec<ArchivedChannel>,
+    pub(crate) addresses: Vec<String>,
+    pub(crate) invoices: Vec<ArchivedInvoice>,
+    /// Ledger balances, credited again on the importing hub
+    pub(crate) balances: Vec<ArchivedBalance>,
+    pub(crate) virtual_channels: Vec<ArchivedVirtualChannel>,
+}
+
+impl UserRows {
+    /// Point the rows to the virtual node the user has on the importing hub
+    ///
+    /// Virtual node IDs are derived from the hub keys, so they change when moving to another hub.
+    pub(crate) fn set_virtual_node_id(&mut self, virtual_node_id: &str) {
+        let Some(old_virtual_node_id) = self
+            .wallet
+            .as_mut()
+            .and_then(|w| w.virtual_node_id.replace(virtual_node_id.to_string()))
+        else {
+            return;
+        };
+        let rebind = |id: &mut String| {
+            if *id == old_virtual_node_id {
+                *id = virtual_node_id.to_string();
+            }
+        };
+        for tx in &mut self.transactions {
+            if let Some(id) = tx.virtual_node_id.as_mut() {
+                rebind(id);
+            }
+        }
+        // mapped channels are stored with the virtual node as peer
+        for channel in &mut self.channels {
+            rebind(&mut channel.peer_pubkey);
+        }
+        for channel in &mut self.virtual_channels {
+            rebind(&mut channel.virtual_node_id);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ArchivedWallet {
+    pub(crate) mnemonic_encrypted: String,
+    pub(crate) derivation_path: String,
+    pub(crate) virtual_node_id: Option<String>,
+    pub(crate) created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ArchivedTransaction {
+    pub(crate) id: Uuid,
+    pub(crate) txid: String,
+    pub(crate) amount: i64,
+    pub(crate) asset_id: Option<String>,
+    pub(crate) status: String,
+    pub(crate) virtual_node_id: Option<String>,
+    pub(crate) created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ArchivedChannel {
+    pub(crate) id: Uuid,
+    pub(crate) channel_id: String,
+    pub(crate) peer_pubkey: String,
+    pub(crate) capacity_sats: i64,
+    pub(crate) status: String,
+}
+
+/// Invoice issued for the user
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ArchivedInvoice {
+    pub(crate) payment_hash: String,
+    pub(crate) invoice: String,
+    pub(crate) amount_msat: Option<i64>,
+    pub(crate) status: String,
+    pub(crate) expires_at: DateTime<Utc>,
+    pub(crate) paid_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ArchivedBalance {
+    pub(crate) asset_id: Option<String>,
+    pub(crate) amount: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ArchivedVirtualChannel {
+    pub(crate) channel_id: String,
+    pub(crate) virtual_node_id: String,
+}
+
+/// Decrypted user archive
+pub(crate) struct OpenedUserArchive {
+    /// Node ID of the hub that exported the archive
+    pub(crate) hub_pubkey: PublicKey,
+    pub(crate) data: UserArchiveData,
+    zip: Vec<u8>,
+}
+
+fn archive_digest(signed: &[u8]) -> Message {
+    Message::from_digest(sha256::Hash::hash(signed).to_byte_array())
+}
+
+/// Zip and encrypt the archive data and the user wallet dir, if it exists, and sign the archive
+/// with the node key of the hub
+pub(crate) fn pack_user_archive(
+    data: &UserArchiveData,
+    wallet_dir: &Path,
+    password: &str,
+    hub_secret: &SecretKey,
+) -> Result<Vec<u8>, APIError> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Zstd);
+    let zip_err = |e: zip::result::ZipError| {
+        APIError::Unexpected(format!("Failed to write user archive: {e}"))
+    };
+
+    zip.start_file(USER_ARCHIVE_DATA_FNAME, options)
+        .map_err(zip_err)?;
+    zip.write_all(&serde_json::to_vec(data).map_err(|e| {
+        APIError::Unexpected(format!("Failed to serialize user archive data: {e}"))
+    })?)?;
+
+    if wallet_dir.is_dir() {
+        for entry in WalkDir::new(wallet_dir).into_iter().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let rel_path = path
+                .strip_prefix(wallet_dir)
+                .map_err(|e| APIError::Unexpected(format!("Failed to get file name: {e}")))?;
+            if rel_path.as_os_str().is_empty()
+                || rel_path.components().any(|c| c.as_os_str() == LOGS_DIR)
+            {
+                continue;
+            }
+            let mut name = USER_ARCHIVE_WALLET_DIR.to_string();
+            for component in rel_path.components() {
+                let component = component.as_os_str().to_str().ok_or_else(|| {
+                    APIError::Unexpected(format!("Invalid file name {}", rel_path.display()))
+                })?;
+                name.push('/');
+                name.push_str(component);
+            }
+            if path.is_file() {
+                zip.start_file(name, options).map_err(zip_err)?;
+                std::io::copy(&mut File::open(path)?, &mut zip)?;
+            } else {
+                zip.add_directory(name, options).map_err(zip_err)?;
+            }
+        }
+    }
+    let zip = zip.finish().map_err(zip_err)?.into_inner();
+
+    let salt = BackupCipher::generate_salt();
+    let ciphertext = BackupCipher::new(password, &salt)?.encrypt(&zip)?;
+    let secp = Secp256k1::signing_only();
+    let mut archive = USER_ARCHIVE_MAGIC.to_vec();
+    archive.push(USER_ARCHIVE_VERSION);
+    archive.extend(PublicKey::from_secret_key(&secp, hub_secret).serialize());
+    archive.push(salt.len() as u8);
+    archive.extend(salt.as_bytes());
+    archive.extend(ciphertext);
+    let signature = secp.sign_ecdsa(&archive_digest(&archive), hub_secret);
+    archive.extend(signature.serialize_compact());
+    Ok(archive)
+}
+
+/// Check the archive was signed by one of the `trusted_hubs`, then decrypt it and read its data,
+/// which must name `local_hub` as destination
+pub(crate) fn open_user_archive(
+    archive: &[u8],
+    password: &str,
+    trusted_hubs: &[PublicKey],
+    local_hub: &PublicKey,
+) -> Result<OpenedUserArchive, APIError> {
+    let invalid = |details: &str| APIError::InvalidUserArchive(details.to_string());
+    let rest = archive
+        .strip_prefix(USER_ARCHIVE_MAGIC)
+        .ok_or_else(|| invalid("not a user archive"))?;
+    let (version, rest) = rest.split_first().ok_or_else(|| invalid("truncated"))?;
+    if *version != USER_ARCHIVE_VERSION {
+        return Err(APIError::InvalidUserArchive(format!(
+            "unsupported version {version}"
+        )));
+    }
+    if rest.len() < PUBLIC_KEY_SIZE + USER_ARCHIVE_SIGNATURE_LEN {
+        return Err(invalid("truncated"));
+    }
+    let (signed, signature) = archive.split_at(archive.len() - USER_ARCHIVE_SIGNATURE_LEN);
+    let rest = &rest[..rest.len() - USER_ARCHIVE_SIGNATURE_LEN];
+    let (hub_pubkey, rest) = rest.split_at(PUBLIC_KEY_SIZE);
+    let hub_pubkey =
+        PublicKey::from_slice(hub_pubkey).map_err(|_| invalid("invalid hub node ID"))?;
+    let signature = Signature::from_compact(signature).map_err(|_| invalid("invalid signature"))?;
+    Secp256k1::verification_only()
+        .verify_ecdsa(&archive_digest(signed), &signature, &hub_pubkey)
+        .map_err(|_| invalid("invalid signature"))?;
+    if !trusted_hubs.contains(&hub_pubkey) {
+        return Err(APIError::InvalidUserArchive(format!(
+            "exported by hub {hub_pubkey}, which is not trusted"
+        )));
+    }
+
+    let (salt_len, rest) = rest.split_first().ok_or_else(|| invalid("truncated"))?;
+    if rest.len() < *salt_len as usize {
+        return Err(invalid("truncated"));
+    }
+    let (salt, ciphertext) = rest.split_at(*salt_len as usize);
+    let salt = std::str::from_utf8(salt).map_err(|_| invalid("invalid salt"))?;
+    let zip = BackupCipher::new(password, salt)?.decrypt(ciphertext)?;
+
+    let mut zip_archive =
+        zip::ZipArchive::new(Cursor::new(&zip)).map_err(|e| invalid(&e.to_string()))?;
+    let mut data = vec![];
+    zip_archive
+        .by_name(USER_ARCHIVE_DATA_FNAME)
+        .map_err(|_| invalid("missing user data"))?
+        .read_to_end(&mut data)?;
+    let data: UserArchiveData =
+        serde_json::from_slice(&data).map_err(|e| invalid(&e.to_string()))?;
+    // each archive credits its balances once, on the hub it was exported for
+    if data.destination_hub != local_hub.to_string() {
+        return Err(APIError::InvalidUserArchive(format!(
+            "exported for hub {}, not this one",
+            data.destination_hub
+        )));
+    }
+
+    Ok(OpenedUserArchive {
+        hub_pubkey,
+        data,
+        zip,
+    })
+}
+
+impl OpenedUserArchive {
+    /// Extract the wallet files into `wallet_dir`, which must not exist or be empty
+    pub(crate) fn extract_wallet(&self, wallet_dir: &Path) -> Result<(), APIError> {
+        if wallet_dir.exists() && read_dir(wallet_dir)?.next().is_some() {
+            return Err(APIError::UserAlreadyExists(self.data.user_id.clone()));
+        }
+        let parent = wallet_dir
+            .parent()
+            .ok_or_else(|| APIError::Unexpected("Invalid wallet dir".to_string()))?;
+        create_dir_all(parent)?;
+
+        // extract next to the destination, so a failure leaves no partial wallet behind
+        let tmp_dir = tempfile::tempdir_in(parent)?;
+        let mut zip_archive = zip::ZipArchive::new(Cursor::new(&self.zip))
+            .map_err(|e| APIError::InvalidUserArchive(e.to_string()))?;
+        for i in 0..zip_archive.len() {
+            let mut file = zip_archive
+                .by_index(i)
+                .map_err(|e| APIError::InvalidUserArchive(e.to_string()))?;
+            let Some(rel_path) = file.enclosed_name().and_then(|p| {
+                p.strip_prefix(USER_ARCHIVE_WALLET_DIR)
+                    .ok()
+                    .map(Path::to_path_buf)
+            }) else {
+                continue;
+            };
+            let out_path = tmp_dir.path().join(rel_path);
+            if file.is_dir() {
+                create_dir_all(&out_path)?;
+                continue;
+            }
+            if let Some(p) = out_path.parent() {
+                create_dir_all(p)?;
+            }
+            std::io::copy(&mut file, &mut File::create(&out_path)?)?;
+        }
+
+        if wallet_dir.exists() {
+            std::fs::remove_dir(wallet_dir)?;
+        }
+        rename(tmp_dir.keep(), wallet_dir)?;
+        Ok(())
+    }
+}
@@ -75,20 +75,10 @@ impl UserManager {
         let mnemonic_encrypted = format!("encrypted_mnemonic_for_{}", user_id);
         let derivation_path = format!("m/84'/1'/0'"); // Testnet derivation path
 
+        // Balances start at zero since they're derived from the ledger
         self.database.create_user_wallet(user_id, &mnemonic_encrypted, &derivation_path).await
             .map_err(|e| AppError::Database(e.to_string()))?;
 
-        // Initialize with zero balances
-        self.database.update_user_balance(user_id, None, 0).await
-            .map_err(|e| AppError::Database(e.to_string()))?;
-
-        Ok(())
-    }
-
-    /// Update user balance
-    pub async fn update_user_balance(&self, user_id: &str, asset_id: Option<&str>, amount: i64) -> Result<(), AppError> {
-        self.database.update_user_balance(user_id, asset_id, amount).await
-            .map_err(|e| AppError::Database(e.to_string()))?;
         Ok(())
     }
 
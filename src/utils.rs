@@ -26,7 +26,7 @@ use std::{
     sync::{Arc, Mutex, MutexGuard},
     time::{Duration, SystemTime},
 };
-use tokio::sync::{Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
+use tokio::sync::{broadcast, Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
 use tokio_util::sync::CancellationToken;
 
 use crate::ldk::{ChannelIdsMap, Router};
@@ -39,13 +39,23 @@ use crate::{
     database::Database,
     disk::FilesystemLogger,
     error::{APIError, AppError},
-    hsm_provider::{HsmProvider, LocalHsmProvider},
+    hsm_provider::{hsm_provider_from_env, HsmProvider},
+    hub_auth::HubAuth,
     ldk::{
         BumpTxEventHandler, ChainMonitor, ChannelManager, InboundPaymentInfoStorage,
         LdkBackgroundServices, NetworkGraph, OnionMessenger, OutboundPaymentInfoStorage,
         OutputSweeper, PeerManager, SwapMap,
     },
+    metrics::Metrics,
+    monitor_mirror::{monitor_mirror_config_from_env, MonitorMirrorConfig},
+    pricing::{maker_price_policy_from_env, MakerPricePolicy},
+    rate_limit::{rate_limit_config_from_env, RateLimitBackend, RateLimiter},
+    telegram_bot::TelegramBotConfig,
+    telegram_login::LoginReplayCache,
+    user_archive::trusted_hubs_from_env,
     user_manager::UserManager,
+    virtual_channel::VirtualChannelManager,
+    virtual_htlc::VirtualHtlcManager,
     virtual_node::VirtualNodeManager,
 };
 
@@ -61,6 +71,8 @@ pub(crate) const ELECTRUM_URL_MAINNET: &str = "ssl://electrum.iriswallet.com:500
 pub(crate) const PROXY_ENDPOINT_LOCAL: &str = "rpc://127.0.0.1:3000/json-rpc";
 pub(crate) const PROXY_ENDPOINT_PUBLIC: &str = "rpcs://proxy.iriswallet.com/0.2/json-rpc";
 const PASSWORD_MIN_LENGTH: u8 = 8;
+/// Payment events kept for slow subscribers before they start missing some
+pub(crate) const USER_PAYMENT_EVENTS_CAPACITY: usize = 256;
 
 pub(crate) struct AppState {
     pub(crate) static_state: Arc<StaticState>,
@@ -72,6 +84,16 @@ pub(crate) struct AppState {
     pub(crate) user_manager: Arc<TokioMutex<Option<UserManager>>>,
     pub(crate) hsm_service: Arc<TokioMutex<Option<Arc<dyn HsmProvider>>>>,
     pub(crate) virtual_node_manager: Arc<TokioMutex<Option<Arc<VirtualNodeManager>>>>,
+    pub(crate) virtual_htlc_manager: Arc<TokioMutex<Option<Arc<VirtualHtlcManager>>>>,
+    pub(crate) maker_price_policy: Option<Arc<MakerPricePolicy>>,
+    pub(crate) monitor_mirror: Option<MonitorMirrorConfig>,
+    pub(crate) rate_limiter: Arc<RateLimiter>,
+    pub(crate) hub_auth: Arc<HubAuth>,
+    /// Node IDs of the hubs whose user archives can be imported
+    pub(crate) user_archive_trusted_hubs: Vec<PublicKey>,
+    /// Bot serving the users, who log in to the hub through it
+    pub(crate) telegram_bot: Option<TelegramBotConfig>,
+    pub(crate) telegram_logins: Arc<LoginReplayCache>,
 }
 
 impl AppState {
@@ -97,6 +119,17 @@ pub(crate) struct StaticState {
     pub(crate) ldk_data_dir: PathBuf,
     pub(crate) logger: Arc<FilesystemLogger>,
     pub(crate) max_media_upload_size_mb: u16,
+    pub(crate) metrics: Arc<Metrics>,
+    /// Payments credited to users, for notifications
+    pub(crate) user_payments: broadcast::Sender<UserPaymentEvent>,
+}
+
+/// Payment credited to a user of the node
+#[derive(Clone, Debug)]
+pub(crate) struct UserPaymentEvent {
+    pub(crate) user_id: String,
+    pub(crate) amount_msat: u64,
+    pub(crate) payment_hash: String,
 }
 
 pub(crate) struct UnlockedAppState {
@@ -367,6 +400,8 @@ pub(crate) async fn start_daemon(args: &LdkUserInfo) -> Result<Arc<AppState>, Ap
         ldk_data_dir,
         logger,
         max_media_upload_size_mb: args.max_media_upload_size_mb,
+        metrics: Arc::new(Metrics::default()),
+        user_payments: broadcast::channel(USER_PAYMENT_EVENTS_CAPACITY).0,
     });
 
     // Load environment variables
@@ -382,6 +417,29 @@ pub(crate) async fn start_daemon(args: &LdkUserInfo) -> Result<Arc<AppState>, Ap
     let database = Arc::new(TokioMutex::new(None));
     let user_manager = Arc::new(TokioMutex::new(None));
 
+    let maker_price_policy = maker_price_policy_from_env()
+        .map_err(|e| AppError::InvalidPricingConfig(e.to_string()))?
+        .map(Arc::new);
+    if maker_price_policy.is_some() {
+        tracing::info!("Maker swaps are checked against the configured pricing policy");
+    }
+
+    let monitor_mirror = monitor_mirror_config_from_env()
+        .map_err(|e| AppError::InvalidMonitorMirrorConfig(e.to_string()))?;
+
+    let rate_limit_config = rate_limit_config_from_env()
+        .map_err(|e| AppError::InvalidRateLimitConfig(e.to_string()))?;
+    if rate_limit_config.backend == RateLimitBackend::Postgres {
+        tracing::info!("Rate limit buckets are shared through the database");
+    }
+    let rate_limiter = Arc::new(RateLimiter::new(rate_limit_config, database.clone()));
+
+    let hub_auth =
+        Arc::new(HubAuth::from_env().map_err(|e| AppError::InvalidHubAuthConfig(e.to_string()))?);
+    let user_archive_trusted_hubs =
+        trusted_hubs_from_env().map_err(AppError::InvalidHubAuthConfig)?;
+    let telegram_bot = TelegramBotConfig::from_env();
+
     Ok(Arc::new(AppState {
         static_state,
         cancel_token,
@@ -392,6 +450,14 @@ pub(crate) async fn start_daemon(args: &LdkUserInfo) -> Result<Arc<AppState>, Ap
         user_manager,
         hsm_service: Arc::new(TokioMutex::new(None)),
         virtual_node_manager: Arc::new(TokioMutex::new(None)),
+        virtual_htlc_manager: Arc::new(TokioMutex::new(None)),
+        maker_price_policy,
+        monitor_mirror,
+        rate_limiter,
+        hub_auth,
+        user_archive_trusted_hubs,
+        telegram_bot,
+        telegram_logins: Arc::new(LoginReplayCache::default()),
     }))
 }
 
@@ -463,6 +529,15 @@ pub(crate) fn get_route(
 
 // Function to initialize database after unlock
 pub(crate) async fn initialize_database_after_unlock(app_state: &Arc<AppState>) -> Result<(), AppError> {
+    let unlocked_state = app_state.get_unlocked_app_state().await.clone();
+    initialize_database(app_state, unlocked_state.as_deref()).await
+}
+
+/// Connect the multi-user database, if configured, and set up the services using it
+pub(crate) async fn initialize_database(
+    app_state: &Arc<AppState>,
+    unlocked_state: Option<&UnlockedAppState>,
+) -> Result<(), AppError> {
     if let Ok(database_url) = std::env::var("DATABASE_URL") {
         tracing::info!("Initializing PostgreSQL database for multi-user support after unlock");
         
@@ -473,7 +548,7 @@ pub(crate) async fn initialize_database_after_unlock(app_state: &Arc<AppState>)
         // RGB library will use its own SQLite database in separate directory
         
         // Initialize blockchain balance service if BitcoindClient is available
-        let user_mgr = if let Some(unlocked_state) = app_state.get_unlocked_app_state().await.as_ref() {
+        let user_mgr = if let Some(unlocked_state) = unlocked_state {
             // Try to get BitcoindClient from RGB wallet wrapper
             if let Ok(bitcoind_client) = get_bitcoind_client_from_unlocked_state(unlocked_state) {
                 let blockchain_service = Arc::new(BlockchainBalanceService::new(bitcoind_client));
@@ -486,12 +561,22 @@ pub(crate) async fn initialize_database_after_unlock(app_state: &Arc<AppState>)
             UserManager::new(db.clone())
         };
         
+        // Restore in-flight virtual HTLCs persisted before the last shutdown
+        let virtual_htlc_mgr = Arc::new(VirtualHtlcManager::new(
+            db.clone(),
+            Arc::new(VirtualChannelManager::new(db.clone())),
+        ));
+        virtual_htlc_mgr.load_pending_htlcs().await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
         *app_state.database.lock().await = Some(db);
         *app_state.user_manager.lock().await = Some(user_mgr.clone());
+        *app_state.virtual_htlc_manager.lock().await = Some(virtual_htlc_mgr);
         
         // Initialize HSM provider after keys manager is available
-        if let Some(unlocked_state) = app_state.get_unlocked_app_state().await.as_ref() {
-            let hsm_provider: Arc<dyn HsmProvider> = Arc::new(LocalHsmProvider::new(unlocked_state.keys_manager.clone()));
+        if let Some(unlocked_state) = unlocked_state {
+            let hsm_provider = hsm_provider_from_env(unlocked_state.keys_manager.clone())
+                .map_err(|e| AppError::Generic(format!("Failed to initialize HSM provider: {}", e)))?;
             let virtual_mgr = Arc::new(VirtualNodeManager::new(hsm_provider.clone(), Arc::new(user_mgr)));
             
             *app_state.hsm_service.lock().await = Some(hsm_provider.clone());
@@ -1,9 +1,17 @@
-use axum::{extract::State, response::Json};
+use axum::{extract::State, http::HeaderMap, response::Json};
 use axum_extra::extract::WithRejection;
+use lightning::ln::channelmanager::PaymentId;
+use lightning::ln::invoice_utils::create_invoice_from_channelmanager;
+use lightning_invoice::{Bolt11Invoice, Currency};
+use rgb_lib::BitcoinNetwork;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::database::UserInvoice;
 use crate::error::APIError;
+use crate::idempotency::{extract_idempotency_key, idempotent_payment_id, run_idempotent};
+use crate::quota::{with_quota, QuotaAction, QuotaSpend};
 use crate::utils::AppState;
 
 #[derive(Deserialize)]
@@ -22,20 +30,35 @@ pub struct VirtualRgbInvoiceResponse {
     pub batch_transfer_idx: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct VirtualSendPaymentRequest {
     pub user_id: String,  // bitMaskRGB sends user_id
     pub invoice: String,
     pub amt_msat: Option<u64>,
+    #[serde(default, skip_serializing)]
+    pub idempotency_key: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct VirtualSendPaymentResponse {
     pub payment_id: String,
     pub payment_hash: Option<String>,
     pub status: String,
 }
 
+#[derive(Deserialize)]
+pub struct VirtualLnInvoiceRequest {
+    pub user_id: String,  // bitMaskRGB sends user_id
+    pub amt_msat: Option<u64>,
+    pub expiry_sec: u32,
+}
+
+#[derive(Serialize)]
+pub struct VirtualLnInvoiceResponse {
+    pub invoice: String,
+    pub payment_hash: String,
+}
+
 #[derive(Deserialize)]
 pub struct VirtualAssetBalanceRequest {
     pub user_id: String,  // bitMaskRGB sends user_id
@@ -73,23 +96,120 @@ pub async fn virtual_rgbinvoice(
 
 pub async fn virtual_sendpayment(
     State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
     WithRejection(Json(req), _rejection): WithRejection<Json<VirtualSendPaymentRequest>, APIError>,
 ) -> Result<Json<VirtualSendPaymentResponse>, APIError> {
     tracing::info!("Virtual send payment request for user_id: {}", req.user_id);
     
+    let idempotency_key = extract_idempotency_key(&headers, req.idempotency_key.as_deref())?;
+    // retries of a request share its payment ID, so the node rejects them if it was already sent
+    let payment_id = idempotency_key
+        .as_deref()
+        .map(|key| idempotent_payment_id("virtual_sendpayment", key));
+    run_idempotent(&app_state, "virtual_sendpayment", idempotency_key, &req, || {
+        send_virtual_payment(app_state.clone(), &req, payment_id)
+    })
+    .await
+}
+
+async fn send_virtual_payment(
+    app_state: Arc<AppState>,
+    req: &VirtualSendPaymentRequest,
+    payment_id: Option<PaymentId>,
+) -> Result<Json<VirtualSendPaymentResponse>, APIError> {
+    let bolt11 = Bolt11Invoice::from_str(&req.invoice)
+        .map_err(|e| APIError::InvalidInvoice(e.to_string()))?;
+    let spend = QuotaSpend {
+        action: QuotaAction::Transaction,
+        sats: req.amt_msat.or(bolt11.amount_milli_satoshis()).unwrap_or_default().div_ceil(1000),
+        asset: bolt11.rgb_contract_id().map(|c| c.to_string()).zip(bolt11.rgb_amount()),
+    };
+    let database = app_state.database.lock().await.clone();
+
     // Use Lightning router for external payments through master node
     let lightning_router = crate::lightning_router::LightningRouter::new(app_state);
-    
-    match lightning_router.send_lightning_payment(req.invoice, req.amt_msat).await {
-        Ok(response) => Ok(Json(VirtualSendPaymentResponse {
-            payment_id: response.payment_id,
-            payment_hash: response.payment_hash,
-            status: response.status,
-        })),
-        Err(e) => Err(APIError::FailedPayment(e.to_string())),
+    let payment = async {
+        match lightning_router.send_lightning_payment(req.invoice.clone(), req.amt_msat, payment_id).await {
+            Ok(response) => Ok(Json(VirtualSendPaymentResponse {
+                payment_id: response.payment_id,
+                payment_hash: response.payment_hash,
+                status: response.status,
+            })),
+            Err(e) => Err(APIError::FailedPayment(e.to_string())),
+        }
+    };
+    // payments go through unchecked while the database isn't connected
+    match database {
+        Some(database) => with_quota(&database, &req.user_id, &spend, payment).await,
+        None => payment.await,
     }
 }
 
+pub async fn virtual_lninvoice(
+    State(app_state): State<Arc<AppState>>,
+    WithRejection(Json(req), _rejection): WithRejection<Json<VirtualLnInvoiceRequest>, APIError>,
+) -> Result<Json<VirtualLnInvoiceResponse>, APIError> {
+    tracing::info!("Virtual LN invoice request for user_id: {}", req.user_id);
+    let invoice = create_user_ln_invoice(&app_state, &req.user_id, req.amt_msat, req.expiry_sec).await?;
+
+    Ok(Json(VirtualLnInvoiceResponse {
+        invoice: invoice.to_string(),
+        payment_hash: crate::utils::hex_str(invoice.payment_hash().as_ref()),
+    }))
+}
+
+/// Create a Lightning invoice paying a user
+///
+/// The invoice is issued by the master node, which receives and claims the payment like any
+/// other, and is stored with the user, who is credited once the payment is claimed.
+pub(crate) async fn create_user_ln_invoice(
+    app_state: &Arc<AppState>,
+    user_id: &str,
+    amt_msat: Option<u64>,
+    expiry_sec: u32,
+) -> Result<Bolt11Invoice, APIError> {
+    let unlocked_state = app_state.check_unlocked().await?.clone().unwrap();
+
+    let database = app_state.database.lock().await.clone()
+        .ok_or_else(|| APIError::Unexpected("Database not available".to_string()))?;
+
+    let currency = match app_state.static_state.network {
+        BitcoinNetwork::Mainnet => Currency::Bitcoin,
+        BitcoinNetwork::Testnet => Currency::BitcoinTestnet,
+        BitcoinNetwork::Regtest => Currency::Regtest,
+        BitcoinNetwork::Signet => Currency::Signet,
+    };
+    let bolt11 = create_invoice_from_channelmanager(
+        &unlocked_state.channel_manager,
+        unlocked_state.keys_manager.clone(),
+        app_state.static_state.logger.clone(),
+        currency,
+        amt_msat,
+        String::new(),
+        expiry_sec,
+        None,
+        None,
+        None,
+    )
+    .map_err(|e| APIError::FailedInvoiceCreation(e.to_string()))?;
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(expiry_sec as i64);
+    database
+        .insert_user_invoice(&UserInvoice {
+            payment_hash: crate::utils::hex_str(bolt11.payment_hash().as_ref()),
+            user_id: user_id.to_string(),
+            invoice: bolt11.to_string(),
+            amount_msat: amt_msat.map(|a| a as i64),
+            status: "pending".to_string(),
+            expires_at,
+        })
+        .await
+        .map_err(|e| APIError::FailedInvoiceCreation(e.to_string()))?;
+    tracing::info!("Created invoice {} for user {}", bolt11.payment_hash(), user_id);
+
+    Ok(bolt11)
+}
+
 pub async fn virtual_assetbalance(
     State(app_state): State<Arc<AppState>>,
     WithRejection(Json(req), rejection): WithRejection<Json<VirtualAssetBalanceRequest>, APIError>,
@@ -1,55 +1,232 @@
+use bitcoin::psbt::Psbt;
 use bitcoin::secp256k1::PublicKey;
+use rgb_lib::wallet::{Recipient, RecipientInfo};
 use rgb_lib::ContractId;
 use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use chrono;
+use crate::database::{LedgerError, LedgerPosting, LEDGER_EXTERNAL_ACCOUNT};
+use crate::routes::{Assignment, DecodeRGBInvoiceResponse, ListAssetsResponse};
+use crate::utils::{hex_str, UnlockedAppState};
 use crate::virtual_htlc::{VirtualSettlement, RgbTransfer};
 
 
-/// Virtual balance manager for tracking BTC and RGB balances per virtual node
+/// Environment variable selecting how user-to-user transfers are settled
+pub const VIRTUAL_TRANSFER_MODE_ENV: &str = "VIRTUAL_TRANSFER_MODE";
+
+/// Fee rate (sat/vB) used for on-chain sends when none is requested
+pub const DEFAULT_ONCHAIN_FEE_RATE: u64 = 25;
+
+/// How transfers between two users of this node are settled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VirtualTransferMode {
+    /// Move funds in the ledger only, funds leave the node on withdraw
+    #[default]
+    Ledger,
+    /// Broadcast an on-chain transaction for every transfer
+    OnChain,
+}
+
+impl VirtualTransferMode {
+    /// Read the mode from the environment, defaulting to ledger-only transfers
+    pub fn from_env() -> Self {
+        match std::env::var(VIRTUAL_TRANSFER_MODE_ENV) {
+            Ok(value) => value.parse().unwrap_or_else(|_| {
+                tracing::warn!("Unknown {} '{}', using ledger transfers", VIRTUAL_TRANSFER_MODE_ENV, value);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+impl std::str::FromStr for VirtualTransferMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ledger" => Ok(Self::Ledger),
+            "onchain" | "on_chain" => Ok(Self::OnChain),
+            other => Err(format!("unknown virtual transfer mode: {}", other)),
+        }
+    }
+}
+
+/// Ledger reference of an attempt to pay a Lightning invoice from a user's balance
+pub fn lightning_payment_reference(payment_hash: &str, attempt_id: &uuid::Uuid) -> String {
+    format!("ln_payment_{}_{}", payment_hash, attempt_id)
+}
+
+fn refund_reference(reference: &str) -> String {
+    format!("{}_refund", reference)
+}
+
+fn fee_refund_reference(reference: &str) -> String {
+    format!("{}_fee_refund", reference)
+}
+
+/// RGB asset send paying an invoice from a user's ledger balance
+#[derive(Debug, PartialEq)]
+pub struct UserAssetSend {
+    pub asset_id: String,
+    pub assignment: Assignment,
+    pub recipient_id: String,
+    pub transport_endpoints: Vec<String>,
+}
+
+impl UserAssetSend {
+    /// Resolve what to send from a decoded RGB invoice
+    ///
+    /// The asset and amount of the invoice are used when set, `asset_id` and `amount` are only
+    /// needed when the invoice leaves them open and must match it otherwise.
+    pub fn new(
+        invoice: DecodeRGBInvoiceResponse,
+        asset_id: Option<String>,
+        amount: Option<u64>,
+    ) -> Result<Self, BalanceError> {
+        let asset_id = match (invoice.asset_id, asset_id) {
+            (Some(invoice_asset), Some(asset_id)) if invoice_asset != asset_id => {
+                return Err(BalanceError::InvalidInvoice(format!(
+                    "invoice is for asset {}, not {}", invoice_asset, asset_id
+                )));
+            }
+            (Some(asset_id), _) | (None, Some(asset_id)) => asset_id,
+            (None, None) => {
+                return Err(BalanceError::InvalidInvoice("invoice doesn't set the asset, an asset ID is required".to_string()));
+            }
+        };
+        let assignment = match (invoice.assignment, amount) {
+            (Assignment::Fungible(invoice_amount), Some(amount)) if invoice_amount != amount => {
+                return Err(BalanceError::InvalidInvoice(format!(
+                    "invoice requests {} units, not {}", invoice_amount, amount
+                )));
+            }
+            (Assignment::Fungible(amount), _) | (Assignment::Any, Some(amount)) => Assignment::Fungible(amount),
+            (Assignment::NonFungible, _) => Assignment::NonFungible,
+            (Assignment::Any, None) => {
+                return Err(BalanceError::InvalidInvoice("invoice doesn't set the amount, an amount is required".to_string()));
+            }
+            (assignment, _) => {
+                return Err(BalanceError::InvalidInvoice(format!("unsupported assignment {:?}", assignment)));
+            }
+        };
+        let send = Self {
+            asset_id,
+            assignment,
+            recipient_id: invoice.recipient_id,
+            transport_endpoints: invoice.transport_endpoints,
+        };
+        if send.units() == 0 {
+            return Err(BalanceError::InvalidInvoice("amount must be positive".to_string()));
+        }
+        Ok(send)
+    }
+
+    /// Units debited from the user, a non-fungible asset counting as one
+    pub fn units(&self) -> u64 {
+        match self.assignment {
+            Assignment::Fungible(amount) => amount,
+            Assignment::NonFungible => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Ledger balance of a user in an RGB asset, with the asset details known to the node
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct UserAssetBalance {
+    pub asset_id: String,
+    pub ticker: Option<String>,
+    pub name: Option<String>,
+    pub precision: Option<u8>,
+    pub balance: u64,
+}
+
+impl UserAssetBalance {
+    /// List the RGB assets a user holds in the ledger, sorted by asset ID
+    pub fn from_balances(balances: &HashMap<String, i64>, node_assets: &ListAssetsResponse) -> Vec<Self> {
+        let mut details = HashMap::new();
+        for asset in node_assets.nia.iter().flatten() {
+            details.insert(asset.asset_id.as_str(), (Some(&asset.ticker), &asset.name, asset.precision));
+        }
+        for asset in node_assets.uda.iter().flatten() {
+            details.insert(asset.asset_id.as_str(), (Some(&asset.ticker), &asset.name, asset.precision));
+        }
+        for asset in node_assets.cfa.iter().flatten() {
+            details.insert(asset.asset_id.as_str(), (None, &asset.name, asset.precision));
+        }
+
+        let mut assets: Vec<Self> = balances
+            .iter()
+            // BTC is keyed as "BTC" in the balances, assets by their ID
+            .filter(|(asset_id, balance)| asset_id.as_str() != "BTC" && **balance > 0)
+            .map(|(asset_id, balance)| {
+                let details = details.get(asset_id.as_str());
+                Self {
+                    asset_id: asset_id.clone(),
+                    ticker: details.and_then(|(ticker, _, _)| ticker.cloned()),
+                    name: details.map(|(_, name, _)| name.to_string()),
+                    precision: details.map(|(_, _, precision)| *precision),
+                    balance: *balance as u64,
+                }
+            })
+            .collect();
+        assets.sort_by(|a, b| a.asset_id.cmp(&b.asset_id));
+        assets
+    }
+}
+
+/// Virtual balance manager for BTC and RGB balances per virtual node
+///
+/// Balances are never stored directly: they're derived from the ledger journal, and every change
+/// is posted as a balanced journal that is rejected if it would overdraw the sender.
 pub struct VirtualBalanceManager {
-    btc_balances: Arc<RwLock<HashMap<PublicKey, u64>>>,
-    rgb_balances: Arc<RwLock<HashMap<(PublicKey, ContractId), u64>>>,
     database: Arc<crate::database::Database>,
+    transfer_mode: VirtualTransferMode,
 }
 
 impl VirtualBalanceManager {
     pub fn new(database: Arc<crate::database::Database>) -> Self {
-        Self {
-            btc_balances: Arc::new(RwLock::new(HashMap::new())),
-            rgb_balances: Arc::new(RwLock::new(HashMap::new())),
-            database,
-        }
+        Self { database, transfer_mode: VirtualTransferMode::from_env() }
+    }
+
+    /// Override the transfer mode read from the environment
+    pub fn with_transfer_mode(mut self, transfer_mode: VirtualTransferMode) -> Self {
+        self.transfer_mode = transfer_mode;
+        self
+    }
+
+    pub fn transfer_mode(&self) -> VirtualTransferMode {
+        self.transfer_mode
     }
 
     /// Apply virtual HTLC settlement to balances
     pub async fn apply_settlement(&self, settlement: &VirtualSettlement) -> Result<(), BalanceError> {
-        // Update BTC balances
-        self.update_btc_balance(
-            &settlement.from_virtual_node,
-            -(settlement.btc_settled as i64),
-        ).await?;
-        
-        self.update_btc_balance(
-            &settlement.to_virtual_node,
-            settlement.btc_settled as i64,
-        ).await?;
+        let from_user_id = self.user_id_for_node(&settlement.from_virtual_node).await?;
+        let to_user_id = self.user_id_for_node(&settlement.to_virtual_node).await?;
 
-        // Update RGB balances if present
+        let mut postings = vec![];
         if let Some(ref rgb_transfer) = settlement.rgb_settled {
-            self.update_rgb_balance(
-                &settlement.from_virtual_node,
-                rgb_transfer.contract_id,
-                -(rgb_transfer.amount as i64),
-            ).await?;
-            
-            self.update_rgb_balance(
-                &settlement.to_virtual_node,
-                rgb_transfer.contract_id,
+            let asset_id = rgb_transfer.contract_id.to_string();
+            postings.extend(LedgerPosting::transfer(
+                &from_user_id,
+                &to_user_id,
+                Some(&asset_id),
                 rgb_transfer.amount as i64,
-            ).await?;
+            ));
         }
+        if postings.is_empty() && settlement.btc_settled == 0 {
+            return Ok(());
+        }
+
+        // BTC balances are tracked in sats, sub-sat amounts are carried in the msat remainder
+        let reference = format!("virtual_htlc_{}", hex_str(&settlement.payment_hash.0));
+        self.database
+            .post_msat_journal(&reference, &from_user_id, &to_user_id, settlement.btc_settled, &postings)
+            .await
+            .map_err(BalanceError::from)?;
 
         tracing::info!(
             "Applied virtual settlement: {} msat BTC + {:?} RGB",
@@ -60,79 +237,59 @@ impl VirtualBalanceManager {
         Ok(())
     }
 
-    /// Update BTC balance for virtual node
-    async fn update_btc_balance(&self, virtual_node: &PublicKey, delta: i64) -> Result<(), BalanceError> {
-        let mut balances = self.btc_balances.write().await;
-        let current = balances.get(virtual_node).copied().unwrap_or(0);
-        let new_balance = (current as i64 + delta).max(0) as u64;
-        balances.insert(*virtual_node, new_balance);
-
-        // Persist to database
-        let user_id = virtual_node.to_string(); // TODO: Map to actual user_id
+    /// Find the user owning a virtual node
+    async fn user_id_for_node(&self, virtual_node: &PublicKey) -> Result<String, BalanceError> {
         self.database
-            .update_user_balance(&user_id, None, new_balance as i64)
+            .get_user_id_by_virtual_node_id(&virtual_node.to_string())
             .await
-            .map_err(|e| BalanceError::Database(e.to_string()))?;
-
-        Ok(())
+            .map_err(|e| BalanceError::Database(e.to_string()))?
+            .ok_or_else(|| BalanceError::UnknownVirtualNode(virtual_node.to_string()))
     }
 
-    /// Update RGB balance for virtual node
-    async fn update_rgb_balance(
-        &self,
-        virtual_node: &PublicKey,
-        contract_id: ContractId,
-        delta: i64,
-    ) -> Result<(), BalanceError> {
-        let mut balances = self.rgb_balances.write().await;
-        let key = (*virtual_node, contract_id);
-        let current = balances.get(&key).copied().unwrap_or(0);
-        let new_balance = (current as i64 + delta).max(0) as u64;
-        balances.insert(key, new_balance);
-
-        // Persist to database
-        let user_id = virtual_node.to_string(); // TODO: Map to actual user_id
-        let asset_id = contract_id.to_string();
-        self.database
-            .update_user_balance(&user_id, Some(&asset_id), new_balance as i64)
+    /// Get BTC balance (in sats) for virtual node
+    pub async fn get_btc_balance(&self, virtual_node: &PublicKey) -> Result<u64, BalanceError> {
+        let user_id = self.user_id_for_node(virtual_node).await?;
+        let balance = self.database
+            .get_user_balance(&user_id, None)
             .await
             .map_err(|e| BalanceError::Database(e.to_string()))?;
-
-        Ok(())
-    }
-
-    /// Get BTC balance for virtual node
-    pub async fn get_btc_balance(&self, virtual_node: &PublicKey) -> u64 {
-        let balances = self.btc_balances.read().await;
-        balances.get(virtual_node).copied().unwrap_or(0)
+        Ok(balance.max(0) as u64)
     }
 
     /// Get RGB balance for virtual node
-    pub async fn get_rgb_balance(&self, virtual_node: &PublicKey, contract_id: ContractId) -> u64 {
-        let balances = self.rgb_balances.read().await;
-        let key = (*virtual_node, contract_id);
-        balances.get(&key).copied().unwrap_or(0)
+    pub async fn get_rgb_balance(&self, virtual_node: &PublicKey, contract_id: ContractId) -> Result<u64, BalanceError> {
+        let user_id = self.user_id_for_node(virtual_node).await?;
+        let balance = self.database
+            .get_user_balance(&user_id, Some(&contract_id.to_string()))
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        Ok(balance.max(0) as u64)
     }
 
     /// Check if virtual node has sufficient balance for transfer
     pub async fn check_sufficient_balance(
         &self,
         virtual_node: &PublicKey,
-        btc_amount: u64,
+        btc_amount_msat: u64,
         rgb_transfer: Option<&RgbTransfer>,
     ) -> Result<(), BalanceError> {
-        // Check BTC balance
-        let btc_balance = self.get_btc_balance(virtual_node).await;
-        if btc_balance < btc_amount {
+        // Check BTC balance, including the sub-sat remainder
+        let user_id = self.user_id_for_node(virtual_node).await?;
+        let btc_balance_msat = self.database
+            .get_user_balance_msat(&user_id)
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?
+            .max(0) as u64;
+        if btc_balance_msat < btc_amount_msat {
             return Err(BalanceError::InsufficientBtc {
-                required: btc_amount,
-                available: btc_balance,
+                required: btc_amount_msat.div_ceil(1000),
+                available: btc_balance_msat / 1000,
             });
         }
 
         // Check RGB balance if needed
         if let Some(rgb_transfer) = rgb_transfer {
-            let rgb_balance = self.get_rgb_balance(virtual_node, rgb_transfer.contract_id).await;
+            let rgb_balance = self.get_rgb_balance(virtual_node, rgb_transfer.contract_id).await?;
             if rgb_balance < rgb_transfer.amount {
                 return Err(BalanceError::InsufficientRgb {
                     contract_id: rgb_transfer.contract_id.to_string(),
@@ -145,8 +302,452 @@ impl VirtualBalanceManager {
         Ok(())
     }
 
-    /// Execute real Bitcoin transfer between user addresses
+    /// Transfer BTC between two users of this node, returns the transfer or transaction ID
+    ///
+    /// A ledger transfer is made at most once per `reference`, a random one is used if none is
+    /// given.
     pub async fn execute_virtual_transfer(
+        &self,
+        from_user_id: i64,
+        to_user_id: i64,
+        amount_sats: u64,
+        reference: Option<String>,
+        unlocked_state: &crate::utils::UnlockedAppState,
+        app_state: &crate::utils::AppState,
+    ) -> Result<String, BalanceError> {
+        match self.transfer_mode {
+            VirtualTransferMode::Ledger => {
+                let transfer_id = reference.unwrap_or_else(|| format!("vt_{}", uuid::Uuid::new_v4()));
+                self.execute_ledger_transfer(from_user_id, to_user_id, amount_sats, transfer_id).await
+            }
+            VirtualTransferMode::OnChain => {
+                self.execute_onchain_transfer(from_user_id, to_user_id, amount_sats, unlocked_state, app_state)
+                    .await
+            }
+        }
+    }
+
+    /// Move BTC between two users in the ledger only, atomically and without chain fees
+    async fn execute_ledger_transfer(
+        &self,
+        from_user_id: i64,
+        to_user_id: i64,
+        amount_sats: u64,
+        transfer_id: String,
+    ) -> Result<String, BalanceError> {
+        if amount_sats == 0 {
+            return Err(BalanceError::Ledger("Transfer amount must be positive".to_string()));
+        }
+        if from_user_id == to_user_id {
+            return Err(BalanceError::Ledger("Cannot transfer to the same user".to_string()));
+        }
+
+        let result = self.database
+            .execute_internal_transfer(
+                &from_user_id.to_string(),
+                &to_user_id.to_string(),
+                amount_sats as i64,
+                None,
+                &transfer_id,
+            )
+            .await;
+        match result.map_err(|e| e.downcast::<LedgerError>()) {
+            Ok(()) => {}
+            // a retry of a transfer already made
+            Err(Ok(LedgerError::DuplicateReference(_))) => {
+                tracing::info!("Ledger transfer {} already completed", transfer_id);
+                return Ok(transfer_id);
+            }
+            Err(Ok(ledger_error)) => return Err(BalanceError::from(ledger_error)),
+            Err(Err(e)) => return Err(BalanceError::Database(e.to_string())),
+        }
+
+        tracing::info!("Ledger transfer completed: {} sats from user {} to user {} ({})",
+            amount_sats, from_user_id, to_user_id, transfer_id);
+
+        Ok(transfer_id)
+    }
+
+    /// Withdraw BTC from a user's ledger balance to an on-chain address
+    ///
+    /// The transaction is built first so its fee is known, then the amount and fee are debited
+    /// before broadcasting so concurrent transfers can't spend them twice, and credited back if
+    /// the broadcast fails. Returns the transaction ID and the fee paid in sats.
+    ///
+    /// A withdrawal is debited at most once per `reference`, a random one is used if none is
+    /// given, and fails with [`BalanceError::PaymentInProgress`] if it was already debited.
+    pub async fn withdraw(
+        &self,
+        user_id: i64,
+        address: String,
+        amount_sats: u64,
+        fee_rate: u64,
+        reference: Option<String>,
+        unlocked_state: Arc<UnlockedAppState>,
+    ) -> Result<(String, u64), BalanceError> {
+        if amount_sats == 0 {
+            return Err(BalanceError::Ledger("Withdrawal amount must be positive".to_string()));
+        }
+
+        let user_id = user_id.to_string();
+        let unlocked = unlocked_state.clone();
+        let unsigned_psbt = tokio::task::spawn_blocking(move || {
+            unlocked.rgb_send_btc_begin(address, amount_sats, fee_rate)
+        })
+        .await
+        .map_err(|e| BalanceError::TransferFailed(e.to_string()))?
+        .map_err(|e| BalanceError::TransferFailed(format!("{:?}", e)))?;
+        let fee_sats = Psbt::from_str(&unsigned_psbt)
+            .map_err(|e| BalanceError::TransferFailed(e.to_string()))?
+            .fee()
+            .map_err(|e| BalanceError::TransferFailed(e.to_string()))?
+            .to_sat();
+
+        let withdrawal_id = reference.unwrap_or_else(|| format!("withdraw_{}", uuid::Uuid::new_v4()));
+        let mut postings = LedgerPosting::transfer(&user_id, LEDGER_EXTERNAL_ACCOUNT, None, amount_sats as i64);
+        if fee_sats > 0 {
+            postings.extend(LedgerPosting::transfer(&user_id, LEDGER_EXTERNAL_ACCOUNT, None, fee_sats as i64));
+        }
+        match self.database.post_journal_once(&withdrawal_id, &postings).await {
+            Ok(_) => {}
+            // a retry of a withdrawal already debited, which must not be sent again
+            Err(LedgerError::DuplicateReference(_)) => {
+                return Err(BalanceError::PaymentInProgress(withdrawal_id));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        tracing::info!(
+            "Withdrawing {} sats (fee {} sats) for user {} ({})",
+            amount_sats, fee_sats, user_id, withdrawal_id
+        );
+        let broadcast = tokio::task::spawn_blocking(move || {
+            let signed_psbt = unlocked_state.rgb_sign_psbt(unsigned_psbt)?;
+            unlocked_state.rgb_send_btc_end(signed_psbt)
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result.map_err(|e| format!("{:?}", e)));
+        let txid = match broadcast {
+            Ok(txid) => txid,
+            Err(e) => {
+                tracing::error!("Withdrawal {} failed, refunding user {}: {}", withdrawal_id, user_id, e);
+                self.database
+                    .reverse_journal(&withdrawal_id, &refund_reference(&withdrawal_id))
+                    .await?;
+                return Err(BalanceError::TransferFailed(format!("Bitcoin transaction failed: {}", e)));
+            }
+        };
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO ln_user_transactions (id, user_id, txid, amount, status) VALUES ($1, $2, $3, $4, 'completed')",
+            uuid::Uuid::new_v4(),
+            user_id,
+            txid,
+            -((amount_sats + fee_sats) as i64)
+        )
+        .execute(self.database.pool())
+        .await
+        {
+            tracing::warn!("Failed to record withdrawal transaction: {}", e);
+        }
+
+        tracing::info!("Withdrawal {} broadcast: {}", withdrawal_id, txid);
+        Ok((txid, fee_sats))
+    }
+
+    /// Debit a user for an operation moving funds out of the node, crediting them back if it fails
+    ///
+    /// `amounts` are the units debited per asset, `None` being BTC in sats.
+    async fn spend_from_ledger<T, E, Fut>(
+        &self,
+        user_id: &str,
+        reference: &str,
+        amounts: &[(Option<&str>, u64)],
+        operation: Fut,
+    ) -> Result<T, BalanceError>
+    where
+        E: std::fmt::Display,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let postings: Vec<LedgerPosting> = amounts
+            .iter()
+            .filter(|(_, amount)| *amount > 0)
+            .flat_map(|(asset_id, amount)| {
+                LedgerPosting::transfer(user_id, LEDGER_EXTERNAL_ACCOUNT, *asset_id, *amount as i64)
+            })
+            .collect();
+        // a reference is debited once, so a refund can't reverse an earlier spend
+        self.database.post_journal_once(reference, &postings).await?;
+
+        match operation.await {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                tracing::error!("Operation {} failed, refunding user {}: {}", reference, user_id, e);
+                self.database
+                    .reverse_journal(reference, &refund_reference(reference))
+                    .await?;
+                Err(BalanceError::TransferFailed(e.to_string()))
+            }
+        }
+    }
+
+    /// Send an RGB asset from a user's ledger balance
+    ///
+    /// The transaction is built first so its fee is known, then the asset units and the fee in
+    /// sats are debited before broadcasting, and credited back if the broadcast fails. Returns
+    /// the transaction ID and the fee paid in sats.
+    pub async fn send_asset(
+        &self,
+        user_id: &str,
+        send: UserAssetSend,
+        fee_rate: u64,
+        min_confirmations: u8,
+        unlocked_state: Arc<UnlockedAppState>,
+    ) -> Result<(String, u64), BalanceError> {
+        if *unlocked_state.rgb_send_lock.lock().unwrap() {
+            return Err(BalanceError::TransferFailed("a channel open is in progress".to_string()));
+        }
+        RecipientInfo::new(send.recipient_id.clone())
+            .map_err(|e| BalanceError::InvalidInvoice(e.to_string()))?;
+
+        let units = send.units();
+        let asset_id = send.asset_id.clone();
+        let recipient_map = HashMap::from([(
+            send.asset_id,
+            vec![Recipient {
+                recipient_id: send.recipient_id,
+                witness_data: None,
+                assignment: send.assignment.into(),
+                transport_endpoints: send.transport_endpoints,
+            }],
+        )]);
+        let unlocked = unlocked_state.clone();
+        let unsigned_psbt = tokio::task::spawn_blocking(move || {
+            unlocked.rgb_send_begin(recipient_map, false, fee_rate, min_confirmations)
+        })
+        .await
+        .map_err(|e| BalanceError::TransferFailed(e.to_string()))?
+        .map_err(|e| BalanceError::TransferFailed(format!("{:?}", e)))?;
+        let fee_sats = Psbt::from_str(&unsigned_psbt)
+            .map_err(|e| BalanceError::TransferFailed(e.to_string()))?
+            .fee()
+            .map_err(|e| BalanceError::TransferFailed(e.to_string()))?
+            .to_sat();
+
+        let reference = format!("send_asset_{}", uuid::Uuid::new_v4());
+        tracing::info!(
+            "Sending {} units of {} (fee {} sats) for user {} ({})",
+            units, asset_id, fee_sats, user_id, reference
+        );
+        let amounts = [(Some(asset_id.as_str()), units), (None, fee_sats)];
+        let send_result = self
+            .spend_from_ledger(user_id, &reference, &amounts, async move {
+                tokio::task::spawn_blocking(move || {
+                    let signed_psbt = unlocked_state.rgb_sign_psbt(unsigned_psbt)?;
+                    unlocked_state.rgb_send_end(signed_psbt)
+                })
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| format!("{:?}", e))
+            })
+            .await?;
+
+        if let Err(e) = self
+            .database
+            .save_user_transaction(user_id, &send_result.txid, -(units as i64), Some(&asset_id), "completed")
+            .await
+        {
+            tracing::warn!("Failed to record asset send transaction: {}", e);
+        }
+
+        tracing::info!("Asset send {} broadcast: {}", reference, send_result.txid);
+        Ok((send_result.txid, fee_sats))
+    }
+
+    /// Fund a channel opened by the node for a user from their ledger balance
+    ///
+    /// The capacity and any RGB amount are debited before `open` runs, and credited back if it
+    /// fails or, by [`Self::credit_closed_channel`], when the channel closes. Returns the result
+    /// of `open`, the temporary channel ID.
+    pub async fn fund_channel_open<Fut>(
+        &self,
+        user_id: &str,
+        peer_pubkey: &str,
+        capacity_sat: u64,
+        asset: Option<(&str, u64)>,
+        open: Fut,
+    ) -> Result<String, BalanceError>
+    where
+        Fut: Future<Output = Result<String, crate::error::APIError>>,
+    {
+        if capacity_sat == 0 {
+            return Err(BalanceError::Ledger("Channel capacity must be positive".to_string()));
+        }
+        let mut amounts = vec![(None, capacity_sat)];
+        if let Some((asset_id, amount)) = asset {
+            amounts.push((Some(asset_id), amount));
+        }
+        let reference = format!("channel_open_{}", uuid::Uuid::new_v4());
+        tracing::info!("Funding channel open {} for user {} with {} sats", reference, user_id, capacity_sat);
+        let temporary_channel_id = self.spend_from_ledger(user_id, &reference, &amounts, open).await?;
+
+        if let Err(e) = self
+            .database
+            .save_funded_user_channel(user_id, &temporary_channel_id, peer_pubkey, capacity_sat as i64, &reference)
+            .await
+        {
+            tracing::error!(
+                "Failed to record channel {} of user {}, {} won't be credited back on close: {}",
+                temporary_channel_id, user_id, reference, e
+            );
+        }
+        Ok(temporary_channel_id)
+    }
+
+    /// Credit back the funds of a channel opened from a user balance once it's closed
+    ///
+    /// The channel funds are hub liquidity while it's open, so the user gets back what they
+    /// funded. Returns whether a credit was posted, false for channels not funded by a user or
+    /// already credited.
+    pub async fn credit_closed_channel(&self, channel_id: &str) -> Result<bool, BalanceError> {
+        let funded = self
+            .database
+            .get_funded_user_channel(channel_id)
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        let Some((user_id, reference)) = funded else {
+            return Ok(false);
+        };
+        // shares the refund reference, so a channel whose open failed can't be credited twice
+        let credited = self
+            .database
+            .reverse_journal(&reference, &refund_reference(&reference))
+            .await?;
+        self.database
+            .close_user_channel(channel_id)
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        if credited {
+            tracing::info!("Credited user {} for closed channel {} ({})", user_id, channel_id, reference);
+        }
+        Ok(credited)
+    }
+
+    /// Pay a Lightning invoice from a user's ledger balance
+    ///
+    /// The amount plus `max_fee_sats` of routing fees are debited before `pay` runs and credited
+    /// back if it fails. Each attempt has its own ledger reference, so an invoice can be paid
+    /// again once an attempt failed, but not while one is pending. Payments are settled by
+    /// [`Self::settle_lightning_payment`] or [`Self::refund_lightning_payment`] once their outcome
+    /// is known.
+    pub async fn fund_lightning_payment<T, E, Fut>(
+        &self,
+        user_id: &str,
+        payment_hash: &str,
+        amount_sats: u64,
+        max_fee_sats: u64,
+        asset: Option<(&str, u64)>,
+        pay: Fut,
+    ) -> Result<T, BalanceError>
+    where
+        E: std::fmt::Display,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if amount_sats == 0 && !asset.is_some_and(|(_, amount)| amount > 0) {
+            return Err(BalanceError::Ledger("Payment amount must be positive".to_string()));
+        }
+        let mut amounts = vec![(None, amount_sats + max_fee_sats)];
+        if let Some((asset_id, amount)) = asset {
+            amounts.push((Some(asset_id), amount));
+        }
+        let reference = lightning_payment_reference(payment_hash, &uuid::Uuid::new_v4());
+        let begun = self
+            .database
+            .begin_user_payment(&reference, payment_hash, user_id, max_fee_sats as i64)
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        if !begun {
+            return Err(BalanceError::PaymentInProgress(payment_hash.to_string()));
+        }
+        tracing::info!("Funding Lightning payment {} for user {}", reference, user_id);
+
+        let result = self.spend_from_ledger(user_id, &reference, &amounts, pay).await;
+        if result.is_err() {
+            self.database
+                .finish_user_payment(&reference, "failed")
+                .await
+                .map_err(|e| BalanceError::Database(e.to_string()))?;
+        }
+        result
+    }
+
+    /// Settle a Lightning payment made from a user's balance once it's sent
+    ///
+    /// The routing fees reserved and not paid are credited back, an unknown fee keeps the whole
+    /// reservation. Returns whether a payment made from a user balance was settled.
+    pub async fn settle_lightning_payment(
+        &self,
+        payment_hash: &str,
+        fee_paid_msat: Option<u64>,
+    ) -> Result<bool, BalanceError> {
+        let pending = self
+            .database
+            .get_pending_user_payment(payment_hash)
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        let Some((user_id, reference, max_fee_sats)) = pending else {
+            return Ok(false);
+        };
+        let max_fee_sats = max_fee_sats as u64;
+        let fee_sats = fee_paid_msat.map_or(max_fee_sats, |fee| fee.div_ceil(1000));
+        if fee_sats > max_fee_sats {
+            tracing::warn!(
+                "Payment {} paid {} sats of fees, over the {} sats reserved", reference, fee_sats, max_fee_sats
+            );
+        }
+        let unused_fee_sats = max_fee_sats.saturating_sub(fee_sats);
+        if unused_fee_sats > 0 {
+            let postings = LedgerPosting::transfer(LEDGER_EXTERNAL_ACCOUNT, &user_id, None, unused_fee_sats as i64);
+            match self.database.post_journal_once(&fee_refund_reference(&reference), &postings).await {
+                Ok(_) | Err(LedgerError::DuplicateReference(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.database
+            .finish_user_payment(&reference, "succeeded")
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        tracing::info!("Settled payment {} of user {} with {} sats of fees", reference, user_id, fee_sats);
+        Ok(true)
+    }
+
+    /// Credit back a failed Lightning payment made from a user's balance, fees included
+    ///
+    /// Returns whether a refund was posted, false for payments not made from a user balance or
+    /// already refunded.
+    pub async fn refund_lightning_payment(&self, payment_hash: &str) -> Result<bool, BalanceError> {
+        let pending = self
+            .database
+            .get_pending_user_payment(payment_hash)
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        let Some((_, reference, _)) = pending else {
+            return Ok(false);
+        };
+        let refunded = self
+            .database
+            .reverse_journal(&reference, &refund_reference(&reference))
+            .await?;
+        self.database
+            .finish_user_payment(&reference, "failed")
+            .await
+            .map_err(|e| BalanceError::Database(e.to_string()))?;
+        Ok(refunded)
+    }
+
+    /// Execute real Bitcoin transfer between user addresses
+    async fn execute_onchain_transfer(
         &self,
         from_user_id: i64,
         to_user_id: i64,
@@ -199,7 +800,7 @@ impl VirtualBalanceManager {
         let txid = match unlocked_state.rgb_send_btc(
             receiver_address.clone(),
             amount_sats,
-            25, // fee_rate (25 sat/vB)
+            DEFAULT_ONCHAIN_FEE_RATE,
             false, // skip_sync
         ) {
             Ok(txid) => {
@@ -258,34 +859,36 @@ pub enum BalanceError {
         required: u64,
         available: u64,
     },
+    #[error("No user found for virtual node {0}")]
+    UnknownVirtualNode(String),
+    #[error("Invalid RGB invoice: {0}")]
+    InvalidInvoice(String),
+    #[error("Transfer failed: {0}")]
+    TransferFailed(String),
+    #[error("Ledger rejected posting: {0}")]
+    Ledger(String),
+    #[error("Another payment of invoice {0} is in progress")]
+    PaymentInProgress(String),
     #[error("Database error: {0}")]
     Database(String),
 }
 
-impl VirtualBalanceManager {
-    /// Get user's actual BTC balance from their real UTXOs (represents real spendable funds)
-    async fn get_actual_user_balance(
-        &self,
-        telegram_id: &str,
-        _unlocked_state: &crate::utils::UnlockedAppState,
-    ) -> Result<u64, anyhow::Error> {
-        let user_telegram_id = telegram_id.parse::<i64>()
-            .map_err(|_| anyhow::anyhow!("Invalid telegram_id: {}", telegram_id))?;
-        
-        // Check user_utxos table for actual spendable balance using telegram_id
-        let balance = sqlx::query!(
-            "SELECT COALESCE(SUM(amount)::BIGINT, 0) as balance FROM user_utxos WHERE user_id = $1 AND spent = false",
-            user_telegram_id
-        )
-        .fetch_one(self.database.pool())
-        .await
-        .map(|row| row.balance.unwrap_or(0))
-        .unwrap_or(0);
-        
-        let actual_balance = balance.max(0) as u64;
-        
-        tracing::info!("User {} (telegram_id) actual UTXO balance: {} sats", telegram_id, actual_balance);
-        
-        Ok(actual_balance)
+impl From<LedgerError> for BalanceError {
+    fn from(error: LedgerError) -> Self {
+        match error {
+            LedgerError::Overdraft { asset, available, required, .. } if asset == "BTC" => {
+                BalanceError::InsufficientBtc {
+                    required: required as u64,
+                    available: available.max(0) as u64,
+                }
+            }
+            LedgerError::Overdraft { asset, available, required, .. } => BalanceError::InsufficientRgb {
+                contract_id: asset,
+                required: required as u64,
+                available: available.max(0) as u64,
+            },
+            LedgerError::Database(e) => BalanceError::Database(e.to_string()),
+            e => BalanceError::Ledger(e.to_string()),
+        }
     }
-}
\ No newline at end of file
+}
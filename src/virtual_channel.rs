@@ -49,12 +49,12 @@ impl VirtualChannelManager {
     }
 
     /// Map payment to virtual node
-    pub async fn map_payment_to_virtual_node(&self, payment_hash: &str, _virtual_node_id: &str, user_id: i64, inbound: bool) -> Result<(), sqlx::Error> {
+    pub async fn map_payment_to_virtual_node(&self, payment_hash: &str, virtual_node_id: &str, user_id: &str, inbound: bool) -> Result<(), sqlx::Error> {
         // Use existing ln_user_transactions table instead of virtual_payments
         let amount = if inbound { 1000 } else { -1000 }; // Placeholder amount
         sqlx::query!(
-            "INSERT INTO ln_user_transactions (id, user_id, txid, amount, asset_id, status) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (txid) DO NOTHING",
-            Uuid::new_v4(), user_id.to_string(), payment_hash, amount, None::<String>, "mapped"
+            "INSERT INTO ln_user_transactions (id, user_id, txid, amount, asset_id, status, virtual_node_id) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (txid, virtual_node_id) DO NOTHING",
+            Uuid::new_v4(), user_id, payment_hash, amount, None::<String>, "mapped", virtual_node_id
         )
         .execute(self.database.pool())
         .await?;
@@ -62,9 +62,10 @@ impl VirtualChannelManager {
     }
 
     /// Get payments for virtual node
-    pub async fn get_payments_for_virtual_node(&self, _virtual_node_id: &str) -> Result<Vec<String>, sqlx::Error> {
+    pub async fn get_payments_for_virtual_node(&self, virtual_node_id: &str) -> Result<Vec<String>, sqlx::Error> {
         let rows = sqlx::query!(
-            "SELECT txid FROM ln_user_transactions WHERE status = 'mapped'",
+            "SELECT txid FROM ln_user_transactions WHERE status = 'mapped' AND virtual_node_id = $1 ORDER BY created_at",
+            virtual_node_id
         )
         .fetch_all(self.database.pool())
         .await?;
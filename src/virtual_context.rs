@@ -1,4 +1,5 @@
-use crate::hsm_provider::VirtualKeysManager;
+use crate::error::APIError;
+use crate::user_manager::UserManager;
 use crate::utils::AppState;
 use crate::virtual_channel::VirtualChannelManager;
 use crate::virtual_htlc::VirtualHtlcManager;
@@ -12,27 +13,35 @@ use std::sync::Arc;
 pub struct VirtualNodeContext {
     pub user_id: String,
     pub virtual_node_id: PublicKey,
-    pub virtual_keys: VirtualKeysManager,
     pub channel_manager: Option<VirtualChannelManager>,
 }
 
 impl VirtualNodeContext {
     /// Extract virtual node context from request and app state
+    ///
+    /// None when the request has no user or virtual nodes are disabled, an error when the user's
+    /// virtual node can't be derived, so the request doesn't fall back to the hub node.
     pub async fn from_request(
         payload: &serde_json::Value,
         headers: &axum::http::HeaderMap,
         app_state: &Arc<AppState>,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>, APIError> {
         // Extract user_id from request
-        let user_id = crate::user_manager::UserManager::extract_user_id(payload, headers)?;
+        let Some(user_id) = UserManager::extract_user_id(payload, headers) else {
+            return Ok(None);
+        };
         
         // Get virtual node manager
         let virtual_mgr = app_state.virtual_node_manager.lock().await;
-        let virtual_mgr = virtual_mgr.as_ref()?;
+        let Some(virtual_mgr) = virtual_mgr.as_ref() else {
+            return Ok(None);
+        };
         
         // Get virtual node for user
-        let virtual_keys = virtual_mgr.get_virtual_node(&user_id).await.ok()?;
-        let virtual_node_id = virtual_keys.get_virtual_node_id();
+        let virtual_node_id = virtual_mgr.get_virtual_node(&user_id).await.map_err(|e| {
+            tracing::error!("Failed to get virtual node of user {}: {}", user_id, e);
+            APIError::Unexpected(e.to_string())
+        })?;
         
         // Get virtual channel manager if database is available
         let channel_manager = if let Some(database) = app_state.database.lock().await.as_ref() {
@@ -41,12 +50,11 @@ impl VirtualNodeContext {
             None
         };
         
-        Some(Self {
+        Ok(Some(Self {
             user_id,
             virtual_node_id,
-            virtual_keys,
             channel_manager,
-        })
+        }))
     }
     
     /// Check if this virtual node owns a channel
@@ -3,9 +3,13 @@ use bitcoin::hashes::Hash;
 use lightning::ln::{PaymentHash, PaymentPreimage};
 use rgb_lib::ContractId;
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::database::{Database, VirtualPaymentRecord};
+use crate::utils::{hex_str, hex_str_to_vec};
+
 /// Virtual HTLC that settles both BTC and RGB atomically
 #[derive(Clone, Debug)]
 pub struct VirtualHtlc {
@@ -30,20 +34,75 @@ pub enum VirtualHtlcStatus {
     Failed,
 }
 
+impl VirtualHtlcStatus {
+    /// Status as stored in the virtual_payments table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VirtualHtlcStatus::Pending => "pending",
+            VirtualHtlcStatus::Settled => "settled",
+            VirtualHtlcStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for VirtualHtlcStatus {
+    type Err = VirtualHtlcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(VirtualHtlcStatus::Pending),
+            "settled" => Ok(VirtualHtlcStatus::Settled),
+            "failed" => Ok(VirtualHtlcStatus::Failed),
+            _ => Err(VirtualHtlcError::Database(format!("Unknown virtual HTLC status: {}", s))),
+        }
+    }
+}
+
 /// Virtual HTLC settlement manager
+///
+/// Pending HTLCs are persisted in the virtual_payments table before they are tracked in memory,
+/// so they can be reloaded with `load_pending_htlcs` after a restart.
 pub struct VirtualHtlcManager {
     pending_htlcs: Arc<Mutex<HashMap<PaymentHash, VirtualHtlc>>>,
+    database: Database,
     virtual_channel_manager: Arc<crate::virtual_channel::VirtualChannelManager>,
 }
 
 impl VirtualHtlcManager {
-    pub fn new(virtual_channel_manager: Arc<crate::virtual_channel::VirtualChannelManager>) -> Self {
+    pub fn new(
+        database: Database,
+        virtual_channel_manager: Arc<crate::virtual_channel::VirtualChannelManager>,
+    ) -> Self {
         Self {
             pending_htlcs: Arc::new(Mutex::new(HashMap::new())),
+            database,
             virtual_channel_manager,
         }
     }
 
+    /// Reload pending virtual HTLCs from the database, returns how many were restored
+    pub async fn load_pending_htlcs(&self) -> Result<usize, VirtualHtlcError> {
+        let records = self
+            .database
+            .get_pending_virtual_payments()
+            .await
+            .map_err(|e| VirtualHtlcError::Database(e.to_string()))?;
+
+        let mut pending = self.pending_htlcs.lock().await;
+        pending.clear();
+        for record in records {
+            match VirtualHtlc::try_from(record) {
+                Ok(virtual_htlc) => {
+                    pending.insert(virtual_htlc.payment_hash, virtual_htlc);
+                }
+                Err(e) => tracing::error!("Skipping invalid persisted virtual HTLC: {}", e),
+            }
+        }
+
+        tracing::info!("Loaded {} pending virtual HTLCs", pending.len());
+        Ok(pending.len())
+    }
+
     /// Create virtual HTLC for BTC + RGB transfer
     pub async fn create_virtual_htlc(
         &self,
@@ -62,16 +121,47 @@ impl VirtualHtlcManager {
             status: VirtualHtlcStatus::Pending,
         };
 
+        // Resolve the users behind both virtual nodes before touching any state
+        let from_user_id = self.resolve_user_id(&from_virtual_node).await?;
+        let to_user_id = self.resolve_user_id(&to_virtual_node).await?;
+
         let mut pending = self.pending_htlcs.lock().await;
+        if pending.contains_key(&payment_hash) {
+            return Err(VirtualHtlcError::DuplicateHtlc);
+        }
+
+        // Persist before tracking in memory so a crash can't lose the HTLC
+        let payment_hash_hex = hex_str(&payment_hash.0);
+        let inserted = self
+            .database
+            .insert_virtual_payment(
+                &payment_hash_hex,
+                &from_virtual_node.to_string(),
+                &to_virtual_node.to_string(),
+                &to_user_id,
+                btc_amount_msat as i64,
+                virtual_htlc
+                    .rgb_transfer
+                    .as_ref()
+                    .map(|t| t.contract_id.to_string())
+                    .as_deref(),
+                virtual_htlc.rgb_transfer.as_ref().map(|t| t.amount as i64),
+            )
+            .await
+            .map_err(|e| VirtualHtlcError::Database(e.to_string()))?;
+        if !inserted {
+            return Err(VirtualHtlcError::DuplicateHtlc);
+        }
+
         pending.insert(payment_hash, virtual_htlc);
+        drop(pending);
 
         // Map payment to virtual nodes in database
-        let payment_hash_hex = payment_hash.0.iter().map(|b| format!("{:02x}", b)).collect::<String>();
         self.virtual_channel_manager
             .map_payment_to_virtual_node(
                 &payment_hash_hex,
                 &from_virtual_node.to_string(),
-1, // TODO: Get actual user_id
+                &from_user_id,
                 false,
             )
             .await
@@ -81,7 +171,7 @@ impl VirtualHtlcManager {
             .map_payment_to_virtual_node(
                 &payment_hash_hex,
                 &to_virtual_node.to_string(),
-2, // TODO: Get actual user_id
+                &to_user_id,
                 true,
             )
             .await
@@ -90,6 +180,15 @@ impl VirtualHtlcManager {
         Ok(())
     }
 
+    /// Find the user owning a virtual node
+    async fn resolve_user_id(&self, virtual_node: &PublicKey) -> Result<String, VirtualHtlcError> {
+        self.database
+            .get_user_id_by_virtual_node_id(&virtual_node.to_string())
+            .await
+            .map_err(|e| VirtualHtlcError::Database(e.to_string()))?
+            .ok_or_else(|| VirtualHtlcError::UnknownVirtualNode(virtual_node.to_string()))
+    }
+
     /// Settle virtual HTLC with preimage - settles BOTH BTC and RGB atomically
     pub async fn settle_virtual_htlc(
         &self,
@@ -97,9 +196,9 @@ impl VirtualHtlcManager {
         preimage: PaymentPreimage,
     ) -> Result<VirtualSettlement, VirtualHtlcError> {
         let mut pending = self.pending_htlcs.lock().await;
-        
-        let mut virtual_htlc = pending
-            .get_mut(&payment_hash)
+
+        let virtual_htlc = pending
+            .get(&payment_hash)
             .ok_or(VirtualHtlcError::HtlcNotFound)?
             .clone();
 
@@ -108,6 +207,10 @@ impl VirtualHtlcManager {
             return Err(VirtualHtlcError::InvalidPreimage);
         }
 
+        self.transition(&virtual_htlc, VirtualHtlcStatus::Settled, Some(&preimage))
+            .await?;
+        pending.remove(&payment_hash);
+
         // Atomic settlement: BTC + RGB
         let settlement = VirtualSettlement {
             payment_hash,
@@ -118,10 +221,6 @@ impl VirtualHtlcManager {
             to_virtual_node: virtual_htlc.to_virtual_node,
         };
 
-        // Update HTLC status
-        virtual_htlc.status = VirtualHtlcStatus::Settled;
-        pending.insert(payment_hash, virtual_htlc);
-
         tracing::info!(
             "Virtual HTLC settled: {} msat BTC + {:?} RGB between {} -> {}",
             settlement.btc_settled,
@@ -136,11 +235,17 @@ impl VirtualHtlcManager {
     /// Fail virtual HTLC
     pub async fn fail_virtual_htlc(&self, payment_hash: PaymentHash) -> Result<(), VirtualHtlcError> {
         let mut pending = self.pending_htlcs.lock().await;
-        
-        if let Some(virtual_htlc) = pending.get_mut(&payment_hash) {
-            virtual_htlc.status = VirtualHtlcStatus::Failed;
-            tracing::info!("Virtual HTLC failed: {}", payment_hash);
-        }
+
+        let virtual_htlc = pending
+            .get(&payment_hash)
+            .ok_or(VirtualHtlcError::HtlcNotFound)?
+            .clone();
+
+        self.transition(&virtual_htlc, VirtualHtlcStatus::Failed, None)
+            .await?;
+        pending.remove(&payment_hash);
+
+        tracing::info!("Virtual HTLC failed: {}", payment_hash);
 
         Ok(())
     }
@@ -157,6 +262,78 @@ impl VirtualHtlcManager {
             .cloned()
             .collect()
     }
+
+    /// Persist a status transition, only Pending -> Settled and Pending -> Failed are allowed
+    async fn transition(
+        &self,
+        virtual_htlc: &VirtualHtlc,
+        to: VirtualHtlcStatus,
+        preimage: Option<&PaymentPreimage>,
+    ) -> Result<(), VirtualHtlcError> {
+        if virtual_htlc.status != VirtualHtlcStatus::Pending || to == VirtualHtlcStatus::Pending {
+            return Err(VirtualHtlcError::InvalidTransition {
+                from: virtual_htlc.status.clone(),
+                to,
+            });
+        }
+
+        let updated = self
+            .database
+            .transition_virtual_payment(
+                &hex_str(&virtual_htlc.payment_hash.0),
+                VirtualHtlcStatus::Pending.as_str(),
+                to.as_str(),
+                preimage.map(|p| hex_str(&p.0)).as_deref(),
+            )
+            .await
+            .map_err(|e| VirtualHtlcError::Database(e.to_string()))?;
+
+        // Someone else already resolved this HTLC
+        if !updated {
+            return Err(VirtualHtlcError::InvalidTransition {
+                from: virtual_htlc.status.clone(),
+                to,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<VirtualPaymentRecord> for VirtualHtlc {
+    type Error = VirtualHtlcError;
+
+    fn try_from(record: VirtualPaymentRecord) -> Result<Self, Self::Error> {
+        let invalid = |what: &str| {
+            VirtualHtlcError::Database(format!("Invalid {} for payment {}", what, record.payment_hash))
+        };
+
+        let payment_hash = hex_str_to_vec(&record.payment_hash)
+            .and_then(|v| <[u8; 32]>::try_from(v).ok())
+            .map(PaymentHash)
+            .ok_or_else(|| invalid("payment hash"))?;
+        let from_virtual_node = PublicKey::from_str(&record.from_virtual_node_id)
+            .map_err(|_| invalid("sender virtual node"))?;
+        let to_virtual_node = PublicKey::from_str(&record.to_virtual_node_id)
+            .map_err(|_| invalid("receiver virtual node"))?;
+        let rgb_transfer = match (&record.rgb_contract_id, record.rgb_amount) {
+            (Some(contract_id), Some(amount)) => Some(RgbTransfer {
+                contract_id: ContractId::from_str(contract_id)
+                    .map_err(|_| invalid("RGB contract ID"))?,
+                amount: amount as u64,
+            }),
+            _ => None,
+        };
+
+        Ok(VirtualHtlc {
+            payment_hash,
+            from_virtual_node,
+            to_virtual_node,
+            btc_amount_msat: record.btc_amount_msat as u64,
+            rgb_transfer,
+            status: VirtualHtlcStatus::from_str(&record.status)?,
+        })
+    }
 }
 
 /// Result of virtual HTLC settlement
@@ -180,6 +357,15 @@ pub enum VirtualHtlcError {
     Database(String),
     #[error("Insufficient balance")]
     InsufficientBalance,
+    #[error("HTLC already exists")]
+    DuplicateHtlc,
+    #[error("No user found for virtual node {0}")]
+    UnknownVirtualNode(String),
+    #[error("Invalid HTLC state transition: {from:?} -> {to:?}")]
+    InvalidTransition {
+        from: VirtualHtlcStatus,
+        to: VirtualHtlcStatus,
+    },
 }
 
 impl VirtualSettlement {
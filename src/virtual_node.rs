@@ -1,4 +1,4 @@
-use crate::hsm_provider::{HsmProvider, VirtualKeysManager};
+use crate::hsm_provider::HsmProvider;
 use crate::user_manager::UserManager;
 use bitcoin::secp256k1::PublicKey;
 use std::str::FromStr;
@@ -18,10 +18,11 @@ impl VirtualNodeManager {
         }
     }
 
-    /// Get or create virtual node for user
-    pub async fn get_virtual_node(&self, user_id: &str) -> Result<VirtualKeysManager, crate::hsm_provider::HsmError> {
-        let virtual_keys = self.hsm_provider.get_virtual_keys_manager(user_id).await?;
-        let virtual_node_id = virtual_keys.get_virtual_node_id();
+    /// Get or create virtual node for user, returning its node ID
+    ///
+    /// Virtual node keys stay in the HSM provider, they're only used through its operations.
+    pub async fn get_virtual_node(&self, user_id: &str) -> Result<PublicKey, crate::hsm_provider::HsmError> {
+        let virtual_node_id = self.hsm_provider.derive_virtual_node_id(user_id).await?;
 
         // Check if virtual node ID exists in database, if not save it
         if let Ok(None) = self.user_manager.get_virtual_node_id(user_id).await {
@@ -30,7 +31,7 @@ impl VirtualNodeManager {
             }
         }
 
-        Ok(virtual_keys)
+        Ok(virtual_node_id)
     }
 
     /// Get virtual node ID for user
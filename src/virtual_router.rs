@@ -1,10 +1,14 @@
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::hashes::Hash;
-use lightning::ln::{PaymentHash, PaymentPreimage};
+use lightning::ln::channelmanager::MIN_FINAL_CLTV_EXPIRY_DELTA;
+use lightning::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
 use lightning::routing::router::Route;
 use lightning::sign::EntropySource;
+use lightning_invoice::{Bolt11Invoice, Currency, InvoiceBuilder};
 use rgb_lib::ContractId;
 use std::sync::Arc;
+use std::time::Duration;
+use crate::hsm_provider::HsmProvider;
 use crate::virtual_htlc::{VirtualHtlcManager, RgbTransfer, VirtualSettlement};
 use crate::virtual_balance::VirtualBalanceManager;
 use crate::utils::UnlockedAppState;
@@ -14,6 +18,7 @@ pub struct VirtualRouter {
     htlc_manager: Arc<VirtualHtlcManager>,
     balance_manager: Arc<VirtualBalanceManager>,
     unlocked_state: Arc<UnlockedAppState>,
+    hsm_provider: Arc<dyn HsmProvider>,
 }
 
 impl VirtualRouter {
@@ -21,15 +26,20 @@ impl VirtualRouter {
         htlc_manager: Arc<VirtualHtlcManager>,
         balance_manager: Arc<VirtualBalanceManager>,
         unlocked_state: Arc<UnlockedAppState>,
+        hsm_provider: Arc<dyn HsmProvider>,
     ) -> Self {
         Self {
             htlc_manager,
             balance_manager,
             unlocked_state,
+            hsm_provider,
         }
     }
 
     /// Send payment between virtual nodes (BTC + RGB)
+    ///
+    /// Both nodes live on this hub, so the payment settles right away with the `preimage` the
+    /// receiver's invoice was created with.
     pub async fn send_virtual_payment(
         &self,
         from_virtual_node: PublicKey,
@@ -37,6 +47,7 @@ impl VirtualRouter {
         btc_amount_msat: u64,
         rgb_transfer: Option<RgbTransfer>,
         payment_hash: PaymentHash,
+        preimage: PaymentPreimage,
     ) -> Result<VirtualPaymentResult, VirtualRouterError> {
         // 1. Check sufficient balance
         self.balance_manager
@@ -56,10 +67,7 @@ impl VirtualRouter {
             .await
             .map_err(|e| VirtualRouterError::HtlcCreation(e.to_string()))?;
 
-        // 3. For now, auto-settle (in real implementation, this would wait for preimage)
-        // TODO: Integrate with actual Lightning routing
-        let preimage = PaymentPreimage([42u8; 32]); // Mock preimage
-        
+        // 3. Settle with the receiver's preimage
         let settlement = self.settle_virtual_payment(payment_hash, preimage).await?;
 
         Ok(VirtualPaymentResult {
@@ -98,19 +106,49 @@ impl VirtualRouter {
         Ok(settlement)
     }
 
-    /// Create Lightning invoice for virtual node
+    /// Create virtual invoice for receiving payment
+    ///
+    /// The BOLT11 invoice is signed by the user's virtual node key, so it names the virtual node
+    /// as payee. It's only payable by other virtual nodes, see [`Self::pay_virtual_invoice`].
     pub async fn create_virtual_invoice(
         &self,
-        virtual_node: PublicKey,
+        user_id: &str,
+        currency: Currency,
         btc_amount_msat: Option<u64>,
         rgb_request: Option<RgbInvoiceRequest>,
         expiry_sec: u32,
     ) -> Result<VirtualInvoice, VirtualRouterError> {
+        let virtual_node = self
+            .hsm_provider
+            .derive_virtual_node_id(user_id)
+            .await
+            .map_err(|e| VirtualRouterError::InvoiceCreation(e.to_string()))?;
+
         // Generate payment hash for virtual invoice
         let preimage = PaymentPreimage(self.unlocked_state.keys_manager.get_secure_random_bytes());
         let payment_hash = PaymentHash(
             bitcoin::hashes::sha256::Hash::hash(&preimage.0).to_byte_array()
         );
+        let payment_secret = PaymentSecret(self.unlocked_state.keys_manager.get_secure_random_bytes());
+
+        let mut builder = InvoiceBuilder::new(currency)
+            .description(format!("Payment to virtual node {}", virtual_node))
+            .payment_hash(bitcoin::hashes::sha256::Hash::from_byte_array(payment_hash.0))
+            .payment_secret(payment_secret)
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(MIN_FINAL_CLTV_EXPIRY_DELTA as u64)
+            .expiry_time(Duration::from_secs(expiry_sec as u64));
+        if let Some(amount_msat) = btc_amount_msat {
+            builder = builder.amount_milli_satoshis(amount_msat);
+        }
+        let raw_invoice = builder
+            .build_raw()
+            .map_err(|e| VirtualRouterError::InvoiceCreation(format!("{:?}", e)))?;
+        let bolt11 = self
+            .hsm_provider
+            .sign_invoice(user_id, raw_invoice)
+            .await
+            .map_err(|e| VirtualRouterError::InvoiceCreation(e.to_string()))?;
 
         let virtual_invoice = VirtualInvoice {
             virtual_node,
@@ -119,6 +157,7 @@ impl VirtualRouter {
             btc_amount_msat,
             rgb_request: rgb_request.clone(),
             expiry_sec,
+            bolt11,
         };
 
         tracing::info!(
@@ -148,6 +187,7 @@ impl VirtualRouter {
             invoice.btc_amount_msat.unwrap_or(0),
             rgb_transfer,
             invoice.payment_hash,
+            invoice.preimage,
         ).await
     }
 }
@@ -167,6 +207,7 @@ pub struct VirtualInvoice {
     pub btc_amount_msat: Option<u64>,
     pub rgb_request: Option<RgbInvoiceRequest>,
     pub expiry_sec: u32,
+    pub bolt11: Bolt11Invoice,
 }
 
 #[derive(Clone, Debug)]
@@ -187,4 +228,6 @@ pub enum VirtualRouterError {
     BalanceUpdate(String),
     #[error("Routing failed: {0}")]
     RoutingFailed(String),
+    #[error("Invoice creation failed: {0}")]
+    InvoiceCreation(String),
 }
\ No newline at end of file